@@ -0,0 +1,181 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Bump arenas, for scratch memory that's all freed at once.
+//!
+//! An `Arena` hands out memory from a buffer by bumping a pointer, and only
+//! gets it back when the whole arena is `reset`. That makes allocating from
+//! it about as cheap as allocating can be, which is what you want for
+//! short-lived scratch space (say, everything needed to handle one request).
+//!
+//! `Allocator` is implemented for `&Arena`, so any number of `RawBuf`s can
+//! share one arena, and since `reset` needs a `&mut Arena`, the borrow
+//! checker makes sure they're all gone before the arena is reused:
+//!
+//! ```ignore
+//! let mut scratch = [0u8; 4096];
+//! let mut arena = Arena::new(&mut scratch);
+//! {
+//!     let a = RawBuf::new(&arena, Layout::array::<u64>(16).unwrap());
+//!     let b = RawBuf::new(&arena, Layout::of::<[u8; 100]>());
+//!     // ...
+//! }
+//! arena.reset();
+//! ```
+//!
+//! This is only built with the `allocator_api` feature. Note that the trait
+//! implemented is this crate's `Allocator`; the compiler we build with
+//! predates the standard library's allocator API, so there's no `Vec::new_in`
+//! to hand an arena to yet.
+use core::{cmp, ptr};
+use core::cell::Cell;
+use core::marker::PhantomData;
+use super::Allocator;
+
+/// A bump allocator over a borrowed buffer.
+pub struct Arena<'a> { /// Address of the first byte of the buffer
+                       start: usize
+                     , /// Address just past the end of the buffer
+                       end: usize
+                     , /// Address of the next free byte
+                       next: Cell<usize>
+                     , _buf: PhantomData<&'a mut [u8]>
+                     }
+
+impl<'a> Arena<'a> {
+    /// Returns a new arena that allocates from `buf`
+    pub fn new(buf: &'a mut [u8]) -> Arena<'a> {
+        let start = buf.as_mut_ptr() as usize;
+        Arena { start: start
+              , end: start + buf.len()
+              , next: Cell::new(start)
+              , _buf: PhantomData
+              }
+    }
+
+    /// Free everything that's been allocated from the arena, all at once.
+    #[inline]
+    pub fn reset(&mut self) { self.next.set(self.start) }
+
+    /// Returns the number of bytes allocated so far, including padding
+    #[inline]
+    pub fn used(&self) -> usize { self.next.get() - self.start }
+
+    /// Returns the size of the arena's buffer (in bytes)
+    #[inline]
+    pub fn capacity(&self) -> usize { self.end - self.start }
+
+    /// Returns the number of bytes left in the arena, ignoring alignment
+    #[inline]
+    pub fn remaining(&self) -> usize { self.end - self.next.get() }
+}
+
+impl<'a, 'b> Allocator for &'b Arena<'a> {
+    /// Allocate `size` bytes from the arena.
+    ///
+    /// # Returns
+    ///   - `None` if there isn't room left, or `align` isn't a power of two
+    unsafe fn allocate(&mut self, size: usize, align: usize)
+                      -> Option<*mut u8> {
+        if !align.is_power_of_two() {
+            return None
+        }
+        let next = self.next.get();
+        let start = match next.checked_add(align - 1) {
+            Some(n) => n & !(align - 1)
+          , None => return None
+        };
+        match start.checked_add(size) {
+            Some(end) if end <= self.end => {
+                self.next.set(end);
+                Some(start as *mut u8)
+            }
+          , _ => None
+        }
+    }
+
+    /// Give back a block.
+    ///
+    /// Memory only really comes back when the arena is reset, except that
+    /// freeing the most recent allocation lets its space be reused, so
+    /// stack-like use doesn't waste anything.
+    unsafe fn deallocate(&mut self, frame: *mut u8, size: usize, _: usize) {
+        if frame as usize + size == self.next.get() {
+            self.next.set(frame as usize);
+        }
+    }
+
+    /// Grow or shrink a block.
+    ///
+    /// The most recent allocation is resized in place if there's room;
+    /// anything else is copied to a new block.
+    unsafe fn reallocate( &mut self, old_frame: *mut u8
+                        , old_size: usize, new_size: usize
+                        , align: usize )
+                        -> Option<*mut u8> {
+        let old = old_frame as usize;
+        if old + old_size == self.next.get() && old % align == 0 {
+            match old.checked_add(new_size) {
+                Some(end) if end <= self.end => {
+                    self.next.set(end);
+                    return Some(old_frame)
+                }
+              , _ => return None
+            }
+        }
+        self.allocate(new_size, align)
+            .map(|new_frame| {
+                let n = cmp::min(old_size, new_size);
+                ptr::copy_nonoverlapping(old_frame, new_frame, n);
+                new_frame
+            })
+    }
+}
+
+/// Check that an arena's allocations are aligned and don't overlap, that it
+/// refuses allocations it hasn't room for, and that `reset` lets the next
+/// allocation reuse the start of the buffer. The kernel's `make test` runs
+/// this as part of its self-test.
+///
+/// # Returns
+///   - `Err(what)` saying which check failed, if one did
+pub fn self_check() -> Result<(), &'static str> {
+    let mut buf = [0u8; 64];
+    let mut arena = Arena::new(&mut buf);
+    let first = unsafe {
+        let mut alloc = &arena;
+        let first = match alloc.allocate(3, 1) {
+            Some(ptr) => ptr as usize
+          , None => return Err("first allocation failed")
+        };
+        if first != arena.start || arena.used() != 3 {
+            return Err("first allocation wasn't at the start of the arena")
+        }
+        let second = match alloc.allocate(8, 8) {
+            Some(ptr) => ptr as usize
+          , None => return Err("second allocation failed")
+        };
+        if second % 8 != 0 || second < first + 3 {
+            return Err("second allocation was misaligned or overlapped")
+        }
+        if alloc.allocate(arena.remaining() + 1, 1).is_some() {
+            return Err("allocation bigger than what was left succeeded")
+        }
+        first
+    };
+
+    arena.reset();
+    if arena.used() != 0 || arena.remaining() != arena.capacity() {
+        return Err("arena wasn't empty after reset")
+    }
+    match unsafe { (&arena).allocate(3, 1) } {
+        Some(ptr) if ptr as usize == first => Ok(())
+      , Some(_) => Err("allocation after reset didn't reuse the start")
+      , None => Err("allocation after reset failed")
+    }
+}
@@ -1,6 +1,6 @@
 mod math;
 #[cfg(feature = "buddy_as_system")]
-mod system;
+pub mod system;
 
 use super::{ RawLink, Framesque, Allocator };
 use self::math::PowersOf2;
@@ -33,7 +33,7 @@ pub struct FreeList<'a> {
 impl<'a> FreeList<'a> {
 
     /// Create a new empty `FreeList`
-    const fn new() -> FreeList<'a> {
+    pub const fn new() -> FreeList<'a> {
         FreeList { head: None, length: 0 }
     }
 
@@ -69,14 +69,17 @@ impl<'a> FreeList<'a> {
     ///   - `mem::transmute()`
     ///   - Dereferencing a raw pointer
     unsafe fn pop(&mut self) -> Option<*mut u8> {
-        self.head.take()
+        let popped = self.head.take()
             .map(|head| {
                 let popped_block
                     = mem::replace(&mut self.head, head.next.resolve_mut());
                 let block_ptr: *mut u8
                     = mem::transmute(popped_block);
                 block_ptr
-            })
+            });
+        // the list is now one block shorter
+        if popped.is_some() { self.length -= 1; }
+        popped
     }
 
     /// Returns true if this `FreeList` has free blocks remaining
@@ -167,6 +170,38 @@ impl<'a> Iterator for FreeListIterMut<'a> {
     }
 }
 
+/// Usage statistics for a `BuddyHeapAllocator`
+#[derive(Debug, Copy, Clone)]
+pub struct HeapStats { /// Total size of the heap (in bytes)
+                       pub heap_size: usize
+                     , /// Number of bytes in free blocks
+                       pub free_bytes: usize
+                     , /// Size of the smallest block the heap hands out
+                       pub min_block_size: usize
+                     }
+
+/// Something `BuddyHeapAllocator::check_consistency` found wrong with a
+/// heap's free lists.
+///
+/// Each variant has the address of the block (or the order of the list)
+/// where the problem was found.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum AllocError { /// A free block that isn't inside the heap
+                      OutOfRange { block: usize, order: usize }
+                    , /// A free block that isn't aligned to its own size
+                      Misaligned { block: usize, order: usize }
+                    , /// A free block that overlaps another free block
+                      /// (or is on the free lists twice)
+                      Overlap { block: usize, order: usize, other: usize }
+                    , /// A free block whose buddy is free too, which means
+                      /// a merge was missed
+                      UnmergedBuddies { block: usize, order: usize }
+                    , /// A free list with more blocks on it than its
+                      /// `length` says, or than could fit in the heap
+                      /// (which probably means it loops)
+                      BadLength { order: usize, length: usize }
+                    }
+
 pub struct BuddyHeapAllocator<'a> {
     /// Address of the base of the heap. This must be aligned
     /// on a `MIN_ALIGN` boundary.
@@ -321,6 +356,97 @@ impl<'a> BuddyHeapAllocator<'a> {
 
     }
 
+    /// Returns the total size of the heap (in bytes)
+    #[inline] pub fn heap_size(&self) -> usize { self.heap_size }
+
+    /// Returns usage statistics for this heap.
+    pub fn stats(&self) -> HeapStats {
+        let free = self.free_lists
+                       .iter()
+                       .enumerate()
+                       .fold(0, |sum, (order, list)|
+                           sum + list.length * self.order_alloc_size(order));
+        HeapStats { heap_size: self.heap_size
+                  , free_bytes: free
+                  , min_block_size: self.min_block_size
+                  }
+    }
+
+    /// Check that the heap's free lists make sense.
+    ///
+    /// This walks every free list, checking that each block is inside the
+    /// heap and aligned to its size, that no two free blocks overlap, that
+    /// no block's buddy is also free (they should have been merged), and
+    /// that each list is as long as it says it is. Blocks are compared
+    /// against every other block, so this is slow, and meant for debugging
+    /// and tests rather than for calling all the time.
+    ///
+    /// # Returns
+    ///   - `Err(AllocError)` describing the first problem found
+    pub fn check_consistency(&self) -> Result<(), AllocError> {
+        let start = self.start_addr as usize;
+        for (order, list) in self.free_lists.iter().enumerate() {
+            let size = self.order_alloc_size(order);
+            // more blocks than that means we're going round in circles
+            let max_blocks = self.heap_size / size;
+            let mut counted = 0;
+            for block in list.iter().take(max_blocks + 1) {
+                counted += 1;
+                let addr = block.as_ptr() as usize;
+                if addr < start || addr + size > start + self.heap_size {
+                    return Err(AllocError::OutOfRange { block: addr
+                                                      , order: order })
+                }
+                if (addr - start) % size != 0 {
+                    return Err(AllocError::Misaligned { block: addr
+                                                      , order: order })
+                }
+                if let Some(other) = self.find_overlap(addr, order) {
+                    return Err(AllocError::Overlap { block: addr
+                                                   , order: order
+                                                   , other: other })
+                }
+                let buddy = unsafe { self.get_buddy(order, addr as *mut u8) };
+                if let Some(buddy) = buddy {
+                    if list.iter().take(max_blocks)
+                           .any(|b| b.as_ptr() == buddy) {
+                        return Err(AllocError::UnmergedBuddies {
+                            block: addr, order: order })
+                    }
+                }
+            }
+            if counted > max_blocks || counted != list.length {
+                return Err(AllocError::BadLength { order: order
+                                                 , length: list.length })
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the address of a free block other than the one at `addr`
+    /// (of order `order`) that overlaps it, if there is one
+    fn find_overlap(&self, addr: usize, order: usize) -> Option<usize> {
+        let end = addr + self.order_alloc_size(order);
+        let mut seen_self = false;
+        for (other_order, list) in self.free_lists.iter().enumerate() {
+            let size = self.order_alloc_size(other_order);
+            let max_blocks = self.heap_size / size;
+            for block in list.iter().take(max_blocks) {
+                let other = block.as_ptr() as usize;
+                // the first time we meet the block itself, it's not an
+                // overlap; any other time, it's on the lists twice
+                if other == addr && other_order == order && !seen_self {
+                    seen_self = true;
+                    continue
+                }
+                if other < end && addr < other + size {
+                    return Some(other)
+                }
+            }
+        }
+        None
+    }
+
     pub unsafe fn get_buddy(&self, order: usize, block: *mut u8)
                             -> Option<*mut u8>
     {
@@ -1,8 +1,8 @@
 //! This module integrates the buddy heap allocator into the Rust runtime.
 use spin::Mutex;
 
-use ::Allocator;
-use super::{BuddyHeapAllocator, FreeList};
+use ::{Allocator, Layout};
+use super::{BuddyHeapAllocator, FreeList, HeapStats, AllocError};
 
 
 static ALLOC: Mutex<Option<BuddyHeapAllocator<'static>>>
@@ -15,6 +15,55 @@ pub unsafe fn init_heap( start_addr: *mut u8
         = Some(BuddyHeapAllocator::new(start_addr, free_lists, heap_size));
 }
 
+/// Returns usage statistics for the system heap.
+///
+/// # Returns
+///   - `Some(HeapStats)` if the heap has been initialized
+///   - `None` if `init_heap` hasn't been called yet
+pub fn heap_stats() -> Option<HeapStats> {
+    ALLOC.lock().as_ref()
+         .map(|heap| heap.stats())
+}
+
+/// Check the system heap's free lists, with
+/// `BuddyHeapAllocator::check_consistency`.
+///
+/// # Returns
+///   - `None` if `init_heap` hasn't been called yet
+pub fn check_consistency() -> Option<Result<(), AllocError>> {
+    ALLOC.lock().as_ref()
+         .map(|heap| heap.check_consistency())
+}
+
+/// A handle to the system heap, for code that wants an `Allocator`.
+///
+/// Unlike `__rust_allocate`, allocating from this returns `None` when the
+/// heap is out of memory (or hasn't been set up), rather than panicking.
+#[derive(Debug, Copy, Clone)]
+pub struct System;
+
+impl Allocator for System {
+    unsafe fn allocate(&mut self, size: usize, align: usize)
+                      -> Option<*mut u8> {
+        ALLOC.lock().as_mut()
+             .and_then(|heap| heap.allocate(size, align))
+    }
+
+    unsafe fn deallocate(&mut self, frame: *mut u8, size: usize, align: usize) {
+        ALLOC.lock().as_mut()
+             .expect("Cannot deallocate memory, no system allocator exists!")
+             .deallocate(frame, size, align)
+    }
+}
+
+/// Called when the system heap can't satisfy an allocation we can't do
+/// without.
+pub fn oom(layout: Layout) -> ! {
+    panic!( "Out of memory: couldn't allocate {} bytes aligned to {} from \
+             the system heap!"
+          , layout.size(), layout.align() )
+}
+
 #[no_mangle]
 pub extern "C" fn __rust_allocate(size: usize, align: usize) -> *mut u8 {
     unsafe {
@@ -0,0 +1,107 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! A minimal owned pointer into the system heap.
+//!
+//! `KBox<T>` is just enough of a `Box<T>` to put things on the heap without
+//! the `alloc` crate: it allocates room for a `T` from the system heap,
+//! moves the value in, and drops the value and frees the room when it goes
+//! away.
+
+use core::{fmt, mem, ptr};
+use core::ops::{Deref, DerefMut};
+use super::{Allocator, Layout};
+use super::buddy::system::{System, oom};
+
+/// An owned `T` on the system heap.
+pub struct KBox<T> { ptr: *mut T }
+
+impl<T> KBox<T> {
+    /// Move `value` onto the system heap.
+    ///
+    /// Zero-sized values don't need any memory, so nothing is allocated for
+    /// them.
+    ///
+    /// # Panics
+    ///   - Through `oom`, if the system heap is out of memory
+    pub fn new(value: T) -> KBox<T> {
+        let layout = Layout::of::<T>();
+        let ptr = if layout.size() == 0 {
+            // any aligned, non-null pointer will do
+            layout.align() as *mut T
+        } else {
+            unsafe { System.allocate(layout.size(), layout.align()) }
+                .unwrap_or_else(|| oom(layout)) as *mut T
+        };
+        unsafe { ptr::write(ptr, value) };
+        KBox { ptr: ptr }
+    }
+
+    /// Take ownership of a `T` on the system heap.
+    ///
+    /// # Unsafe due to
+    ///   - `ptr` must have come from `KBox::into_raw`, and must not be owned
+    ///     by anything else, or it'll be freed twice
+    pub unsafe fn from_raw(ptr: *mut T) -> KBox<T> {
+        KBox { ptr: ptr }
+    }
+
+    /// Give up ownership of the value without dropping or freeing it.
+    ///
+    /// # Returns
+    ///   - A pointer to the value, which can be turned back into a `KBox`
+    ///     with `from_raw`
+    pub fn into_raw(boxed: KBox<T>) -> *mut T {
+        let ptr = boxed.ptr;
+        mem::forget(boxed);
+        ptr
+    }
+
+    /// Move the value back off the heap, freeing the room it was using.
+    pub fn into_inner(boxed: KBox<T>) -> T {
+        unsafe {
+            let ptr = KBox::into_raw(boxed);
+            let value = ptr::read(ptr);
+            free(ptr);
+            value
+        }
+    }
+}
+
+/// Free the room for the `T` at `ptr`, without dropping it.
+unsafe fn free<T>(ptr: *mut T) {
+    let layout = Layout::of::<T>();
+    if layout.size() != 0 {
+        System.deallocate(ptr as *mut u8, layout.size(), layout.align())
+    }
+}
+
+impl<T> Deref for KBox<T> {
+    type Target = T;
+    #[inline] fn deref(&self) -> &T { unsafe { &*self.ptr } }
+}
+
+impl<T> DerefMut for KBox<T> {
+    #[inline] fn deref_mut(&mut self) -> &mut T { unsafe { &mut *self.ptr } }
+}
+
+impl<T> Drop for KBox<T> {
+    fn drop(&mut self) {
+        unsafe {
+            // move the value out so that it's dropped, then free its room
+            mem::drop(ptr::read(self.ptr));
+            free(self.ptr);
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for KBox<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
@@ -122,12 +122,45 @@ pub trait Allocator {
 }
 
 
+impl<'a, A: Allocator> Allocator for &'a mut A {
+    #[inline]
+    unsafe fn allocate(&mut self, size: usize, align: usize)
+                      -> Option<*mut u8> {
+        (**self).allocate(size, align)
+    }
+
+    #[inline]
+    unsafe fn deallocate(&mut self, frame: *mut u8, size: usize, align: usize) {
+        (**self).deallocate(frame, size, align)
+    }
+
+    #[inline]
+    unsafe fn reallocate( &mut self, old_frame: *mut u8
+                        , old_size: usize, new_size: usize
+                        , align: usize )
+                        -> Option<*mut u8> {
+        (**self).reallocate(old_frame, old_size, new_size, align)
+    }
+}
 
 mod rawlink;
 pub use self::rawlink::RawLink;
 
+mod raw_buf;
+pub use self::raw_buf::{RawBuf, Layout};
+
 #[cfg(feature = "buddy")]
 pub mod buddy;
 
+#[cfg(feature = "buddy_as_system")]
+mod kbox;
+#[cfg(feature = "buddy_as_system")]
+pub use self::kbox::KBox;
+
 #[cfg(feature = "simple")]
 pub mod simple;
+
+#[cfg(feature = "allocator_api")]
+pub mod arena;
+#[cfg(feature = "allocator_api")]
+pub use self::arena::Arena;
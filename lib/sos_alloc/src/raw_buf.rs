@@ -0,0 +1,138 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Owned raw allocations.
+//!
+//! A `RawBuf` owns a block of memory handed out by an `Allocator`, along with
+//! the `Layout` it was allocated with, and gives the block back when it's
+//! dropped. Since `Allocator::deallocate` needs the original size and
+//! alignment, keeping them together means nobody has to remember them.
+//!
+//! The allocator is stored by value, so it can be anything implementing
+//! `Allocator`, including a `&mut` reference to one.
+
+use core::{mem, ptr, slice};
+use super::Allocator;
+
+/// The size and alignment of a block of memory.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Layout { size: usize
+                  , align: usize
+                  }
+
+impl Layout {
+    /// Create a new `Layout`.
+    ///
+    /// # Returns
+    ///   - `None` if `align` is not a power of two
+    pub fn new(size: usize, align: usize) -> Option<Layout> {
+        if align.is_power_of_two() {
+            Some(Layout { size: size, align: align })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the layout of a `T`
+    #[inline]
+    pub fn of<T>() -> Layout {
+        Layout { size: mem::size_of::<T>(), align: mem::align_of::<T>() }
+    }
+
+    /// Returns the layout of an array of `n` `T`s
+    ///
+    /// # Returns
+    ///   - `None` if the size of the array would overflow a `usize`
+    pub fn array<T>(n: usize) -> Option<Layout> {
+        mem::size_of::<T>().checked_mul(n)
+            .map(|size| Layout { size: size, align: mem::align_of::<T>() })
+    }
+
+    /// Returns the size of this layout (in bytes)
+    #[inline] pub fn size(&self) -> usize { self.size }
+
+    /// Returns the alignment of this layout (in bytes)
+    #[inline] pub fn align(&self) -> usize { self.align }
+}
+
+/// A block of memory owned by the allocator `A`, freed when dropped.
+pub struct RawBuf<A: Allocator> { ptr: *mut u8
+                                , layout: Layout
+                                , alloc: A
+                                }
+
+impl<A: Allocator> RawBuf<A> {
+
+    /// Allocate a new block of memory with the given `layout` from `alloc`.
+    ///
+    /// The contents of the block are uninitialized.
+    ///
+    /// # Returns
+    ///   - `Some(RawBuf)` if the allocation succeeded
+    ///   - `None` if the allocator is out of memory
+    pub fn new(mut alloc: A, layout: Layout) -> Option<RawBuf<A>> {
+        unsafe { alloc.allocate(layout.size, layout.align) }
+            .map(|ptr| RawBuf { ptr: ptr, layout: layout, alloc: alloc })
+    }
+
+    /// Take ownership of a block of memory that was allocated by `alloc`.
+    ///
+    /// # Unsafe due to
+    ///   - `ptr` must have been allocated by `alloc` with exactly `layout`,
+    ///     and must not be owned by anything else, or it'll be freed twice
+    pub unsafe fn from_raw(ptr: *mut u8, layout: Layout, alloc: A)
+                           -> RawBuf<A> {
+        RawBuf { ptr: ptr, layout: layout, alloc: alloc }
+    }
+
+    /// Give up ownership of the block without freeing it.
+    ///
+    /// # Returns
+    ///   - The block's address, its layout, and the allocator it came from,
+    ///     which are everything needed to turn it back into a `RawBuf`
+    ///     with `from_raw`, or to free it by hand
+    pub fn into_raw(self) -> (*mut u8, Layout, A) {
+        let (ptr, layout) = (self.ptr, self.layout);
+        let alloc = unsafe { ptr::read(&self.alloc) };
+        mem::forget(self);
+        (ptr, layout, alloc)
+    }
+
+    /// Returns a pointer to the start of the block
+    #[inline] pub fn as_ptr(&self) -> *mut u8 { self.ptr }
+
+    /// Returns the layout the block was allocated with
+    #[inline] pub fn layout(&self) -> Layout { self.layout }
+
+    /// Returns the size of the block (in bytes)
+    #[inline] pub fn len(&self) -> usize { self.layout.size }
+
+    /// Returns the contents of the block as a slice of bytes.
+    ///
+    /// Note that unless the block has been written to, these bytes are
+    /// uninitialized.
+    #[inline]
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr, self.layout.size) }
+    }
+
+    /// Returns the contents of the block as a mutable slice of bytes.
+    #[inline]
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.layout.size) }
+    }
+}
+
+impl<A: Allocator> Drop for RawBuf<A> {
+    fn drop(&mut self) {
+        unsafe {
+            self.alloc.deallocate( self.ptr
+                                 , self.layout.size, self.layout.align )
+        }
+    }
+}
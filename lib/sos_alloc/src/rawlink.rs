@@ -15,11 +15,17 @@
 //! `map()`, `and_then()`, etc).
 
 use core::ptr;
+use core::cmp::Ordering;
 use core::fmt;
 use core::mem;
 use core::ops;
 
 /// A `RawLink` provides an `Option`-like interface to a raw pointer.
+///
+/// Note that `==` on two `RawLink`s compares the *addresses* they point to,
+/// like `==` on raw pointers does: two links to different nodes holding
+/// equal values are not equal. To compare the values themselves, use
+/// `resolve_eq` and `resolve_cmp`.
 #[allow(raw_pointer_derive)]
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct RawLink<T>(*mut T);
@@ -66,6 +72,49 @@ impl<T> RawLink<T> {
 
     pub const fn from_raw(ptr: *mut T) -> RawLink<T> { RawLink(ptr) }
 
+    /// Get a link to the structure that `field` is a field of.
+    ///
+    /// This is the `container_of` trick for intrusive structures whose link
+    /// isn't the first thing in each node: given a reference to the link
+    /// field, and the field's offset (in bytes) from the start of its
+    /// containing `C`, this returns a link to the `C`.
+    ///
+    /// # Unsafe due to
+    ///   - `field` must really be a field of a `C`, `offset` bytes in;
+    ///     otherwise the link points somewhere that isn't a `C` at all
+    #[inline]
+    pub unsafe fn from_field<C>(field: &mut T, offset: usize) -> RawLink<C> {
+        RawLink((field as *mut T as *mut u8).offset(-(offset as isize))
+                    as *mut C)
+    }
+
+    /// Returns a link `count` `T`s past this one (or before it, if `count`
+    /// is negative).
+    ///
+    /// Offsetting `RawLink::none()` gives `RawLink::none()`.
+    ///
+    /// # Unsafe due to
+    ///   - The result must be in (or one past the end of) the same
+    ///     allocation as this link
+    #[inline]
+    pub unsafe fn offset(self, count: isize) -> RawLink<T> {
+        if self.is_none() { self }
+        else { RawLink(self.0.offset(count)) }
+    }
+
+    /// Returns a link `count` `T`s past this one (or before it, if `count`
+    /// is negative), without caring where it ends up.
+    ///
+    /// Unlike `offset`, this is safe, since the result is never dereferenced
+    /// here; it's just arithmetic, wrapping around the address space. Like
+    /// `offset`, `RawLink::none()` stays `RawLink::none()`.
+    #[inline]
+    pub fn wrapping_offset(self, count: isize) -> RawLink<T> {
+        if self.is_none() { return self }
+        let bytes = count.wrapping_mul(mem::size_of::<T>() as isize);
+        RawLink((self.0 as usize).wrapping_add(bytes as usize) as *mut T)
+    }
+
     /// Resolve the `RawLink` to an `Option`
     ///
     /// # Returns
@@ -120,6 +169,45 @@ impl<T> RawLink<T> {
         unimplemented!()
     }
 }
+impl<T: PartialEq> RawLink<T> {
+    /// Compare the values two links point to (rather than their addresses,
+    /// which is what `==` compares).
+    ///
+    /// # Returns
+    ///   - `true` if both links are non-null, and their referents are equal
+    ///   - `false` if they aren't equal, or either link is null (even if
+    ///     both are: a null link has no value to be equal to)
+    ///
+    /// # Unsafe due to
+    ///   - Dereferencing both links, which must point to valid `T`s if they
+    ///     aren't null
+    pub unsafe fn resolve_eq(&self, other: &RawLink<T>) -> bool {
+        match (self.resolve(), other.resolve()) {
+            (Some(a), Some(b)) => a == b
+          , _ => false
+        }
+    }
+}
+
+impl<T: Ord> RawLink<T> {
+    /// Order two links by the values they point to (rather than their
+    /// addresses).
+    ///
+    /// # Returns
+    ///   - `Some(Ordering)` comparing the referents, if both links are
+    ///     non-null
+    ///   - `None` if either link is null
+    ///
+    /// # Unsafe due to
+    ///   - Dereferencing both links, which must point to valid `T`s if they
+    ///     aren't null
+    pub unsafe fn resolve_cmp(&self, other: &RawLink<T>) -> Option<Ordering> {
+        match (self.resolve(), other.resolve()) {
+            (Some(a), Some(b)) => Some(a.cmp(b))
+          , _ => None
+        }
+    }
+}
 //
 // impl<T> ops::Deref for RawLink<T> {
 //     type Target = T;
@@ -19,7 +19,7 @@ impl FrameNumber {
 
 impl Framesque for FrameNumber {
     #[inline] fn as_ptr(&self) -> *mut u8 {
-        self.0 as *mut u8 // HOPEFULLY this is good
+        (self.0 * ::PAGE_SIZE) as *mut u8
     }
 }
 
@@ -39,7 +39,19 @@ pub struct SimpleAreaAllocator { next_free: FrameNumber
                                , kern_end: FrameNumber
                                , mb_start: FrameNumber
                                , mb_end: FrameNumber
+                               , /// Other ranges of frames that must never
+                                 /// be handed out (first to last, inclusive)
+                                 reserved: [(FrameNumber, FrameNumber)
+                                           ; MAX_RESERVED]
+                               , n_reserved: usize
                                }
+
+/// The maximum number of ranges that can be passed to `reserve`
+pub const MAX_RESERVED: usize = 32;
+// The memory areas come from the multiboot info, which lives for as long as
+// the kernel does, so it's fine to move the allocator between threads.
+unsafe impl Send for SimpleAreaAllocator { }
+
 impl SimpleAreaAllocator {
     fn next_area(&mut self) {
         // println!("In next_area");
@@ -69,10 +81,52 @@ impl SimpleAreaAllocator {
             , kern_end: FrameNumber::containing(kernel_end)
             , mb_start: FrameNumber::containing(multiboot_start)
             , mb_end: FrameNumber::containing(multiboot_end)
+            , reserved: [(FrameNumber(0), FrameNumber(0)); MAX_RESERVED]
+            , n_reserved: 0
             };
         new_allocator.next_area();
         new_allocator
     }
+
+    /// Never hand out the frames containing the addresses `start` up to (but
+    /// not including) `end`.
+    ///
+    /// This is for memory-mapped devices, and anything else that might sit
+    /// in an available memory area. Frames that were already handed out
+    /// before they were reserved aren't taken back, so reserve things as
+    /// early as possible.
+    ///
+    /// # Returns
+    ///   - `false` if `MAX_RESERVED` ranges have already been reserved
+    pub fn reserve(&mut self, start: usize, end: usize) -> bool {
+        if end <= start {
+            return true
+        }
+        if self.n_reserved == MAX_RESERVED {
+            return false
+        }
+        self.reserved[self.n_reserved] = ( FrameNumber::containing(start)
+                                         , FrameNumber::containing(end - 1) );
+        self.n_reserved += 1;
+        true
+    }
+
+    /// Returns the last frame of the reserved range containing `frame`
+    fn reserved_range(&self, frame: FrameNumber) -> Option<FrameNumber> {
+        self.reserved[..self.n_reserved].iter()
+            .find(|&&(first, last)| frame >= first && frame <= last)
+            .map(|&(_, last)| last)
+    }
+
+    /// Returns true if the frame containing `address` has been reserved,
+    /// either with `reserve` or because it holds the kernel or the Multiboot
+    /// info.
+    pub fn is_reserved(&self, address: usize) -> bool {
+        let frame = FrameNumber::containing(address);
+        (frame >= self.kern_start && frame <= self.kern_end)
+            || (frame >= self.mb_start && frame <= self.mb_end)
+            || self.reserved_range(frame).is_some()
+    }
 }
 
 impl Allocator for SimpleAreaAllocator {
@@ -92,19 +146,24 @@ impl Allocator for SimpleAreaAllocator {
                     // println!("...and returning None");
                 }
               , // this frame is in use by the kernel.
-                f if f >= self.kern_start || f <= self.kern_end => {
+                f if f >= self.kern_start && f <= self.kern_end => {
                     // skip ahead to the end of the kernel
                     // println!("In kernel frame, skipping.");
                     self.next_free = self.kern_end.next();
                     // println!("...and returning None");
                 }
               , // this frame is part of the multiboot info.
-                f if f >= self.mb_start || f <= self.mb_end => {
+                f if f >= self.mb_start && f <= self.mb_end => {
                     // skip ahead to the end of the multiboot info.
                     // println!("In multiboot frame, skipping...");
                     self.next_free = self.mb_end.next();
                     // println!("...and returning None");
                 }
+              , // this frame has been reserved.
+                f if self.reserved_range(f).is_some() => {
+                    // skip ahead to the end of the reserved range.
+                    self.next_free = self.reserved_range(f).unwrap().next();
+                }
               , // this frame is free.
                 frame => {
                     // advance the next free frame and return this frame.
@@ -132,7 +191,7 @@ impl Allocator for SimpleAreaAllocator {
         //                 None
         //             }
         //           , // this frame is in use by the kernel.
-        //             f if f >= self.kern_start || f <= self.kern_end => {
+        //             f if f >= self.kern_start && f <= self.kern_end => {
         //                 // skip ahead to the end of the kernel
         //                 // println!("In kernel frame, skipping.");
         //                 self.next_free = self.kern_end.next();
@@ -140,7 +199,7 @@ impl Allocator for SimpleAreaAllocator {
         //                 None
         //             }
         //           , // this frame is part of the multiboot info.
-        //             f if f >= self.mb_start || f <= self.mb_end => {
+        //             f if f >= self.mb_start && f <= self.mb_end => {
         //                 // skip ahead to the end of the multiboot info.
         //                 // println!("In multiboot frame, skipping...");
         //                 self.next_free = self.mb_end.next();
@@ -11,10 +11,17 @@
 #![feature( no_std
           , lang_items )]
 #![feature( const_fn
+          , core_slice_ext
+          , core_str_ext
           , slice_patterns )]
 #![no_std]
 
+use core::{mem, slice, str};
+
 const END_TAG_LEN: u32 = 8;
+
+/// The value a Multiboot 2 bootloader leaves in `eax` when it starts us
+pub const BOOTLOADER_MAGIC: u32 = 0x36d76289;
 pub mod elf;
 pub mod elf64;
 
@@ -55,6 +62,45 @@ impl Info {
             })
     }
 
+    /// Returns the framebuffer the bootloader set up, if it set one up.
+    #[inline]
+    pub fn framebuffer(&self) -> Option<&'static FramebufferTag> {
+        self.get_tag(TagType::FramebufferInfo)
+            .map(|tag| unsafe {
+                &*((tag as *const Tag) as *const FramebufferTag)
+            })
+    }
+
+    /// Returns the command line the bootloader was told to boot the kernel
+    /// with, if it passed one.
+    ///
+    /// A command line tag too short to hold even its own header is broken,
+    /// and counts as no command line at all.
+    pub fn command_line(&self) -> Option<&'static str> {
+        self.get_tag(TagType::CommandLine)
+            .and_then(|tag| {
+                // the tag is just its header and a null-terminated string
+                let max_len = match (tag.length as usize)
+                                        .checked_sub(mem::size_of::<Tag>()) {
+                    Some(len) => len
+                  , None => return None
+                };
+                let bytes = unsafe {
+                    slice::from_raw_parts( (tag as *const Tag).offset(1)
+                                               as *const u8
+                                         , max_len )
+                };
+                let len = bytes.iter()
+                               .position(|b| *b == 0)
+                               .unwrap_or(max_len);
+                Some(str::from_utf8(&bytes[..len]).unwrap_or(""))
+            })
+    }
+
+    /// Returns an iterator over the boot modules loaded by the bootloader.
+    #[inline]
+    pub fn modules(&self) -> Modules { Modules(self.tags()) }
+
     #[inline]
     fn tags(&self) -> Tags { Tags(&self.tag_start as *const Tag) }
 
@@ -146,6 +192,96 @@ impl Iterator for Tags {
     }
 }
 
+/// A boot module loaded into memory by the bootloader.
+///
+/// There's one of these tags for every module.
+#[repr(C)]
+pub struct ModuleTag { tag: Tag
+                     , /// Physical address of the start of the module
+                       pub mod_start: u32
+                     , /// Physical address of the end of the module
+                       pub mod_end: u32
+                     , /// First byte of the module's null-terminated
+                       /// command line string
+                       cmdline: u8
+                     }
+
+impl ModuleTag {
+
+    /// Returns the length of the module (in bytes)
+    #[inline] pub fn len(&self) -> usize {
+        (self.mod_end - self.mod_start) as usize
+    }
+
+    /// Returns the contents of the module.
+    ///
+    /// # Unsafe due to
+    ///   - Assuming the module's physical address is identity-mapped
+    pub unsafe fn data(&self) -> &'static [u8] {
+        slice::from_raw_parts(self.mod_start as *const u8, self.len())
+    }
+
+    /// Returns the command line the module was loaded with.
+    ///
+    /// A module tag too short to hold even the two addresses is broken, and
+    /// counts as having an empty command line.
+    pub fn cmdline(&self) -> &'static str {
+        // the string fills the rest of the tag after the two addresses
+        let max_len = match (self.tag.length as usize).checked_sub(16) {
+            Some(len) => len
+          , None => return ""
+        };
+        let bytes = unsafe {
+            slice::from_raw_parts(&self.cmdline as *const u8, max_len)
+        };
+        let len = bytes.iter()
+                       .position(|b| *b == 0)
+                       .unwrap_or(max_len);
+        str::from_utf8(&bytes[..len]).unwrap_or("")
+    }
+}
+
+/// Iterator over Multiboot module tags
+pub struct Modules(Tags);
+
+impl Iterator for Modules {
+    type Item = &'static ModuleTag;
+
+    fn next(&mut self) -> Option<&'static ModuleTag> {
+        self.0
+            .find(|t| t.ty == TagType::Modules)
+            .map(|tag| unsafe { &*((tag as *const Tag) as *const ModuleTag) })
+    }
+}
+
+/// Describes the framebuffer the bootloader left us.
+///
+/// The colour information that follows these fields in the tag depends on
+/// `fb_type`, and isn't parsed.
+#[repr(C, packed)]
+pub struct FramebufferTag { tag: Tag
+                          , /// Physical address of the framebuffer
+                            pub addr: u64
+                          , /// Number of bytes in each line
+                            pub pitch: u32
+                          , /// Width, in pixels (or characters, for text)
+                            pub width: u32
+                          , /// Height, in pixels (or characters, for text)
+                            pub height: u32
+                          , /// Bits per pixel
+                            pub bpp: u8
+                          , /// 0 for indexed colour, 1 for direct RGB, and
+                            /// 2 for EGA text mode
+                            pub fb_type: u8
+                          , _reserved: u16
+                          }
+
+impl FramebufferTag {
+    /// Returns true if this is an EGA text mode framebuffer (like the VGA
+    /// text buffer) rather than a graphics mode
+    #[inline] pub fn is_text(&self) -> bool { self.fb_type == 2 }
+}
+
 #[repr(C)]
 pub struct MemMapTag { tag: Tag
                      , entry_size: u32
@@ -155,27 +291,38 @@ pub struct MemMapTag { tag: Tag
 
 impl MemMapTag {
 
+    /// Returns an iterator over the memory areas that are available for use
     pub fn areas(&self) -> MemAreas {
+        MemAreas { only_available: true, ..self.all_areas() }
+    }
+
+    /// Returns an iterator over all memory areas, including reserved ones
+    pub fn all_areas(&self) -> MemAreas {
         MemAreas { curr: (&self.first_entry) as *const MemArea
                  , last: ((self as *const MemMapTag as u32) +
                          self.tag.length - self.entry_size)
                          as *const MemArea
                  , size: self.entry_size
+                 , only_available: false
                  }
     }
 }
 
 #[repr(u32)]
-#[derive(Debug, Eq, PartialEq, Ord, PartialOrd)]
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Copy, Clone)]
 pub enum MemAreaType { Available = 1
+                     , Reserved  = 2
                      , ACPI      = 3
                      , Preserve  = 4
+                     , Defective = 5
                      }
 
 #[repr(C)]
 pub struct MemArea { pub base: u64
                    , pub length: u64
-                   , ty: MemAreaType
+                   , // this is a raw `u32` rather than a `MemAreaType`, since
+                     // bootloaders may give us types that we don't know about
+                     ty: u32
                    , _pad: u32
                    }
 
@@ -183,6 +330,24 @@ impl MemArea {
     #[inline] pub fn address(&self) -> usize {
         (self.base + self.length - 1) as usize
     }
+
+    /// Returns the type of this memory area.
+    ///
+    /// The Multiboot 2 spec says that unknown types should be treated as
+    /// reserved, so that's what we do.
+    pub fn ty(&self) -> MemAreaType {
+        match self.ty {
+            1 => MemAreaType::Available
+          , 3 => MemAreaType::ACPI
+          , 4 => MemAreaType::Preserve
+          , 5 => MemAreaType::Defective
+          , _ => MemAreaType::Reserved
+        }
+    }
+
+    #[inline] pub fn is_available(&self) -> bool {
+        self.ty() == MemAreaType::Available
+    }
 }
 
 #[allow(raw_pointer_derive)]
@@ -190,6 +355,8 @@ impl MemArea {
 pub struct MemAreas { curr: *const MemArea
                     , last: *const MemArea
                     , size: u32
+                    , /// if this is set, skip areas that aren't available
+                      only_available: bool
                     }
 
 impl Iterator for MemAreas {
@@ -201,7 +368,7 @@ impl Iterator for MemAreas {
         } else {
             let current = unsafe { &*self.curr };
             self.curr = (self.curr as u32 + self.size) as *const MemArea;
-            if current.ty == MemAreaType::Available {
+            if current.is_available() || !self.only_available {
                 Some(current)
             } else {
                 self.next()
@@ -10,6 +10,7 @@
 #![crate_name = "sos_vga"]
 
 #![feature( no_std
+          , asm
           , const_fn
           , core_slice_ext
           , core_str_ext
@@ -23,7 +24,7 @@
 #[cfg(feature = "system_term")]
 extern crate spin;
 
-use core::mem;
+use core::{mem, ptr};
 use core::fmt::{Write, Result};
 use core::ptr::Unique;
 
@@ -38,17 +39,101 @@ pub static CONSOLE: Mutex<Terminal>
        , 0xB8000
     )});
 
-// TODO: support varying VGA screen sizes?
+/// Width of the standard text mode, which a `Terminal` assumes until it's
+/// told otherwise
 pub const X_MAX: usize = 80;
+/// Height of the standard text mode
 pub const Y_MAX: usize = 25;
 
-/// The type signature fot the actual VGA buffer
-pub type Buffer = [[Char; X_MAX]; Y_MAX];
+/// Size of the text mode memory, in bytes. No text mode can have more
+/// characters on screen than fit in this.
+pub const BUFFER_BYTES: usize = 0x8000;
+/// The most characters a text mode can have on screen
+pub const MAX_CELLS: usize = BUFFER_BYTES / 2;
 
 const ANSI_ESCAPE: &'static str = "\x1b";
 const FG_MASK: u8 = 0b0000_1111;
 const BG_MASK: u8 = 0b1111_0000;
 
+/// Ports for the VGA CRT controller's index and data registers
+const CRTC_INDEX: u16 = 0x3D4;
+const CRTC_DATA: u16 = 0x3D5;
+
+/// CRT controller registers for the high and low bytes of the cursor position
+const CURSOR_HIGH: u8 = 0x0E;
+const CURSOR_LOW: u8 = 0x0F;
+
+/// CRT controller register holding the number of characters per line, less
+/// one
+const HORIZONTAL_DISPLAY_END: u8 = 0x01;
+/// CRT controller register holding bits 8 and 9 of the vertical display end
+/// (in bits 1 and 6)
+const OVERFLOW: u8 = 0x07;
+/// CRT controller register whose low 5 bits hold the character height, in
+/// scanlines, less one
+const MAX_SCAN_LINE: u8 = 0x09;
+/// CRT controller register holding the low 8 bits of the number of
+/// scanlines on screen, less one
+const VERTICAL_DISPLAY_END: u8 = 0x12;
+
+/// Write a byte to an I/O port.
+///
+/// We don't have access to the kernel's `Port` type here, so this is just a
+/// tiny local copy of the one thing we need it for.
+#[inline]
+unsafe fn outb(port: u16, value: u8) {
+    asm!(  "out dx, al"
+        :: "{dx}"(port)
+         , "{al}"(value)
+        :: "intel"
+         , "volatile" );
+}
+
+/// Read a byte from an I/O port.
+#[inline]
+unsafe fn inb(port: u16) -> u8 {
+    let value: u8;
+    asm!(  "in al, dx"
+        :  "={al}"(value)
+        :  "{dx}"(port)
+        :: "intel"
+         , "volatile" );
+    value
+}
+
+/// Read one of the CRT controller's registers
+#[inline]
+unsafe fn read_crtc(register: u8) -> u8 {
+    outb(CRTC_INDEX, register);
+    inb(CRTC_DATA)
+}
+
+/// Work out the current text mode's dimensions from the CRT controller.
+///
+/// The controller knows how many characters wide the screen is, and how
+/// many scanlines tall; dividing that by the height of a character gives
+/// the number of rows (400 scanlines of 16-line characters is 25 rows, and
+/// the same 400 scanlines of an 8x8 font is 50).
+///
+/// # Returns
+///   - `Some((columns, rows))`
+///   - `None` if the registers don't describe a text mode that fits in the
+///     text buffer (if we're in a graphics mode, say)
+pub fn detect_mode() -> Option<(usize, usize)> {
+    let (columns, scanlines, char_height) = unsafe {
+        let overflow = read_crtc(OVERFLOW) as usize;
+        let end = read_crtc(VERTICAL_DISPLAY_END) as usize
+                | ((overflow >> 1) & 1) << 8
+                | ((overflow >> 6) & 1) << 9;
+        ( read_crtc(HORIZONTAL_DISPLAY_END) as usize + 1
+        , end + 1
+        , (read_crtc(MAX_SCAN_LINE) & 0x1F) as usize + 1 )
+    };
+    let rows = scanlines / char_height;
+    if rows == 0 || columns * rows > MAX_CELLS { None }
+    else { Some((columns, rows)) }
+}
+
 /// VGA color codes
 #[derive(Eq, PartialEq, Ord, PartialOrd, Copy, Clone)]
 #[repr(u8)]
@@ -109,15 +194,23 @@ pub struct Char { pub ascii: u8
                 , pub colors: Palette
                 }
 
-pub struct Terminal { buffer: Unique<Buffer>
+/// A terminal on the VGA text buffer.
+///
+/// The buffer is `width` characters by `height`, one row after another.
+pub struct Terminal { buffer: Unique<Char>
                     , x: usize
                     , y: usize
+                    , width: usize
+                    , height: usize
                     , colors: Palette
                     }
 impl Terminal {
 
     /// Constructs a new `Terminal` for abuffer starting at the given address.
     ///
+    /// The terminal starts out assuming the standard 80x25 text mode; see
+    /// `set_dimensions` and `detect_dimensions`.
+    ///
     /// # Arguments:
     ///   - `colors`: the default color palette for the terminal
     ///   - `buffer_start`: the address of the to the memory location where
@@ -128,14 +221,64 @@ impl Terminal {
     pub const unsafe fn new(colors: Palette, buffer_start: usize)
                             -> Terminal {
         Terminal { x: 0, y: 0
+                 , width: X_MAX, height: Y_MAX
                  , colors: colors
                  , buffer: Unique::new(buffer_start as *mut _)
                  }
     }
 
+    /// Returns the character at column `x` of row `y`
     #[inline]
-    fn buffer(&mut self) -> &mut  Buffer {
-        unsafe { self.buffer.get_mut() }
+    fn cell(&mut self, x: usize, y: usize) -> &mut Char {
+        debug_assert!(x < self.width && y < self.height);
+        unsafe { &mut *self.buffer.offset((y * self.width + x) as isize) }
+    }
+
+    /// Returns the number of characters in each row
+    #[inline] pub fn width(&self) -> usize { self.width }
+
+    /// Returns the number of rows
+    #[inline] pub fn height(&self) -> usize { self.height }
+
+    /// Tell the terminal that the screen is `width` characters by `height`,
+    /// after the text mode has been changed.
+    ///
+    /// This clears the screen and moves the cursor to the top left corner,
+    /// since whatever was on screen is laid out for the old dimensions.
+    ///
+    /// # Panics
+    ///   - If either dimension is zero, or the screen wouldn't fit in the
+    ///     text buffer
+    pub fn set_dimensions(&mut self, width: usize, height: usize)
+                          -> &mut Self {
+        assert!( width > 0 && height > 0 && width * height <= MAX_CELLS
+               , "a {}x{} text mode doesn't fit in the VGA buffer"
+               , width, height );
+        self.width = width;
+        self.height = height;
+        self.x = 0;
+        self.y = 0;
+        self.clear();
+        self.update_cursor();
+        self
+    }
+
+    /// Find out the current text mode's dimensions with `detect_mode`, and
+    /// use them if they're different from the ones we had.
+    ///
+    /// # Returns
+    ///   - `false` if the mode couldn't be detected, in which case the
+    ///     dimensions are left alone
+    pub fn detect_dimensions(&mut self) -> bool {
+        match detect_mode() {
+            Some((width, height)) => {
+                if (width, height) != (self.width, self.height) {
+                    self.set_dimensions(width, height);
+                }
+                true
+            }
+          , None => false
+        }
     }
 
     /// Set the color palette used for writing subsequent characters.
@@ -146,30 +289,54 @@ impl Terminal {
 
     /// Scrolls the terminal one row.
     fn scroll(&mut self) {
-        // construct an iterator over the whole buffer
-        let mut rows = self.buffer()
-                           .iter_mut();
-
-        // the current row in the buffer
-        let mut current = rows.next()
-                           .unwrap();
-
-        while let Some(next) = rows.next() {
-            // while there are rows remaining in the iterator, swap the
-            // next row with the current one (moving it back by one)
-            mem::swap(current, next);
-            // and advance our pointer to the current row.
-            current = next;
+        let (width, height) = (self.width, self.height);
+        unsafe {
+            // move every row but the first up by one (the rows overlap, so
+            // this has to be a `copy`, not a `copy_nonoverlapping`)...
+            let buffer = *self.buffer;
+            ptr::copy(buffer.offset(width as isize), buffer
+                     , width * (height - 1));
+            // ...and empty the last one
+            ptr::write_bytes( buffer.offset((width * (height - 1)) as isize)
+                            , 0, width );
         }
-
-        // empty the last line in the buffer
-        unsafe { *current = mem::zeroed() }
     }
 
     /// Clear the terminal
     pub fn clear(&mut self) -> &mut Self {
         // to clear the terminal, we just zero out the whole buffer.
-        unsafe { *(self.buffer()) = mem::zeroed(); }
+        let cells = self.width * self.height;
+        unsafe { ptr::write_bytes(*self.buffer, 0, cells) }
+        self
+    }
+
+    /// Move the blinking hardware cursor to the current position.
+    pub fn update_cursor(&self) {
+        let position = self.y * self.width + self.x;
+        unsafe {
+            outb(CRTC_INDEX, CURSOR_LOW);
+            outb(CRTC_DATA, position as u8);
+            outb(CRTC_INDEX, CURSOR_HIGH);
+            outb(CRTC_DATA, (position >> 8) as u8);
+        }
+    }
+
+    /// Erase the character before the cursor, and move the cursor back.
+    ///
+    /// If the cursor is at the start of a line, this wraps back to the end
+    /// of the previous line. At the top-left corner it does nothing.
+    pub fn backspace(&mut self) -> &mut Self {
+        if self.x > 0 {
+            self.x -= 1;
+        } else if self.y > 0 {
+            self.y -= 1;
+            self.x = self.width - 1;
+        } else {
+            return self
+        }
+        let (x, y, colors) = (self.x, self.y, self.colors);
+        *self.cell(x, y) = Char { ascii: b' ', colors: colors };
+        self.update_cursor();
         self
     }
 
@@ -184,22 +351,21 @@ impl Terminal {
             // otherwise, it's a regular character, so we just set the
             // byte at the current position in the buffer to that
             // character (with the current color palette)
-            self.buffer()[self.y][self.x]
-                = Char { ascii: byte
-                       , colors: self.colors };
+            let (x, y, colors) = (self.x, self.y, self.colors);
+            *self.cell(x, y) = Char { ascii: byte, colors: colors };
             // and advance our column position by one
             self.x += 1;
 
-            if self.x >= X_MAX {
+            if self.x >= self.width {
                 // if we've reached the end of the line, advance to the next
                 self.x = 0;
                 self.y += 1;
             }
         }
-        if self.y >= Y_MAX {
+        if self.y >= self.height {
             // if we've reached the bottom of the terminal, scroll.
             self.scroll();
-            self.y = Y_MAX- 1;
+            self.y = self.height - 1;
         }
         self
     }
@@ -340,6 +506,7 @@ impl Write for Terminal {
                 self.write_byte(*byte);
             }
         }
+        self.update_cursor();
         Ok(())
     }
 
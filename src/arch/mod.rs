@@ -10,7 +10,8 @@
 
 // 64-bit x86_64 (long mode)
 #[cfg(target_arch="x86_64")] mod x86_64;
-#[cfg(target_arch="x86_64")] pub use self::x86_64::{ cpu, drivers };
+#[cfg(target_arch="x86_64")] pub use self::x86_64::{ acpi, cpu, drivers
+                                                    , sections };
 
 // 32-bit x86 (protected mode)
 // TODO: NYI
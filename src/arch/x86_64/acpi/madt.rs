@@ -0,0 +1,157 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! The Multiple APIC Description Table.
+//!
+//! The MADT (signature `APIC`) lists the machine's interrupt controllers,
+//! including one local APIC (or x2APIC) entry for every CPU the firmware
+//! knows about, which makes it the place to find out how many CPUs there
+//! are.
+//!
+//! Refer to section 5.2.12 of the _Advanced Configuration and Power Interface
+//! Specification_ for more information.
+use ::memory::PAddr;
+use super::find_table;
+use super::super::cpu::apic;
+
+/// The most CPUs we keep track of
+pub const MAX_CPUS: usize = 64;
+
+/// MADT entry type for a processor's local APIC
+const ENTRY_LOCAL_APIC: u8 = 0;
+/// MADT entry type for a processor's local x2APIC
+const ENTRY_LOCAL_X2APIC: u8 = 9;
+
+/// Local APIC flag set if the processor is usable
+const FLAG_ENABLED: u32 = 1 << 0;
+/// Local APIC flag set if a disabled processor can be brought online later
+const FLAG_ONLINE_CAPABLE: u32 = 1 << 1;
+
+/// A CPU listed in the MADT.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct CpuInfo { /// The processor's ACPI processor UID
+                     pub acpi_id: u32
+                   , /// The ID of the processor's local APIC
+                     pub apic_id: u32
+                   , /// Whether the processor can be used
+                     pub enabled: bool
+                   , /// Whether a disabled processor could be hot-plugged
+                     /// later
+                     pub online_capable: bool
+                   }
+
+impl CpuInfo {
+    const fn empty() -> CpuInfo {
+        CpuInfo { acpi_id: 0, apic_id: 0, enabled: false
+                , online_capable: false }
+    }
+}
+
+/// The CPUs in the machine.
+pub struct CpuTopology { cpus: [CpuInfo; MAX_CPUS]
+                       , n_cpus: usize
+                       , /// Number of CPUs that didn't fit in `cpus`
+                         n_dropped: usize
+                       , /// Physical address of the local APICs' registers,
+                         /// according to the MADT
+                         pub local_apic_addr: Option<PAddr>
+                       }
+
+impl CpuTopology {
+    fn new() -> CpuTopology {
+        CpuTopology { cpus: [CpuInfo::empty(); MAX_CPUS]
+                    , n_cpus: 0
+                    , n_dropped: 0
+                    , local_apic_addr: None
+                    }
+    }
+
+    /// Add a CPU, unless we already know about its APIC ID.
+    fn push(&mut self, cpu: CpuInfo) {
+        if self.cpus().iter().any(|c| c.apic_id == cpu.apic_id) {
+            return
+        }
+        if self.n_cpus == MAX_CPUS {
+            self.n_dropped += 1;
+        } else {
+            self.cpus[self.n_cpus] = cpu;
+            self.n_cpus += 1;
+        }
+    }
+
+    /// Returns every CPU we know about, enabled or not
+    #[inline] pub fn cpus(&self) -> &[CpuInfo] { &self.cpus[..self.n_cpus] }
+
+    /// Returns the number of CPUs that can be used
+    pub fn n_enabled(&self) -> usize {
+        self.cpus().iter().filter(|cpu| cpu.enabled).count()
+    }
+
+    /// Returns the number of CPUs the MADT listed past `MAX_CPUS`, which
+    /// aren't in `cpus` at all
+    #[inline] pub fn n_dropped(&self) -> usize { self.n_dropped }
+}
+
+/// Read a little-endian integer from the first `n` bytes of `bytes`
+fn read_le(bytes: &[u8], n: usize) -> u32 {
+    bytes[..n].iter().rev().fold(0, |x, &b| x << 8 | b as u32)
+}
+
+/// Returns the CPU we're running on, for when the MADT can't tell us
+fn this_cpu() -> CpuInfo {
+    CpuInfo { acpi_id: 0, apic_id: apic::id()
+            , enabled: true, online_capable: false }
+}
+
+/// Find out which CPUs there are, from the MADT.
+///
+/// Both the legacy local APIC entries and the x2APIC ones (used for APIC IDs
+/// that don't fit in a byte) are counted. If there's no MADT, or it's too
+/// short to have any entries, we only know about the CPU we're running on,
+/// so that's all this reports.
+pub fn cpu_topology() -> CpuTopology {
+    let mut topology = CpuTopology::new();
+    let madt = match find_table(b"APIC") {
+        Some(madt) if madt.data().len() >= 8 => madt.data()
+      , _ => {
+            topology.push(this_cpu());
+            return topology
+        }
+    };
+    topology.local_apic_addr = Some(PAddr::from_u64(read_le(madt, 4) as u64));
+
+    // after the local APIC address and flags come the entries, each of which
+    // starts with its type and length
+    let mut entries = &madt[8..];
+    while entries.len() >= 2 {
+        let (ty, len) = (entries[0], entries[1] as usize);
+        if len < 2 || len > entries.len() {
+            break
+        }
+        let entry = &entries[..len];
+        let cpu = match ty {
+            ENTRY_LOCAL_APIC if len >= 8 =>
+                Some(( entry[2] as u32, entry[3] as u32
+                     , read_le(&entry[4..], 4) ))
+          , ENTRY_LOCAL_X2APIC if len >= 16 =>
+                Some(( read_le(&entry[12..], 4), read_le(&entry[4..], 4)
+                     , read_le(&entry[8..], 4) ))
+          , _ => None
+        };
+        if let Some((acpi_id, apic_id, flags)) = cpu {
+            topology.push(CpuInfo { acpi_id: acpi_id
+                                  , apic_id: apic_id
+                                  , enabled: flags & FLAG_ENABLED != 0
+                                  , online_capable:
+                                        flags & FLAG_ONLINE_CAPABLE != 0
+                                  });
+        }
+        entries = &entries[len..];
+    }
+    topology
+}
@@ -0,0 +1,187 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Just enough ACPI to find the firmware's tables.
+//!
+//! The firmware leaves a Root System Description Pointer (RSDP) somewhere in
+//! the BIOS area, which points at the RSDT (or, on ACPI 2.0 and later, the
+//! XSDT): a list of the physical addresses of all the other tables. Each of
+//! those starts with the same header, including a four-byte signature that
+//! says which table it is.
+//!
+//! We don't interpret AML, so this only gets us the static tables, but
+//! that's where the interesting ones (like the MADT) are anyway.
+//!
+//! Refer to chapter 5 of the _Advanced Configuration and Power Interface
+//! Specification_ for more information.
+use core::{mem, slice};
+use ::memory::{PAddr, PHYS_MAP_SIZE, phys_to_virt};
+
+pub mod madt;
+
+pub use self::madt::{cpu_topology, CpuTopology, CpuInfo};
+
+/// The RSDP's signature. Note the trailing space.
+const RSDP_SIGNATURE: &'static [u8; 8] = b"RSD PTR ";
+
+/// Where the BIOS data area keeps the EBDA's segment
+const EBDA_SEGMENT_PTR: u64 = 0x40E;
+/// The area of the BIOS ROM that the RSDP can be found in
+const BIOS_AREA_START: u64 = 0xE0000;
+const BIOS_AREA_END: u64 = 0x100000;
+
+/// The ACPI 1.0 Root System Description Pointer.
+#[allow(dead_code)]
+#[repr(C, packed)]
+struct Rsdp { signature: [u8; 8]
+            , checksum: u8
+            , oem_id: [u8; 6]
+            , /// 0 for ACPI 1.0, 2 for ACPI 2.0 and later
+              revision: u8
+            , /// Physical address of the RSDT
+              rsdt_addr: u32
+            }
+
+/// The ACPI 2.0 RSDP, which adds the XSDT.
+#[allow(dead_code)]
+#[repr(C, packed)]
+struct Rsdp2 { v1: Rsdp
+             , /// Length of the whole RSDP, for the extended checksum
+               length: u32
+             , /// Physical address of the XSDT
+               xsdt_addr: u64
+             , extended_checksum: u8
+             , _reserved: [u8; 3]
+             }
+
+/// The header every System Description Table starts with.
+#[allow(dead_code)]
+#[repr(C, packed)]
+pub struct SdtHeader { /// Says which table this is (e.g. `b"APIC"`)
+                       pub signature: [u8; 4]
+                     , /// Length of the whole table, including the header
+                       pub length: u32
+                     , pub revision: u8
+                     , checksum: u8
+                     , pub oem_id: [u8; 6]
+                     , pub oem_table_id: [u8; 8]
+                     , pub oem_revision: u32
+                     , pub creator_id: u32
+                     , pub creator_revision: u32
+                     }
+
+impl SdtHeader {
+    /// Returns the table's contents after the header
+    pub fn data(&self) -> &[u8] {
+        let header = mem::size_of::<SdtHeader>();
+        unsafe {
+            slice::from_raw_parts( (self as *const _ as *const u8)
+                                       .offset(header as isize)
+                                 , self.length as usize - header )
+        }
+    }
+}
+
+/// Returns true if the bytes of `bytes` add up to zero (mod 256), which is
+/// how every ACPI structure is checksummed
+fn checksum_ok(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0
+}
+
+/// Returns `len` bytes of physical memory starting at `addr`.
+///
+/// # Returns
+///   - `None` if any of it is outside the direct map
+unsafe fn phys_bytes<'a>(addr: u64, len: usize) -> Option<&'a [u8]> {
+    if addr.checked_add(len as u64)
+           .map_or(true, |end| end > PHYS_MAP_SIZE as u64) {
+        return None
+    }
+    let virt = phys_to_virt(PAddr::from_u64(addr)).as_usize();
+    Some(slice::from_raw_parts(virt as *const u8, len))
+}
+
+/// Look for the RSDP in the `len` bytes of physical memory at `start`.
+///
+/// The RSDP is always 16-byte aligned, so that's all we check.
+unsafe fn scan_for_rsdp(start: u64, len: u64) -> Option<&'static Rsdp2> {
+    let v1_len = mem::size_of::<Rsdp>();
+    let mut addr = start;
+    while addr + v1_len as u64 <= start + len {
+        match phys_bytes(addr, v1_len) {
+            Some(bytes) if &bytes[..8] == &RSDP_SIGNATURE[..]
+                        && checksum_ok(bytes) =>
+                // the `Rsdp2` fields past `v1` are only read if the
+                // revision says they're there
+                return Some(&*(bytes.as_ptr() as *const Rsdp2))
+          , _ => { }
+        }
+        addr += 16;
+    }
+    None
+}
+
+/// Find the RSDP, in either the first KiB of the EBDA or the BIOS ROM area.
+fn find_rsdp() -> Option<&'static Rsdp2> {
+    unsafe {
+        let ebda = phys_bytes(EBDA_SEGMENT_PTR, 2)
+            .map(|seg| ((seg[0] as u64) | (seg[1] as u64) << 8) << 4);
+        ebda.and_then(|ebda| if ebda == 0 { None }
+                             else { scan_for_rsdp(ebda, 1024) })
+            .or_else(|| scan_for_rsdp( BIOS_AREA_START
+                                     , BIOS_AREA_END - BIOS_AREA_START ))
+    }
+}
+
+/// Returns the table header at physical address `addr`.
+///
+/// # Returns
+///   - `None` if the table is outside the direct map, or its checksum is bad
+fn table_at(addr: u64) -> Option<&'static SdtHeader> {
+    unsafe {
+        phys_bytes(addr, mem::size_of::<SdtHeader>())
+            .map(|bytes| &*(bytes.as_ptr() as *const SdtHeader))
+            .and_then(|header| {
+                let len = header.length as usize;
+                if len < mem::size_of::<SdtHeader>() { return None }
+                phys_bytes(addr, len).and_then(|table|
+                    if checksum_ok(table) { Some(header) } else { None })
+            })
+    }
+}
+
+/// Find the ACPI table with the given `signature`.
+///
+/// This goes through the XSDT if there is one, and the RSDT otherwise.
+///
+/// # Returns
+///   - `Some(&SdtHeader)` with the table's header; its contents follow it
+///   - `None` if there's no such table, or there's no ACPI at all
+pub fn find_table(signature: &[u8; 4]) -> Option<&'static SdtHeader> {
+    let rsdp = match find_rsdp() {
+        Some(rsdp) => rsdp
+      , None => return None
+    };
+    // the XSDT has 8-byte entries, and the RSDT 4-byte ones
+    let (root, entry_size) = if rsdp.v1.revision >= 2 && rsdp.xsdt_addr != 0 {
+        (rsdp.xsdt_addr, 8)
+    } else {
+        (rsdp.v1.rsdt_addr as u64, 4)
+    };
+    let root = match table_at(root) {
+        Some(root) => root
+      , None => return None
+    };
+    root.data()
+        .chunks(entry_size)
+        .filter(|entry| entry.len() == entry_size)
+        .map(|entry| entry.iter().rev()
+                          .fold(0u64, |addr, &b| addr << 8 | b as u64))
+        .filter_map(table_at)
+        .find(|table| &table.signature == signature)
+}
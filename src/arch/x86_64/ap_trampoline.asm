@@ -0,0 +1,96 @@
+global ap_trampoline
+global ap_trampoline_end
+global ap_trampoline_cr3
+global ap_trampoline_stack
+global ap_trampoline_entry
+global ap_trampoline_arg
+
+; Where `smp::start_aps` copies the trampoline to. A startup IPI can only
+; start a CPU at the beginning of a page below 1 MiB, in real mode, so the
+; trampoline can't run where it's linked. This must match `TRAMPOLINE_ADDR`
+; in `smp.rs`.
+%define TRAMPOLINE_BASE 0x8000
+
+; The address `label` ends up at once the trampoline has been copied
+%define TRAMP(label) (TRAMPOLINE_BASE + (label - ap_trampoline))
+
+section .text
+
+; Application processors start here, in real mode, when the BSP sends them a
+; startup IPI.
+;
+; We go straight from real mode to long mode (enabling protection and paging
+; at the same time is allowed), using the BSP's page tables, and then call
+; the entry point the BSP left in `ap_trampoline_entry` with its argument in
+; rdi and the stack it allocated. All of those are filled in by the BSP,
+; in the copy of the trampoline, before it wakes each AP up.
+bits 16
+ap_trampoline:
+    cli
+    cld
+    xor     ax, ax
+    mov     ds, ax
+
+    o32 lgdt [TRAMP(ap_gdt.ptr)]
+
+    ; enable PAE-flag in cr4 (Physical Address Extension)
+    mov     eax, cr4
+    or      eax, 1 << 5
+    mov     cr4, eax
+
+    ; use the BSP's PML4; it identity maps this page, so we'll keep running
+    ; once paging is on
+    mov     eax, [TRAMP(ap_trampoline_cr3)]
+    mov     cr3, eax
+
+    ; set the long mode bit in the EFER MSR
+    mov     ecx, 0xC0000080
+    rdmsr
+    or      eax, 1 << 8
+    wrmsr
+
+    ; enable paging, write protection, and protected mode, all at once
+    mov     eax, cr0
+    or      eax, (1 << 31) | (1 << 16) | 1
+    mov     cr0, eax
+
+    jmp     dword ap_gdt.code:TRAMP(ap_long_mode)
+
+bits 64
+ap_long_mode:
+    mov     ax, ap_gdt.data
+    mov     ss, ax
+    mov     ds, ax
+    mov     es, ax
+
+    mov     rsp, [TRAMP(ap_trampoline_stack)]
+    mov     rdi, [TRAMP(ap_trampoline_arg)]
+    mov     rax, [TRAMP(ap_trampoline_entry)]
+    call    rax
+    ; the entry point never returns
+    ud2
+
+; A GDT laid out just like the one in `boot.asm`, so that the selectors are
+; the same. The BSP's GDT is above 1 MiB, where real mode can't see it.
+align 8
+ap_gdt:
+    dq 0 ; zero entry
+.code: equ $ - ap_gdt
+    dq (1<<44) | (1<<47) | (1<<41) | (1<<43) | (1<<53) ; code segment
+.data: equ $ - ap_gdt
+    dq (1<<44) | (1<<47) | (1<<41) ; data segment
+.ptr:
+    dw $ - ap_gdt - 1
+    dd TRAMP(ap_gdt)
+
+; Filled in by the BSP
+align 8
+ap_trampoline_cr3:          ; physical address of the PML4 (below 4 GiB)
+    dq 0
+ap_trampoline_stack:        ; top of the AP's stack
+    dq 0
+ap_trampoline_entry:        ; address of the Rust entry point
+    dq 0
+ap_trampoline_arg:          ; argument passed to the entry point in rdi
+    dq 0
+ap_trampoline_end:
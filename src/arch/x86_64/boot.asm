@@ -1,4 +1,7 @@
 %define PAGE_TABLE_SIZE 512 * 8
+; PML4 entry for the direct map of physical memory; this must match
+; `PHYS_OFFSET` in `memory/addr.rs`
+%define PHYS_P4_INDEX 256
 
 ; page_map: macro to map the first entry in the first argument to the second
 %macro  page_map 2
@@ -12,6 +15,8 @@
 
 global start
 global gdt64_offset
+global stack_end
+global stack_top
 
 extern start_64
 
@@ -71,10 +76,16 @@ err:
 
 ; Creates the page tables by mapping:
 ;   - the first PML4 entry -> PDP
+;   - the first higher half PML4 entry -> the same PDP
 ;   - the first PDP entry -> PD
 ;   - each PD entry to its own 2mB page
+;
+; So the first gigabyte is mapped twice: identity mapped, which is where the
+; kernel runs, and at PHYS_OFFSET, where the Rust code looks for physical
+; memory.
 create_page_tables:
     page_map    pml4_table, pdp_table   ; map first PML4 entry to PDP table
+    page_map    pml4_table + PHYS_P4_INDEX * 8, pdp_table
     page_map    pdp_table,  pd_table    ; map first PDP entry to PD table
 
     ; map each PD table entry to its own 2mB page
@@ -123,6 +134,7 @@ set_long_mode:
 start:
     mov     esp, stack_top
     mov     edi, ebx       ; Move Multiboot info pointer to edi
+    mov     esi, eax       ; and the bootloader's magic number to esi
 
     call    is_multiboot
     call    is_cpuid
@@ -157,7 +169,8 @@ pd_table:                   ; Page-Directory Table
 page_table:                 ; Page Table
     resb    PAGE_TABLE_SIZE
 stack_end:
-    resb    4096
+    ; `interrupts::initialize` builds the IDT (4 KiB of it) on this stack
+    resb    4096 * 4
 stack_top:
 
 section .rodata
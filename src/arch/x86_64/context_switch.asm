@@ -0,0 +1,52 @@
+extern finish_switch
+
+global switch_context
+global task_start
+
+section .text
+bits 64
+
+; Switch from one task's stack to another's.
+;
+; Arguments (System V calling convention):
+;   rdi: pointer to where to save the old task's stack pointer
+;   rsi: the new task's stack pointer
+;
+; The callee-saved registers are pushed onto the old task's stack, so all that
+; needs to be remembered about it is where its stack pointer ended up. The
+; caller-saved registers have already been saved by the Rust code calling us,
+; if it cared about them.
+;
+; The new task's stack must have the callee-saved registers on top, followed
+; by a return address: either one pushed by the `call` that got it here, or,
+; for a brand new task, `task_start`.
+switch_context:
+    push    rbx
+    push    rbp
+    push    r12
+    push    r13
+    push    r14
+    push    r15
+    mov     [rdi], rsp
+
+    mov     rsp, rsi
+    pop     r15
+    pop     r14
+    pop     r13
+    pop     r12
+    pop     rbp
+    pop     rbx
+    ret
+
+; Where a new task "returns" to the first time it's switched to.
+;
+; First let the scheduler finish the switch (just as `reschedule` does when
+; `switch_context` returns to it), so that the task we came from can run on
+; another CPU. Context switches happen with interrupts disabled, so enable
+; them, and then call the task's entry point, which the initial stack left in
+; rbx (which `finish_switch` preserves). The entry point never returns.
+task_start:
+    call    finish_switch
+    sti
+    call    rbx
+    ud2
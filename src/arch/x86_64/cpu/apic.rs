@@ -0,0 +1,317 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! The local APIC.
+//!
+//! Every CPU has its own local APIC, which receives interrupts and hands them
+//! to the CPU. Its registers are memory mapped, at the physical address given
+//! by the `IA32_APIC_BASE` MSR.
+//!
+//! When the APIC decides to deliver an interrupt but the interrupt goes away
+//! before the CPU gets to it, it delivers a _spurious_ interrupt instead, on
+//! the vector set in its spurious interrupt vector register (SVR).
+//! Spurious interrupts are not in service, so they must **not** be
+//! acknowledged with an EOI: doing so would end whichever interrupt actually
+//! is being serviced.
+//!
+//! The APIC also has a timer of its own, which counts down from an initial
+//! count at the bus (or core crystal) clock divided by a configurable
+//! divisor. Nothing tells us what that clock's frequency is, so
+//! `calibrate_timer` measures it against the PIT, and `start_timer` uses
+//! that to turn a frequency into an initial count. In periodic mode, the
+//! timer drives the system tick (and preemption) in place of the PIT's
+//! IRQ 0.
+//!
+//! Refer to chapter 10 of the _Intel® 64 and IA-32 Architectures Software
+//! Developer’s Manual_ for more information.
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::sync::atomic::{ATOMIC_BOOL_INIT, ATOMIC_USIZE_INIT};
+use io::Mmio;
+use ::memory::{PAddr, VAddr, PHYS_OFFSET};
+use ::memory::frame;
+use alloc::PAGE_SIZE;
+use super::{cpuid, msr};
+use super::paging::{AddressSpace, WRITABLE, NO_CACHE};
+use super::super::drivers::pit;
+
+/// The interrupt vector reserved for spurious APIC interrupts.
+///
+/// Spurious vectors have to end in `0xF` on older CPUs, and who'd want to
+/// use vector `0xFF` for anything else anyway.
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+/// The interrupt vector the APIC timer is programmed with by `start_timer`.
+///
+/// This is the first vector past the PICs' range.
+pub const TIMER_VECTOR: u8 = 0x30;
+
+/// The divisor `start_timer` programs the APIC timer with
+pub const TIMER_DIVIDE: u8 = 16;
+
+/// `IA32_APIC_BASE` bit that globally enables the APIC
+const BASE_ENABLE: u64 = 1 << 11;
+/// Mask for the base address in `IA32_APIC_BASE`
+const BASE_ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+/// Offset of the end of interrupt register
+const EOI: usize = 0xB0;
+/// Offset of the spurious interrupt vector register
+const SVR: usize = 0xF0;
+/// SVR bit that software-enables the APIC
+const SVR_ENABLE: u32 = 1 << 8;
+/// Offset of the low half of the interrupt command register
+const ICR_LOW: usize = 0x300;
+/// Offset of the high half of the ICR, which holds the destination
+const ICR_HIGH: usize = 0x310;
+
+/// Offset of the LVT timer register, which holds the timer's vector and mode
+const LVT_TIMER: usize = 0x320;
+/// Offset of the timer's initial count register; writing it starts the timer
+const TIMER_INITIAL_COUNT: usize = 0x380;
+/// Offset of the timer's current count register
+const TIMER_CURRENT_COUNT: usize = 0x390;
+/// Offset of the timer's divide configuration register
+const TIMER_DIVIDE_CONFIG: usize = 0x3E0;
+
+/// LVT bit that masks the interrupt
+const LVT_MASKED: u32 = 1 << 16;
+/// LVT timer mode: periodic, reloading the initial count every time it
+/// reaches zero (the default, 0, is one-shot)
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+
+/// How long to let the timer count for when calibrating (in milliseconds)
+const CALIBRATION_MS: u64 = 50;
+
+/// ICR delivery mode: INIT
+const ICR_INIT: u32 = 0b101 << 8;
+/// ICR delivery mode: startup IPI
+const ICR_STARTUP: u32 = 0b110 << 8;
+/// ICR bit for an asserted level (every IPI but an INIT de-assert)
+const ICR_ASSERT: u32 = 1 << 14;
+/// ICR bit set while the last IPI hasn't been sent yet
+const ICR_PENDING: u32 = 1 << 12;
+
+/// Virtual address of the APIC's registers, or zero if it isn't set up
+static BASE: AtomicUsize = ATOMIC_USIZE_INIT;
+/// Whether `init` has enabled the APIC
+static ENABLED: AtomicBool = ATOMIC_BOOL_INIT;
+/// The APIC timer's input frequency (before the divisor) in Hz, or zero if
+/// it hasn't been calibrated
+static TIMER_CLOCK_HZ: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Returns true if this CPU has a local APIC
+#[inline]
+pub fn is_supported() -> bool {
+    cpuid::edx_features().contains(cpuid::APIC)
+}
+
+/// Returns true if `init` has enabled the local APIC
+#[inline]
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Returns this CPU's (initial) local APIC ID, according to `cpuid`
+#[inline]
+pub fn id() -> u32 {
+    cpuid::cpuid(1).ebx >> 24
+}
+
+/// Returns the physical address of the APIC's registers
+pub fn base() -> PAddr {
+    PAddr::from_u64(unsafe { msr::rdmsr(msr::IA32_APIC_BASE) }
+                        & BASE_ADDR_MASK)
+}
+
+/// Returns the APIC register at `offset`.
+///
+/// # Panics
+///   - If the APIC hasn't been set up by `init`
+fn register(offset: usize) -> Mmio<u32> {
+    let base = BASE.load(Ordering::Relaxed);
+    assert!(base != 0, "the local APIC hasn't been initialized!");
+    unsafe { Mmio::new(base + offset) }
+}
+
+/// Enable the local APIC.
+///
+/// This maps the APIC's registers (uncached, since they're device registers)
+/// and then points the SVR at `SPURIOUS_VECTOR`, which `handle_interrupt`
+/// knows not to send an EOI for.
+///
+/// # Returns
+///   - `false` if the CPU has no APIC, or its registers couldn't be mapped
+pub fn init() -> bool {
+    if !is_supported() {
+        return false
+    }
+    let base = base();
+    frame::reserve(base, PAddr::from_u64(base.as_u64() + PAGE_SIZE as u64));
+    // the APIC's registers are usually above the first gigabyte, so they
+    // aren't in the direct map yet; map the page they're in where the direct
+    // map would put it
+    let page = VAddr::from_usize(base.as_u64() as usize + PHYS_OFFSET);
+    let space = AddressSpace::current();
+    let mapped = unsafe {
+        space.entry_mut(page).map_or(false, |e| !e.is_unused())
+            || space.map_to(page, base, WRITABLE | NO_CACHE)
+    };
+    if !mapped {
+        return false
+    }
+    BASE.store(page.as_usize(), Ordering::Relaxed);
+    unsafe {
+        msr::wrmsr( msr::IA32_APIC_BASE
+                  , msr::rdmsr(msr::IA32_APIC_BASE) | BASE_ENABLE );
+    }
+    register(SVR).write(SVR_ENABLE | SPURIOUS_VECTOR as u32);
+    ENABLED.store(true, Ordering::Relaxed);
+    true
+}
+
+/// Send an inter-processor interrupt to the CPU whose APIC ID is `apic_id`.
+///
+/// `command` is the low half of the ICR: the delivery mode, vector, and so
+/// on. This waits until the APIC has sent the IPI.
+fn send_ipi(apic_id: u8, command: u32) {
+    register(ICR_HIGH).write((apic_id as u32) << 24);
+    // writing the low half is what sends the IPI
+    register(ICR_LOW).write(command);
+    while register(ICR_LOW).read() & ICR_PENDING != 0 { super::spin_hint() }
+}
+
+/// Send an INIT IPI, which resets the CPU `apic_id` and makes it wait for a
+/// startup IPI.
+pub fn send_init(apic_id: u8) {
+    send_ipi(apic_id, ICR_INIT | ICR_ASSERT)
+}
+
+/// Send a startup IPI, which starts the CPU `apic_id` in real mode at
+/// physical address `page << 12`.
+pub fn send_startup(apic_id: u8, page: u8) {
+    send_ipi(apic_id, ICR_STARTUP | ICR_ASSERT | page as u32)
+}
+
+/// Signal the end of the interrupt currently being serviced.
+///
+/// This must never be called for a spurious interrupt.
+#[inline]
+pub fn end_of_interrupt() {
+    register(EOI).write(0);
+}
+
+/// Returns the divide configuration register value for dividing by `divide`.
+///
+/// The encoding is bits 0, 1, and 3 of the register, with bit 2 always zero:
+/// `0b1011` divides by 1, and `0b0000` to `0b1010` (skipping bit 2) by 2 to
+/// 128.
+///
+/// # Panics
+///   - If `divide` isn't a power of two between 1 and 128
+fn divide_config(divide: u8) -> u32 {
+    match divide {
+        1 => 0b1011
+      , 2 => 0b0000
+      , 4 => 0b0001
+      , 8 => 0b0010
+      , 16 => 0b0011
+      , 32 => 0b1000
+      , 64 => 0b1001
+      , 128 => 0b1010
+      , _ => panic!("the APIC timer can't divide by {}", divide)
+    }
+}
+
+/// Start the APIC timer interrupting periodically.
+///
+/// The timer counts down from `initial_count` at its input clock divided by
+/// `divide`, raises `vector` when it gets to zero, and starts again.
+///
+/// # Panics
+///   - If the APIC hasn't been set up by `init`
+///   - If `divide` isn't a power of two between 1 and 128
+pub fn apic_timer_periodic(vector: u8, initial_count: u32, divide: u8) {
+    register(TIMER_DIVIDE_CONFIG).write(divide_config(divide));
+    register(LVT_TIMER).write(LVT_TIMER_PERIODIC | vector as u32);
+    // the timer starts counting as soon as the initial count is written, so
+    // this has to come last
+    register(TIMER_INITIAL_COUNT).write(initial_count);
+}
+
+/// Stop the APIC timer, and mask its interrupt.
+pub fn stop_timer() {
+    register(LVT_TIMER).write(LVT_MASKED);
+    register(TIMER_INITIAL_COUNT).write(0);
+}
+
+/// Measure the APIC timer's input frequency against the PIT.
+///
+/// This runs the timer one-shot, with its interrupt masked, and sees how far
+/// it counts down in 50 ms (busy-waiting with interrupts disabled, like
+/// `tsc::calibrate_tsc`). The result is remembered for
+/// `timer_count_for`.
+///
+/// # Returns
+///   - The timer's input frequency, in Hz, before the divisor
+///
+/// # Panics
+///   - If the APIC hasn't been set up by `init`
+pub fn calibrate_timer() -> u64 {
+    let elapsed = super::without_interrupts(|| {
+        register(TIMER_DIVIDE_CONFIG).write(divide_config(TIMER_DIVIDE));
+        register(LVT_TIMER).write(LVT_MASKED);
+        pit::start_channel2(pit::ms_to_ticks(CALIBRATION_MS) as u16);
+        register(TIMER_INITIAL_COUNT).write(!0);
+        while !pit::channel2_done() { super::spin_hint() }
+        let remaining = register(TIMER_CURRENT_COUNT).read();
+        register(TIMER_INITIAL_COUNT).write(0);
+        (!0 - remaining) as u64
+    });
+    let hz = elapsed * TIMER_DIVIDE as u64 * 1000 / CALIBRATION_MS;
+    TIMER_CLOCK_HZ.store(hz as usize, Ordering::Relaxed);
+    hz
+}
+
+/// Returns the initial count that makes the APIC timer fire `hz` times a
+/// second, when it's dividing its input clock by `divide`.
+///
+/// # Returns
+///   - `None` if the timer hasn't been calibrated, or `hz` is too low to
+///     fit in the 32-bit count (or zero)
+pub fn timer_count_for(hz: u64, divide: u8) -> Option<u32> {
+    match (TIMER_CLOCK_HZ.load(Ordering::Relaxed) as u64, hz) {
+        (0, _) | (_, 0) => None
+      , (clock, hz) => {
+            let count = clock / divide as u64 / hz;
+            if count == 0 || count > ::core::u32::MAX as u64 { None }
+            else { Some(count as u32) }
+        }
+    }
+}
+
+/// Make the APIC timer raise `TIMER_VECTOR` `hz` times a second.
+///
+/// The timer is calibrated first, if it hasn't been already.
+///
+/// # Returns
+///   - `false` if the APIC isn't enabled, or can't tick at `hz`
+pub fn start_timer(hz: u64) -> bool {
+    if !is_enabled() {
+        return false
+    }
+    if TIMER_CLOCK_HZ.load(Ordering::Relaxed) == 0 {
+        calibrate_timer();
+    }
+    match timer_count_for(hz, TIMER_DIVIDE) {
+        Some(count) => {
+            apic_timer_periodic(TIMER_VECTOR, count, TIMER_DIVIDE);
+            true
+        }
+      , None => false
+    }
+}
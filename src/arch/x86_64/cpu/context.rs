@@ -70,3 +70,46 @@ impl Context {
         }
     }
 }
+
+extern {
+    /// Save the callee-saved registers on the current stack, store the stack
+    /// pointer in `*old_rsp`, and then switch to the stack at `new_rsp` and
+    /// restore the registers saved there.
+    ///
+    /// This returns once something switches back to the old stack.
+    /// Defined in `context_switch.asm`.
+    pub fn switch_context(old_rsp: *mut *mut u8, new_rsp: *mut u8);
+
+    /// The trampoline new tasks start in. Defined in `context_switch.asm`.
+    fn task_start();
+}
+
+/// Number of callee-saved registers pushed by `switch_context`
+const N_CALLEE_SAVED: usize = 6;
+
+/// Set up a stack so that `switch_context` to it begins executing `entry`.
+///
+/// # Arguments
+///   - `top`: the top of the stack, which must be 16-byte aligned
+///   - `entry`: the function to begin executing
+///
+/// # Returns
+///   - The stack pointer to pass to `switch_context`
+///
+/// # Unsafe due to
+///   - Writing to the memory just below `top`
+pub unsafe fn init_stack(top: *mut u8, entry: extern "C" fn() -> !)
+                         -> *mut u8 {
+    // `task_start` will be "returned" to, and then `call`s `entry`, which
+    // leaves `entry`'s stack pointer 16-byte aligned minus a return address,
+    // just like the ABI wants.
+    let ret = (top as *mut u64).offset(-1);
+    *ret = task_start as u64;
+    let regs = ret.offset(-(N_CALLEE_SAVED as isize));
+    for i in 0..N_CALLEE_SAVED {
+        *regs.offset(i as isize) = 0;
+    }
+    // `rbx` is the last register popped, right below the return address
+    *ret.offset(-1) = entry as u64;
+    regs as *mut u8
+}
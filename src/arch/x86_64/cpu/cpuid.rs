@@ -0,0 +1,153 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Querying CPU features with `cpuid`.
+//!
+//! `boot.asm` has already checked that `cpuid` is supported before we ever
+//! get to long mode, so it's always safe to use here.
+use core::str;
+
+/// The first extended leaf; querying it gives the highest extended leaf
+const EXTENDED_BASE: u32 = 0x8000_0000;
+/// The three extended leaves the brand string is spread across
+const BRAND_LEAVES: [u32; 3] = [0x8000_0002, 0x8000_0003, 0x8000_0004];
+
+/// The registers returned by a `cpuid` query
+#[derive(Copy, Clone, Debug)]
+pub struct CpuidResult { pub eax: u32
+                       , pub ebx: u32
+                       , pub ecx: u32
+                       , pub edx: u32
+                       }
+
+/// Execute `cpuid` for the given leaf (with a subleaf of zero).
+#[inline]
+pub fn cpuid(leaf: u32) -> CpuidResult {
+    let (eax, ebx, ecx, edx): (u32, u32, u32, u32);
+    unsafe {
+        asm!(  "cpuid"
+            :  "={eax}"(eax), "={ebx}"(ebx), "={ecx}"(ecx), "={edx}"(edx)
+            :  "{eax}"(leaf), "{ecx}"(0u32)
+            :: "intel" );
+    }
+    CpuidResult { eax: eax, ebx: ebx, ecx: ecx, edx: edx }
+}
+
+bitflags! {
+    /// Feature flags returned in `edx` by `cpuid` leaf 1
+    flags EdxFeatures: u32 { const FPU  = 1 << 0
+                           , const TSC  = 1 << 4
+                           , const MSR  = 1 << 5
+                           , const APIC = 1 << 9
+                           , const FXSR = 1 << 24
+                           , const SSE  = 1 << 25
+                           , const SSE2 = 1 << 26
+                           }
+}
+
+bitflags! {
+    /// Feature flags returned in `ecx` by `cpuid` leaf 1
+    flags EcxFeatures: u32 { const SSE3   = 1 << 0
+                           , const X2APIC = 1 << 21
+                           , const XSAVE  = 1 << 26
+                           , const RDRAND = 1 << 30
+                           }
+}
+
+bitflags! {
+    /// Extended feature flags returned in `ebx` by `cpuid` leaf 7
+    flags ExtendedFeatures: u32 { const RDSEED = 1 << 18
+                                }
+}
+
+/// Returns the feature flags `cpuid` reports in `edx`
+#[inline]
+pub fn edx_features() -> EdxFeatures {
+    EdxFeatures::from_bits_truncate(cpuid(1).edx)
+}
+
+/// Returns the feature flags `cpuid` reports in `ecx`
+#[inline]
+pub fn ecx_features() -> EcxFeatures {
+    EcxFeatures::from_bits_truncate(cpuid(1).ecx)
+}
+
+/// Write `word` into `dst` as four bytes, least significant first, which is
+/// how `cpuid` packs strings into registers
+fn put_u32(dst: &mut [u8], word: u32) {
+    for (i, byte) in dst[..4].iter_mut().enumerate() {
+        *byte = (word >> (i * 8)) as u8;
+    }
+}
+
+/// The CPU vendor's ID string, like `GenuineIntel` or `AuthenticAMD`.
+#[derive(Copy, Clone)]
+pub struct VendorId([u8; 12]);
+
+impl VendorId {
+    /// Returns the vendor ID as a string (or `"unknown"` if it isn't ASCII)
+    pub fn as_str(&self) -> &str {
+        str::from_utf8(&self.0).unwrap_or("unknown")
+    }
+}
+
+/// The CPU's brand string, like `Intel(R) Core(TM) i7-4770 CPU @ 3.40GHz`.
+#[derive(Copy, Clone)]
+pub struct BrandString([u8; 48]);
+
+impl BrandString {
+    /// Returns the brand string, without its padding.
+    ///
+    /// The string is NUL-terminated if it's shorter than 48 bytes, and some
+    /// CPUs right-justify it with leading spaces, so both are trimmed.
+    pub fn as_str(&self) -> &str {
+        let len = self.0.iter().position(|&b| b == 0).unwrap_or(48);
+        str::from_utf8(&self.0[..len]).map(|s| s.trim_matches(' '))
+                                      .unwrap_or("unknown")
+    }
+}
+
+/// Returns the vendor ID string, from leaf 0
+pub fn vendor() -> VendorId {
+    let leaf = cpuid(0);
+    let mut id = [0u8; 12];
+    // the string is in ebx, edx, ecx, in that order
+    put_u32(&mut id[0..], leaf.ebx);
+    put_u32(&mut id[4..], leaf.edx);
+    put_u32(&mut id[8..], leaf.ecx);
+    VendorId(id)
+}
+
+/// Returns the brand string, from leaves `0x80000002` through `0x80000004`.
+///
+/// # Returns
+///   - `None` if the CPU doesn't have those leaves
+pub fn brand_string() -> Option<BrandString> {
+    if cpuid(EXTENDED_BASE).eax < BRAND_LEAVES[2] {
+        return None
+    }
+    let mut brand = [0u8; 48];
+    for (i, &leaf) in BRAND_LEAVES.iter().enumerate() {
+        let regs = cpuid(leaf);
+        let chunk = &mut brand[i * 16..];
+        put_u32(&mut chunk[0..], regs.eax);
+        put_u32(&mut chunk[4..], regs.ebx);
+        put_u32(&mut chunk[8..], regs.ecx);
+        put_u32(&mut chunk[12..], regs.edx);
+    }
+    Some(BrandString(brand))
+}
+
+/// Returns the extended feature flags `cpuid` reports in `ebx` for leaf 7
+/// (which are all clear if the CPU doesn't have leaf 7)
+pub fn extended_features() -> ExtendedFeatures {
+    if cpuid(0).eax < 7 {
+        return ExtendedFeatures::empty()
+    }
+    ExtendedFeatures::from_bits_truncate(cpuid(7).ebx)
+}
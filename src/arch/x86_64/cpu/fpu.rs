@@ -0,0 +1,289 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! x87 FPU and SSE support.
+//!
+//! Until the control registers are set up to say that we know how to deal
+//! with them, x87 and SSE instructions raise invalid opcode (`#UD`) or device
+//! not available (`#NM`) exceptions. Since the compiler is free to emit SSE
+//! instructions for things that have nothing to do with floating point (like
+//! copying structs), this needs to happen early in boot.
+use core::{fmt, ptr};
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use super::control_regs;
+use super::cpuid::{self, FPU, FXSR, SSE};
+
+/// `cr0.MP`: `wait`/`fwait` respect the task-switched flag
+pub const CR0_MP: u64 = 1 << 1;
+/// `cr0.EM`: emulate the x87 FPU (i.e. raise `#UD` on FPU instructions)
+pub const CR0_EM: u64 = 1 << 2;
+/// `cr0.TS`: a task switch happened, so FPU instructions raise `#NM`
+pub const CR0_TS: u64 = 1 << 3;
+/// `cr4.OSFXSR`: the OS supports `fxsave`/`fxrstor` (and SSE)
+pub const CR4_OSFXSR: u64 = 1 << 9;
+/// `cr4.OSXMMEXCPT`: the OS handles SIMD floating-point exceptions (`#XM`)
+pub const CR4_OSXMMEXCPT: u64 = 1 << 10;
+
+/// Enable the x87 FPU and SSE.
+///
+/// This requires the FPU, FXSR (`fxsave`/`fxrstor`), and SSE features to be
+/// reported by `cpuid` leaf 1; if any of them is missing, nothing is changed.
+/// Otherwise, this:
+///
+///   - clears `cr0.EM`, so FPU instructions are executed rather than trapped
+///   - sets `cr0.MP`, so `fwait` also respects `cr0.TS`
+///   - clears `cr0.TS`, so the FPU can be used right away
+///   - sets `cr4.OSFXSR`, enabling SSE and `fxsave`/`fxrstor`
+///   - sets `cr4.OSXMMEXCPT`, so SIMD floating-point errors raise `#XM`
+///     rather than `#UD`
+///   - and resets the FPU to its default state with `fninit`.
+///
+/// # Returns
+///   - `true` if the FPU and SSE were enabled
+///   - `false` if the CPU doesn't support them
+pub fn init_fpu() -> bool {
+    if !cpuid::edx_features().contains(FPU | FXSR | SSE) {
+        return false
+    }
+    unsafe {
+        let cr0 = control_regs::cr0_read();
+        control_regs::cr0_write((cr0 & !(CR0_EM | CR0_TS)) | CR0_MP);
+        let cr4 = control_regs::cr4_read();
+        control_regs::cr4_write(cr4 | CR4_OSFXSR | CR4_OSXMMEXCPT);
+        asm!("fninit" :::: "volatile");
+    }
+    true
+}
+
+//==------------------------------------------------------------------------==
+// SIMD floating-point exceptions
+//
+// With `cr4.OSXMMEXCPT` set, an SSE instruction whose exception is unmasked
+// in MXCSR raises `#XM` (vector 19). The CPU doesn't say which exception it
+// was; that's in MXCSR's flags, which stay set until they're cleared.
+
+bitflags! {
+    /// The SSE control and status register
+    flags Mxcsr: u32 { /// Invalid operation happened
+                       const INVALID        = 1 << 0
+                     , /// Denormal operand
+                       const DENORMAL       = 1 << 1
+                     , /// Divide by zero
+                       const DIVIDE_BY_ZERO = 1 << 2
+                     , /// Overflow
+                       const OVERFLOW       = 1 << 3
+                     , /// Underflow
+                       const UNDERFLOW      = 1 << 4
+                     , /// Precision (the result was rounded)
+                       const PRECISION      = 1 << 5
+                     , /// Denormal operands are treated as zero
+                       const DENORMALS_ARE_ZERO = 1 << 6
+                     , /// Invalid operation exceptions are masked
+                       const INVALID_MASK        = 1 << 7
+                     , const DENORMAL_MASK       = 1 << 8
+                     , const DIVIDE_BY_ZERO_MASK = 1 << 9
+                     , const OVERFLOW_MASK       = 1 << 10
+                     , const UNDERFLOW_MASK      = 1 << 11
+                     , const PRECISION_MASK      = 1 << 12
+                     , /// Low bit of the rounding mode
+                       const ROUNDING_0     = 1 << 13
+                     , /// High bit of the rounding mode
+                       const ROUNDING_1     = 1 << 14
+                     , /// Results that would underflow are flushed to zero
+                       const FLUSH_TO_ZERO  = 1 << 15
+                     }
+}
+
+/// Each of MXCSR's exception flags, with what it means
+const MXCSR_EXCEPTIONS: [(Mxcsr, &'static str); 6]
+    = [ (INVALID, "invalid operation")
+      , (DENORMAL, "denormal operand")
+      , (DIVIDE_BY_ZERO, "divide by zero")
+      , (OVERFLOW, "overflow")
+      , (UNDERFLOW, "underflow")
+      , (PRECISION, "precision")
+      ];
+
+/// How far the exception flags are from their mask bits
+const MXCSR_MASK_SHIFT: u32 = 7;
+
+impl Mxcsr {
+    /// Returns the exception flags that are set and not masked, which are
+    /// the ones that could have raised `#XM`
+    pub fn unmasked_exceptions(&self) -> Mxcsr {
+        let flags = self.bits & 0x3F;
+        let masks = (self.bits >> MXCSR_MASK_SHIFT) & 0x3F;
+        Mxcsr::from_bits_truncate(flags & !masks)
+    }
+}
+
+impl fmt::Display for Mxcsr {
+    /// Lists the exception flags that are set, in words.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut first = true;
+        for &(flag, name) in MXCSR_EXCEPTIONS.iter() {
+            if self.contains(flag) {
+                try!(f.write_str(if first { "" } else { ", " }));
+                try!(f.write_str(name));
+                first = false;
+            }
+        }
+        if first { f.write_str("no exception flags") } else { Ok(()) }
+    }
+}
+
+/// Read MXCSR, with `stmxcsr`.
+pub fn read_mxcsr() -> Mxcsr {
+    let mut bits: u32 = 0;
+    unsafe {
+        asm!(  "stmxcsr [$0]"
+            :: "r"(&mut bits)
+            :  "memory"
+            :  "intel", "volatile" );
+    }
+    Mxcsr::from_bits_truncate(bits)
+}
+
+/// Handle a SIMD floating-point (`#XM`) exception, from the instruction at
+/// `rip`, by panicking with a description of which exceptions happened.
+pub fn handle_simd_exception(rip: u64) -> ! {
+    let mxcsr = read_mxcsr();
+    panic!( "SIMD FLOATING-POINT EXCEPTION ({}) by instruction at {:#x}\n\
+             (MXCSR = {:#x}; all flags set: {})"
+          , mxcsr.unmasked_exceptions(), rip, mxcsr.bits(), mxcsr )
+}
+
+//==------------------------------------------------------------------------==
+// Lazy FPU context switching
+//
+// Saving and restoring the FPU state on every context switch is expensive,
+// and most tasks never touch the FPU anyway. Instead, a context switch just
+// sets `cr0.TS`; the next FPU or SSE instruction then raises `#NM`, and only
+// at that point do we save the old owner's state and load the new task's.
+
+/// Size of the area written by `fxsave`
+pub const FXSAVE_SIZE: usize = 512;
+
+/// `fxsave` and `fxrstor` require a 16-byte aligned area
+const FXSAVE_ALIGN: usize = 16;
+
+/// Default x87 control word: all exceptions masked, 64-bit precision
+const DEFAULT_FCW: u16 = 0x037F;
+/// Default MXCSR: all SIMD exceptions masked
+const DEFAULT_MXCSR: u32 = 0x1F80;
+
+/// A task's saved FPU and SSE state.
+///
+/// We can't ask the compiler for 16-byte alignment, so the buffer has some
+/// slack, and the state lives at whichever offset into it happens to be
+/// aligned. If the `FpuState` is moved somewhere with a different alignment,
+/// the saved state is shifted over the next time it's used.
+pub struct FpuState { buf: [u8; FXSAVE_SIZE + FXSAVE_ALIGN - 1]
+                    , /// offset into `buf` of the saved state
+                      offset: usize
+                    }
+
+impl FpuState {
+    /// Returns the state of a freshly `fninit`ed FPU
+    pub fn new() -> Self {
+        let mut state = FpuState { buf: [0; FXSAVE_SIZE + FXSAVE_ALIGN - 1]
+                                 , offset: 0 };
+        unsafe {
+            let area = state.area();
+            *(area as *mut u16) = DEFAULT_FCW;
+            *(area.offset(24) as *mut u32) = DEFAULT_MXCSR;
+        }
+        state
+    }
+
+    /// Returns a pointer to the aligned save area, moving the saved state
+    /// there if needed.
+    fn area(&mut self) -> *mut u8 {
+        let base = self.buf.as_mut_ptr();
+        let offset = (FXSAVE_ALIGN - (base as usize % FXSAVE_ALIGN))
+                   % FXSAVE_ALIGN;
+        unsafe {
+            if offset != self.offset {
+                ptr::copy( base.offset(self.offset as isize)
+                         , base.offset(offset as isize)
+                         , FXSAVE_SIZE );
+                self.offset = offset;
+            }
+            base.offset(offset as isize)
+        }
+    }
+
+    /// Save the current FPU state into this `FpuState`.
+    pub unsafe fn save(&mut self) {
+        asm!(  "fxsave [$0]"
+            :: "r"(self.area())
+            :  "memory"
+            :  "intel", "volatile" );
+    }
+
+    /// Load the FPU state from this `FpuState`.
+    pub unsafe fn restore(&mut self) {
+        asm!(  "fxrstor [$0]"
+            :: "r"(self.area())
+            :  "memory"
+            :  "intel", "volatile" );
+    }
+}
+
+/// The `FpuState` whose contents are currently loaded in the FPU, if any
+static FPU_OWNER: AtomicUsize = ATOMIC_USIZE_INIT;
+/// The `FpuState` of the task that's currently running
+static FPU_CURRENT: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Tell the FPU code that we're switching to a task whose FPU state is
+/// stored in `next`.
+///
+/// The scheduler should call this on every context switch. This doesn't
+/// touch the FPU state; it just sets `cr0.TS` so that we find out if the new
+/// task tries to use the FPU.
+///
+/// # Unsafe due to
+///   - `next` must stay valid (and the task it belongs to must not be
+///     destroyed) for as long as it could be the FPU's owner
+pub unsafe fn task_switched(next: *mut FpuState) {
+    FPU_CURRENT.store(next as usize, Ordering::SeqCst);
+    control_regs::cr0_write(control_regs::cr0_read() | CR0_TS);
+}
+
+/// Forget about `state`, because the task it belongs to is going away.
+///
+/// If it owns the FPU, its contents are discarded rather than saved.
+pub fn forget(state: *mut FpuState) {
+    FPU_OWNER.compare_and_swap(state as usize, 0, Ordering::SeqCst);
+}
+
+/// Handle a device not available (`#NM`) exception.
+///
+/// This happens when a task uses the FPU after a context switch. The FPU is
+/// handed over to the current task: the previous owner's state is saved, and
+/// the current task's is restored.
+///
+/// # Panics
+///   - If `#NM` happens without a task switch, which means the FPU wasn't
+///     enabled properly
+pub fn handle_device_not_available() {
+    unsafe {
+        let cr0 = control_regs::cr0_read();
+        assert!( cr0 & CR0_TS != 0
+               , "#NM without a task switch; was the FPU enabled?");
+        asm!("clts" :::: "volatile");
+
+        let current = FPU_CURRENT.load(Ordering::SeqCst);
+        let owner = FPU_OWNER.load(Ordering::SeqCst);
+        if current == owner || current == 0 { return }
+
+        if owner != 0 { (*(owner as *mut FpuState)).save() }
+        (*(current as *mut FpuState)).restore();
+        FPU_OWNER.store(current, Ordering::SeqCst);
+    }
+}
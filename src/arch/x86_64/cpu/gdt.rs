@@ -0,0 +1,143 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Each CPU's GDT and TSS.
+//!
+//! `boot.asm` (and, for the APs, `ap_trampoline.asm`) only load a minimal
+//! GDT, with a code and a data segment, to get into long mode. Each CPU then
+//! switches to a GDT of its own, in its `PerCpu`, with the same code and data
+//! segments plus a descriptor for its own TSS: loading a TSS marks its
+//! descriptor busy, so CPUs can't share one.
+//!
+//! The TSS is there for its interrupt stack table (IST), which lets an
+//! exception handler run on a stack of its own: a double fault caused by a
+//! stack overflow would just fault again on the stack that overflowed.
+use core::mem;
+use alloc::PAGE_SIZE;
+use super::{DTable, DTablePtr};
+use super::segment::{self, Selector};
+
+/// Number of entries in a GDT: the null descriptor, the code and data
+/// segments, and the two halves of the TSS descriptor
+const GDT_ENTRIES: usize = 5;
+
+/// The kernel code segment (the same as `boot.asm`'s)
+pub const KERNEL_CODE: Selector = Selector::new(1);
+/// The kernel data segment (the same as `boot.asm`'s)
+pub const KERNEL_DATA: Selector = Selector::new(2);
+/// This CPU's TSS
+pub const TSS: Selector = Selector::new(3);
+
+/// The code and data segment descriptors, as in `boot.asm`
+const CODE_DESCRIPTOR: u64 = (1<<44) | (1<<47) | (1<<41) | (1<<43) | (1<<53);
+const DATA_DESCRIPTOR: u64 = (1<<44) | (1<<47) | (1<<41);
+
+/// Type of an available 64-bit TSS, in a TSS descriptor's access byte
+const TSS_AVAILABLE: u64 = 0x9;
+/// Present bit of a descriptor's access byte
+const PRESENT: u64 = 1 << 47;
+
+/// The IST entry the double fault handler's gate switches to
+pub const DOUBLE_FAULT_IST: u8 = 1;
+
+/// Size of each CPU's double fault stack
+pub const IST_STACK_SIZE: usize = 4 * PAGE_SIZE;
+
+/// A 64-bit task state segment.
+///
+/// Refer to section 7.7 of the _Intel® 64 and IA-32 Architectures Software
+/// Developer’s Manual_, volume 3, for more information.
+#[repr(C, packed)]
+pub struct Tss { _reserved_0: u32
+               , /// Stack pointers for switching to rings 0 - 2
+                 rsp: [u64; 3]
+               , _reserved_1: u64
+               , /// Stacks that IDT gates can switch to, numbered 1 - 7
+                 ist: [u64; 7]
+               , _reserved_2: u64
+               , _reserved_3: u16
+               , /// Offset of the I/O permission bitmap from the start of
+                 /// the TSS; pointing it past the end means there isn't one
+                 iomap_base: u16
+               }
+
+impl Tss {
+    /// Returns a TSS with no stacks in it
+    pub const fn new() -> Tss {
+        Tss { _reserved_0: 0, rsp: [0; 3], _reserved_1: 0, ist: [0; 7]
+            , _reserved_2: 0, _reserved_3: 0
+            , iomap_base: 104 // mem::size_of::<Tss>()
+            }
+    }
+
+    /// Set IST entry `index` (1 - 7) to the stack whose top is at `top`.
+    ///
+    /// # Panics
+    ///   - If `index` isn't between 1 and 7
+    pub fn set_ist(&mut self, index: u8, top: u64) {
+        assert!( index >= 1 && index <= 7
+               , "there's no IST entry {}; they're numbered 1 - 7", index );
+        self.ist[index as usize - 1] = top;
+    }
+}
+
+/// A GDT.
+pub struct Gdt([u64; GDT_ENTRIES]);
+
+impl Gdt {
+    /// Returns a GDT of null descriptors, to be filled in by `load`
+    pub const fn new() -> Gdt { Gdt([0; GDT_ENTRIES]) }
+
+    /// Fill in the descriptors, with the TSS descriptor pointing at `tss`.
+    fn fill(&mut self, tss: &Tss) {
+        let base = tss as *const Tss as u64;
+        let limit = mem::size_of::<Tss>() as u64 - 1;
+        self.0[0] = 0;
+        self.0[KERNEL_CODE.index() as usize] = CODE_DESCRIPTOR;
+        self.0[KERNEL_DATA.index() as usize] = DATA_DESCRIPTOR;
+        // a TSS descriptor is 16 bytes, with the top half of the base
+        // address in the second 8
+        self.0[TSS.index() as usize] = (limit & 0xFFFF)
+                                     | (base & 0xFF_FFFF) << 16
+                                     | TSS_AVAILABLE << 40
+                                     | PRESENT
+                                     | (limit >> 16 & 0xF) << 48
+                                     | (base >> 24 & 0xFF) << 56;
+        self.0[TSS.index() as usize + 1] = base >> 32;
+    }
+}
+
+impl DTable for Gdt {
+    #[inline] unsafe fn load(&self) {
+        // the limit is the offset of the table's last byte, not its size
+        let ptr = DTablePtr { limit: (mem::size_of::<Gdt>() - 1) as u16
+                            , base: self as *const Gdt
+                            };
+        asm!(  "lgdt [$0]"
+            :: "r"(&ptr)
+            :  "memory"
+            :  "intel" );
+    }
+}
+
+/// Switch this CPU to `gdt`, with a descriptor for `tss`, and load `tss`.
+///
+/// Afterwards, `cs` and the data segment registers are reloaded from `gdt`,
+/// and the task register points at `tss`.
+///
+/// # Unsafe due to
+///   - `gdt` and `tss` must stay where they are, and mustn't be used by any
+///     other CPU, for as long as this CPU runs with them
+///   - Any IST stacks in `tss` must be valid stacks
+pub unsafe fn load(gdt: &mut Gdt, tss: &Tss) {
+    gdt.fill(tss);
+    gdt.load();
+    segment::reload_cs(KERNEL_CODE);
+    segment::reload_data_segments(KERNEL_DATA);
+    segment::load_tss(TSS);
+}
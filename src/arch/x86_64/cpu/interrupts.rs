@@ -10,10 +10,18 @@
 //!
 //! Refer to section 6.10 of the _Intel® 64 and IA-32 Architectures
 //! Software Developer’s Manual_ for more information.
-use core::mem;
+use core::{fmt, mem};
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
 use spin::Mutex;
-use super::{Registers, DTable, segment};
+use ::util::once::Once;
+use super::{Registers, DTable, DTablePtr, segment, control_regs, paging, fpu
+           , apic, gdt, percpu, rflags, tsc};
+use super::rflags::RFlags;
+use ::memory::VAddr;
+use ::task::preempt;
+use super::super::sections;
 
+#[macro_use]
 #[path = "../../x86_all/interrupts.rs"] mod interrupts_all;
 #[path = "../../x86_all/pics.rs"] pub mod pics;
 pub use self::interrupts_all::*;
@@ -26,13 +34,37 @@ extern {
     static gdt64_offset: u16;
 
     /// Array of interrupt handlers from ASM
-    static int_handlers: [Option<Handler>; IDT_ENTRIES];
+    static int_handlers: [Option<Isr>; IDT_ENTRIES];
+
+    /// Number of entries the ASM actually put in `int_handlers`
+    static int_handlers_len: usize;
+}
+
+/// The number of entries `interrupt_handlers.asm` defines `int_handlers` with
+/// (its `IDT_ENTRIES`).
+///
+/// This has to be kept in sync with the ASM by hand, but at least we check
+/// it: `IDT_ENTRIES_MATCH_ASM` doesn't compile unless it equals
+/// `IDT_ENTRIES`, and `initialize` checks it against `int_handlers_len`.
+const ASM_IDT_ENTRIES: usize = 256;
+
+/// Compile-time check that `IDT_ENTRIES == ASM_IDT_ENTRIES`.
+///
+/// If `IDT_ENTRIES` is bigger, this array has the wrong length, and if it's
+/// smaller, the subtraction overflows; either way, it won't compile.
+#[allow(dead_code)]
+const IDT_ENTRIES_MATCH_ASM: [(); 0] = [(); IDT_ENTRIES - ASM_IDT_ENTRIES];
+
+/// Entry point for the ASM interrupt stubs.
+#[no_mangle]
+pub extern "C" fn handle_interrupt(state: &InterruptCtx64) {
+    Idt64::handle_interrupt(state)
 }
 
 /// State stored when handling an interrupt.
 #[allow(dead_code)]
 #[repr(C, packed)]
-struct InterruptCtx64 {  /// callee-saved registers
+pub struct InterruptCtx64 {  /// callee-saved registers
                          registers: Registers
                        , /// interrupt ID number
                          int_id:  u32
@@ -40,8 +72,180 @@ struct InterruptCtx64 {  /// callee-saved registers
                        , /// error number
                          err_no:  u32
                        , __pad_2: u32
+                       , /// instruction pointer at the time of the interrupt
+                         rip: u64
+                       , /// code segment at the time of the interrupt
+                         cs: u64
+                       , /// value of the `rflags` register before the interrupt
+                         rflags: u64
+                       , /// stack pointer at the time of the interrupt
+                         rsp: u64
+                       , /// stack segment at the time of the interrupt
+                         ss: u64
                        }
 
+/// Page fault error code bit set if the faulting page was present
+const PF_PRESENT: u32 = 1 << 0;
+/// Page fault error code bit set if the faulting access was a write
+const PF_WRITE: u32 = 1 << 1;
+/// Page fault error code bit set if the fault happened in user mode
+const PF_USER: u32 = 1 << 2;
+/// Page fault error code bit set if a reserved bit was set in a page table
+const PF_RESERVED: u32 = 1 << 3;
+/// Page fault error code bit set if the fault was an instruction fetch
+const PF_FETCH: u32 = 1 << 4;
+
+/// How many faults of one kind we can be handling at once.
+///
+/// If a page fault (say) happens while we're handling a page fault, the
+/// handler is broken somehow, and trying to handle this one will only fault
+/// again, until the stack runs out and the machine triple faults, so we give
+/// up right away instead.
+const MAX_FAULT_DEPTH: usize = 1;
+
+/// Number of page faults currently being handled
+static PAGE_FAULT_DEPTH: AtomicUsize = ATOMIC_USIZE_INIT;
+/// Number of general protection faults currently being handled
+static GP_FAULT_DEPTH: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Counts a fault as being handled, until it's dropped.
+struct FaultGuard(&'static AtomicUsize);
+
+impl FaultGuard {
+    /// Count another fault in `depth`.
+    ///
+    /// If that's more than `MAX_FAULT_DEPTH`, this doesn't return: it prints
+    /// `msg` and `addr` with `panic::die_raw`, without trusting anything
+    /// else to work, and halts.
+    fn enter(depth: &'static AtomicUsize, msg: &str, addr: u64) -> FaultGuard {
+        if depth.fetch_add(1, Ordering::SeqCst) >= MAX_FAULT_DEPTH {
+            ::panic::die_raw(msg, addr)
+        }
+        FaultGuard(depth)
+    }
+}
+
+impl Drop for FaultGuard {
+    fn drop(&mut self) { self.0.fetch_sub(1, Ordering::SeqCst); }
+}
+
+/// Returns true if the CPU pushes an error code for exception `vector`
+#[inline]
+fn has_error_code(vector: u32) -> bool {
+    match vector { 0x08 | 0x0a...0x0e | 0x11 | 0x15 | 0x1d | 0x1e => true
+                 , _ => false
+                 }
+}
+
+impl InterruptCtx64 {
+    /// Returns the value `rflags` had when the interrupt happened
+    #[inline]
+    pub fn rflags(&self) -> RFlags {
+        RFlags::from_bits_truncate(self.rflags)
+    }
+
+    /// Handle a page fault (`#PF`) exception.
+    ///
+    /// Writes to copy-on-write pages are resolved by the paging code, after
+    /// which we just return to retry the faulting instruction. Any other page
+    /// fault is fatal (for now).
+    fn handle_page_fault(&self) {
+        let cr2 = unsafe { control_regs::cr2_read() };
+        let _depth = FaultGuard::enter( &PAGE_FAULT_DEPTH
+                                      , "fatal nested page fault at ", cr2 );
+        let addr = VAddr::from_usize(cr2 as usize);
+        let write_to_present = PF_PRESENT | PF_WRITE;
+        if self.err_no & write_to_present == write_to_present
+            && paging::handle_cow_fault(addr) {
+            return
+        }
+        panic!( "PAGE FAULT at {:#?} (error code {:#x}) by instruction \
+                 at {:#x}"
+              , addr, self.err_no, self.rip )
+    }
+
+    /// Handle a general protection fault (`#GP`), which is always fatal.
+    fn handle_general_protection(&self) -> ! {
+        let _depth = FaultGuard::enter( &GP_FAULT_DEPTH
+                                      , "fatal nested general protection \
+                                         fault at ", self.rip );
+        Idt64::handle_cpu_exception(self)
+    }
+
+    /// Handle an alignment check (`#AC`) exception.
+    ///
+    /// The CPU doesn't tell us which data address was misaligned, only the
+    /// instruction that made the access, so that's what we report.
+    fn handle_alignment_check(&self) -> ! {
+        let (cr0, ac): (u64, bool) = unsafe {
+            ( control_regs::cr0_read()
+            , self.rflags().contains(rflags::AC) )
+        };
+        panic!( "ALIGNMENT CHECK: unaligned access by instruction at {:#x}\n\
+                 (alignment checks are enabled: cr0 = {:#x}, rflags.AC = {})"
+              , self.rip, cr0, ac )
+    }
+}
+
+impl fmt::Display for InterruptCtx64 {
+    /// Format a complete crash report for this interrupt.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // copy everything out of the packed struct first
+        let (id, err) = (self.int_id, self.err_no);
+        let (rip, cs, rsp, ss) = (self.rip, self.cs, self.rsp, self.ss);
+        let r = self.registers;
+
+        let name = EXCEPTIONS.get(id as usize).map_or("Reserved", |n| *n);
+        if (id as usize) < N_EXCEPTIONS {
+            try!(write!(f, "CPU EXCEPTION {:#04x}: {}", id, name));
+        } else {
+            try!(write!(f, "UNEXPECTED INTERRUPT {:#04x}", id));
+        }
+
+        if has_error_code(id) {
+            try!(write!(f, "\n  error code {:#x}", err));
+            if id == 0x0e {
+                try!(write!( f, " ({} {} in {} mode{})"
+                           , if err & PF_FETCH != 0 { "fetch" }
+                             else if err & PF_WRITE != 0 { "write" }
+                             else { "read" }
+                           , if err & PF_PRESENT != 0 { "protection fault" }
+                             else { "of non-present page" }
+                           , if err & PF_USER != 0 { "user" }
+                             else { "kernel" }
+                           , if err & PF_RESERVED != 0 { ", reserved bit set" }
+                             else { "" } ));
+                let cr2 = unsafe { control_regs::cr2_read() };
+                try!(write!(f, "\n  faulting address {:#018x}", cr2));
+            } else if id >= 0x0a && id <= 0x0d && err != 0 {
+                // #TS, #NP, #SS, and #GP give a selector; the others (#DF,
+                // #AC, #CP, #VC, #SX) have error codes that mean something
+                // else entirely
+                let table = match (err >> 1) & 0b11 { 0b00 => "GDT"
+                                                    , 0b10 => "LDT"
+                                                    , _    => "IDT"
+                                                    };
+                try!(write!( f, " ({} index {}{})"
+                           , table, err >> 3
+                           , if err & 1 != 0 { ", external" } else { "" } ));
+            }
+        }
+
+        try!(write!( f, "\n  rip {:#018x}  cs {:#06x}  rflags {:?}"
+                   , rip, cs, self.rflags() ));
+        try!(write!(f, "\n  rsp {:#018x}  ss {:#06x}", rsp, ss));
+        let (rax, rcx, rdx) = (r.rax, r.rcx, r.rdx);
+        let (rsi, rdi, r8) = (r.rsi, r.rdi, r.r8);
+        let (r9, r10, r11) = (r.r9, r.r10, r.r11);
+        try!(write!( f, "\n  rax {:#018x}  rcx {:#018x}  rdx {:#018x}"
+                   , rax, rcx, rdx ));
+        try!(write!( f, "\n  rsi {:#018x}  rdi {:#018x}  r8  {:#018x}"
+                   , rsi, rdi, r8 ));
+        write!( f, "\n  r9  {:#018x}  r10 {:#018x}  r11 {:#018x}"
+              , r9, r10, r11 )
+    }
+}
+
 impl InterruptContext for InterruptCtx64 {
     type Registers = Registers;
     // All these inline functions are basically just faking
@@ -69,8 +273,9 @@ struct Gate64 { /// bits 0 - 15 of the offset
                 offset_lower: u16
               , /// code segment selector (GDT or LDT)
                 selector: segment::Selector
-              , /// always zero
-                zero: u8
+              , /// the IST entry (1 - 7) to switch stacks to, or zero to
+                /// stay on the current stack. the top 5 bits are reserved.
+                ist: u8
               , /// indicates the gate's type and attributes.
                 /// the second half indicates the type:
                 ///   + `0b1100`: Call gate
@@ -97,7 +302,7 @@ impl Gate64 {
     const fn absent() -> Self {
         Gate64 { offset_lower: 0
                , selector: segment::Selector::from_raw(0)
-               , zero: 0
+               , ist: 0
                , type_attr: GateType::Absent as u8
                , offset_mid: 0
                , offset_upper: 0
@@ -108,33 +313,34 @@ impl Gate64 {
 
 impl Gate for Gate64 {
 
-    /// Creates a new IDT gate pointing at the given handler function.
+    /// Creates a new IDT gate of type `ty` pointing at the given handler
+    /// function.
     ///
     /// The `handler` function must have been created with valid interrupt
     /// calling conventions.
-    fn from_handler(handler: Handler) -> Self {
-        unsafe { // trust me on this.
-                 // `mem::transmute()` is glorious black magic
-            let (low, mid, high): (u16, u16, u32)
-                = mem::transmute(handler);
+    unsafe fn from_handler_as(handler: Handler, ty: GateType) -> Self {
+        // trust me on this.
+        // `mem::transmute()` is glorious black magic
+        let (low, mid, high): (u16, u16, u32)
+            = mem::transmute(handler);
 
-            Gate64 { offset_lower: low
-                   , selector: segment::Selector::new(gdt64_offset)
-                   , zero: 0
-                   // Bit 7 is the present bit
-                   // Bits 4-0 indicate this is an interrupt gate
-                   , type_attr: GateType::Interrupt as u8
-                   , offset_mid: mid
-                   , offset_upper: high
-                   , reserved: 0
-                   }
-        }
+        Gate64 { offset_lower: low
+               , selector: segment::Selector::from_raw(gdt64_offset)
+               , ist: 0
+               // Bit 7 is the present bit, bits 6-5 are the DPL (always 0,
+               // so that user code can't `int` its way in), and bits 3-0
+               // are the gate's type
+               , type_attr: ty.type_attr(0)
+               , offset_mid: mid
+               , offset_upper: high
+               , reserved: 0
+               }
     }
 }
 
 //==------------------------------------------------------------------------==
 // 64-bit implementation of the IDT trait
-struct Idt64([Gate64; IDT_ENTRIES]);
+pub struct Idt64([Gate64; IDT_ENTRIES]);
 
 impl Idt for Idt64 {
     // type Ptr = IdtPtr<Self>;
@@ -148,36 +354,305 @@ impl Idt for Idt64 {
     //            }
     // }
 
-    /// Add an entry for the given handler at the given index
-    fn add_gate(&mut self, index: usize, handler: Handler) {
-        self.0[index] = Gate64::from_handler(handler)
+    /// Enable interrupts.
+    ///
+    /// Any IRQ that's unmasked and was requested while interrupts were
+    /// disabled is delivered straight away; `initialize` gets rid of the
+    /// keyboard's stale requests before calling this.
+    ///
+    /// In debug builds, this checks that `initialize` has got far enough for
+    /// interrupts to go somewhere sensible: a full IDT has been loaded, and
+    /// the PICs no longer deliver IRQs on the CPU exception vectors.
+    unsafe fn enable_interrupts() {
+        debug_assert!( idt_loaded()
+                     , "enable_interrupts() called before the IDT was \
+                        loaded! call interrupts::initialize() instead" );
+        debug_assert!( pics::is_initialized()
+                     , "enable_interrupts() called before the PICs were \
+                        remapped! call interrupts::initialize() instead" );
+        asm!("sti" :::: "volatile")
+    }
+
+    /// Add an entry of type `ty` for the given ISR at the given index.
+    ///
+    /// In debug builds, this checks that the ISR is in the kernel's `.text`
+    /// section; `install` checks every gate again before loading the table.
+    fn add_gate_as(&mut self, index: usize, isr: Isr, ty: GateType) {
+        debug_assert!( sections::in_text(isr.handler() as usize)
+                     , "the handler for vector {:#x} is at {:#x}, which \
+                        isn't in the kernel's text!"
+                     , index, isr.handler() as usize );
+        self.0[index] = Gate64::from_isr_as(isr, ty)
     }
 
     /// Assembly interrupt handlers call into this
     extern "C" fn handle_interrupt(state: &Self::Ctx) {
+        let id = state.int_id();
+        interrupt_counts()[id as usize].fetch_add(1, Ordering::Relaxed);
+        // spurious APIC interrupts aren't in service, so there's nothing to
+        // handle, and sending an EOI would end some other interrupt instead
+        if id == apic::SPURIOUS_VECTOR as u32 {
+            return
+        }
+        let cpu = percpu::current();
+        let depth = cpu.irq_depth.fetch_add(1, Ordering::SeqCst) + 1;
+        if cfg!(irq_latency) {
+            let start = tsc::rdtsc();
+            Self::dispatch(state);
+            record_latency(id as usize, tsc::rdtsc() - start);
+        } else {
+            Self::dispatch(state);
+        }
+        // send the end interrupt signal, unless the handler already did,
+        // with `allow_nesting`
+        let early = cpu.early_eoi.fetch_and(!(1 << depth), Ordering::SeqCst);
+        if early & (1 << depth) == 0 {
+            unsafe { end_interrupt(id) }
+        }
+        cpu.irq_depth.fetch_sub(1, Ordering::SeqCst);
+        // only now, with the interrupt over and its EOI sent, is it safe to
+        // switch tasks
+        if depth == 1 && preempt::is_preemptible() && preempt::take_request() {
+            ::task::scheduler::preempt();
+        }
+    }
+}
+
+impl Idt64 {
+    /// Run the registered handler for an interrupt, or the built-in handling
+    /// if there isn't one.
+    #[inline]
+    fn dispatch(state: &InterruptCtx64) {
+        // copy the handler out, so we don't hold the lock while it runs.
+        // if we came in through a trap gate, interrupts are still enabled,
+        // so they have to be disabled while we have the lock
+        let handler = super::without_interrupts(||
+            HANDLERS.lock()[state.int_id() as usize]);
+        match handler {
+            Some(handler) => handler(state)
+          , None => Self::handle_default(state)
+        }
+    }
+
+    /// Built-in handling for interrupts with no registered handler
+    fn handle_default(state: &InterruptCtx64) {
         let id = state.int_id();
         match id {
-            // interrupts 0 - 16 are CPU exceptions
-            0x00...0x0f => Self::handle_cpu_exception(state)
-            // System timer
-          , 0x20 => { /* TODO: make this work */ }
-            // Keyboard
-          , 0x21 => { /* TODO: make this work */ }
-          , _ => panic!("Unknown interrupt: #{} Sorry!", id)
+            // interrupts 0 - 31 are CPU exceptions
+            0x00...0x1f => Self::handle_exception(state)
+            // System timer (the PIT, unless the APIC timer has taken over),
+            // which also counts against the current task's quantum
+          , 0x20 => {
+                timer_tick();
+                preempt::tick();
+            }
+            // Keyboard: save the scancode, and wake up whoever is waiting
+            // to read it
+          , 0x21 => {
+                super::super::drivers::keyboard::handle_irq();
+                ::io::term::INPUT_WAITERS.wake_all();
+            }
+            // Some other device interrupted us, and nobody cares. There's no
+            // need to die over it: `handle_interrupt` will still end the IRQ.
+          , 0x22...0x2f => Self::warn_unhandled("IRQ", id - 0x20)
+            // The APIC timer, which replaces the PIT's tick, and counts
+            // against the current task's quantum the same way
+          , id if id == apic::TIMER_VECTOR as u32 => {
+                timer_tick();
+                preempt::tick();
+            }
+          , _ => Self::warn_unhandled("interrupt vector", id)
+        }
+    }
+
+    /// Handle a CPU exception: with the handler set for it by
+    /// `set_exception_handler`, if there is one and it deals with the
+    /// exception, and with the built-in handling otherwise.
+    fn handle_exception(state: &InterruptCtx64) {
+        let id = state.int_id();
+        if let Some(handler) = exception_handler(id as usize) {
+            if handler(state) {
+                return
+            }
+        }
+        match id {
+            0x07 => fpu::handle_device_not_available()
+          , 0x0d => state.handle_general_protection()
+          , 0x0e => state.handle_page_fault()
+            // Alignment check
+          , 0x11 => state.handle_alignment_check()
+            // SIMD floating-point
+          , 0x13 => fpu::handle_simd_exception(state.rip)
+          , _ => Self::handle_cpu_exception(state)
+        }
+    }
+
+    /// Print a warning about an interrupt nobody handled.
+    ///
+    /// If the console is in use by whatever we interrupted, we skip the
+    /// warning rather than deadlocking.
+    fn warn_unhandled(what: &str, number: u32) {
+        use core::fmt::Write;
+        if let Some(mut console) = ::io::term::CONSOLE.try_lock() {
+            let _ = writeln!( console, "warning: unhandled {} {}; ignoring it"
+                            , what, number );
         }
-        // send the PICs the end interrupt signal
-        unsafe { pics::end_pic_interrupt(id as u8); }
     }
 }
 
 impl DTable for Idt64 {
     #[inline] unsafe fn load(&self) {
+        let ptr = self.get_ptr();
         asm!(  "lidt [$0]"
-            :: "A"(self.get_ptr())
-            :: "intel" );
+            :: "r"(&ptr)
+            :  "memory"
+            :  "intel" );
+    }
+}
+
+/// Number of vectors reserved for CPU exceptions
+pub const N_EXCEPTIONS: usize = 32;
+
+/// Reasons `Idt64::install` might refuse to load an IDT.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum IdtError { /// The table isn't aligned on an 8-byte boundary. This
+                    /// contains the table's address.
+                    Misaligned(usize)
+                  , /// The table pointer's limit doesn't cover exactly
+                    /// `IDT_ENTRIES` gates. This contains the bad limit.
+                    BadLimit(u16)
+                  , /// There's no gate for this CPU exception vector, so if
+                    /// the exception happens, we'll triple fault.
+                    MissingException(usize)
+                  , /// This vector's gate points outside the kernel's
+                    /// `.text` section, at data or garbage. This contains
+                    /// the vector and the handler's address.
+                    BadHandler(usize, u64)
+                  }
+
+impl Gate64 {
+    #[inline] fn is_present(&self) -> bool { self.type_attr & 0x80 != 0 }
+
+    /// Decode this gate's fields.
+    fn info(&self) -> GateInfo {
+        let ty = GateType::from_type_bits(self.type_attr);
+        GateInfo { handler: self.offset_lower as u64
+                          | (self.offset_mid as u64) << 16
+                          | (self.offset_upper as u64) << 32
+                 , selector: self.selector
+                 , ty: ty
+                 , dpl: (self.type_attr >> 5) & 0b11
+                 }
+    }
+}
+
+/// The decoded contents of an IDT gate.
+#[derive(Debug, Copy, Clone)]
+pub struct GateInfo { /// Address of the gate's handler function
+                      pub handler: u64
+                    , /// Code segment the handler runs in
+                      pub selector: segment::Selector
+                    , /// What kind of gate this is
+                      pub ty: GateType
+                    , /// Lowest privilege level allowed to call the gate
+                      /// with `int`
+                      pub dpl: u8
+                    }
+
+/// Iterator over the present gates in an IDT.
+pub struct Gates<'a> { idt: &'a Idt64
+                     , vector: usize
+                     }
+
+impl<'a> Iterator for Gates<'a> {
+    type Item = (usize, GateInfo);
+
+    fn next(&mut self) -> Option<(usize, GateInfo)> {
+        while self.vector < IDT_ENTRIES {
+            let vector = self.vector;
+            self.vector += 1;
+            let gate = &self.idt.0[vector];
+            if gate.is_present() {
+                return Some((vector, gate.info()))
+            }
+        }
+        None
+    }
+}
+
+impl Idt64 {
+    /// Returns an iterator over the vector number and decoded contents of
+    /// every present gate in this IDT.
+    pub fn gates(&self) -> Gates {
+        Gates { idt: self, vector: 0 }
+    }
+
+    /// Check that this IDT is sane, and load it if it is.
+    ///
+    /// This checks that the table is 8-byte aligned, that the pointer we'd
+    /// pass to `lidt` has the right limit, that there's a gate for every
+    /// CPU exception vector, and that every gate points into the kernel's
+    /// `.text` section. The unchecked `DTable::load` is still there for when
+    /// you really know what you're doing.
+    pub fn install(&self) -> Result<(), IdtError> {
+        let ptr = self.get_ptr();
+        let (base, limit) = (ptr.base as usize, ptr.limit);
+        if base % 8 != 0 {
+            return Err(IdtError::Misaligned(base))
+        }
+        if limit as usize != mem::size_of::<Gate64>() * IDT_ENTRIES - 1 {
+            return Err(IdtError::BadLimit(limit))
+        }
+        if let Some(vector) = (0..N_EXCEPTIONS)
+                                .find(|&i| !self.0[i].is_present()) {
+            return Err(IdtError::MissingException(vector))
+        }
+        if let Some((vector, gate)) = self.gates().find(|&(_, ref gate)|
+                !sections::in_text(gate.handler as usize)) {
+            return Err(IdtError::BadHandler(vector, gate.handler))
+        }
+        unsafe { self.load() };
+        kassert!(self.is_loaded(), "lidt didn't load the IDT we gave it!");
+        Ok(())
+    }
+
+    /// Returns true if this is the IDT the CPU is currently using, according
+    /// to `sidt`.
+    pub fn is_loaded(&self) -> bool {
+        let (loaded, ours) = (sidt(), self.get_ptr());
+        let (loaded_base, loaded_limit) = (loaded.base, loaded.limit);
+        loaded_base as usize == ours.base as usize
+            && loaded_limit == ours.limit
     }
 }
 
+/// Returns true if the CPU has an IDT with a gate for every vector loaded.
+///
+/// Unlike `Idt64::is_loaded`, this doesn't need the IDT itself, so it can be
+/// used before `IDT` has been initialized.
+#[inline]
+fn idt_loaded() -> bool {
+    let ptr = sidt();
+    let (base, limit) = (ptr.base, ptr.limit);
+    !base.is_null()
+        && limit as usize == mem::size_of::<Gate64>() * IDT_ENTRIES - 1
+}
+
+/// Read back the IDT pointer the CPU is using, with `sidt`.
+///
+/// This is the pointer most recently loaded with `lidt`, which makes it handy
+/// for checking that the IDT really did get loaded.
+pub fn sidt() -> DTablePtr<Idt64> {
+    let mut ptr = DTablePtr { limit: 0, base: 0 as *const Idt64 };
+    unsafe {
+        asm!(  "sidt [$0]"
+            :: "r"(&mut ptr)
+            :  "memory"
+            :  "intel", "volatile" );
+    }
+    ptr
+}
+
 // impl IdtPtrOps for IdtPtr<Idt64> {
 //     /// Load the IDT at the given location.
 //     /// This just calls `lidt`.
@@ -188,19 +663,415 @@ impl DTable for Idt64 {
 //     }
 // }
 
-/// Global Interrupt Descriptor Table instance
 /// Our global IDT.
-static IDT: Mutex<Idt64>
-    = Mutex::new(Idt64([Gate64::absent(); IDT_ENTRIES]));
+///
+/// This is built and loaded once, by `initialize`, and never changes after
+/// that: every gate points at the same ASM stub it always will, and what
+/// those stubs end up running is decided by `HANDLERS` instead. So the table
+/// itself needs no lock.
+static IDT: Once<Idt64> = Once::new();
+
+/// Returns the global IDT, if `initialize` has built it
+#[inline]
+pub fn idt() -> Option<&'static Idt64> {
+    IDT.get()
+}
+
+/// The type of gate each vector gets when `initialize` builds the IDT.
+///
+/// Vectors are interrupt gates unless `set_gate_type` says otherwise.
+static GATE_TYPES: Mutex<[GateType; IDT_ENTRIES]>
+    = Mutex::new([GateType::Interrupt; IDT_ENTRIES]);
+
+/// Choose whether `vector` gets an interrupt gate or a trap gate.
+///
+/// The handler for an interrupt gate always runs with interrupts disabled.
+/// A trap gate leaves them however they were, which is what you want for
+/// things like breakpoints and system calls, whose handlers may take a while
+/// and don't need to keep other interrupts out.
+///
+/// This only affects the IDT that `initialize` builds, so it has to be called
+/// before `initialize`.
+///
+/// # Returns
+///   - `false` if the IDT has already been built, so nothing changed
+///
+/// # Panics
+///   - If `ty` isn't `GateType::Interrupt` or `GateType::Trap`
+pub fn set_gate_type(vector: u8, ty: GateType) -> bool {
+    assert!( ty == GateType::Interrupt || ty == GateType::Trap
+           , "IDT gates must be interrupt or trap gates, not {}", ty );
+    super::without_interrupts(|| {
+        let mut types = GATE_TYPES.lock();
+        if IDT.is_completed() {
+            return false
+        }
+        types[vector as usize] = ty;
+        true
+    })
+}
+
+/// Acknowledge interrupt `id`, with whichever interrupt controller raised
+/// it. Anything that isn't from the APIC timer or the PICs (CPU exceptions
+/// and software interrupts) doesn't need acknowledging.
+unsafe fn end_interrupt(id: u32) {
+    if id == apic::TIMER_VECTOR as u32 {
+        apic::end_of_interrupt()
+    } else {
+        pics::end_pic_interrupt(id as u8)
+    }
+}
+
+/// Keeps interrupts enabled in the middle of a handler; see
+/// `allow_nesting`.
+#[must_use = "interrupts are disabled again as soon as the guard is dropped"]
+pub struct NestingGuard { _private: () }
+
+impl Drop for NestingGuard {
+    fn drop(&mut self) {
+        Idt64::disable_interrupts()
+    }
+}
+
+/// Let other interrupts in for the rest of the current handler.
+///
+/// Interrupt gates run their handlers with interrupts disabled, so a slow
+/// handler holds everything else up. This acknowledges the interrupt being
+/// handled right away, rather than once the handler returns, and then
+/// enables interrupts, so that anything else (including the same IRQ again)
+/// can interrupt what's left of the handler. Interrupts are disabled again
+/// when the returned guard is dropped, which has to be before the handler
+/// returns.
+///
+/// Nested interrupts are handled on the same stack as the one they
+/// interrupted, so each level of nesting costs the stack a saved context.
+/// A nested interrupt never preempts the task: if the timer asks for a task
+/// switch, it waits until the outermost handler has finished.
+///
+/// # Unsafe due to
+///   - Once this returns, the handler can be interrupted by another
+///     instance of itself, so it must be reentrant, and mustn't hold any
+///     lock that another interrupt handler might take
+///   - `state` must be the state passed to the handler that's calling this
+pub unsafe fn allow_nesting(state: &InterruptCtx64) -> NestingGuard {
+    let cpu = percpu::current();
+    let depth = cpu.irq_depth.load(Ordering::SeqCst);
+    kassert!(depth > 0, "allow_nesting() called outside an interrupt!");
+    end_interrupt(state.int_id());
+    cpu.early_eoi.fetch_or(1 << depth, Ordering::SeqCst);
+    Idt64::enable_interrupts();
+    NestingGuard { _private: () }
+}
+
+/// A Rust interrupt handler, called with the state saved by the interrupt.
+pub type InterruptHandler = fn(&InterruptCtx64);
+
+/// Handlers registered for each interrupt vector.
+///
+/// If a vector has a handler here, it's called instead of the built-in
+/// handling in `handle_interrupt`. Interrupt handlers take this lock too, so
+/// it must only be taken with interrupts disabled.
+static HANDLERS: Mutex<[Option<InterruptHandler>; IDT_ENTRIES]>
+    = Mutex::new([None; IDT_ENTRIES]);
+
+/// Install `handler` for interrupt `vector`.
+///
+/// # Returns
+///   - The handler that was previously installed for `vector`, if any, so
+///     that it can be put back later with `register_handler`
+///
+/// # Panics
+///   - If `vector` is `apic::SPURIOUS_VECTOR`, which is reserved
+///   - In debug builds, if `handler` isn't in the kernel's `.text` section
+pub fn register_handler(vector: u8, handler: InterruptHandler)
+                        -> Option<InterruptHandler> {
+    assert!( vector != apic::SPURIOUS_VECTOR
+           , "vector {:#x} is reserved for spurious APIC interrupts", vector );
+    debug_assert!( sections::in_text(handler as usize)
+                 , "the handler for vector {:#x} is at {:#x}, which isn't \
+                    in the kernel's text!"
+                 , vector, handler as usize );
+    super::without_interrupts(|| {
+        let mut handlers = HANDLERS.lock();
+        let previous = handlers[vector as usize];
+        handlers[vector as usize] = Some(handler);
+        previous
+    })
+}
+
+/// Remove the handler for interrupt `vector`, going back to the built-in
+/// handling for it.
+///
+/// # Returns
+///   - The handler that was installed for `vector`, if any
+pub fn unregister_handler(vector: u8) -> Option<InterruptHandler> {
+    super::without_interrupts(|| {
+        let mut handlers = HANDLERS.lock();
+        let previous = handlers[vector as usize];
+        handlers[vector as usize] = None;
+        previous
+    })
+}
+
+/// A handler for a CPU exception, which runs before the built-in handling.
+///
+/// # Returns
+///   - `true` if it dealt with the exception, in which case we return from
+///     the exception straight away
+///   - `false` to carry on with the built-in handling, as though there
+///     were no handler
+pub type ExceptionHandler = fn(&InterruptCtx64) -> bool;
+
+/// The handler set for each CPU exception with `set_exception_handler`, as
+/// an address, or zero if there isn't one.
+///
+/// An exception can happen anywhere, including while the table is being
+/// changed, so rather than a lock, each entry is only ever accessed as an
+/// atomic through `exception_handlers`.
+static mut EXCEPTION_HANDLERS: [usize; N_EXCEPTIONS] = [0; N_EXCEPTIONS];
+
+#[inline]
+fn exception_handlers() -> &'static [AtomicUsize; N_EXCEPTIONS] {
+    // `AtomicUsize` has the same representation as `usize`
+    unsafe { mem::transmute(&EXCEPTION_HANDLERS) }
+}
+
+/// Returns the handler set for exception `vector`, if there is one
+#[inline]
+fn exception_handler(vector: usize) -> Option<ExceptionHandler> {
+    match exception_handlers()[vector].load(Ordering::Acquire) {
+        0 => None
+      , addr => Some(unsafe { mem::transmute(addr) })
+    }
+}
+
+/// Have `handler` look at CPU exception `vector` before the built-in
+/// handling does, or with `None`, go back to just the built-in handling.
+///
+/// Unlike `register_handler`, which replaces the handling of a vector
+/// outright, this leaves the built-in handling in place for whatever the
+/// handler doesn't deal with, so (say) a handler for page faults in some
+/// region of memory can pass the rest on to the kernel's usual page fault
+/// handling.
+///
+/// # Returns
+///   - The handler that was set for `vector` before, if any, so that a
+///     temporary handler can put it back when it's done
+///
+/// # Panics
+///   - If `vector` isn't a CPU exception
+///   - In debug builds, if `handler` isn't in the kernel's `.text` section
+pub fn set_exception_handler( vector: u8, handler: Option<ExceptionHandler>)
+                             -> Option<ExceptionHandler> {
+    assert!( (vector as usize) < N_EXCEPTIONS
+           , "vector {:#x} isn't a CPU exception", vector );
+    let addr = handler.map_or(0, |handler| handler as usize);
+    debug_assert!( addr == 0 || sections::in_text(addr)
+                 , "the handler for exception {:#x} is at {:#x}, which \
+                    isn't in the kernel's text!"
+                 , vector, addr );
+    match exception_handlers()[vector as usize].swap(addr, Ordering::AcqRel) {
+        0 => None
+      , previous => Some(unsafe { mem::transmute(previous) })
+    }
+}
+
+/// Number of times each interrupt vector has been handled.
+///
+/// `AtomicUsize` isn't `Copy`, so we can't write an array of them as an
+/// array expression; this is an array of plain `usize`s instead, which is
+/// only ever accessed as atomics through `interrupt_counts`.
+static mut INTERRUPT_COUNTS: [usize; IDT_ENTRIES] = [0; IDT_ENTRIES];
+
+/// Returns the number of times each interrupt vector has been handled,
+/// indexed by vector.
+///
+/// Vectors with no handler in the IDT never reach `handle_interrupt`, so they
+/// are never counted.
+#[inline]
+pub fn interrupt_counts() -> &'static [AtomicUsize; IDT_ENTRIES] {
+    // `AtomicUsize` has the same representation as `usize`
+    unsafe { mem::transmute(&INTERRUPT_COUNTS) }
+}
+
+// Handler latency, measured with the TSC when built with `--cfg irq_latency`.
+// Like `INTERRUPT_COUNTS`, these are only ever accessed as atomics.
+/// Number of times each vector's latency has been measured
+static mut LATENCY_SAMPLES: [usize; IDT_ENTRIES] = [0; IDT_ENTRIES];
+/// Total TSC ticks spent handling each vector
+static mut LATENCY_TOTAL: [usize; IDT_ENTRIES] = [0; IDT_ENTRIES];
+/// Most TSC ticks spent handling each vector at once
+static mut LATENCY_MAX: [usize; IDT_ENTRIES] = [0; IDT_ENTRIES];
+
+/// How long the handler for an interrupt vector has taken to run.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct LatencyStats { /// Number of times the handler was timed
+                          pub samples: usize
+                        , /// The longest the handler has taken (in TSC
+                          /// ticks)
+                          pub max_cycles: u64
+                        , /// How long the handler takes on average (in TSC
+                          /// ticks)
+                          pub avg_cycles: u64
+                        }
+
+impl LatencyStats {
+    /// Returns `max_cycles` in nanoseconds, if the TSC has been calibrated
+    pub fn max_ns(&self) -> Option<u64> {
+        tsc::tsc_hz().map(|_| tsc::tsc_to_ns(self.max_cycles))
+    }
+
+    /// Returns `avg_cycles` in nanoseconds, if the TSC has been calibrated
+    pub fn avg_ns(&self) -> Option<u64> {
+        tsc::tsc_hz().map(|_| tsc::tsc_to_ns(self.avg_cycles))
+    }
+}
+
+/// Returns the latency sample counts, totals, and maximums, as atomics.
+#[inline]
+fn latency_counters() -> ( &'static [AtomicUsize; IDT_ENTRIES]
+                         , &'static [AtomicUsize; IDT_ENTRIES]
+                         , &'static [AtomicUsize; IDT_ENTRIES] ) {
+    unsafe { ( mem::transmute(&LATENCY_SAMPLES)
+             , mem::transmute(&LATENCY_TOTAL)
+             , mem::transmute(&LATENCY_MAX) ) }
+}
+
+/// Record that handling `vector` took `cycles` TSC ticks.
+fn record_latency(vector: usize, cycles: u64) {
+    let (samples, total, max) = latency_counters();
+    let cycles = cycles as usize;
+    samples[vector].fetch_add(1, Ordering::Relaxed);
+    total[vector].fetch_add(cycles, Ordering::Relaxed);
+    let mut old = max[vector].load(Ordering::Relaxed);
+    while cycles > old {
+        match max[vector].compare_and_swap(old, cycles, Ordering::Relaxed) {
+            prev if prev == old => break
+          , prev => old = prev
+        }
+    }
+}
+
+/// Returns how long the handler for `vector` has taken to run.
+///
+/// This is only measured when the kernel is built with `--cfg irq_latency`;
+/// otherwise, there are never any samples. Handlers that never return (like
+/// a fatal exception's) aren't counted either.
+pub fn interrupt_latency(vector: u8) -> LatencyStats {
+    let (samples, total, max) = latency_counters();
+    let vector = vector as usize;
+    let n = samples[vector].load(Ordering::Relaxed);
+    LatencyStats { samples: n
+                 , max_cycles: max[vector].load(Ordering::Relaxed) as u64
+                 , avg_cycles: if n == 0 { 0 }
+                               else { (total[vector].load(Ordering::Relaxed)
+                                        / n) as u64 }
+                 }
+}
+
+/// Number of system timer interrupts since interrupts were enabled
+static TICKS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// The frequency of the system timer (in Hz), if nobody reprograms the PIT
+pub const DEFAULT_TIMER_HZ: usize = 18;
+
+/// The frequency `initialize` runs the APIC timer at, if there's an APIC
+pub const APIC_TIMER_HZ: usize = 100;
+
+/// The frequency of the system timer (in Hz), or zero if it's still the
+/// PIT's `DEFAULT_TIMER_HZ`
+static TIMER_HZ: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Returns the number of system timer interrupts we've handled.
+#[inline]
+pub fn ticks() -> usize {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Returns how many times a second the system timer ticks
+#[inline]
+pub fn timer_hz() -> usize {
+    match TIMER_HZ.load(Ordering::Relaxed) {
+        0 => DEFAULT_TIMER_HZ
+      , hz => hz
+    }
+}
+
+/// Count a system timer tick, and check for expired timers.
+fn timer_tick() {
+    let now = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    ::task::timer::tick(now);
+}
+
+/// Call `f` with each vector that `int_handlers` has a handler for.
+///
+/// This also prints which vectors those were, and warns about any CPU
+/// exception that doesn't have one: the ASM should always define those, and
+/// an exception with no gate turns into a general protection fault, which
+/// is a lot harder to make sense of.
+fn for_each_handler<F>(mut f: F) where F: FnMut(usize, Isr) {
+    let handlers = unsafe { &int_handlers };
+    let mut missing = [false; N_EXCEPTIONS];
+    // the start of the run of vectors with handlers we're in, if any
+    let mut run: Option<usize> = None;
+    print!("Interrupt handlers present for vectors");
+    for vector in 0..IDT_ENTRIES + 1 {
+        match handlers.get(vector).and_then(|isr| *isr) {
+            Some(isr) => {
+                f(vector, isr);
+                if run.is_none() { run = Some(vector) }
+            }
+          , None => {
+                if let Some(start) = run.take() {
+                    if start == vector - 1 { print!(" {:#04x}", start) }
+                    else { print!(" {:#04x}-{:#04x}", start, vector - 1) }
+                }
+                if vector < N_EXCEPTIONS { missing[vector] = true }
+            }
+        }
+    }
+    println!(".");
+    for (vector, _) in missing.iter().enumerate().filter(|&(_, &m)| m) {
+        println!( "warning: no handler for CPU exception {:#04x} ({})"
+                , vector, EXCEPTIONS.get(vector).map_or("Reserved", |n| *n) );
+    }
+}
 
 pub fn initialize() {
-    let mut idt = IDT.lock();
+    // the compile-time check only covers the number we wrote down, not what
+    // the ASM was really assembled with
+    let asm_entries = unsafe { int_handlers_len };
+    assert!( asm_entries == IDT_ENTRIES
+           , "int_handlers has {} entries, but IDT_ENTRIES is {}! \
+              interrupt_handlers.asm and interrupts.rs disagree"
+           , asm_entries, IDT_ENTRIES );
 
-    // TODO: load interrupts into IDT
+    // point a gate at every stub, and then seal the table
+    let idt = IDT.call_once(|| {
+        let mut idt = Idt64([Gate64::absent(); IDT_ENTRIES]);
+        let types = GATE_TYPES.lock();
+        for_each_handler(|vector, isr|
+            idt.add_gate_as(vector, isr, types[vector]));
+        // a double fault is often a stack overflow, so it gets a stack of
+        // its own (from each CPU's TSS) rather than faulting again
+        idt.0[0x08].ist = gdt::DOUBLE_FAULT_IST;
+        idt
+    });
 
     unsafe {
-        idt.load();                 // Load the IDT pointer
+        idt.install()               // Load the IDT pointer
+           .expect("Couldn't load the IDT!");
         pics::initialize();         // initialize the PICs
+        // point the APIC's SVR at 0xFF, and have its timer take over the
+        // system tick from the PIT
+        if !::cmdline::has_flag("noapic") && apic::init()
+            && apic::start_timer(APIC_TIMER_HZ as u64) {
+            pics::mask_irq(pics::IRQ::Timer);
+            TIMER_HZ.store(APIC_TIMER_HZ, Ordering::Relaxed);
+        }
+        // whatever the keyboard sent during boot has been latched by the
+        // PIC, and would arrive as soon as interrupts are enabled
+        super::super::drivers::keyboard::unmask_irq();
         Idt64::enable_interrupts(); // enable interrupts
     }
 }
@@ -15,31 +15,140 @@
 #[path = "../../x86_all/cpu.rs"] mod cpu_all;
 
 pub mod interrupts;
+pub mod apic;
+pub mod msr;
+pub mod percpu;
+pub mod smp;
+pub mod rflags;
 pub mod paging;
 pub mod context;
 pub mod control_regs;
+pub mod cpuid;
+pub mod fpu;
+pub mod segment;
+pub mod gdt;
+pub mod rand;
+pub mod tsc;
 
 pub use self::context::Registers;
 pub use self::cpu_all::*;
 
-pub mod segment {
+use ::util::defer;
 
-    bitflags! {
-        flags Selector: u16 { const RING_0 = 0b00
-                            , const RING_1 = 0b01
-                            , const RING_2 = 0b10
-                            , const RING_3 = 0b11
-                            , const GDT    = 0 << 3
-                            , const LDT    = 1 << 3
-                            }
+/// Tell the CPU that we're spinning, waiting for something to change.
+///
+/// This is the `pause` instruction, which lets a hyperthread's sibling have
+/// the core's resources while we wait, saves a little power, and avoids the
+/// pipeline flush a tight loop otherwise takes when the thing it's waiting
+/// for finally happens. Every busy-wait loop should call this once each time
+/// around.
+#[inline(always)]
+pub fn spin_hint() {
+    unsafe { asm!("pause" :::: "volatile") }
+}
+
+/// Reset the machine, by pulsing the CPU reset line through the 8042
+/// keyboard controller.
+pub fn reboot() -> ! {
+    unsafe {
+        let controller: Port = Port::new(0x64);
+        // wait until the controller's input buffer is empty
+        while controller.in8() & 0x02 != 0 { spin_hint() }
+        controller.out8(0xFE);
     }
+    // if the reset didn't happen, there's not much else to do
+    loop { unsafe { asm!("hlt" :::: "volatile") } }
+}
 
-    impl Selector {
-        pub const fn new(index: u16) -> Self {
-            Selector { bits: index << 3 }
-        }
-        pub const fn from_raw(bits: u16) -> Self {
-            Selector { bits: bits }
-        }
+/// The ports emulators watch for an ACPI shutdown, with the value that
+/// turns them off: newer QEMUs, then Bochs and older QEMUs, then VirtualBox.
+const SHUTDOWN_PORTS: [(u16, u16); 3] = [ (0x604, 0x2000)
+                                        , (0xB004, 0x2000)
+                                        , (0x4004, 0x3400)
+                                        ];
+
+/// Turn the machine off.
+///
+/// For now, this only knows how to turn off an emulator, by writing to the
+/// port its ACPI power management hardware is at. Doing the same on real
+/// hardware needs the `\_S5` sleep type from the DSDT, which we don't parse
+/// (yet), so if none of the ports work, we just halt.
+pub fn shutdown() -> ! {
+    for &(port, value) in SHUTDOWN_PORTS.iter() {
+        unsafe { Port::<u16>::new(port).out16(value) };
     }
+    // if we're still here, nobody was listening
+    halt()
+}
+
+/// Stop this CPU for good.
+///
+/// Interrupts are disabled first, so only an NMI will wake it, and even
+/// then it goes straight back to sleep.
+pub fn halt() -> ! {
+    loop { unsafe { asm!("cli; hlt" :::: "volatile") } }
+}
+
+/// The alignment mask bit in `cr0`
+pub const CR0_AM: u64 = 1 << 18;
+
+/// Returns true if interrupts are enabled (if `rflags.IF` is set)
+#[inline]
+pub fn interrupts_enabled() -> bool {
+    rflags::read_rflags().contains(rflags::IF)
+}
+
+/// Returns the value of the stack pointer.
+///
+/// This is always inlined, so it's the caller's stack pointer (give or take
+/// whatever the caller has pushed since it was entered).
+#[inline(always)]
+pub fn current_rsp() -> usize {
+    let rsp: usize;
+    // `volatile`, so that LLVM doesn't reuse a value read somewhere else,
+    // where the stack pointer might have been different
+    unsafe { asm!("mov $0, rsp" : "=r"(rsp) ::: "intel", "volatile") }
+    rsp
+}
+
+/// Returns the value of the frame pointer, which is the address of the
+/// caller's stack frame if the kernel was built with frame pointers.
+#[inline(always)]
+pub fn current_rbp() -> usize {
+    let rbp: usize;
+    unsafe { asm!("mov $0, rbp" : "=r"(rbp) ::: "intel", "volatile") }
+    rbp
+}
+
+/// Run `f` with interrupts disabled.
+///
+/// If interrupts were enabled beforehand, they're enabled again once `f`
+/// returns; otherwise they're left disabled. This makes it safe to take a
+/// lock that interrupt handlers also take, without deadlocking if an
+/// interrupt arrives while we hold it.
+pub fn without_interrupts<F, R>(f: F) -> R
+where F: FnOnce() -> R {
+    let enabled = interrupts_enabled();
+    unsafe { asm!("cli" :::: "volatile") }
+    let _restore = defer(|| if enabled {
+        unsafe { asm!("sti" :::: "volatile") }
+    });
+    f()
+}
+
+/// Turn on alignment checking.
+///
+/// This sets both `cr0.AM` and `rflags.AC`; once they're both set, an
+/// unaligned memory access raises an alignment check (`#AC`) exception rather
+/// than quietly being slower. Note that the CPU only checks accesses made at
+/// privilege level 3.
+pub unsafe fn enable_alignment_checks() {
+    control_regs::cr0_write(control_regs::cr0_read() | CR0_AM);
+    rflags::write_rflags(rflags::read_rflags() | rflags::AC);
+}
+
+/// Turn off alignment checking.
+pub unsafe fn disable_alignment_checks() {
+    rflags::write_rflags(rflags::read_rflags() - rflags::AC);
+    control_regs::cr0_write(control_regs::cr0_read() & !CR0_AM);
 }
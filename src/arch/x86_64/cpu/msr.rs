@@ -0,0 +1,44 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Model-specific registers.
+//!
+//! Refer to chapter 35 of the _Intel® 64 and IA-32 Architectures Software
+//! Developer’s Manual_ for the list of MSRs.
+
+/// The local APIC's base address and enable bit
+pub const IA32_APIC_BASE: u32 = 0x1B;
+
+/// Read the model-specific register `msr`.
+///
+/// # Unsafe due to
+///   - Reading an MSR that doesn't exist raises a general protection fault
+#[inline]
+pub unsafe fn rdmsr(msr: u32) -> u64 {
+    let (high, low): (u32, u32);
+    asm!(  "rdmsr"
+        :  "={edx}"(high), "={eax}"(low)
+        :  "{ecx}"(msr)
+        :: "intel", "volatile" );
+    ((high as u64) << 32) | low as u64
+}
+
+/// Write `value` to the model-specific register `msr`.
+///
+/// # Unsafe due to
+///   - Writing an MSR that doesn't exist (or a reserved bit of one) raises a
+///     general protection fault
+///   - MSRs control all sorts of CPU behaviour
+#[inline]
+pub unsafe fn wrmsr(msr: u32, value: u64) {
+    asm!(  "wrmsr"
+        :: "{ecx}"(msr)
+         , "{edx}"((value >> 32) as u32)
+         , "{eax}"(value as u32)
+        :: "intel", "volatile" );
+}
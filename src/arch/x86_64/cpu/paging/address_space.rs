@@ -0,0 +1,302 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Address spaces.
+//!
+//! An address space is just a P4 (PML4) table; switching address spaces means
+//! loading a different P4 into `cr3`. Every address space shares the kernel's
+//! mappings, so that the kernel stays mapped no matter which task is running,
+//! while the rest of the P4 is private to the address space.
+use ::memory::{PAddr, VAddr, phys_to_virt};
+use ::memory::frame;
+use alloc::{Allocator, PAGE_SIZE};
+use super::{Entry, EntryFlags, Table, Page, Frame};
+use super::{ADDR_MASK, PRESENT, WRITABLE, HUGE_PAGE};
+use super::super::control_regs;
+
+/// Index of the first P4 entry in the higher half.
+///
+/// Everything from here to the end of the P4 belongs to the kernel.
+pub const KERNEL_P4_START: usize = 256;
+
+/// Returns true if the P4 entry at `index` maps kernel memory.
+///
+/// Besides the higher half (which starts with the direct map of physical
+/// memory), the first P4 entry is shared too: it holds the identity mapping
+/// of the first gigabyte that `boot.asm` sets up, which is where the kernel
+/// image currently lives.
+#[inline]
+fn is_kernel_entry(index: usize) -> bool {
+    index == 0 || index >= KERNEL_P4_START
+}
+
+/// Returns the page table in the frame at `frame`, through the direct map.
+#[inline]
+unsafe fn table_at<'a>(frame: PAddr) -> &'a mut Table {
+    &mut *(phys_to_virt(frame).as_usize() as *mut Table)
+}
+
+/// Reasons `AddressSpace::unmap` might not be able to unmap a page.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum UnmapError { /// Nothing is mapped at that address
+                      NotMapped
+                    , /// The address is in a huge page, which can't be
+                      /// unmapped one page at a time
+                      HugePage
+                    }
+
+/// If the page table that `entry` points at no longer maps anything, free it
+/// and clear `entry`.
+///
+/// `entry` mustn't be under one of the kernel's P4 entries, since those
+/// tables are shared by every address space.
+///
+/// # Returns
+///   - `true` if the table was freed
+unsafe fn free_if_empty(entry: &mut Entry) -> bool {
+    let frame = entry.addr();
+    if !table_at(frame).iter().all(Entry::is_unused) {
+        return false
+    }
+    entry.set_unused();
+    frame::release_frame(frame);
+    true
+}
+
+/// A virtual address space, represented by the frame holding its P4 table.
+pub struct AddressSpace { p4_frame: PAddr }
+
+impl AddressSpace {
+
+    /// Returns the address space that's currently loaded in `cr3`
+    pub fn current() -> Self {
+        let cr3 = unsafe { control_regs::cr3_read() };
+        AddressSpace { p4_frame: PAddr::from_u64(cr3 & ADDR_MASK) }
+    }
+
+    /// Create an `AddressSpace` for an existing P4 table.
+    ///
+    /// # Unsafe due to
+    ///   - The caller must guarantee that `p4_frame` holds a valid P4 table
+    ///     that maps the kernel, or else switching to it will go badly.
+    pub unsafe fn from_p4(p4_frame: PAddr) -> Self {
+        AddressSpace { p4_frame: p4_frame }
+    }
+
+    /// Create a new address space, allocating its P4 table from `alloc`.
+    ///
+    /// The kernel's P4 entries are copied from the current address space, so
+    /// the P3 tables (and everything below them) are shared with it. Since
+    /// only the P4 entries are copied, any mappings the kernel adds later in
+    /// a P4 slot that was unused at this point will not show up here.
+    ///
+    /// # Returns
+    ///   - `Some(AddressSpace)` if a frame for the P4 could be allocated
+    ///   - `None` if the allocator is out of memory
+    pub fn new<A>(alloc: &mut A) -> Option<Self>
+    where A: Allocator {
+        unsafe { alloc.allocate(PAGE_SIZE, PAGE_SIZE) }
+            .map(|frame| {
+                let space = AddressSpace {
+                    p4_frame: PAddr::from_u64(frame as u64)
+                };
+                let current = Self::current();
+                unsafe {
+                    let (new, kernel) = (space.p4(), current.p4());
+                    for (i, entry) in new.iter_mut().enumerate() {
+                        *entry = if is_kernel_entry(i) { kernel[i] }
+                                 else { Entry::unused() };
+                    }
+                }
+                space
+            })
+    }
+
+    /// Returns the physical address of this address space's P4 table
+    #[inline] pub fn p4_frame(&self) -> PAddr { self.p4_frame }
+
+    /// Returns a reference to this address space's P4 table.
+    ///
+    /// Page tables live in the first gigabyte, so they can be reached
+    /// through the direct map.
+    #[inline]
+    unsafe fn p4(&self) -> &mut Table {
+        table_at(self.p4_frame)
+    }
+
+    /// Returns the P1 entry that maps `addr` in this address space.
+    ///
+    /// # Returns
+    ///   - `Some(&mut Entry)` if the P4, P3, and P2 entries for `addr` are all
+    ///     present. The P1 entry itself may or may not be present.
+    ///   - `None` if one of the higher-level tables is missing, or if `addr`
+    ///     is in a huge page (which isn't mapped by a P1 entry at all).
+    pub unsafe fn entry_mut(&self, addr: VAddr) -> Option<&mut Entry> {
+        let addr = addr.as_usize();
+        let mut table = self.p4();
+        // walk down through the P4, P3, and P2 tables
+        for level in (1..4).rev() {
+            let entry = table[(addr >> (12 + 9 * level)) & 0x1ff];
+            let flags = entry.flags();
+            if !flags.contains(PRESENT) || flags.contains(HUGE_PAGE) {
+                return None
+            }
+            table = table_at(entry.addr());
+        }
+        Some(&mut table[(addr >> 12) & 0x1ff])
+    }
+
+    /// Map the page containing `page` to the frame containing `frame`, with
+    /// the given `flags`.
+    ///
+    /// Any missing P3, P2, or P1 tables on the way are allocated from the
+    /// kernel's frame allocator. `PRESENT` is always added to `flags`.
+    ///
+    /// # Returns
+    ///   - `true` if the page was mapped
+    ///   - `false` if a table couldn't be allocated, if `page` is already
+    ///     mapped, or if it's part of a huge page
+    ///   - `false` if `page` isn't canonical; the table indices would just
+    ///     ignore the bits that make it non-canonical, and map some other
+    ///     page instead
+    ///
+    /// # Panics
+    ///   - If `frame` is past the end of physical memory
+    ///
+    /// # Unsafe due to
+    ///   - Mapping a frame that's already in use elsewhere (aliasing)
+    pub unsafe fn map_to(&self, page: VAddr, frame: PAddr, flags: EntryFlags)
+                         -> bool {
+        if !page.is_canonical() {
+            return false
+        }
+        let page = Page::containing(page).start_address();
+        let addr = page.as_usize();
+        let mut table = self.p4();
+        for level in (1..4).rev() {
+            let entry = &mut table[(addr >> (12 + 9 * level)) & 0x1ff];
+            if entry.is_unused() {
+                let new = match frame::allocate_zeroed_frame() {
+                    Some(new) => new
+                  , None => return false
+                };
+                entry.set(new, PRESENT | WRITABLE);
+            } else if entry.flags().contains(HUGE_PAGE) {
+                return false
+            }
+            table = table_at(entry.addr());
+        }
+        let entry = &mut table[(addr >> 12) & 0x1ff];
+        if !entry.is_unused() {
+            return false
+        }
+        entry.set(Frame::containing(frame).start_address(), flags | PRESENT);
+        if self.is_current() {
+            super::flush(page);
+        }
+        true
+    }
+
+    /// Remove the mapping for the page containing `page`.
+    ///
+    /// The frame that was mapped there is handed back rather than freed,
+    /// since it might not be ours to free (device memory, say), and the page
+    /// tables are left alone, even if they're now empty. `unmap_and_free`
+    /// does both.
+    ///
+    /// # Returns
+    ///   - `Ok(PAddr)` with the frame that was mapped there
+    ///   - `Err(UnmapError)` if `page` wasn't mapped (which a non-canonical
+    ///     address never is), or is part of a huge page
+    ///
+    /// # Unsafe due to
+    ///   - Anything still using the page will fault (or worse, if the frame
+    ///     is reused)
+    pub unsafe fn unmap(&self, page: VAddr) -> Result<PAddr, UnmapError> {
+        if !page.is_canonical() {
+            return Err(UnmapError::NotMapped)
+        }
+        let page = Page::containing(page).start_address();
+        let addr = page.as_usize();
+        let mut table = self.p4();
+        for level in (1..4).rev() {
+            let entry = table[(addr >> (12 + 9 * level)) & 0x1ff];
+            let flags = entry.flags();
+            if !flags.contains(PRESENT) {
+                return Err(UnmapError::NotMapped)
+            } else if flags.contains(HUGE_PAGE) {
+                return Err(UnmapError::HugePage)
+            }
+            table = table_at(entry.addr());
+        }
+        let entry = &mut table[(addr >> 12) & 0x1ff];
+        if entry.is_unused() {
+            return Err(UnmapError::NotMapped)
+        }
+        let frame = entry.addr();
+        entry.set_unused();
+        if self.is_current() {
+            super::flush(page);
+        }
+        Ok(frame)
+    }
+
+    /// Unmap the page containing `page`, and release the frame that was
+    /// mapped there.
+    ///
+    /// If the page is in the user half, and that leaves the P1 table that
+    /// mapped it empty, it's freed too, and so is the P2 above it if that's
+    /// then empty. P3 tables are never freed.
+    ///
+    /// Tables under the kernel's P4 entries (`is_kernel_entry`) are never
+    /// freed either, however empty: they're shared by every address space,
+    /// which would be left pointing at freed frames.
+    ///
+    /// # Returns
+    ///   - `Err(UnmapError)` if `page` wasn't mapped, or is part of a huge
+    ///     page
+    ///
+    /// # Unsafe due to
+    ///   - The same things as `unmap`
+    pub unsafe fn unmap_and_free(&self, page: VAddr) -> Result<(), UnmapError> {
+        let frame = try!(self.unmap(page));
+        frame::release_frame(frame);
+        if is_kernel_entry(page.p4_index()) {
+            return Ok(())
+        }
+
+        // `unmap` got through these tables, so they're all present
+        let p3 = table_at(self.p4()[page.p4_index()].addr());
+        let p3_entry = &mut p3[page.p3_index()];
+        let p2 = table_at(p3_entry.addr());
+        if free_if_empty(&mut p2[page.p2_index()]) {
+            free_if_empty(p3_entry);
+            // `invlpg` also drops any cached entries of the freed tables
+            if self.is_current() {
+                super::flush(page);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns true if this address space is the one currently loaded
+    #[inline]
+    pub fn is_current(&self) -> bool {
+        self.p4_frame == Self::current().p4_frame
+    }
+
+    /// Switch to this address space.
+    ///
+    /// Writing `cr3` flushes the TLB, so this does nothing if we're already
+    /// in this address space.
+    pub fn switch_to(&self) {
+        if !self.is_current() {
+            unsafe { control_regs::cr3_write(self.p4_frame.as_u64()) }
+        }
+    }
+}
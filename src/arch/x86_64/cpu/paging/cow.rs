@@ -0,0 +1,100 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Copy-on-write pages.
+//!
+//! A copy-on-write page is mapped read-only, with the `COPY_ON_WRITE` bit set
+//! in one of the PTE bits the CPU ignores. Several mappings may share the
+//! page's frame; the first time one of them is written to, the page fault
+//! handler gives that mapping its own copy of the frame and makes it writable
+//! again. The frame's reference count tells us when only one mapping is left,
+//! in which case that mapping can just take the frame back over.
+use core::ptr;
+use ::memory::{VAddr, phys_to_virt};
+use ::memory::frame;
+use alloc::PAGE_SIZE;
+use super::{AddressSpace, flush, PRESENT, WRITABLE, COPY_ON_WRITE};
+
+impl AddressSpace {
+    /// Mark the page containing `addr` copy-on-write, so that its frame can
+    /// be shared with another mapping.
+    ///
+    /// Marking the page counts one more mapping of its frame: the caller is
+    /// responsible for actually installing that mapping, marked the same way,
+    /// wherever the frame is being shared. Marking a page that's already
+    /// copy-on-write does nothing, so it can't count the same mapping twice;
+    /// to share an already-shared frame with yet another mapping, count that
+    /// one with `frame::share_frame`.
+    ///
+    /// # Returns
+    ///   - `true` if the page is (now) copy-on-write
+    ///   - `false` if `addr` isn't mapped by a present 4 KiB page
+    pub fn mark_copy_on_write(&self, addr: VAddr) -> bool {
+        match unsafe { self.entry_mut(addr) } {
+            Some(entry) if entry.flags().contains(COPY_ON_WRITE) => true
+          , Some(entry) if entry.flags().contains(PRESENT) => {
+                let mut flags = entry.flags();
+                flags.remove(WRITABLE);
+                flags.insert(COPY_ON_WRITE);
+                let frame = entry.addr();
+                entry.set(frame, flags);
+                frame::share_frame(frame);
+                if self.is_current() { unsafe { flush(addr) } }
+                true
+            }
+          , _ => false
+        }
+    }
+}
+
+/// Handle a write fault on a (possibly) copy-on-write page.
+///
+/// This should be called by the page fault handler for page faults caused by
+/// writing to a present page. If the page is copy-on-write, it's made
+/// writable (copying its frame if it's still shared), and the faulting
+/// instruction can just be retried.
+///
+/// The frame allocator's locks are only ever held with interrupts disabled,
+/// and nothing faults while holding one (see `memory::frame`), so a fault
+/// can't arrive while this CPU holds one of them.
+///
+/// # Returns
+///   - `true` if the fault was handled
+///   - `false` if the page wasn't copy-on-write, and this is a real fault
+pub fn handle_cow_fault(addr: VAddr) -> bool {
+    let space = AddressSpace::current();
+    let entry = match unsafe { space.entry_mut(addr) } {
+        Some(entry) if entry.flags().contains(COPY_ON_WRITE) => entry
+      , _ => return false
+    };
+
+    let old_frame = entry.addr();
+    let mut flags = entry.flags();
+    flags.remove(COPY_ON_WRITE);
+    flags.insert(WRITABLE);
+
+    if frame::ref_count(old_frame) <= 1 {
+        // we're the last mapping of this frame, so we can just have it
+        entry.set(old_frame, flags);
+    } else {
+        let new_frame = frame::allocate_frame()
+            .expect("Out of frames while copying a copy-on-write page!");
+        unsafe {
+            ptr::copy_nonoverlapping( phys_to_virt(old_frame).as_usize()
+                                        as *const u8
+                                    , phys_to_virt(new_frame).as_usize()
+                                        as *mut u8
+                                    , PAGE_SIZE );
+        }
+        entry.set(new_frame, flags);
+        frame::release_frame(old_frame);
+    }
+
+    unsafe { flush(addr) }
+    true
+}
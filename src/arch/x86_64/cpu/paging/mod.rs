@@ -0,0 +1,225 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! x86_64 four-level paging.
+
+use ::memory::{PAddr, VAddr, LOWER_HALF_END, HIGHER_HALF_START, PADDR_BITS};
+use alloc::PAGE_SIZE;
+
+pub use self::entry::*;
+pub use self::address_space::{AddressSpace, UnmapError};
+pub use self::cow::handle_cow_fault;
+
+mod address_space;
+mod cow;
+
+/// A page of virtual memory, by number (its address over `PAGE_SIZE`).
+///
+/// Pages can only be made from canonical addresses, and the arithmetic on
+/// them won't go anywhere else: it stops at the ends of whichever half of
+/// the address space the page is in, rather than wrapping around or landing
+/// in the non-canonical hole (or, worse, jumping across it, which would give
+/// a canonical page nowhere near the one we started from).
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Copy, Clone)]
+pub struct Page { number: usize }
+
+/// Number of the first page past the end of the lower half
+const LOWER_HALF_END_PAGE: usize = LOWER_HALF_END / PAGE_SIZE;
+/// Number of the first page in the higher half
+const HIGHER_HALF_START_PAGE: usize = HIGHER_HALF_START / PAGE_SIZE;
+
+impl Page {
+    /// Returns the page containing `addr`.
+    ///
+    /// # Panics
+    ///   - If `addr` isn't canonical
+    #[inline]
+    pub fn containing(addr: VAddr) -> Page {
+        assert!( addr.is_canonical()
+               , "{:?} isn't a canonical address", addr );
+        Page { number: addr.as_usize() / PAGE_SIZE }
+    }
+
+    /// Returns this page's number
+    #[inline] pub fn number(&self) -> usize { self.number }
+
+    /// Returns the address of the start of this page
+    #[inline]
+    pub fn start_address(&self) -> VAddr {
+        VAddr::from_usize(self.number * PAGE_SIZE)
+    }
+
+    /// Returns the page at `number`, if it's in the same half of the address
+    /// space as this one
+    #[inline]
+    fn in_same_half(&self, number: usize) -> Option<Page> {
+        let same = if self.number >= HIGHER_HALF_START_PAGE {
+            number >= HIGHER_HALF_START_PAGE
+        } else {
+            number < LOWER_HALF_END_PAGE
+        };
+        if same { Some(Page { number: number }) } else { None }
+    }
+
+    /// Returns the page `n` pages after this one.
+    ///
+    /// # Returns
+    ///   - `None` if that's past the end of this half of the address space
+    #[inline]
+    pub fn checked_add(&self, n: usize) -> Option<Page> {
+        self.number.checked_add(n).and_then(|number| self.in_same_half(number))
+    }
+
+    /// Returns the page `n` pages before this one.
+    ///
+    /// # Returns
+    ///   - `None` if that's before the start of this half of the address
+    ///     space
+    #[inline]
+    pub fn checked_sub(&self, n: usize) -> Option<Page> {
+        self.number.checked_sub(n).and_then(|number| self.in_same_half(number))
+    }
+
+    /// Returns the next page, unless this is the last one in its half
+    #[inline] pub fn next(&self) -> Option<Page> { self.checked_add(1) }
+
+    /// Returns the previous page, unless this is the first one in its half
+    #[inline] pub fn prev(&self) -> Option<Page> { self.checked_sub(1) }
+}
+
+/// A frame of physical memory, by number (its address over `PAGE_SIZE`).
+///
+/// Like `Page`'s, the arithmetic on these stops at the ends of physical
+/// memory (as far as `PADDR_BITS` can reach), rather than wrapping around.
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Copy, Clone)]
+pub struct Frame { number: u64 }
+
+/// Number of the first frame past the end of physical memory
+const FRAME_END: u64 = (1 << PADDR_BITS) / PAGE_SIZE as u64;
+
+impl Frame {
+    /// Returns the frame containing `addr`.
+    ///
+    /// # Panics
+    ///   - If `addr` has more than `PADDR_BITS` bits
+    #[inline]
+    pub fn containing(addr: PAddr) -> Frame {
+        let number = addr.as_u64() / PAGE_SIZE as u64;
+        assert!( number < FRAME_END
+               , "{:?} is past the end of physical memory", addr );
+        Frame { number: number }
+    }
+
+    /// Returns this frame's number
+    #[inline] pub fn number(&self) -> u64 { self.number }
+
+    /// Returns the address of the start of this frame
+    #[inline]
+    pub fn start_address(&self) -> PAddr {
+        PAddr::from_u64(self.number * PAGE_SIZE as u64)
+    }
+
+    /// Returns the frame `n` frames after this one.
+    ///
+    /// # Returns
+    ///   - `None` if that's past the end of physical memory
+    #[inline]
+    pub fn checked_add(&self, n: u64) -> Option<Frame> {
+        match self.number.checked_add(n) {
+            Some(number) if number < FRAME_END => Some(Frame { number: number })
+          , _ => None
+        }
+    }
+
+    /// Returns the frame `n` frames before this one.
+    ///
+    /// # Returns
+    ///   - `None` if that's before the first frame
+    #[inline]
+    pub fn checked_sub(&self, n: u64) -> Option<Frame> {
+        self.number.checked_sub(n).map(|number| Frame { number: number })
+    }
+
+    /// Returns the next frame, unless this is the last one
+    #[inline] pub fn next(&self) -> Option<Frame> { self.checked_add(1) }
+
+    /// Returns the previous frame, unless this is frame zero
+    #[inline] pub fn prev(&self) -> Option<Frame> { self.checked_sub(1) }
+}
+
+pub const N_ENTRIES: usize = 512;
+
+pub type Table = [Entry; N_ENTRIES];
+
+/// Flush the TLB entry for the page containing `addr`.
+#[inline]
+pub unsafe fn flush(addr: VAddr) {
+    asm!(  "invlpg [$0]"
+        :: "r"(addr.as_usize())
+        :  "memory"
+        :  "intel", "volatile" );
+}
+
+pub mod entry {
+    use ::memory::PAddr;
+
+    /// Mask for the bits of an entry that hold the physical address
+    pub const ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+    #[derive(Copy, Clone)]
+    pub struct Entry(u64);
+
+    bitflags! {
+        flags EntryFlags: u64 {
+            const PRESENT =         1 << 0
+          , const WRITABLE =        1 << 1
+          , const USER_ACCESSIBLE = 1 << 2
+          , const WRITE_THROUGH =   1 << 3
+          , const NO_CACHE =        1 << 4
+          , const ACCESSED =        1 << 5
+          , const DIRTY =           1 << 6
+          , const HUGE_PAGE =       1 << 7
+          , const GLOBAL =          1 << 8
+            // bits 9 - 11 are ignored by the CPU and are ours to use
+          , const COPY_ON_WRITE =   1 << 9
+          , const NO_EXECUTE =      1 << 63
+        }
+    }
+
+    impl Entry {
+        /// Returns a new, unused entry
+        #[inline] pub const fn unused() -> Self { Entry(0) }
+
+        #[inline] pub fn is_unused(&self) -> bool {
+            self.0 == 0
+        }
+        #[inline] pub fn set_unused(&mut self) {
+            self.0 = 0
+        }
+        #[inline] pub fn flags(&self) -> EntryFlags {
+            EntryFlags::from_bits_truncate(self.0)
+        }
+
+        /// Returns the physical address this entry points at
+        #[inline] pub fn addr(&self) -> PAddr {
+            PAddr::from_u64(self.0 & ADDR_MASK)
+        }
+
+        /// Point this entry at `addr`, with the given flags.
+        ///
+        /// # Panics
+        ///   - If `addr` is not page-aligned
+        #[inline] pub fn set(&mut self, addr: PAddr, flags: EntryFlags) {
+            assert!( addr.as_u64() & !ADDR_MASK == 0
+                   , "page table entries must point at page-aligned \
+                      addresses, but {:?} isn't", addr );
+            self.0 = addr.as_u64() | flags.bits();
+        }
+    }
+
+}
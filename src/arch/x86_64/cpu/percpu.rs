@@ -0,0 +1,194 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Per-CPU data.
+//!
+//! Each CPU's `gs` base register points at that CPU's `PerCpu` structure, so
+//! a field can be read with a single `gs`-relative `mov`. Since a single
+//! instruction can't be interrupted halfway through, this is safe even if we
+//! get preempted and moved to another CPU: we just read the field of
+//! whichever CPU we were on when the instruction ran.
+//!
+//! Anything other CPUs can get at, like the scheduler's per-CPU locked run
+//! queue, needs a lock (or atomics) all the same.
+use core::mem;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::sync::atomic::{ATOMIC_BOOL_INIT, ATOMIC_USIZE_INIT};
+use spin::Mutex;
+use task::queue::TaskQueue;
+use super::{msr, gdt};
+use super::gdt::{Gdt, Tss};
+use super::super::acpi::madt::MAX_CPUS;
+
+/// The MSR holding the `gs` segment base
+const IA32_GS_BASE: u32 = 0xC000_0101;
+
+/// Data private to a single CPU.
+///
+/// The layout matters: the assembly in this module reads fields at fixed
+/// offsets from `gs` (`this` at 0, and `current_task` at 16).
+#[repr(C)]
+pub struct PerCpu { /// Pointer to this structure, so we can find it from `gs`
+                    this: *mut PerCpu
+                  , /// This CPU's ID
+                    pub id: usize
+                  , /// Pointer to the task running on this CPU, or null
+                    current_task: usize
+                  , /// Tasks waiting for their turn to run on this CPU.
+                    ///
+                    /// Only this CPU pushes to it, but other CPUs pop from
+                    /// it when they steal work, hence the lock.
+                    pub run_queue: Mutex<TaskQueue>
+                  , /// The task this CPU most recently switched away from,
+                    /// until the scheduler has finished switching
+                    pub prev_task: AtomicUsize
+                  , /// Number of interrupt handlers running on this CPU
+                    /// (more than one, once they nest)
+                    pub irq_depth: AtomicUsize
+                  , /// Bit `n` is set once the handler at depth `n` has
+                    /// acknowledged its interrupt early, with
+                    /// `interrupts::allow_nesting`
+                    pub early_eoi: AtomicUsize
+                  , /// Number of `task::preempt::PreemptGuard`s held on
+                    /// this CPU
+                    pub preempt_count: AtomicUsize
+                  , /// Set when the current task should be preempted as
+                    /// soon as it's safe to
+                    pub need_resched: AtomicBool
+                  , /// This CPU's GDT, with a descriptor for `tss`
+                    gdt: Gdt
+                  , /// This CPU's TSS, with its interrupt stacks
+                    pub tss: Tss
+                  }
+
+impl PerCpu {
+    /// Returns a new `PerCpu` for the CPU numbered `id`
+    pub const fn new(id: usize) -> PerCpu {
+        PerCpu { this: 0 as *mut PerCpu, id: id, current_task: 0
+               , run_queue: Mutex::new(TaskQueue::new())
+               , prev_task: ATOMIC_USIZE_INIT
+               , irq_depth: ATOMIC_USIZE_INIT
+               , early_eoi: ATOMIC_USIZE_INIT
+               , preempt_count: ATOMIC_USIZE_INIT
+               , need_resched: ATOMIC_BOOL_INIT
+               , gdt: Gdt::new()
+               , tss: Tss::new()
+               }
+    }
+}
+
+/// Every CPU's `PerCpu`, indexed by ID, or zero for IDs not in use.
+///
+/// Like `INTERRUPT_COUNTS`, this is only ever accessed as atomics, through
+/// `cpus`.
+static mut CPUS: [usize; MAX_CPUS] = [0; MAX_CPUS];
+
+#[inline]
+fn cpus() -> &'static [AtomicUsize; MAX_CPUS] {
+    unsafe { mem::transmute(&CPUS) }
+}
+
+/// Returns the per-CPU data of the CPU numbered `id`, if it's been set up
+pub fn cpu(id: usize) -> Option<&'static PerCpu> {
+    cpus().get(id)
+          .map(|ptr| ptr.load(Ordering::SeqCst))
+          .and_then(|ptr| unsafe { (ptr as *const PerCpu).as_ref() })
+}
+
+/// The bootstrap processor's per-CPU data
+static mut BSP: PerCpu = PerCpu::new(0);
+
+/// The stack the bootstrap processor handles double faults on
+static mut BSP_DOUBLE_FAULT_STACK: [u8; gdt::IST_STACK_SIZE]
+    = [0; gdt::IST_STACK_SIZE];
+
+/// Whether `gs` has been pointed at a `PerCpu` yet
+static INITIALIZED: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Point this CPU's `gs` base at `cpu`, and switch to `cpu`'s GDT and TSS.
+///
+/// This also makes `cpu` findable by its ID, with `percpu::cpu`.
+///
+/// # Unsafe due to
+///   - `cpu` must not be in use by any other CPU
+///   - Any IST stacks in `cpu.tss` must already be set up
+///
+/// # Panics
+///   - If `cpu.id` isn't less than `MAX_CPUS`
+pub unsafe fn init(cpu: &'static mut PerCpu) {
+    assert!( cpu.id < MAX_CPUS
+           , "CPU {} is past the limit of {} CPUs", cpu.id, MAX_CPUS );
+    cpu.this = cpu as *mut PerCpu;
+    gdt::load(&mut cpu.gdt, &cpu.tss);
+    msr::wrmsr(IA32_GS_BASE, cpu.this as u64);
+    cpus()[cpu.id].store(cpu.this as usize, Ordering::SeqCst);
+    INITIALIZED.store(true, Ordering::SeqCst);
+}
+
+/// Set up per-CPU data for the bootstrap processor.
+pub fn init_bsp() {
+    unsafe {
+        let top = BSP_DOUBLE_FAULT_STACK.as_ptr() as u64
+                + gdt::IST_STACK_SIZE as u64;
+        BSP.tss.set_ist(gdt::DOUBLE_FAULT_IST, top);
+        init(&mut BSP)
+    }
+}
+
+/// Returns true once `gs` points at this CPU's `PerCpu`
+#[inline]
+pub fn is_initialized() -> bool {
+    INITIALIZED.load(Ordering::Relaxed)
+}
+
+/// Returns this CPU's per-CPU data.
+///
+/// # Panics
+///   - If `init` hasn't been called yet
+pub fn current() -> &'static PerCpu {
+    assert!(is_initialized(), "per-CPU data used before percpu::init()!");
+    unsafe {
+        let this: *const PerCpu;
+        asm!(  "mov $0, gs:[0]"
+            :  "=r"(this)
+            ::: "intel", "volatile" );
+        &*this
+    }
+}
+
+/// Returns the current task pointer from this CPU's `PerCpu`.
+///
+/// Before `init`, there's no current task, so this returns null.
+#[inline]
+pub fn current_task() -> usize {
+    if !is_initialized() {
+        return 0
+    }
+    let task: usize;
+    unsafe {
+        asm!(  "mov $0, gs:[16]"
+            :  "=r"(task)
+            ::: "intel", "volatile" );
+    }
+    task
+}
+
+/// Set the current task pointer in this CPU's `PerCpu`.
+///
+/// # Panics
+///   - If `init` hasn't been called yet
+#[inline]
+pub fn set_current_task(task: usize) {
+    assert!(is_initialized(), "per-CPU data used before percpu::init()!");
+    unsafe {
+        asm!(  "mov gs:[16], $0"
+            :: "r"(task)
+            :  "memory"
+            :  "intel", "volatile" );
+    }
+}
@@ -0,0 +1,108 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Hardware random numbers, with `rdrand` and `rdseed`.
+//!
+//! Both instructions set the carry flag if they produced a number, and clear
+//! it (and zero the destination) if the hardware wasn't ready. `rdrand`
+//! reads from a DRBG that's reseeded from the hardware entropy source, and
+//! Intel says ten tries in a row failing means something's broken;
+//! `rdseed` reads the entropy source more or less directly, so it runs dry
+//! much more easily, and the advice is just to pause and try again.
+//!
+//! Refer to the Intel Digital Random Number Generator Software
+//! Implementation Guide, section 5.2.
+use super::cpuid;
+use super::tsc;
+
+/// How many times to try `rdrand` before giving up, as Intel recommends
+const RDRAND_RETRIES: usize = 10;
+
+/// How many times to try `rdseed` before giving up
+const RDSEED_RETRIES: usize = 100;
+
+/// Returns true if this CPU has `rdrand`
+#[inline]
+pub fn has_rdrand() -> bool {
+    cpuid::ecx_features().contains(cpuid::RDRAND)
+}
+
+/// Returns true if this CPU has `rdseed`
+#[inline]
+pub fn has_rdseed() -> bool {
+    cpuid::extended_features().contains(cpuid::RDSEED)
+}
+
+/// Run `rdrand` once.
+///
+/// # Unsafe due to
+///   - `rdrand` is an invalid opcode on CPUs that don't have it
+#[inline]
+unsafe fn rdrand_once() -> Option<u64> {
+    let (value, ok): (u64, u8);
+    asm!(  "rdrand $0
+            setc $1"
+        :  "=r"(value), "=r"(ok)
+        :: "cc"
+        :  "intel", "volatile" );
+    if ok != 0 { Some(value) } else { None }
+}
+
+/// Run `rdseed` once.
+///
+/// # Unsafe due to
+///   - `rdseed` is an invalid opcode on CPUs that don't have it
+#[inline]
+unsafe fn rdseed_once() -> Option<u64> {
+    let (value, ok): (u64, u8);
+    asm!(  "rdseed $0
+            setc $1"
+        :  "=r"(value), "=r"(ok)
+        :: "cc"
+        :  "intel", "volatile" );
+    if ok != 0 { Some(value) } else { None }
+}
+
+/// Returns a random number from `rdrand`.
+///
+/// # Returns
+///   - `None` if the CPU doesn't have `rdrand`, or it failed
+///     `RDRAND_RETRIES` times in a row
+pub fn rdrand() -> Option<u64> {
+    if !has_rdrand() {
+        return None
+    }
+    (0..RDRAND_RETRIES).filter_map(|_| unsafe { rdrand_once() }).next()
+}
+
+/// Returns a random number straight from the entropy source, with
+/// `rdseed`.
+///
+/// # Returns
+///   - `None` if the CPU doesn't have `rdseed`, or it still had nothing
+///     after `RDSEED_RETRIES` tries
+pub fn rdseed() -> Option<u64> {
+    if !has_rdseed() {
+        return None
+    }
+    (0..RDSEED_RETRIES).filter_map(|_| unsafe {
+        rdseed_once().or_else(|| {
+            // give the entropy source a moment to refill
+            super::spin_hint();
+            None
+        })
+    }).next()
+}
+
+/// Returns a seed for a pseudo-random number generator.
+///
+/// This is the best we can get: `rdseed` if the CPU has it, then `rdrand`,
+/// and if neither works, the TSC, which at least differs from boot to boot.
+pub fn seed() -> u64 {
+    rdseed().or_else(rdrand).unwrap_or_else(tsc::rdtsc)
+}
@@ -0,0 +1,90 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! The `rflags` register.
+//!
+//! Refer to section 3.4.3 of the _Intel® 64 and IA-32 Architectures Software
+//! Developer’s Manual_ for what all of these do.
+
+bitflags! {
+    flags RFlags: u64 { /// Carry flag
+                        const CF   = 1 << 0
+                      , /// Parity flag
+                        const PF   = 1 << 2
+                      , /// Auxiliary carry flag
+                        const AF   = 1 << 4
+                      , /// Zero flag
+                        const ZF   = 1 << 6
+                      , /// Sign flag
+                        const SF   = 1 << 7
+                      , /// Trap flag (single-step)
+                        const TF   = 1 << 8
+                      , /// Interrupt enable flag
+                        const IF   = 1 << 9
+                      , /// Direction flag
+                        const DF   = 1 << 10
+                      , /// Overflow flag
+                        const OF   = 1 << 11
+                      , /// Low bit of the I/O privilege level
+                        const IOPL_0 = 1 << 12
+                      , /// High bit of the I/O privilege level
+                        const IOPL_1 = 1 << 13
+                      , /// Nested task flag
+                        const NT   = 1 << 14
+                      , /// Resume flag
+                        const RF   = 1 << 16
+                      , /// Virtual-8086 mode
+                        const VM   = 1 << 17
+                      , /// Alignment check
+                        const AC   = 1 << 18
+                      , /// Virtual interrupt flag
+                        const VIF  = 1 << 19
+                      , /// Virtual interrupt pending
+                        const VIP  = 1 << 20
+                      , /// Able to use `cpuid`
+                        const ID   = 1 << 21
+                      }
+}
+
+/// Bit 1 of `rflags` is reserved, and always set
+const RESERVED_ONE: u64 = 1 << 1;
+
+impl RFlags {
+    /// Returns the I/O privilege level (0 - 3)
+    #[inline]
+    pub fn iopl(&self) -> u8 {
+        ((self.bits >> 12) & 0b11) as u8
+    }
+}
+
+/// Read the current value of `rflags`.
+#[inline]
+pub fn read_rflags() -> RFlags {
+    let bits: u64;
+    unsafe {
+        asm!( "pushfq
+               pop $0"
+            : "=r"(bits) ::: "intel", "volatile" );
+    }
+    RFlags::from_bits_truncate(bits)
+}
+
+/// Write `flags` to `rflags`.
+///
+/// The reserved bit that always has to be set is taken care of.
+///
+/// # Unsafe due to
+///   - This can enable interrupts, single-stepping, and so on
+#[inline]
+pub unsafe fn write_rflags(flags: RFlags) {
+    asm!( "push $0
+           popfq"
+        :: "r"(flags.bits() | RESERVED_ONE)
+        :  "memory", "cc"
+        :  "intel", "volatile" );
+}
@@ -0,0 +1,170 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Segment selectors, and loading them into the segment registers.
+//!
+//! Long mode mostly ignores segmentation, but the CPU still checks the
+//! selectors in `cs` and `ss`, so they have to be reloaded whenever the GDT
+//! they point into is replaced.
+//!
+//! The same goes for the task register, which selects the TSS: it has to be
+//! loaded, with `load_tss`, before the CPU can switch to the stacks the TSS
+//! lists.
+use core::mem;
+use super::DTablePtr;
+
+/// The type of an available 64-bit TSS descriptor
+const TSS_AVAILABLE: u8 = 0x9;
+/// The type of a busy 64-bit TSS descriptor (one that's been loaded)
+const TSS_BUSY: u8 = 0xB;
+
+bitflags! {
+    flags Selector: u16 { const RING_0 = 0b00
+                        , const RING_1 = 0b01
+                        , const RING_2 = 0b10
+                        , const RING_3 = 0b11
+                        , const GDT    = 0 << 2
+                        , const LDT    = 1 << 2
+                        }
+}
+
+impl Selector {
+    /// Returns the selector for entry `index` of the GDT, at ring 0
+    pub const fn new(index: u16) -> Self {
+        Selector { bits: index << 3 }
+    }
+
+    /// Returns the selector with the raw value `bits`, as it would be
+    /// written to a segment register
+    pub const fn from_raw(bits: u16) -> Self {
+        Selector { bits: bits }
+    }
+
+    /// Returns the index of the descriptor this selects
+    #[inline] pub fn index(&self) -> u16 { self.bits >> 3 }
+
+    /// Returns the requested privilege level
+    #[inline] pub fn rpl(&self) -> u16 { self.bits & 0b11 }
+}
+
+/// Returns the selector currently in `cs`
+pub fn cs() -> Selector {
+    let bits: u16;
+    unsafe {
+        asm!("mov $0, cs" : "=r"(bits) ::: "intel");
+    }
+    Selector::from_raw(bits)
+}
+
+/// Load `selector` into `cs`.
+///
+/// `cs` can't be written with a `mov`, and long mode has no far jump to an
+/// immediate address, so this pushes `selector` and the address of the next
+/// instruction and does a far return to them.
+///
+/// # Unsafe due to
+///   - `selector` must select a present, 64-bit code segment in the GDT
+///     that's currently loaded; anything else is a general protection fault
+pub unsafe fn reload_cs(selector: Selector) {
+    asm!(  "pushq $0
+            leaq 1f(%rip), %rax
+            pushq %rax
+            lretq
+            1:"
+        :: "ri"(selector.bits() as u64)
+        :  "rax", "memory"
+        :  "volatile" );
+}
+
+/// Load `selector` into `ds`, `es`, and `ss`.
+///
+/// `fs` and `gs` are left alone: loading a selector into either of them
+/// clears its base, and `gs`'s base is where the per-CPU data is.
+///
+/// # Unsafe due to
+///   - `selector` must select a present, writable data segment in the GDT
+///     that's currently loaded, or be the null selector (which is fine for
+///     `ds` and `es`, and for `ss` at ring 0 in long mode)
+pub unsafe fn reload_data_segments(selector: Selector) {
+    asm!(  "mov ds, $0
+            mov es, $0
+            mov ss, $0"
+        :: "r"(selector.bits())
+        :  "memory"
+        :  "intel", "volatile" );
+}
+
+/// Read back the GDT pointer the CPU is using, with `sgdt`.
+pub fn sgdt() -> DTablePtr<u64> {
+    let mut ptr = DTablePtr { limit: 0, base: 0 as *const u64 };
+    unsafe {
+        asm!(  "sgdt [$0]"
+            :: "r"(&mut ptr)
+            :  "memory"
+            :  "intel", "volatile" );
+    }
+    ptr
+}
+
+/// Returns the first 8 bytes of the GDT descriptor `selector` selects.
+///
+/// # Returns
+///   - `None` if `selector` is the null selector, points into the LDT, or
+///     `len` bytes of descriptor starting there wouldn't fit in the GDT
+fn gdt_descriptor(selector: Selector, len: usize) -> Option<u64> {
+    let gdt = sgdt();
+    let (base, limit) = (gdt.base, gdt.limit);
+    let offset = selector.index() as usize * mem::size_of::<u64>();
+    if selector.index() == 0 || selector.contains(LDT) || base.is_null()
+        || offset + len > limit as usize + 1 {
+        return None
+    }
+    Some(unsafe { *base.offset(selector.index() as isize) })
+}
+
+/// Returns the selector currently in the task register
+pub fn tr() -> Selector {
+    let bits: u16;
+    unsafe { asm!("str $0" : "=r"(bits) ::: "intel") }
+    Selector::from_raw(bits)
+}
+
+/// Load `selector` into the task register, with `ltr`.
+///
+/// This is what tells the CPU where the TSS is, and so where to find the
+/// stacks for interrupts (the IST) and for changes of privilege level. The
+/// CPU marks the TSS's descriptor busy once it's loaded, so a TSS can only
+/// be loaded once (per CPU, and each CPU needs its own).
+///
+/// # Unsafe due to
+///   - The TSS the descriptor points at must stay where it is, and must
+///     stay valid, for as long as it's loaded
+///
+/// # Panics
+///   - If `selector` doesn't select a present, available 64-bit TSS
+///     descriptor in the GDT that's currently loaded
+pub unsafe fn load_tss(selector: Selector) {
+    // TSS descriptors are 16 bytes in long mode
+    let descriptor = gdt_descriptor(selector, 16).unwrap_or_else(||
+        panic!( "can't load TSS selector {:#x}: it isn't in the GDT"
+              , selector.bits() ));
+    let access = (descriptor >> 40) as u8;
+    let (present, ty) = (access & 0x80 != 0, access & 0xF);
+    assert!( present
+           , "can't load TSS selector {:#x}: its descriptor isn't present"
+           , selector.bits() );
+    assert!( ty == TSS_AVAILABLE
+           , "can't load TSS selector {:#x}: its descriptor has type {:#x}, \
+              not an available TSS ({:#x}){}"
+           , selector.bits(), ty, TSS_AVAILABLE
+           , if ty == TSS_BUSY { "; it's already loaded" } else { "" } );
+    asm!(  "ltr $0"
+        :: "r"(selector.bits())
+        :  "memory"
+        :  "intel", "volatile" );
+}
@@ -0,0 +1,180 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Starting the other CPUs.
+//!
+//! Only the bootstrap processor (BSP) runs the bootloader's code; the others
+//! (the application processors, or APs) sit in reset until the BSP wakes them
+//! up with an INIT IPI followed by two startup IPIs. They start in real mode,
+//! at the trampoline in `ap_trampoline.asm`, which takes them to long mode
+//! and then calls `ap_main`.
+//!
+//! Refer to section 8.4 of the _Intel® 64 and IA-32 Architectures Software
+//! Developer’s Manual_, volume 3, for more information.
+use core::{mem, ptr};
+use core::intrinsics::volatile_store;
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use alloc::PAGE_SIZE;
+use ::memory::{PAddr, phys_to_virt, vmalloc};
+use super::super::acpi;
+use super::super::drivers::pit;
+use super::{apic, control_regs, percpu, fpu, gdt, interrupts};
+use super::percpu::PerCpu;
+
+/// The physical address the AP trampoline is copied to.
+///
+/// This must match `TRAMPOLINE_BASE` in `ap_trampoline.asm`, and has to be
+/// kept out of the frame allocator's hands.
+pub const TRAMPOLINE_ADDR: u64 = 0x8000;
+
+/// Size of each AP's stack
+const AP_STACK_SIZE: usize = 4 * PAGE_SIZE;
+
+/// How long to wait for an AP to check in before giving up on it (in ms)
+const STARTUP_TIMEOUT_MS: usize = 100;
+
+extern {
+    /// Start and end of the trampoline code, from `ap_trampoline.asm`
+    static ap_trampoline: u8;
+    static ap_trampoline_end: u8;
+    /// Slots in the trampoline that the BSP fills in for each AP
+    static ap_trampoline_cr3: u8;
+    static ap_trampoline_stack: u8;
+    static ap_trampoline_entry: u8;
+    static ap_trampoline_arg: u8;
+}
+
+/// Number of APs that have made it into `ap_main`
+static CHECKED_IN: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Returns the number of CPUs running (the BSP, and every AP that started)
+#[inline]
+pub fn cpus_online() -> usize {
+    CHECKED_IN.load(Ordering::SeqCst) + 1
+}
+
+/// Returns the address of `sym`'s copy in the copied trampoline.
+unsafe fn trampoline_copy(sym: &u8) -> *mut u8 {
+    let offset = sym as *const u8 as usize
+               - &ap_trampoline as *const u8 as usize;
+    (phys_to_virt(PAddr::from_u64(TRAMPOLINE_ADDR)).as_usize() + offset)
+        as *mut u8
+}
+
+/// Fill in one of the trampoline's slots.
+unsafe fn set_slot(slot: &u8, value: u64) {
+    volatile_store(trampoline_copy(slot) as *mut u64, value)
+}
+
+/// Where the trampoline takes each AP.
+///
+/// This per-CPU setup is the same as the BSP's in `kernel_main`, minus
+/// everything that's shared. `percpu::init` comes first, since it moves us
+/// off the trampoline's GDT, onto this CPU's own GDT and TSS: until then, an
+/// exception would be taken with the trampoline's segments and no IST stack.
+extern "C" fn ap_main(cpu: &'static mut PerCpu) -> ! {
+    unsafe { percpu::init(cpu) };
+    fpu::init_fpu();
+    // every CPU has its own IDT register, but they can share the table
+    if let Some(idt) = interrupts::idt() {
+        unsafe { super::DTable::load(idt) };
+    }
+    CHECKED_IN.fetch_add(1, Ordering::SeqCst);
+    // and now there's nothing for us to do (yet). Interrupts are still
+    // disabled from the trampoline, so this sleeps until an NMI or INIT.
+    loop { unsafe { asm!("hlt" :::: "volatile") } }
+}
+
+/// Wait up to `STARTUP_TIMEOUT_MS` for `CHECKED_IN` to go past `before`.
+fn wait_for_check_in(before: usize) -> bool {
+    for _ in 0..STARTUP_TIMEOUT_MS {
+        if CHECKED_IN.load(Ordering::SeqCst) > before {
+            return true
+        }
+        pit::busy_wait_us(1000);
+    }
+    CHECKED_IN.load(Ordering::SeqCst) > before
+}
+
+/// Start every AP listed in the MADT.
+///
+/// The APs are started one at a time, since they share the trampoline, and
+/// we wait for each one to check in before starting the next, so once this
+/// returns, every AP it counted is running. Each AP gets its own stack,
+/// double fault stack, and `PerCpu`, allocated with `vmalloc`.
+///
+/// This needs the local APIC to have been set up by `apic::init`, and the PIT
+/// for timing. CPUs whose APIC IDs don't fit in a byte are skipped, since we
+/// don't do x2APIC.
+///
+/// # Returns
+///   - The number of APs that were started
+pub fn start_aps() -> usize {
+    if !apic::is_enabled() {
+        return 0
+    }
+    let topology = acpi::cpu_topology();
+    let me = apic::id();
+    unsafe {
+        let start = &ap_trampoline as *const u8;
+        let len = &ap_trampoline_end as *const u8 as usize - start as usize;
+        assert!(len <= PAGE_SIZE, "the AP trampoline doesn't fit in a page!");
+        ptr::copy_nonoverlapping(start, trampoline_copy(&ap_trampoline), len);
+        set_slot(&ap_trampoline_cr3, control_regs::cr3_read());
+        set_slot(&ap_trampoline_entry, ap_main as usize as u64);
+    }
+
+    let mut started = 0;
+    for cpu in topology.cpus().iter()
+                      .filter(|cpu| cpu.enabled && cpu.apic_id != me) {
+        if cpu.apic_id > 0xFF {
+            println!( "warning: can't start CPU with x2APIC ID {}; skipping it"
+                    , cpu.apic_id );
+            continue
+        }
+        let (stack, ist, area) = match ( vmalloc(AP_STACK_SIZE)
+                                       , vmalloc(gdt::IST_STACK_SIZE)
+                                       , vmalloc(mem::size_of::<PerCpu>()) ) {
+            (Some(stack), Some(ist), Some(area)) =>
+                (stack, ist, area as *mut PerCpu)
+          , _ => {
+                println!("warning: out of memory while starting CPUs");
+                break
+            }
+        };
+        let apic_id = cpu.apic_id as u8;
+        let before = CHECKED_IN.load(Ordering::SeqCst);
+        unsafe {
+            ptr::write(area, PerCpu::new(before + 1));
+            (*area).tss.set_ist( gdt::DOUBLE_FAULT_IST
+                               , ist as u64 + gdt::IST_STACK_SIZE as u64 );
+            set_slot(&ap_trampoline_stack, stack as u64 + AP_STACK_SIZE as u64);
+            set_slot(&ap_trampoline_arg, area as u64);
+        }
+
+        apic::send_init(apic_id);
+        pit::busy_wait_us(10_000);
+        let page = (TRAMPOLINE_ADDR >> 12) as u8;
+        apic::send_startup(apic_id, page);
+        pit::busy_wait_us(200);
+        // the second startup IPI is only needed if the first was missed
+        if CHECKED_IN.load(Ordering::SeqCst) == before {
+            apic::send_startup(apic_id, page);
+        }
+
+        if wait_for_check_in(before) {
+            started += 1;
+        } else {
+            // the stacks and `PerCpu` stay allocated, in case it turns up
+            // late and starts using them
+            println!( "warning: CPU with APIC ID {} didn't start", apic_id );
+            break
+        }
+    }
+    started
+}
@@ -0,0 +1,72 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! The time-stamp counter.
+//!
+//! The TSC counts CPU cycles (or, on newer CPUs, ticks at a constant rate),
+//! which makes it a cheap, high-resolution clock once we know its frequency.
+//! We find that out by counting TSC ticks over an interval timed by the PIT.
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use super::super::drivers::pit;
+
+/// How long to count TSC ticks for when calibrating (in milliseconds)
+const CALIBRATION_MS: u64 = 50;
+
+/// The TSC frequency in Hz, or zero if it hasn't been calibrated
+static TSC_HZ: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Read the time-stamp counter.
+#[inline]
+pub fn rdtsc() -> u64 {
+    let (high, low): (u32, u32);
+    unsafe {
+        asm!(  "rdtsc"
+            :  "={edx}"(high), "={eax}"(low)
+            ::: "intel", "volatile" );
+    }
+    ((high as u64) << 32) | low as u64
+}
+
+/// Measure the TSC frequency against the PIT.
+///
+/// This busy-waits for 50 ms with interrupts disabled (so that nothing can
+/// stretch the interval), and remembers the result for `tsc_hz` and
+/// `tsc_to_ns`.
+///
+/// # Returns
+///   - The TSC frequency, in ticks per second
+pub fn calibrate_tsc() -> u64 {
+    let ticks = super::without_interrupts(|| {
+        pit::start_channel2(pit::ms_to_ticks(CALIBRATION_MS) as u16);
+        let start = rdtsc();
+        while !pit::channel2_done() { super::spin_hint() }
+        rdtsc() - start
+    });
+    let hz = ticks * 1000 / CALIBRATION_MS;
+    TSC_HZ.store(hz as usize, Ordering::Relaxed);
+    hz
+}
+
+/// Returns the TSC frequency measured by `calibrate_tsc`, if it's been run
+pub fn tsc_hz() -> Option<u64> {
+    match TSC_HZ.load(Ordering::Relaxed) {
+        0 => None
+      , hz => Some(hz as u64)
+    }
+}
+
+/// Convert a number of TSC ticks to nanoseconds.
+///
+/// # Panics
+///   - If the TSC hasn't been calibrated yet
+pub fn tsc_to_ns(cycles: u64) -> u64 {
+    let hz = tsc_hz().expect("tsc_to_ns() called before calibrate_tsc()!");
+    // split this up so that `cycles * 1e9` can't overflow for large counts
+    (cycles / hz) * 1_000_000_000
+        + (cycles % hz) * 1_000_000_000 / hz
+}
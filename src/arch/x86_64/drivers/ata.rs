@@ -0,0 +1,407 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! ATA PIO disk driver.
+//!
+//! This is about the simplest possible way to talk to a hard disk: we poke
+//! the drive's I/O ports to ask for some sectors using 28-bit LBA
+//! addressing, and then poll the status register until the drive is ready to
+//! hand us the data, a word at a time. It's slow, but it works on basically
+//! everything (including QEMU's emulated IDE controller), so it's good enough
+//! to start building a filesystem on top of.
+//!
+//! Refer to the OS Dev Wiki for more information:
+//! http://wiki.osdev.org/ATA_PIO_Mode
+use core::{fmt, str};
+use super::super::cpu::{self, Port};
+use spin::Mutex;
+
+/// Size of a disk sector (in bytes)
+pub const SECTOR_SIZE: usize = 512;
+
+/// Number of 16-bit words in a disk sector
+const SECTOR_WORDS: usize = SECTOR_SIZE / 2;
+
+/// The largest address that can be expressed using 28-bit LBA
+const MAX_LBA28: u32 = 0x0FFF_FFFF;
+
+/// How many times to poll the status register, waiting for the drive to
+/// stop being busy or to have data for us, before giving up on it. A drive
+/// that's spinning up can take a while, so this is generous: at about a
+/// microsecond per port read, it's around a second.
+const TIMEOUT_POLLS: usize = 1_000_000;
+
+/// Errors that can occur while talking to an ATA drive.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum AtaError { /// No drive is attached at the requested position
+                    NoDrive
+                  , /// The drive exists, but isn't an ATA or ATAPI device
+                    /// (it's probably a SATA device)
+                    NotAta
+                  , /// The drive set the `ERR` bit. This contains the value
+                    /// of the drive's error register.
+                    DeviceError(u8)
+                  , /// The drive set the `DF` (drive fault) bit
+                    DriveFault
+                  , /// The buffer is too small for the requested sectors
+                    BufferTooSmall
+                  , /// The LBA is too large to address with 28-bit LBA
+                    LbaOutOfRange
+                  , /// A transfer of zero sectors was asked for (which the
+                    /// drive would take to mean 256)
+                    NoSectors
+                  , /// The drive didn't finish what it was doing within
+                    /// `TIMEOUT_POLLS` polls; it's probably missing or hung
+                    Timeout
+                  }
+
+/// Commands understood by ATA drives.
+#[repr(u8)]
+#[derive(Eq, PartialEq, Copy, Clone)]
+enum Command { ReadSectors  = 0x20
+             , WriteSectors = 0x30
+             , CacheFlush   = 0xE7
+             , Identify     = 0xEC
+             , IdentifyPacket = 0xA1
+             }
+
+bitflags! {
+    flags Status: u8 { /// An error occurred (see the error register)
+                       const ERR = 1 << 0
+                     , /// The drive has data to transfer, or is ready to
+                       /// accept data
+                       const DRQ = 1 << 3
+                     , /// Overlapped mode service request
+                       const SRV = 1 << 4
+                     , /// Drive fault error (does not set `ERR`)
+                       const DF  = 1 << 5
+                     , /// The drive is spun up and ready
+                       const RDY = 1 << 6
+                     , /// The drive is preparing to send or receive data
+                       const BSY = 1 << 7
+                     }
+}
+
+/// A drive position on an ATA bus.
+#[repr(u8)]
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Drive { Master = 0
+               , Slave  = 1
+               }
+
+impl Drive {
+    /// Returns the value to write to the drive/head register to select this
+    /// drive in LBA mode
+    #[inline]
+    fn select_bits(&self) -> u8 {
+        0xE0 | ((*self as u8) << 4)
+    }
+}
+
+/// What a drive told us about itself in response to IDENTIFY.
+#[derive(Copy, Clone)]
+pub struct DriveInfo { model: [u8; 40]
+                     , serial: [u8; 20]
+                     , /// Number of addressable sectors on the drive
+                       pub sectors: u64
+                     , /// True if the drive supports 48-bit LBA
+                       pub lba48: bool
+                     , /// True if this is an ATAPI (packet) device, like a
+                       /// CD drive, rather than a disk
+                       pub packet: bool
+                     }
+
+/// Copy an IDENTIFY string out of `words` into `buf`.
+///
+/// The drive sends its strings with each pair of characters swapped, so the
+/// high byte of each word comes first.
+fn identify_string(words: &[u16], buf: &mut [u8]) {
+    for (pair, word) in buf.chunks_mut(2).zip(words) {
+        pair[0] = (*word >> 8) as u8;
+        pair[1] = *word as u8;
+    }
+}
+
+/// Returns the text in `bytes`, without the spaces it's padded with.
+fn trim_string(bytes: &[u8]) -> &str {
+    str::from_utf8(bytes).unwrap_or("").trim()
+}
+
+impl DriveInfo {
+    /// Parse the 256 words of IDENTIFY data.
+    fn from_identify(words: &[u16; SECTOR_WORDS], packet: bool) -> DriveInfo {
+        let mut info = DriveInfo { model: [0; 40], serial: [0; 20]
+                                 , sectors: 0
+                                 // word 83, bit 10: 48-bit LBA supported
+                                 , lba48: words[83] & (1 << 10) != 0
+                                 , packet: packet
+                                 };
+        identify_string(&words[10..20], &mut info.serial);
+        identify_string(&words[27..47], &mut info.model);
+        info.sectors = if info.lba48 {
+            // words 100 - 103: total sectors addressable with 48-bit LBA
+            words[100..104].iter().rev()
+                .fold(0, |sectors, &word| sectors << 16 | word as u64)
+        } else {
+            // words 60 - 61: total sectors addressable with 28-bit LBA
+            words[60] as u64 | (words[61] as u64) << 16
+        };
+        info
+    }
+
+    /// Returns the drive's model name
+    #[inline] pub fn model(&self) -> &str { trim_string(&self.model) }
+
+    /// Returns the drive's serial number
+    #[inline] pub fn serial(&self) -> &str { trim_string(&self.serial) }
+
+    /// Returns the size of the drive (in bytes)
+    #[inline]
+    pub fn size(&self) -> u64 { self.sectors * SECTOR_SIZE as u64 }
+}
+
+impl fmt::Debug for DriveInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!( f, "DriveInfo {{ model: {:?}, serial: {:?}, sectors: {}, \
+                    lba48: {}, packet: {} }}"
+              , self.model(), self.serial(), self.sectors, self.lba48
+              , self.packet )
+    }
+}
+
+/// An ATA bus, and the I/O ports used to talk to the drives on it.
+pub struct Bus { data: Port<u16>
+               , error: Port
+               , sector_count: Port
+               , lba_low: Port
+               , lba_mid: Port
+               , lba_high: Port
+               , drive_select: Port
+               , /// Reading this port gives the status; writing to it sends
+                 /// a command
+                 command: Port
+               , /// The alternate status register; reading this doesn't
+                 /// clear any pending interrupts
+                 alt_status: Port
+               }
+
+impl Bus {
+
+    /// The primary ATA bus, at I/O ports `0x1F0` - `0x1F7`.
+    const fn primary() -> Bus {
+        unsafe {
+            Bus { data:         Port::new(0x1F0)
+                , error:        Port::new(0x1F1)
+                , sector_count: Port::new(0x1F2)
+                , lba_low:      Port::new(0x1F3)
+                , lba_mid:      Port::new(0x1F4)
+                , lba_high:     Port::new(0x1F5)
+                , drive_select: Port::new(0x1F6)
+                , command:      Port::new(0x1F7)
+                , alt_status:   Port::new(0x3F6)
+                }
+        }
+    }
+
+    #[inline]
+    fn status(&self) -> Status {
+        Status::from_bits_truncate(unsafe { self.command.in8() })
+    }
+
+    #[inline]
+    fn send_command(&self, command: Command) {
+        unsafe { self.command.out8(command as u8) }
+    }
+
+    /// Wait roughly 400 nanoseconds, by reading the alternate status
+    /// register four times.
+    ///
+    /// The drive needs this long to push its new status onto the bus after
+    /// we select it or send it a command.
+    #[inline]
+    fn delay(&self) {
+        for _ in 0..4 {
+            unsafe { self.alt_status.in8(); }
+        }
+    }
+
+    /// Spin until the drive is no longer busy.
+    ///
+    /// # Returns
+    ///   - `Ok(Status)` with the drive's status, once `BSY` is clear
+    ///   - `Err(AtaError::Timeout)` if it was still busy after
+    ///     `TIMEOUT_POLLS` polls
+    fn wait_not_busy(&self) -> Result<Status, AtaError> {
+        for _ in 0..TIMEOUT_POLLS {
+            let status = self.status();
+            if !status.contains(BSY) { return Ok(status) }
+            cpu::spin_hint();
+        }
+        Err(AtaError::Timeout)
+    }
+
+    /// Spin until the drive is ready to transfer data.
+    ///
+    /// # Returns
+    ///   - `Ok(())` once the drive has set `DRQ`
+    ///   - `Err(AtaError)` if the drive reported an error instead, or
+    ///     didn't get ready within `TIMEOUT_POLLS` polls
+    fn wait_ready(&self) -> Result<(), AtaError> {
+        for _ in 0..TIMEOUT_POLLS {
+            let status = try!(self.wait_not_busy());
+            if status.contains(ERR) {
+                return Err(AtaError::DeviceError(unsafe { self.error.in8() }))
+            } else if status.contains(DF) {
+                return Err(AtaError::DriveFault)
+            } else if status.contains(DRQ) {
+                return Ok(())
+            }
+            cpu::spin_hint();
+        }
+        Err(AtaError::Timeout)
+    }
+
+    /// Select `drive`, and set up the LBA and sector count registers for a
+    /// transfer of `count` sectors starting at `lba`.
+    ///
+    /// A sector count of zero means 256 sectors to the drive, which would
+    /// leave it holding data nobody reads, so we refuse it.
+    fn setup_transfer( &self, drive: Drive, lba: u32, count: u8
+                     , buf_len: usize )
+                     -> Result<(), AtaError> {
+        if count == 0 {
+            return Err(AtaError::NoSectors)
+        }
+        if lba > MAX_LBA28 || lba + count as u32 > MAX_LBA28 + 1 {
+            return Err(AtaError::LbaOutOfRange)
+        }
+        if buf_len < count as usize * SECTOR_SIZE {
+            return Err(AtaError::BufferTooSmall)
+        }
+        unsafe {
+            // the top four bits of the LBA go in the drive select register
+            self.drive_select
+                .out8(drive.select_bits() | ((lba >> 24) as u8 & 0x0F));
+            self.delay();
+            self.sector_count.out8(count);
+            self.lba_low.out8(lba as u8);
+            self.lba_mid.out8((lba >> 8) as u8);
+            self.lba_high.out8((lba >> 16) as u8);
+        }
+        Ok(())
+    }
+
+    /// Identify the given drive on this bus.
+    ///
+    /// ATAPI devices don't answer IDENTIFY, so if the drive turns out to be
+    /// one, it's sent IDENTIFY PACKET DEVICE instead.
+    ///
+    /// # Returns
+    ///   - `Ok(DriveInfo)` describing the drive, if there is one at that
+    ///     position
+    ///   - `Err(AtaError::NoDrive)` if nothing is attached there
+    ///   - `Err(AtaError::NotAta)` if the drive isn't an ATA or ATAPI device
+    pub fn identify(&self, drive: Drive) -> Result<DriveInfo, AtaError> {
+        unsafe {
+            self.drive_select.out8(0xA0 | ((drive as u8) << 4));
+            self.delay();
+            self.sector_count.out8(0);
+            self.lba_low.out8(0);
+            self.lba_mid.out8(0);
+            self.lba_high.out8(0);
+        }
+        self.send_command(Command::Identify);
+
+        // a status of zero means there's no drive at all
+        if self.status().bits() == 0 {
+            return Err(AtaError::NoDrive)
+        }
+
+        try!(self.wait_not_busy());
+        // ATAPI and SATA devices identify themselves by putting a signature
+        // in the LBA registers (rather than following the spec, ugh)
+        let signature = unsafe { (self.lba_mid.in8(), self.lba_high.in8()) };
+        let packet = match signature {
+            (0, 0) => false
+          , (0x14, 0xEB) => {
+                self.send_command(Command::IdentifyPacket);
+                self.delay();
+                true
+            }
+          , _ => return Err(AtaError::NotAta)
+        };
+        try!(self.wait_ready());
+
+        let mut identity = [0u16; SECTOR_WORDS];
+        unsafe { self.data.in16_string(identity.as_mut_ptr(), SECTOR_WORDS); }
+        Ok(DriveInfo::from_identify(&identity, packet))
+    }
+
+    /// Read `count` sectors from `drive`, starting at `lba`, into `buf`.
+    ///
+    /// # Arguments
+    ///   - `drive`: the drive on this bus to read from
+    ///   - `lba`: the 28-bit logical block address of the first sector
+    ///   - `count`: the number of sectors to read (at least one)
+    ///   - `buf`: the buffer to read into; this must be at least
+    ///     `count * SECTOR_SIZE` bytes long
+    pub fn read_sectors( &self, drive: Drive, lba: u32, count: u8
+                       , buf: &mut [u8])
+                       -> Result<(), AtaError> {
+        try!(self.setup_transfer(drive, lba, count, buf.len()));
+        self.send_command(Command::ReadSectors);
+
+        for sector in buf.chunks_mut(SECTOR_SIZE).take(count as usize) {
+            // the drive raises DRQ once for every sector it sends us
+            self.delay();
+            try!(self.wait_ready());
+            unsafe {
+                self.data.in16_string( sector.as_mut_ptr() as *mut u16
+                                     , SECTOR_WORDS );
+            }
+        }
+        Ok(())
+    }
+
+    /// Write `count` sectors from `buf` to `drive`, starting at `lba`.
+    ///
+    /// # Arguments
+    ///   - `drive`: the drive on this bus to write to
+    ///   - `lba`: the 28-bit logical block address of the first sector
+    ///   - `count`: the number of sectors to write (at least one)
+    ///   - `buf`: the data to write; this must be at least
+    ///     `count * SECTOR_SIZE` bytes long
+    pub fn write_sectors( &self, drive: Drive, lba: u32, count: u8
+                        , buf: &[u8])
+                        -> Result<(), AtaError> {
+        try!(self.setup_transfer(drive, lba, count, buf.len()));
+        self.send_command(Command::WriteSectors);
+
+        for sector in buf.chunks(SECTOR_SIZE).take(count as usize) {
+            self.delay();
+            try!(self.wait_ready());
+            unsafe {
+                self.data.out16_string( sector.as_ptr() as *const u16
+                                      , SECTOR_WORDS );
+            }
+        }
+
+        // make sure the drive actually commits the data before we return
+        self.send_command(Command::CacheFlush);
+        let status = try!(self.wait_not_busy());
+        if status.contains(ERR) {
+            Err(AtaError::DeviceError(unsafe { self.error.in8() }))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// The primary ATA bus
+pub static PRIMARY: Mutex<Bus>
+    = Mutex::new(Bus::primary());
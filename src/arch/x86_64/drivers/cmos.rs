@@ -0,0 +1,116 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! CMOS NVRAM access.
+//!
+//! The CMOS is a little battery-backed RAM, accessed by writing a register
+//! number to port `0x70` and then reading or writing port `0x71`. The RTC
+//! lives in the first few registers, and the rest is general-purpose storage
+//! that survives reboots.
+//!
+//! The top bit of the index port doubles as the NMI disable bit, so we set it
+//! for the duration of each access (so an NMI can't leave the CMOS in an
+//! unexpected state) and put it back the way it was afterwards. The index
+//! port can't be read back, so we remember what the bit should be ourselves.
+//!
+//! Refer to the OS Dev Wiki for more information:
+//! http://wiki.osdev.org/CMOS
+use super::super::cpu::{self, Port};
+use spin::Mutex;
+
+/// Number of CMOS registers (the top bit of the index selects NMI masking)
+pub const CMOS_REGISTERS: u8 = 0x80;
+
+/// Setting this bit in the index disables NMIs
+const NMI_DISABLE: u8 = 0x80;
+
+/// Status register D, which is safe to leave selected between accesses
+const STATUS_D: u8 = 0x0D;
+
+struct Cmos { /// Index port, used to select a register
+              index: Port
+            , /// Data port, for reading or writing the selected register
+              data: Port
+            , /// Unused port, for waiting a little between accesses
+              delay: Port
+            , /// Whether NMIs should stay disabled between accesses
+              nmi_disabled: bool
+            }
+
+impl Cmos {
+    /// Give the (often slow) CMOS time to notice that the index changed
+    #[inline]
+    unsafe fn io_delay(&self) { self.delay.out8(0) }
+
+    unsafe fn select(&self, reg: u8) {
+        self.index.out8(NMI_DISABLE | reg);
+        self.io_delay();
+    }
+
+    /// Deselect the register, leaving NMIs as they were before the access
+    unsafe fn deselect(&self) {
+        let nmi = if self.nmi_disabled { NMI_DISABLE } else { 0 };
+        self.index.out8(nmi | STATUS_D);
+        self.io_delay();
+    }
+
+    unsafe fn read(&self, reg: u8) -> u8 {
+        self.select(reg);
+        let value = self.data.in8();
+        self.deselect();
+        value
+    }
+
+    unsafe fn write(&self, reg: u8, value: u8) {
+        self.select(reg);
+        self.data.out8(value);
+        self.io_delay();
+        self.deselect();
+    }
+}
+
+static CMOS: Mutex<Cmos>
+    = Mutex::new(unsafe {
+        Cmos { index: Port::new(0x70)
+             , data: Port::new(0x71)
+             , delay: Port::new(0x80)
+             , nmi_disabled: false
+             }
+    });
+
+/// Read CMOS register `reg`.
+///
+/// # Panics
+///   - If `reg` is not a valid CMOS register
+pub fn cmos_read(reg: u8) -> u8 {
+    assert!(reg < CMOS_REGISTERS, "no such CMOS register {:#x}", reg);
+    // the RTC interrupt handler also talks to the CMOS, so keep it out
+    // while we have a register selected
+    cpu::without_interrupts(|| unsafe { CMOS.lock().read(reg) })
+}
+
+/// Write `value` to CMOS register `reg`.
+///
+/// # Panics
+///   - If `reg` is not a valid CMOS register
+pub fn cmos_write(reg: u8, value: u8) {
+    assert!(reg < CMOS_REGISTERS, "no such CMOS register {:#x}", reg);
+    cpu::without_interrupts(|| unsafe { CMOS.lock().write(reg, value) })
+}
+
+/// Disable (or re-enable) NMIs, using the CMOS index port's top bit.
+///
+/// This stays in effect across later CMOS accesses, which only disable NMIs
+/// for as long as they have a register selected.
+pub fn set_nmi_disabled(disabled: bool) {
+    cpu::without_interrupts(|| unsafe {
+        let mut cmos = CMOS.lock();
+        cmos.nmi_disabled = disabled;
+        cmos.deselect();
+    })
+}
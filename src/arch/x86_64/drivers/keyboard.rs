@@ -1,4 +0,0 @@
-use super::super::cpu::Port;
-
-struct Keyboard { port: Port
-}
@@ -0,0 +1,124 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Which key a scancode set 1 make code is.
+//!
+//! This is just a pair of lookup tables, with no I/O and no state, so it can
+//! be checked against the scancode tables without a keyboard in sight. A
+//! `Key` is a physical key, not a character: which character a key types is
+//! up to the `KeyboardLayout`.
+//!
+//! These would be `const fn`s, but a `const fn` can't `match` (or index an
+//! array) on the compiler we build with.
+//!
+//! Refer to the OS Dev Wiki for the scancode tables:
+//! http://wiki.osdev.org/PS/2_Keyboard#Scan_Code_Set_1
+use self::Key::*;
+
+/// A key on a PC keyboard.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Key { /// One of the keys that types a character, named by the
+               /// (unshifted) character it types on a US QWERTY keyboard.
+               /// Letters are lowercase.
+               Char(u8)
+             , /// The extra key left of `z` on ISO keyboards
+               NonUsBackslash
+             , Escape, Backspace, Tab, Enter, Space
+             , LeftShift, RightShift, LeftCtrl, RightCtrl
+             , LeftAlt, RightAlt
+             , /// The Windows keys
+               LeftGui, RightGui
+             , Menu
+             , CapsLock, NumLock, ScrollLock
+             , /// A function key, from `F(1)` to `F(12)`
+               F(u8)
+             , /// A keypad digit
+               Keypad(u8)
+             , KeypadDot, KeypadPlus, KeypadMinus, KeypadStar, KeypadSlash
+             , KeypadEnter
+             , Up, Down, Left, Right
+             , Home, End, PageUp, PageDown, Insert, Delete
+             , PrintScreen
+             }
+
+/// The key for each set 1 make code without an `0xE0` prefix
+static SET_1: [Option<Key>; 0x59]
+    = [ None,              Some(Escape),      Some(Char(b'1')), Some(Char(b'2'))
+      , Some(Char(b'3')),  Some(Char(b'4')),  Some(Char(b'5')), Some(Char(b'6'))
+      , Some(Char(b'7')),  Some(Char(b'8')),  Some(Char(b'9')), Some(Char(b'0'))
+      , Some(Char(b'-')),  Some(Char(b'=')),  Some(Backspace),  Some(Tab)
+        // 10
+      , Some(Char(b'q')),  Some(Char(b'w')),  Some(Char(b'e')), Some(Char(b'r'))
+      , Some(Char(b't')),  Some(Char(b'y')),  Some(Char(b'u')), Some(Char(b'i'))
+      , Some(Char(b'o')),  Some(Char(b'p')),  Some(Char(b'[')), Some(Char(b']'))
+      , Some(Enter),       Some(LeftCtrl),    Some(Char(b'a')), Some(Char(b's'))
+        // 20
+      , Some(Char(b'd')),  Some(Char(b'f')),  Some(Char(b'g')), Some(Char(b'h'))
+      , Some(Char(b'j')),  Some(Char(b'k')),  Some(Char(b'l')), Some(Char(b';'))
+      , Some(Char(b'\'')), Some(Char(b'`')),  Some(LeftShift),  Some(Char(b'\\'))
+      , Some(Char(b'z')),  Some(Char(b'x')),  Some(Char(b'c')), Some(Char(b'v'))
+        // 30
+      , Some(Char(b'b')),  Some(Char(b'n')),  Some(Char(b'm')), Some(Char(b','))
+      , Some(Char(b'.')),  Some(Char(b'/')),  Some(RightShift), Some(KeypadStar)
+      , Some(LeftAlt),     Some(Space),       Some(CapsLock),   Some(F(1))
+      , Some(F(2)),        Some(F(3)),        Some(F(4)),       Some(F(5))
+        // 40
+      , Some(F(6)),        Some(F(7)),        Some(F(8)),       Some(F(9))
+      , Some(F(10)),       Some(NumLock),     Some(ScrollLock), Some(Keypad(7))
+      , Some(Keypad(8)),   Some(Keypad(9)),  Some(KeypadMinus), Some(Keypad(4))
+      , Some(Keypad(5)),   Some(Keypad(6)),   Some(KeypadPlus), Some(Keypad(1))
+        // 50
+      , Some(Keypad(2)),   Some(Keypad(3)),   Some(Keypad(0)),  Some(KeypadDot)
+      , None,              None,              Some(NonUsBackslash), Some(F(11))
+      , Some(F(12))
+      ];
+
+/// Returns the key whose set 1 make code is `scancode`.
+///
+/// # Returns
+///   - `None` if no key has that code
+pub fn decode_set1(scancode: u8) -> Option<Key> {
+    SET_1.get(scancode as usize).and_then(|key| *key)
+}
+
+/// Returns the key whose set 1 make code is `scancode`, prefixed by `0xE0`.
+///
+/// # Returns
+///   - `None` if no key has that code. This includes the fake shifts
+///     (`E0 2A` and `E0 36`) that some keyboards wrap around the arrow
+///     keys and print screen, which aren't keys at all
+pub fn decode_set1_extended(scancode: u8) -> Option<Key> {
+    match scancode {
+        0x1C => Some(KeypadEnter)
+      , 0x1D => Some(RightCtrl)
+      , 0x35 => Some(KeypadSlash)
+      , 0x37 => Some(PrintScreen)
+      , 0x38 => Some(RightAlt)
+      , 0x47 => Some(Home)
+      , 0x48 => Some(Up)
+      , 0x49 => Some(PageUp)
+      , 0x4B => Some(Left)
+      , 0x4D => Some(Right)
+      , 0x4F => Some(End)
+      , 0x50 => Some(Down)
+      , 0x51 => Some(PageDown)
+      , 0x52 => Some(Insert)
+      , 0x53 => Some(Delete)
+      , 0x5B => Some(LeftGui)
+      , 0x5C => Some(RightGui)
+      , 0x5D => Some(Menu)
+      , _ => None
+    }
+}
+
+/// Returns the key a decoded make code is, whether or not it was extended.
+#[inline]
+pub fn decode(scancode: u8, extended: bool) -> Option<Key> {
+    if extended { decode_set1_extended(scancode) }
+    else { decode_set1(scancode) }
+}
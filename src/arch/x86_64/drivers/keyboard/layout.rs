@@ -0,0 +1,84 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Keyboard layouts.
+//!
+//! A layout translates the scancode of a key into the character printed on
+//! that key. Scancodes identify a physical key position, so the same
+//! scancode means `q` on a QWERTY keyboard and `'` on a Dvorak keyboard.
+
+/// A keyboard layout maps scancode set 1 make codes to ASCII.
+pub trait KeyboardLayout: Sync {
+    /// The human-readable name of this layout
+    fn name(&self) -> &'static str;
+
+    /// Translate a scancode into a character.
+    ///
+    /// # Arguments
+    ///   - `scancode`: the make code of the key that was pressed
+    ///   - `shifted`: whether shift was held down when it was pressed
+    ///
+    /// # Returns
+    ///   - `Some(u8)` containing the ASCII character for that key
+    ///   - `None` if the key doesn't produce a character (e.g. shift)
+    fn translate(&self, scancode: u8, shifted: bool) -> Option<u8>;
+}
+
+/// A keyboard layout described by a pair of lookup tables.
+///
+/// Each table is indexed by scancode, and contains `0` for keys that don't
+/// produce a character.
+pub struct TableLayout { pub name: &'static str
+                       , /// Characters produced when shift is not held
+                         pub unshifted: &'static [u8]
+                       , /// Characters produced when shift is held
+                         pub shifted: &'static [u8]
+                       }
+
+impl KeyboardLayout for TableLayout {
+
+    #[inline] fn name(&self) -> &'static str { self.name }
+
+    fn translate(&self, scancode: u8, shifted: bool) -> Option<u8> {
+        let table = if shifted { self.shifted } else { self.unshifted };
+        match table.get(scancode as usize) {
+            Some(&0) | None => None
+          , Some(&c)        => Some(c)
+        }
+    }
+}
+
+/// The standard US QWERTY layout
+pub static US_QWERTY: TableLayout
+    = TableLayout { name: "US QWERTY"
+                  , unshifted: b"\0\x1b1234567890-=\x08\
+                                 \tqwertyuiop[]\n\
+                                 \0asdfghjkl;'`\
+                                 \0\\zxcvbnm,./\0\
+                                 *\0 "
+                  , shifted: b"\0\x1b!@#$%^&*()_+\x08\
+                               \tQWERTYUIOP{}\n\
+                               \0ASDFGHJKL:\"~\
+                               \0|ZXCVBNM<>?\0\
+                               *\0 "
+                  };
+
+/// The US Dvorak simplified keyboard layout
+pub static DVORAK: TableLayout
+    = TableLayout { name: "US Dvorak"
+                  , unshifted: b"\0\x1b1234567890[]\x08\
+                                 \t',.pyfgcrl/=\n\
+                                 \0aoeuidhtns-`\
+                                 \0\\;qjkxbmwvz\0\
+                                 *\0 "
+                  , shifted: b"\0\x1b!@#$%^&*(){}\x08\
+                               \t\"<>PYFGCRL?+\n\
+                               \0AOEUIDHTNS_~\
+                               \0|:QJKXBMWVZ\0\
+                               *\0 "
+                  };
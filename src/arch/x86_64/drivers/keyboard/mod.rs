@@ -0,0 +1,386 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! PS/2 keyboard driver.
+//!
+//! This decodes scancode set 1 (which the 8042 controller translates
+//! everything into by default) or, after `set_scancode_set(2)`, the keyboard's
+//! own set 2, works out which `Key` each scancode is, and turns key presses
+//! into characters using the current `KeyboardLayout`.
+//!
+//! The keyboard's interrupt handler just reads each byte the controller has
+//! for us and pushes it onto `SCANCODES`, without taking the keyboard lock;
+//! it's decoded later, by whoever polls the keyboard. Polling also reads the
+//! controller directly once `SCANCODES` is empty, so the keyboard still works
+//! before its IRQ is unmasked (or if it never is).
+use super::super::cpu::{self, Port};
+use super::super::cpu::interrupts::pics::{self, IRQ};
+use spin::Mutex;
+use util::RingBuffer;
+
+pub mod key;
+pub mod layout;
+pub mod scancode;
+
+pub use self::key::Key;
+pub use self::layout::{KeyboardLayout, US_QWERTY, DVORAK};
+use self::scancode::Decoder;
+
+/// Bit set in the 8042 status register when there's data to be read
+const OUTPUT_FULL: u8  = 0x01;
+/// Bit set in the 8042 status register while it hasn't yet taken the last
+/// byte we wrote
+const INPUT_FULL: u8   = 0x02;
+
+/// Keyboard command to set the LEDs; the LED bitmask follows it
+const CMD_SET_LEDS: u8 = 0xED;
+/// Keyboard command to pick a scancode set; the set's number follows it
+const CMD_SCANCODE_SET: u8 = 0xF0;
+/// The keyboard's reply when it accepts a command byte
+const ACK: u8          = 0xFA;
+/// The keyboard's reply when it wants the last byte sent again
+const RESEND: u8       = 0xFE;
+
+/// Controller commands to read and write its configuration byte, which is
+/// read from (or then written to) the data port
+const CTRL_READ_CONFIG: u8  = 0x20;
+const CTRL_WRITE_CONFIG: u8 = 0x60;
+/// Configuration bit that has the controller translate set 2 into set 1
+const CONFIG_TRANSLATE: u8  = 1 << 6;
+
+/// LED bits for `CMD_SET_LEDS`
+const LED_SCROLL: u8   = 1 << 0;
+const LED_NUM: u8      = 1 << 1;
+const LED_CAPS: u8     = 1 << 2;
+
+/// How many times to poll the 8042 status register before giving up on it
+const TIMEOUT_SPINS: usize = 100_000;
+/// How many times to send a byte the keyboard asks us to resend
+const MAX_RESENDS: usize = 3;
+
+/// Scancode bytes read by the interrupt handler that haven't been decoded yet
+static SCANCODES: RingBuffer<[u8; 64]> = RingBuffer::new([0; 64]);
+
+/// A key press or release.
+#[derive(Debug, Copy, Clone)]
+pub struct KeyEvent { /// The key's make code (without the break bit)
+                      pub scancode: u8
+                    , /// Whether the key was extended (prefixed by `0xE0`)
+                      pub extended: bool
+                    , /// `true` if the key was pressed, `false` if released
+                      pub pressed: bool
+                    , /// Which key it was, if it's one we know about
+                      pub key: Option<Key>
+                    , /// The character this key produces in the current
+                      /// layout, if any
+                      pub ascii: Option<u8>
+                    }
+
+/// A PS/2 keyboard.
+pub struct Keyboard { /// Port that scancodes are read from
+                      data: Port
+                    , /// The 8042 controller's status port (which it's
+                      /// also sent commands through)
+                      status: Port
+                    , /// The layout used to translate scancodes
+                      layout: &'static KeyboardLayout
+                    , /// Whether either shift key is held down
+                      shift: bool
+                    , caps_lock: bool
+                    , num_lock: bool
+                    , scroll_lock: bool
+                    , /// Decodes scancodes in the set the keyboard is
+                      /// sending, and remembers any prefixes seen so far
+                      decoder: Decoder
+                    }
+
+impl Keyboard {
+    const fn new(layout: &'static KeyboardLayout) -> Self {
+        unsafe {
+            Keyboard { data: Port::new(0x60)
+                     , status: Port::new(0x64)
+                     , layout: layout
+                     , shift: false
+                     , caps_lock: false
+                     , num_lock: false
+                     , scroll_lock: false
+                     , decoder: scancode::SET_1
+                     }
+        }
+    }
+
+    /// Translate a make code into a character using the current layout.
+    fn translate(&self, scancode: u8) -> Option<u8> {
+        // caps lock only affects letters, so we have to check what the key
+        // is in *this* layout before deciding whether it's shifted.
+        let is_letter = self.layout
+                            .translate(scancode, false)
+                            .map_or(false, |c| c >= b'a' && c <= b'z');
+        let shifted = self.shift ^ (self.caps_lock && is_letter);
+        self.layout.translate(scancode, shifted)
+    }
+
+    /// Handle a byte of scancode data from the keyboard.
+    ///
+    /// # Returns
+    ///   - `Some(KeyEvent)` if the byte completed a key press or release
+    ///   - `None` if it was a prefix byte
+    pub fn handle_scancode(&mut self, byte: u8) -> Option<KeyEvent> {
+        let (scancode, extended, pressed) = match self.decoder.feed(byte) {
+            Some(key) => (key.scancode, key.extended, key.pressed)
+          , None => return None
+        };
+
+        let key = key::decode(scancode, extended);
+        let leds_before = (self.caps_lock, self.num_lock, self.scroll_lock);
+        match key {
+            Some(Key::LeftShift) | Some(Key::RightShift) => self.shift = pressed
+          , Some(Key::CapsLock) if pressed => self.caps_lock = !self.caps_lock
+          , Some(Key::NumLock) if pressed => self.num_lock = !self.num_lock
+          , Some(Key::ScrollLock) if pressed =>
+                self.scroll_lock = !self.scroll_lock
+          , _ => { }
+        }
+        let leds = (self.caps_lock, self.num_lock, self.scroll_lock);
+        if leds != leds_before {
+            // if the keyboard doesn't answer, the LEDs are just wrong, which
+            // isn't worth losing the key press over
+            let (caps, num, scroll) = leds;
+            self.set_leds(caps, num, scroll);
+        }
+
+        // extended keys don't produce characters (yet, anyway)
+        let ascii = if pressed && !extended { self.translate(scancode) }
+                    else { None };
+
+        Some(KeyEvent { scancode: scancode
+                      , extended: extended
+                      , pressed: pressed
+                      , key: key
+                      , ascii: ascii
+                      })
+    }
+
+    /// Returns true if the controller has a byte of scancode data for us
+    #[inline]
+    pub fn has_data(&self) -> bool {
+        unsafe { self.status.in8() & OUTPUT_FULL != 0 }
+    }
+
+    /// Decode the next scancode byte, without waiting.
+    ///
+    /// Bytes the interrupt handler has already read come first; once there
+    /// are none left, we check the controller ourselves. This runs with
+    /// interrupts disabled, so the interrupt handler can't read the same
+    /// byte as us, and since updating the LEDs means waiting for the
+    /// keyboard's ACKs, which it would otherwise take for scancodes.
+    ///
+    /// # Returns
+    ///   - `Some(KeyEvent)` if a key was pressed or released
+    ///   - `None` if there was nothing to read (or just a prefix byte)
+    pub fn poll(&mut self) -> Option<KeyEvent> {
+        cpu::without_interrupts(|| {
+            let byte = SCANCODES.try_pop().or_else(|| {
+                if self.has_data() { Some(unsafe { self.data.in8() }) }
+                else { None }
+            });
+            byte.and_then(|byte| self.handle_scancode(byte))
+        })
+    }
+
+    /// Throw away any scancode data waiting in the controller, or read by
+    /// the interrupt handler but not yet decoded.
+    ///
+    /// The decoder is reset as well, since whatever it was partway through
+    /// won't be finished now.
+    ///
+    /// # Returns
+    ///   - The number of bytes thrown away
+    pub fn discard_input(&mut self) -> usize {
+        let mut discarded = 0;
+        while SCANCODES.try_pop().is_some() {
+            discarded += 1;
+        }
+        // the keyboard might keep sending, so don't wait forever for it to
+        // stop
+        while discarded < TIMEOUT_SPINS && self.has_data() {
+            unsafe { self.data.in8() };
+            discarded += 1;
+        }
+        self.decoder.reset();
+        discarded
+    }
+
+    /// Wait for the status register to have `bit` equal to `set`.
+    ///
+    /// # Returns
+    ///   - `false` if it still wasn't after `TIMEOUT_SPINS` polls
+    fn wait_status(&self, bit: u8, set: bool) -> bool {
+        (0..TIMEOUT_SPINS).any(|_| unsafe {
+            let done = (self.status.in8() & bit != 0) == set;
+            if !done { cpu::spin_hint() }
+            done
+        })
+    }
+
+    /// Send a byte to the keyboard and wait for it to acknowledge it.
+    ///
+    /// # Returns
+    ///   - `true` if the keyboard replied with an ACK
+    ///   - `false` if the controller or the keyboard timed out, the keyboard
+    ///     replied with something else, or it kept asking for a resend
+    fn send(&self, byte: u8) -> bool {
+        for _ in 0..MAX_RESENDS {
+            if !self.wait_status(INPUT_FULL, false) { return false }
+            unsafe { self.data.out8(byte) };
+            if !self.wait_status(OUTPUT_FULL, true) { return false }
+            match unsafe { self.data.in8() } {
+                ACK => return true
+              , RESEND => continue
+              , _ => return false
+            }
+        }
+        false
+    }
+
+    /// Turn the caps lock, num lock, and scroll lock LEDs on or off.
+    ///
+    /// This polls for the keyboard's ACKs, giving up if it doesn't answer
+    /// within `TIMEOUT_SPINS` polls, so it never hangs on a missing or
+    /// unresponsive keyboard. It should be called with interrupts disabled
+    /// (or from the keyboard's interrupt handler), so that the ACK isn't
+    /// read as a scancode by someone else.
+    ///
+    /// # Returns
+    ///   - `true` if the keyboard acknowledged the new LED state
+    pub fn set_leds(&mut self, caps: bool, num: bool, scroll: bool) -> bool {
+        let mut mask = 0;
+        if caps { mask |= LED_CAPS }
+        if num { mask |= LED_NUM }
+        if scroll { mask |= LED_SCROLL }
+        self.send(CMD_SET_LEDS) && self.send(mask)
+    }
+
+    /// Send a command to the 8042 controller.
+    ///
+    /// # Returns
+    ///   - `false` if the controller didn't take it in time
+    fn controller_command(&self, command: u8) -> bool {
+        if !self.wait_status(INPUT_FULL, false) { return false }
+        unsafe { self.status.out8(command) };
+        true
+    }
+
+    /// Turn the controller's translation of set 2 into set 1 on or off.
+    ///
+    /// # Returns
+    ///   - `false` if the controller timed out
+    fn set_translation(&self, on: bool) -> bool {
+        if !self.controller_command(CTRL_READ_CONFIG)
+            || !self.wait_status(OUTPUT_FULL, true) {
+            return false
+        }
+        let config = unsafe { self.data.in8() };
+        let config = if on { config | CONFIG_TRANSLATE }
+                     else { config & !CONFIG_TRANSLATE };
+        if !self.controller_command(CTRL_WRITE_CONFIG)
+            || !self.wait_status(INPUT_FULL, false) {
+            return false
+        }
+        unsafe { self.data.out8(config) };
+        true
+    }
+
+    /// Switch to scancode set `set` (1 or 2).
+    ///
+    /// Either way, the keyboard is told to send set 2, which every PS/2
+    /// keyboard supports (set 1 support is patchier). What changes is
+    /// whether the controller translates that into set 1 for us, or we
+    /// decode it ourselves; the latter helps on machines where translation
+    /// is broken or missing (some USB legacy emulation, for instance).
+    ///
+    /// Like `set_leds`, this polls for the keyboard's replies, so it should
+    /// be called with interrupts disabled.
+    ///
+    /// # Returns
+    ///   - `true` if the keyboard and the controller took the new set
+    ///   - `false` if `set` isn't 1 or 2, or something didn't answer, in
+    ///     which case we carry on decoding the old set
+    pub fn set_scancode_set(&mut self, set: u8) -> bool {
+        let decoder = match set {
+            1 => scancode::SET_1
+          , 2 => scancode::SET_2
+          , _ => return false
+        };
+        if self.send(CMD_SCANCODE_SET) && self.send(2)
+            && self.set_translation(set == 1) {
+            self.decoder = decoder;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns the scancode set currently being decoded (1 or 2)
+    #[inline] pub fn scancode_set(&self) -> u8 { self.decoder.set() }
+
+    /// Returns the layout currently being used to translate keys.
+    #[inline] pub fn layout(&self) -> &'static KeyboardLayout { self.layout }
+}
+
+/// The system's PS/2 keyboard
+pub static KEYBOARD: Mutex<Keyboard>
+    = Mutex::new(Keyboard::new(&US_QWERTY));
+
+/// Change the keyboard layout.
+///
+/// The default layout is `US_QWERTY`.
+pub fn set_layout(layout: &'static KeyboardLayout) {
+    KEYBOARD.lock().layout = layout;
+}
+
+/// Unmask the keyboard's IRQ, throwing away whatever it sent while it was
+/// masked (or before we were listening at all), so that the first keyboard
+/// interrupt is for a key pressed from now on.
+///
+/// # Returns
+///   - `true` if there was a stale keyboard interrupt to get rid of
+pub fn unmask_irq() -> bool {
+    pics::unmask_irq_drained(IRQ::PS2Keyboard, || {
+        KEYBOARD.lock().discard_input();
+    })
+}
+
+/// Returns true if there are scancode bytes waiting to be decoded by `poll`,
+/// either read by the interrupt handler or still in the controller
+pub fn has_input() -> bool {
+    let status: Port = unsafe { Port::new(0x64) };
+    !SCANCODES.is_empty() || unsafe { status.in8() } & OUTPUT_FULL != 0
+}
+
+/// Handle the keyboard's interrupt: read the byte the controller has for us
+/// and save it for `poll`.
+///
+/// This doesn't take the keyboard lock, so it can't be kept waiting by
+/// whoever is decoding. If nobody has been decoding, and `SCANCODES` is
+/// full, the byte is dropped.
+pub fn handle_irq() {
+    let (data, status): (Port, Port)
+        = unsafe { (Port::new(0x60), Port::new(0x64)) };
+    // by the time we get here, the byte may already have been read (as a
+    // reply to a command, say), and reading again would repeat it
+    if unsafe { status.in8() } & OUTPUT_FULL != 0 {
+        let _ = SCANCODES.try_push(unsafe { data.in8() });
+    }
+}
+
+/// Switch the keyboard to scancode set `set`; see
+/// `Keyboard::set_scancode_set`.
+pub fn set_scancode_set(set: u8) -> bool {
+    cpu::without_interrupts(|| KEYBOARD.lock().set_scancode_set(set))
+}
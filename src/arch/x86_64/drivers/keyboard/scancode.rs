@@ -0,0 +1,182 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Decoding scancode sets 1 and 2.
+//!
+//! A key press or release arrives as one or more bytes, and how they're
+//! encoded depends on the scancode set:
+//!
+//!   - In set 1, a release is the same byte as the press with the high bit
+//!     set, and extended keys are prefixed by `0xE0`.
+//!   - In set 2, a release is the same byte as the press, prefixed by
+//!     `0xF0`; extended keys are prefixed by `0xE0` (before the `0xF0`, for
+//!     a release). The codes themselves are different from set 1's, too.
+//!
+//! Either way, the `Decoder` hands back the key's set 1 make code, since
+//! that's what the `KeyboardLayout`s are written in terms of.
+//!
+//! Refer to the OS Dev Wiki for the scancode tables:
+//! http://wiki.osdev.org/PS/2_Keyboard#Scan_Code_Sets
+
+/// Prefix for the extended keys (arrows, right ctrl, etc.), in both sets
+const EXTENDED: u8 = 0xE0;
+
+/// Set 1: bit set in a scancode when the key is being released
+const BREAK_BIT: u8 = 0x80;
+
+/// Set 2: prefix for a key being released
+const BREAK_PREFIX: u8 = 0xF0;
+
+/// Set 2: prefix for the pause key, which sends `E1 14 77 E1 F0 14 F0 77`
+/// when it's pressed and nothing when it's released
+const PAUSE_PREFIX: u8 = 0xE1;
+/// Number of bytes in the pause key's sequence
+const PAUSE_LEN: u8 = 8;
+
+/// The set 1 make code for each set 2 make code, or 0 if there isn't a key
+/// with that code.
+///
+/// Extended keys use the same table: in both sets, nearly every extended
+/// key has the code of the keypad key it duplicates (up is `E0 75` in set 2
+/// and `E0 48` in set 1, and keypad 8 is `75` and `48`). The exceptions are
+/// the Windows and menu keys, which only exist as extended keys, and go in
+/// otherwise unused slots (`1F`, `27`, and `2F`).
+static SET_2_TO_SET_1: [u8; 0x84]
+    = [ 0x00, 0x43, 0x00, 0x3F, 0x3D, 0x3B, 0x3C, 0x58   // 00
+      , 0x00, 0x44, 0x42, 0x40, 0x3E, 0x0F, 0x29, 0x00
+      , 0x00, 0x38, 0x2A, 0x00, 0x1D, 0x10, 0x02, 0x00   // 10
+      , 0x00, 0x00, 0x2C, 0x1F, 0x1E, 0x11, 0x03, 0x5B
+      , 0x00, 0x2E, 0x2D, 0x20, 0x12, 0x05, 0x04, 0x5C   // 20
+      , 0x00, 0x39, 0x2F, 0x21, 0x14, 0x13, 0x06, 0x5D
+      , 0x00, 0x31, 0x30, 0x23, 0x22, 0x15, 0x07, 0x00   // 30
+      , 0x00, 0x00, 0x32, 0x24, 0x16, 0x08, 0x09, 0x00
+      , 0x00, 0x33, 0x25, 0x17, 0x18, 0x0B, 0x0A, 0x00   // 40
+      , 0x00, 0x34, 0x35, 0x26, 0x27, 0x19, 0x0C, 0x00
+      , 0x00, 0x00, 0x28, 0x00, 0x1A, 0x0D, 0x00, 0x00   // 50
+      , 0x3A, 0x36, 0x1C, 0x1B, 0x00, 0x2B, 0x00, 0x00
+      , 0x00, 0x56, 0x00, 0x00, 0x00, 0x00, 0x0E, 0x00   // 60
+      , 0x00, 0x4F, 0x00, 0x4B, 0x47, 0x00, 0x00, 0x00
+      , 0x52, 0x53, 0x50, 0x4C, 0x4D, 0x48, 0x01, 0x45   // 70
+      , 0x57, 0x4E, 0x51, 0x4A, 0x37, 0x49, 0x46, 0x00
+      , 0x00, 0x00, 0x00, 0x41                           // 80
+      ];
+
+/// Returns the set 1 make code for the set 2 make code `code`
+fn set_2_to_set_1(code: u8) -> Option<u8> {
+    match SET_2_TO_SET_1.get(code as usize) {
+        Some(&0) | None => None
+      , Some(&c)        => Some(c)
+    }
+}
+
+/// A decoded key press or release.
+#[derive(Debug, Copy, Clone)]
+pub struct RawKey { /// The key's set 1 make code
+                    pub scancode: u8
+                  , /// Whether the key was extended (prefixed by `0xE0`)
+                    pub extended: bool
+                  , /// `true` if the key was pressed, `false` if released
+                    pub pressed: bool
+                  }
+
+/// Where a set 2 decoder is in a multi-byte sequence.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Set2State { /// Waiting for the first byte of a sequence
+                     Start
+                   , /// After `0xE0`
+                     Extended
+                   , /// After `0xF0`
+                     Break
+                   , /// After `0xE0 0xF0`
+                     ExtendedBreak
+                   , /// Skipping the rest of the pause key's sequence; this
+                     /// many bytes to go
+                     Pause(u8)
+                   }
+
+/// A scancode decoder, for one scancode set or the other.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Decoder { /// Set 1, and whether the last byte was `0xE0`
+                   Set1 { extended: bool }
+                 , Set2(Set2State)
+                 }
+
+/// A set 1 decoder, at the start of a sequence
+pub const SET_1: Decoder = Decoder::Set1 { extended: false };
+/// A set 2 decoder, at the start of a sequence
+pub const SET_2: Decoder = Decoder::Set2(Set2State::Start);
+
+impl Decoder {
+    /// Returns the number of the scancode set this decodes
+    pub fn set(&self) -> u8 {
+        match *self {
+            Decoder::Set1 { .. } => 1
+          , Decoder::Set2(_) => 2
+        }
+    }
+
+    /// Forget about any sequence that's been started, as though the last
+    /// byte had completed one.
+    pub fn reset(&mut self) {
+        *self = match *self {
+            Decoder::Set1 { .. } => SET_1
+          , Decoder::Set2(_) => SET_2
+        }
+    }
+
+    /// Decode a byte of scancode data.
+    ///
+    /// # Returns
+    ///   - `Some(RawKey)` if the byte completed a key press or release
+    ///   - `None` if it was part of a longer sequence, or completed a
+    ///     sequence that doesn't correspond to a key we know about
+    pub fn feed(&mut self, byte: u8) -> Option<RawKey> {
+        match *self {
+            Decoder::Set1 { ref mut extended } =>
+                if byte == EXTENDED {
+                    *extended = true;
+                    None
+                } else {
+                    let key = RawKey { scancode: byte & !BREAK_BIT
+                                     , extended: *extended
+                                     , pressed: byte & BREAK_BIT == 0
+                                     };
+                    *extended = false;
+                    Some(key)
+                }
+          , Decoder::Set2(ref mut state) => feed_set_2(state, byte)
+        }
+    }
+}
+
+/// Run one byte through the set 2 state machine.
+fn feed_set_2(state: &mut Set2State, byte: u8) -> Option<RawKey> {
+    use self::Set2State::*;
+    let (extended, pressed) = match (*state, byte) {
+        (Pause(left), _) => {
+            *state = if left > 1 { Pause(left - 1) } else { Start };
+            return None
+        }
+      , (Start, PAUSE_PREFIX) => {
+            *state = Pause(PAUSE_LEN - 1);
+            return None
+        }
+      , (Start, EXTENDED) => { *state = Extended; return None }
+      , (Start, BREAK_PREFIX) => { *state = Break; return None }
+      , (Extended, BREAK_PREFIX) => { *state = ExtendedBreak; return None }
+      , (Start, _) => (false, true)
+      , (Extended, _) => (true, true)
+      , (Break, _) => (false, false)
+      , (ExtendedBreak, _) => (true, false)
+    };
+    *state = Start;
+    set_2_to_set_1(byte).map(|scancode| RawKey { scancode: scancode
+                                                , extended: extended
+                                                , pressed: pressed
+                                                })
+}
@@ -1 +1,6 @@
 pub mod keyboard;
+pub mod ata;
+pub mod cmos;
+pub mod pit;
+pub mod serial;
+pub mod qemu;
@@ -0,0 +1,120 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Intel 8253/8254 Programmable Interval Timer.
+//!
+//! The PIT has three channels counting down at a fixed frequency. Channel 0
+//! is wired to IRQ 0 (the system timer), and channel 2 to the PC speaker.
+//! Since channel 2's gate and output can be controlled and read through port
+//! `0x61`, it's handy for timing short intervals without any interrupts.
+//!
+//! Refer to the OS Dev Wiki for more information:
+//! http://wiki.osdev.org/Programmable_Interval_Timer
+use super::super::cpu::{self, Port};
+
+/// The frequency the PIT counts at (in Hz)
+pub const PIT_FREQUENCY: u64 = 1_193_182;
+
+/// Channel `n`'s data port is `CHANNEL_0 + n`
+const CHANNEL_0: u16 = 0x40;
+const COMMAND: u16 = 0x43;
+/// The keyboard controller's port B, which controls channel 2's gate
+const PORT_B: u16 = 0x61;
+
+/// Port B bit that enables channel 2 counting
+const GATE_2: u8 = 1 << 0;
+/// Port B bit that connects channel 2 to the speaker
+const SPEAKER: u8 = 1 << 1;
+/// Port B bit that reflects channel 2's output
+const OUT_2: u8 = 1 << 5;
+
+// The command byte is laid out as `cc aa mmm b`:
+//   - `cc` selects the channel (0 - 2)
+//   - `aa` is the access mode; we always use `11`, lobyte then hibyte
+//   - `mmm` is the operating mode
+//   - `b` selects BCD counting, which we never want
+/// Access mode: write the low byte of the count, then the high byte
+const LOHI: u8 = 0b11 << 4;
+/// Mode 0: interrupt on terminal count (a one-shot countdown)
+const MODE_ONESHOT: u8 = 0b000 << 1;
+/// Mode 2: rate generator (periodic)
+const MODE_RATE: u8 = 0b010 << 1;
+
+/// Returns the command byte to program `channel` in `mode`
+#[inline]
+const fn command(channel: u8, mode: u8) -> u8 {
+    (channel << 6) | LOHI | mode
+}
+
+/// Program `channel`'s mode and then load `count` into it.
+unsafe fn program(channel: u8, mode: u8, count: u16) {
+    Port::<u8>::new(COMMAND).out8(command(channel, mode));
+    let data: Port = Port::new(CHANNEL_0 + channel as u16);
+    data.out8(count as u8);
+    data.out8((count >> 8) as u8);
+}
+
+/// Fire IRQ 0 once, `count` PIT ticks from now.
+///
+/// This puts channel 0 in mode 0, so the system timer stops ticking
+/// periodically until `set_periodic` is called.
+pub fn pit_oneshot(count: u16) {
+    unsafe { program(0, MODE_ONESHOT, count) }
+}
+
+/// Make IRQ 0 fire every `count` PIT ticks (this is how the BIOS leaves it,
+/// with `count` = 0, meaning 65536).
+pub fn set_periodic(count: u16) {
+    unsafe { program(0, MODE_RATE, count) }
+}
+
+/// Start channel 2 counting down `count` PIT ticks.
+///
+/// Channel 2's output goes high once the count reaches zero, which can be
+/// checked with `channel2_done`. The speaker is disconnected, so this is
+/// silent.
+pub fn start_channel2(count: u16) {
+    unsafe {
+        let port_b: Port = Port::new(PORT_B);
+        // disable the gate (and the speaker) while we set up the count
+        let b = port_b.in8() & !(GATE_2 | SPEAKER);
+        port_b.out8(b);
+
+        program(2, MODE_ONESHOT, count);
+
+        // and now start counting
+        port_b.out8(b | GATE_2);
+    }
+}
+
+/// Returns true once channel 2 has finished counting down
+#[inline]
+pub fn channel2_done() -> bool {
+    unsafe { Port::<u8>::new(PORT_B).in8() & OUT_2 != 0 }
+}
+
+/// Returns the number of PIT ticks in `ms` milliseconds
+#[inline]
+pub fn ms_to_ticks(ms: u64) -> u64 {
+    PIT_FREQUENCY * ms / 1000
+}
+
+/// Spin for `micros` microseconds, timed by channel 2.
+///
+/// This doesn't need interrupts, so it can be used for device timing during
+/// early boot. Since channel 2 can count at most 65535 ticks (about 55 ms) at
+/// a time, longer waits are broken up into several countdowns.
+pub fn busy_wait_us(micros: u64) {
+    let mut ticks = PIT_FREQUENCY * micros / 1_000_000;
+    while ticks > 0 {
+        let chunk = if ticks > 0xffff { 0xffff } else { ticks };
+        start_channel2(chunk as u16);
+        while !channel2_done() { cpu::spin_hint() }
+        ticks -= chunk;
+    }
+}
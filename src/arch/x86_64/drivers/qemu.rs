@@ -0,0 +1,40 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! QEMU's `isa-debug-exit` device, for ending a test run.
+//!
+//! When QEMU is started with `-device isa-debug-exit,iobase=0xf4,iosize=0x04`,
+//! writing a value `v` to port `0xf4` makes QEMU exit with status
+//! `(v << 1) | 1`. That's how `make test` finds out whether the kernel's self
+//! tests passed. On anything other than QEMU (or without the device), the
+//! write does nothing, and `exit` just halts.
+use super::super::cpu::{self, Port};
+
+/// The I/O port the exit device is set up at
+pub const EXIT_PORT: u16 = 0xf4;
+
+/// What to tell QEMU about the run.
+///
+/// The values are arbitrary, except that they can't be 0: QEMU exits with
+/// status 1 for a write of 0, which is the same status it exits with for
+/// all sorts of other reasons.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u32)]
+pub enum ExitCode { /// QEMU exits with status 33
+                    Success = 0x10
+                  , /// QEMU exits with status 35
+                    Failure = 0x11
+                  }
+
+/// Make QEMU exit, with a status that says how the run went.
+///
+/// If we aren't in QEMU, this halts the CPU instead.
+pub fn exit(code: ExitCode) -> ! {
+    unsafe { Port::<u32>::new(EXIT_PORT).out32(code as u32) };
+    cpu::halt()
+}
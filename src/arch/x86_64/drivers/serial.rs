@@ -0,0 +1,121 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! A 16550 UART serial port, for output.
+//!
+//! Not every machine has a serial port, and writing to one that isn't there
+//! waits forever for the transmitter to empty, so `init` checks for the UART
+//! first (by writing to its scratch register and reading it back), and a
+//! port that wasn't found quietly ignores everything written to it.
+//!
+//! Refer to the OS Dev Wiki for more information:
+//! http://wiki.osdev.org/Serial_Ports
+use core::fmt;
+use spin::Mutex;
+use super::super::cpu::{self, Port};
+
+/// I/O port base of the first serial port
+pub const COM1_BASE: u16 = 0x3F8;
+
+/// Value for the divisor latch; the UART's clock is 115200 Hz, so this gives
+/// 38400 baud
+const BAUD_DIVISOR: u16 = 3;
+
+/// "Transmitter holding register empty" bit of the line status register
+const LSR_THR_EMPTY: u8 = 1 << 5;
+
+/// How many times to check the line status before giving up on a byte.
+///
+/// A UART that stops draining is as good as absent; better to drop output
+/// than to hang the kernel over it.
+const TRANSMIT_TRIES: usize = 100_000;
+
+/// A serial port.
+pub struct Serial { /// The I/O port the UART's registers start at
+                    base: u16
+                  , /// Whether `init` found a UART there
+                    present: bool
+                  }
+
+impl Serial {
+    /// Returns the serial port at `base`, which won't be used until `init`
+    /// has found it
+    pub const fn new(base: u16) -> Serial {
+        Serial { base: base, present: false }
+    }
+
+    /// Returns the register at `offset` from `base`
+    #[inline]
+    fn reg(&self, offset: u16) -> Port {
+        unsafe { Port::new(self.base + offset) }
+    }
+
+    /// Look for the UART, and set it up if it's there.
+    ///
+    /// The port is checked by writing two values to the scratch register
+    /// and reading them back; reading nothing there (`0xFF`, usually) means
+    /// there's no UART.
+    ///
+    /// # Returns
+    ///   - `true` if the port is present, and ready for output
+    pub fn init(&mut self) -> bool {
+        let scratch = self.reg(7);
+        self.present = unsafe {
+            [0xA5u8, 0x5A].iter().all(|&probe| {
+                scratch.out8(probe);
+                scratch.in8() == probe
+            })
+        };
+        if self.present {
+            unsafe {
+                self.reg(1).out8(0x00);                      // no interrupts
+                self.reg(3).out8(0x80);                      // divisor latch
+                self.reg(0).out8(BAUD_DIVISOR as u8);
+                self.reg(1).out8((BAUD_DIVISOR >> 8) as u8);
+                self.reg(3).out8(0x03);                      // 8N1
+                self.reg(2).out8(0xC7);                      // FIFOs on
+                self.reg(4).out8(0x03);                      // DTR and RTS
+            }
+        }
+        self.present
+    }
+
+    /// Returns true if `init` found the port
+    #[inline] pub fn is_present(&self) -> bool { self.present }
+
+    /// Send `byte`.
+    ///
+    /// If the port isn't present, this does nothing.
+    pub fn write_byte(&mut self, byte: u8) {
+        if !self.present {
+            return
+        }
+        let status = self.reg(5);
+        for _ in 0..TRANSMIT_TRIES {
+            if unsafe { status.in8() } & LSR_THR_EMPTY != 0 {
+                unsafe { self.reg(0).out8(byte) };
+                return
+            }
+            cpu::spin_hint();
+        }
+    }
+}
+
+impl fmt::Write for Serial {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            // terminals expect a carriage return before each line feed
+            if byte == b'\n' { self.write_byte(b'\r') }
+            self.write_byte(byte);
+        }
+        Ok(())
+    }
+}
+
+/// The first serial port
+pub static COM1: Mutex<Serial> = Mutex::new(Serial::new(COM1_BASE));
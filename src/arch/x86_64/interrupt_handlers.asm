@@ -1,4 +1,12 @@
-extern handle_exception
+extern handle_interrupt
+
+global int_handlers
+global int_handlers_len
+
+; The number of interrupt vectors we have stubs for. This must match
+; `IDT_ENTRIES` in `interrupts.rs`, which checks it against `int_handlers_len`
+; when the IDT is initialized.
+%define IDT_ENTRIES 256
 
 section .text
 bits 64
@@ -11,4 +19,80 @@ bits 64
 struc InterruptCtx
     ; the registers rsi, rdi, r11, r10, r9, r8, rdx, rcx, and rax
     .regs: resq 9
+    ; the interrupt vector number (padded to 8 bytes)
+    .int_id: resq 1
+    ; the error code, or 0 if the CPU didn't push one (padded to 8 bytes)
+    .err_no: resq 1
 endstruc
+
+; Push the caller-saved registers in the order `InterruptCtx` expects.
+%macro push_regs 0
+    push    rax
+    push    rcx
+    push    rdx
+    push    r8
+    push    r9
+    push    r10
+    push    r11
+    push    rdi
+    push    rsi
+%endmacro
+
+%macro pop_regs 0
+    pop     rsi
+    pop     rdi
+    pop     r11
+    pop     r10
+    pop     r9
+    pop     r8
+    pop     rdx
+    pop     rcx
+    pop     rax
+%endmacro
+
+; All the stubs end up here, with the vector number and error code on the
+; stack on top of the interrupt stack frame.
+;
+; The stack is 16-byte aligned once the registers have been pushed (the CPU
+; aligns it before pushing the frame, and the frame, error code, vector, and
+; registers add up to 16 quadwords), so we can call straight into Rust.
+isr_common:
+    push_regs
+    mov     rdi, rsp        ; pass a pointer to the `InterruptCtx`
+    cld                     ; the System V ABI expects the direction flag clear
+    call    handle_interrupt
+    pop_regs
+    add     rsp, 16         ; pop the vector number and error code
+    iretq
+
+; The stub for each vector.
+;
+; If the CPU doesn't push an error code for a vector, its stub pushes a zero in
+; its place, so that the stack looks the same for every interrupt. Which
+; vectors have error codes must agree with `has_error_code` in `interrupts.rs`.
+%assign i 0
+%rep IDT_ENTRIES
+isr_ %+ i:
+    %if !(i == 0x08 || (i >= 0x0a && i <= 0x0e) || i == 0x11 \
+          || i == 0x15 || i == 0x1d || i == 0x1e)
+    push    qword 0
+    %endif
+    push    qword i
+    jmp     isr_common
+%assign i i + 1
+%endrep
+
+section .rodata
+
+; The address of each vector's stub, indexed by vector. Rust sees this as
+; `[Option<Isr>; IDT_ENTRIES]`, so a vector without a stub would be a zero.
+int_handlers:
+%assign i 0
+%rep IDT_ENTRIES
+    dq      isr_ %+ i
+%assign i i + 1
+%endrep
+
+; The number of entries in `int_handlers`.
+int_handlers_len:
+    dq      IDT_ENTRIES
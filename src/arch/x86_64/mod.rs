@@ -7,5 +7,7 @@
 //  directory of this repository for more information.
 //
 //! x86_64 architecture-specific implementation.
+pub mod acpi;
 pub mod cpu;
 pub mod drivers;
+pub mod sections;
@@ -0,0 +1,110 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Where the linker put the kernel's own sections.
+//!
+//! `linker.ld` exports a symbol at each end of the kernel image, and of each
+//! of the sections we care about. Only the symbols' addresses mean
+//! anything; there's nothing stored at them. The kernel is linked at the
+//! physical address it's loaded at, so these are physical addresses, too.
+use core::fmt;
+use ::memory::PAddr;
+
+extern {
+    /// Start of the kernel image. Exported by `linker.ld`
+    static __kernel_start: u8;
+    /// End of the kernel image. Exported by `linker.ld`
+    static __kernel_end: u8;
+    /// Start of `.text`. Exported by `linker.ld`
+    static __text_start: u8;
+    /// End of `.text`. Exported by `linker.ld`
+    static __text_end: u8;
+    /// Start of `.rodata`. Exported by `linker.ld`
+    static __rodata_start: u8;
+    /// End of `.rodata`. Exported by `linker.ld`
+    static __rodata_end: u8;
+    /// Start of `.data`. Exported by `linker.ld`
+    static __data_start: u8;
+    /// End of `.data`. Exported by `linker.ld`
+    static __data_end: u8;
+    /// Start of `.bss`. Exported by `linker.ld`
+    static __bss_start: u8;
+    /// End of `.bss`. Exported by `linker.ld`
+    static __bss_end: u8;
+}
+
+/// Returns the address of a linker symbol
+#[inline]
+fn addr(symbol: &u8) -> usize { symbol as *const u8 as usize }
+
+/// Returns the address of the start of the kernel's `.text` section
+#[inline]
+pub fn text_start() -> usize {
+    unsafe { addr(&__text_start) }
+}
+
+/// Returns the address just past the end of the kernel's `.text` section
+#[inline]
+pub fn text_end() -> usize {
+    unsafe { addr(&__text_end) }
+}
+
+/// Returns true if `addr` is in the kernel's `.text` section, which is where
+/// every function we could sensibly jump to lives.
+#[inline]
+pub fn in_text(addr: usize) -> bool {
+    text_start() <= addr && addr < text_end()
+}
+
+/// Where the kernel image is in memory, and how big its sections are.
+#[derive(Debug, Copy, Clone)]
+pub struct KernelImage { /// The physical address the image starts at
+                         pub start: PAddr
+                       , /// The physical address just past the end of the
+                         /// image (including `.bss`)
+                         pub end: PAddr
+                       , /// Size of `.text`, in bytes
+                         pub text: usize
+                       , /// Size of `.rodata`, in bytes
+                         pub rodata: usize
+                       , /// Size of `.data`, in bytes
+                         pub data: usize
+                       , /// Size of `.bss`, in bytes
+                         pub bss: usize
+                       }
+
+impl KernelImage {
+    /// Returns the size of the whole image, in bytes
+    #[inline]
+    pub fn size(&self) -> usize {
+        (self.end.as_u64() - self.start.as_u64()) as usize
+    }
+}
+
+impl fmt::Display for KernelImage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!( f, "{} KiB at {}-{} (text {} KiB, rodata {} KiB, data {} KiB, \
+                    bss {} KiB)"
+              , self.size() / 1024, self.start, self.end
+              , self.text / 1024, self.rodata / 1024, self.data / 1024
+              , self.bss / 1024 )
+    }
+}
+
+/// Returns where the kernel image is, and how big its sections are
+pub fn kernel_image() -> KernelImage {
+    unsafe {
+        KernelImage { start: PAddr::from_u64(addr(&__kernel_start) as u64)
+                    , end: PAddr::from_u64(addr(&__kernel_end) as u64)
+                    , text: text_end() - text_start()
+                    , rodata: addr(&__rodata_end) - addr(&__rodata_start)
+                    , data: addr(&__data_end) - addr(&__data_start)
+                    , bss: addr(&__bss_end) - addr(&__bss_start)
+                    }
+    }
+}
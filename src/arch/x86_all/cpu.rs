@@ -8,72 +8,172 @@
 //
 //! Common functionality for x86 and x86_64 CPUs
 use ::{io,util};
+use core::marker::PhantomData;
+
+/// A value that can be read from or written to an I/O port in one
+/// instruction.
+///
+/// This is implemented for `u8`, `u16`, and `u32`, which correspond to the
+/// `in`/`out` instructions' byte, word, and long word forms.
+pub trait PortWidth: Copy {
+    /// Read a value of this width from the port numbered `port`
+    unsafe fn port_in(port: u16) -> Self;
+    /// Write `value` to the port numbered `port`
+    unsafe fn port_out(port: u16, value: Self);
+}
 
-pub struct Port(u16);
-
-impl Port {
-
-    pub const unsafe fn new(number: u16) -> Port { Port(number) }
-
-    /// Read a byte (8 bits) from this port
-    pub unsafe fn in8(&self) -> u8 {
+impl PortWidth for u8 {
+    #[inline]
+    unsafe fn port_in(port: u16) -> u8 {
         let result: u8;
         asm!(  "in al, dx"
             :  "={al}"(result)
-            :  "{dx}"(self.0)
+            :  "{dx}"(port)
             :: "intel"
              , "volatile" );
         result
     }
-    /// Read a word (16 bits) from this port
-    pub unsafe fn in16(&self) -> u16 {
+
+    #[inline]
+    unsafe fn port_out(port: u16, value: u8) {
+         asm!(  "out dx, al"
+             :: "{dx}"(port)
+              , "{al}"(value)
+             :: "intel"
+              , "volatile" );
+    }
+}
+
+impl PortWidth for u16 {
+    #[inline]
+    unsafe fn port_in(port: u16) -> u16 {
         let result: u16;
         asm!(  "in ax, dx"
             :  "={ax}"(result)
-            :  "{dx}"(self.0)
+            :  "{dx}"(port)
             :: "intel"
              , "volatile" );
         result
     }
 
-    /// Read a long word (32 bits) from this port
-    pub unsafe fn in32(&self) -> u32 {
+    #[inline]
+    unsafe fn port_out(port: u16, value: u16) {
+         asm!(  "out dx, ax"
+             :: "{dx}"(port)
+              , "{ax}"(value)
+             :: "intel"
+              , "volatile" );
+    }
+}
+
+impl PortWidth for u32 {
+    #[inline]
+    unsafe fn port_in(port: u16) -> u32 {
         let result: u32;
         asm!(  "in eax, dx"
             :  "={eax}"(result)
-            :  "{dx}"(self.0)
+            :  "{dx}"(port)
             :: "intel"
              , "volatile" );
         result
     }
 
-    pub unsafe fn out8(&self, value: u8) {
-         asm!(  "out dx, al"
-             :: "{dx}"(self.0)
-              , "{al}"(value)
+    #[inline]
+    unsafe fn port_out(port: u16, value: u32) {
+         asm!(  "out dx, eax"
+             :: "{dx}"(port)
+              , "{eax}"(value)
              :: "intel"
               , "volatile" );
     }
+}
+
+/// An I/O port that is read and written `T` at a time.
+///
+/// The width is part of the type so that a device whose registers are only
+/// a byte wide (like the 8259 PIC) can't accidentally be sent a word or a
+/// long word: a `Port<u8>` only has `in8` and `out8`. Most ports are byte
+/// ports, so that's the default.
+pub struct Port<T = u8> { number: u16
+                        , width: PhantomData<T>
+                        }
+
+impl<T> Port<T> {
+    pub const unsafe fn new(number: u16) -> Port<T> {
+        Port { number: number, width: PhantomData }
+    }
 
-    pub unsafe fn out16(&self, value: u16) {
-         asm!(  "out dx, ax"
-             :: "{dx}"(self.0)
-              , "{ax}"(value)
-             :: "intel"
-              , "volatile" );
+    /// Returns this port's number
+    #[inline] pub fn number(&self) -> u16 { self.number }
+}
+
+impl<T: PortWidth> Port<T> {
+    /// Read a `T` from this port
+    #[inline]
+    pub unsafe fn read(&self) -> T { T::port_in(self.number) }
+
+    /// Write a `T` to this port
+    #[inline]
+    pub unsafe fn write(&self, value: T) { T::port_out(self.number, value) }
+}
+
+impl Port<u8> {
+    /// Read a byte (8 bits) from this port
+    #[inline] pub unsafe fn in8(&self) -> u8 { self.read() }
+
+    /// Write a byte (8 bits) to this port
+    #[inline] pub unsafe fn out8(&self, value: u8) { self.write(value) }
+}
+
+impl Port<u16> {
+    /// Read a word (16 bits) from this port
+    #[inline] pub unsafe fn in16(&self) -> u16 { self.read() }
+
+    /// Write a word (16 bits) to this port
+    #[inline] pub unsafe fn out16(&self, value: u16) { self.write(value) }
+
+    /// Read `count` words (16 bits each) from this port into the memory
+    /// starting at `dst`, using the `rep insw` string instruction.
+    ///
+    /// # Unsafe due to
+    ///   - Writing `count` words through a raw pointer
+    pub unsafe fn in16_string(&self, dst: *mut u16, count: usize) {
+        asm!(  "cld
+                rep insw"
+            :: "{dx}"(self.number)
+             , "{rdi}"(dst)
+             , "{rcx}"(count)
+            :  "rdi", "rcx", "memory"
+            :  "intel"
+             , "volatile" );
     }
 
-    pub unsafe fn out32(&self, value: u32) {
-         asm!(  "out dx, eax"
-             :: "{dx}"(self.0)
-              , "{eax}"(value)
-             :: "intel"
-              , "volatile" );
+    /// Write `count` words (16 bits each) from the memory starting at `src`
+    /// to this port, using the `rep outsw` string instruction.
+    ///
+    /// # Unsafe due to
+    ///   - Reading `count` words through a raw pointer
+    pub unsafe fn out16_string(&self, src: *const u16, count: usize) {
+        asm!(  "cld
+                rep outsw"
+            :: "{dx}"(self.number)
+             , "{rsi}"(src)
+             , "{rcx}"(count)
+            :  "rsi", "rcx"
+            :  "intel"
+             , "volatile" );
     }
 }
 
+impl Port<u32> {
+    /// Read a long word (32 bits) from this port
+    #[inline] pub unsafe fn in32(&self) -> u32 { self.read() }
+
+    /// Write a long word (32 bits) to this port
+    #[inline] pub unsafe fn out32(&self, value: u32) { self.write(value) }
+}
 
-impl io::Read for Port {
+impl io::Read for Port<u8> {
     type Error = util::Void;
 
     /// Reads a single byte into the given buffer
@@ -104,7 +204,7 @@ impl io::Read for Port {
 
 }
 
-impl io::Write for Port {
+impl io::Write for Port<u8> {
     type Error = util::Void;
 
     fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
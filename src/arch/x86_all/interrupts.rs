@@ -12,28 +12,97 @@ use super::super::{DTablePtr, DTable};
 use core::mem::size_of;
 use core::fmt;
 
+/// The raw entry point of an interrupt service routine.
 pub type Handler = unsafe extern "C" fn() -> ();
 pub const IDT_ENTRIES: usize = 256;
 
+/// An interrupt service routine that's safe to point an IDT gate at.
+///
+/// The CPU jumps to a gate's handler with the interrupt stack frame (and
+/// maybe an error code) on the stack, and returns from it with `iretq`, so an
+/// ordinary function can't go in a gate: its prologue and `ret` would wreck
+/// the stack. The ISRs that can are the assembly stubs, which save the
+/// context and call `handle_interrupt`. An `Isr` can only be made from one
+/// of those (with the `isr!` macro) or with the `unsafe` `from_raw`, so
+/// passing a plain function to an IDT by accident doesn't type check.
+#[derive(Copy, Clone)]
+pub struct Isr(Handler);
+
+impl Isr {
+    /// Wrap a raw handler.
+    ///
+    /// This is the escape hatch; prefer `isr!`.
+    ///
+    /// # Unsafe due to
+    ///   - `handler` must follow the interrupt calling convention: return
+    ///     with `iretq`, pop the error code if the CPU pushed one, and
+    ///     preserve every register
+    pub const unsafe fn from_raw(handler: Handler) -> Isr { Isr(handler) }
+
+    /// Returns the address of the handler
+    #[inline] pub fn handler(&self) -> Handler { self.0 }
+}
+
+/// Get the `Isr` for an interrupt stub defined in assembly.
+///
+/// Only use this with the name of a stub from `interrupt_handlers.asm`.
+macro_rules! isr {
+    ($stub:ident) => {{
+        extern { fn $stub(); }
+        unsafe { $crate::arch::cpu::interrupts::Isr::from_raw($stub) }
+    }}
+}
+
 /// x86 interrupt gate types.
 ///
-/// Bit-and this with the attribute half-byte to produce the
-/// `type_attr` field for a `Gate`
+/// Each value is the gate's whole `type_attr` byte with a DPL of 0: the type
+/// in the low half-byte, and the present bit set for everything but `Absent`.
+/// Use `type_attr` to get the byte with some other DPL.
+///
+/// The difference between interrupt and trap gates is what happens to
+/// `rflags.IF`: entering an interrupt gate clears it, so the handler can't be
+/// interrupted, while a trap gate leaves it alone.
 #[repr(u8)]
-#[derive(Debug)]
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
 pub enum GateType { Absent    = 0b0000_0000
                   , Interrupt = 0b1000_1110
                   , Call      = 0b1000_1100
                   , Trap      = 0b1000_1111
                   }
 
+impl GateType {
+    /// Returns the `type_attr` byte for a gate of this type that can be
+    /// called with `int` from privilege level `dpl` and up.
+    ///
+    /// Only the low two bits of `dpl` are used. `Absent` gates are never
+    /// present, whatever their DPL.
+    #[inline]
+    pub fn type_attr(self, dpl: u8) -> u8 {
+        self as u8 | (dpl & 0b11) << 5
+    }
+
+    /// Returns the type with the given type half-byte, as found in the low
+    /// four bits of a gate's `type_attr`.
+    ///
+    /// Types that can't go in an IDT come out as `Absent`.
+    pub fn from_type_bits(bits: u8) -> GateType {
+        match bits & 0x0f {
+            0b1110 => GateType::Interrupt
+          , 0b1111 => GateType::Trap
+          , 0b1100 => GateType::Call
+          , _      => GateType::Absent
+        }
+    }
+}
+
 impl fmt::Display for GateType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self { &GateType::Absent    => write!(f, "Absent")
-                   , &GateType::Interrupt => write!(f, "Interrupt")
-                   , &GateType::Call      => write!(f, "Call")
-                   , &GateType::Trap      => write!(f, "Trap")
-                   }
+        // `pad` rather than `write!`, so that width and alignment work
+        f.pad(match self { &GateType::Absent    => "Absent"
+                         , &GateType::Interrupt => "Interrupt"
+                         , &GateType::Call      => "Call"
+                         , &GateType::Trap      => "Trap"
+                         })
     }
 }
 
@@ -72,8 +141,34 @@ pub static EXCEPTIONS: &'static [&'static str]
        , "Virtualization Exception"
        ];
 
-pub trait Gate {
-    fn from_handler(handler: Handler) -> Self;
+pub trait Gate: Sized {
+    /// Create a gate of type `ty` pointing at a raw handler.
+    ///
+    /// # Unsafe due to
+    ///   - `handler` must follow the interrupt calling convention; see
+    ///     `Isr::from_raw`. This is for when there's no `Isr` to be had.
+    unsafe fn from_handler_as(handler: Handler, ty: GateType) -> Self;
+
+    /// Create an interrupt gate pointing at a raw handler.
+    ///
+    /// # Unsafe due to
+    ///   - the same things as `from_handler_as`
+    #[inline]
+    unsafe fn from_handler(handler: Handler) -> Self {
+        Self::from_handler_as(handler, GateType::Interrupt)
+    }
+
+    /// Create a gate of type `ty` pointing at an interrupt service routine.
+    #[inline]
+    fn from_isr_as(isr: Isr, ty: GateType) -> Self {
+        unsafe { Self::from_handler_as(isr.handler(), ty) }
+    }
+
+    /// Create an interrupt gate pointing at an interrupt service routine.
+    #[inline]
+    fn from_isr(isr: Isr) -> Self {
+        Self::from_isr_as(isr, GateType::Interrupt)
+    }
 }
 
 // /// This is the format that `lidt` expects for the pointer to the IDT.
@@ -104,12 +199,14 @@ pub trait InterruptContext {
 }
 
 pub trait Idt: Sized {
-    type Ctx: InterruptContext;
+    type Ctx: InterruptContext + fmt::Display;
     type GateSize: Gate;
 
     /// Get the IDT pointer struct to pass to `lidt`
     fn get_ptr(&self) -> DTablePtr<Self> {
-        DTablePtr { limit: (size_of::<Self::GateSize>() * IDT_ENTRIES) as u16
+        // the limit is the offset of the table's last byte, not its size
+        DTablePtr { limit: (size_of::<Self::GateSize>() * IDT_ENTRIES - 1)
+                               as u16
                   , base: self as *const Self
                   }
     }
@@ -130,16 +227,19 @@ pub trait Idt: Sized {
         unsafe { asm!("cli" :::: "volatile"); }
     }
 
-    fn add_gate(&mut self, idx: usize, handler: Handler);
+    /// Add a gate of type `ty` for the given ISR at the given index
+    fn add_gate_as(&mut self, idx: usize, isr: Isr, ty: GateType);
+
+    /// Add an interrupt gate for the given ISR at the given index
+    #[inline]
+    fn add_gate(&mut self, idx: usize, isr: Isr) {
+        self.add_gate_as(idx, isr, GateType::Interrupt)
+    }
 
-    fn handle_cpu_exception(state: &Self::Ctx)  {
-        // TODO: we can handle various types of CPU exception differently
-        // TODO: make some nice debugging dumps
-        unsafe {
-            panic!( "CPU EXCEPTION {:#04x}: {}"
-                  , state.err_no()
-                  , state.exception() );
-              }
+    /// Handle a CPU exception we can't recover from, by panicking with a
+    /// crash report.
+    fn handle_cpu_exception(state: &Self::Ctx) -> ! {
+        panic!("{}", state)
     }
 
     extern "C" fn handle_interrupt(state: &Self::Ctx);
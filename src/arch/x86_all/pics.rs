@@ -19,15 +19,28 @@
 //! 8 ... 15 in the IDT. This conflicts with some of the interrupt numbers
 //! used by CPU exceptions. Therefore, we must remap the PIC vectors so that
 //! PIC1 starts at 32 and PIC2 at 40.
+//!
+//! Masking a line doesn't stop the PIC from noticing requests on it: they're
+//! latched in the interrupt request register (IRR) regardless, and a masked
+//! line's request is delivered the moment it's unmasked (or, if interrupts
+//! are disabled, the moment they're enabled). That's usually what we want,
+//! but a request that was latched long ago, like the keyboard's from a key
+//! pressed in the bootloader, is stale, and its handler will find garbage
+//! (or nothing) waiting for it. `unmask_irq_drained` deals with that by
+//! letting the device's driver clear the request before the line opens.
 
 use ::io::Write;
-use super::super::Port;
+use super::super::{Port, without_interrupts};
 use spin::Mutex;
-use core::mem::transmute;
+use core::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
 
 /// Starting offset for PIC1
 const OFFSET: u8 = 0x20;
 
+/// How many times `drain_pending` calls its drain function before deciding
+/// the device is just busy
+const MAX_DRAINS: usize = 16;
+
 /// Commands to send to the PIC
 #[repr(u8)]
 #[derive(Eq, PartialEq, Ord, PartialOrd, Copy, Clone)]
@@ -78,9 +91,12 @@ struct PIC {
     /// The base offset to which interrupts on this PIC are mapped
     offset: u8
   , /// The port on the CPU that sends commands to this PIC.
-    command_port: Port
+    ///
+    /// The 8259's registers are all a byte wide, so its ports are declared
+    /// as `Port<u8>`: this way, only `in8` and `out8` are available.
+    command_port: Port<u8>
   , /// The port that sends and recieves data from the PIC
-    data_port: Port
+    data_port: Port<u8>
 }
 
 impl PIC {
@@ -129,18 +145,31 @@ impl PIC {
         self.send_command(Command::Init)
     }
 
+    // after a read command, the register comes back on the command port:
+    // reading the data port always gives the mask
     #[inline]
     fn read_ISR(&self) -> u8 {
         self.send_command(Command::ReadISR);
-        unsafe { self.data_port.in8() }
+        unsafe { self.command_port.in8() }
     }
 
     #[inline]
     fn read_IRR(&self) -> u8 {
         self.send_command(Command::ReadIRR);
+        unsafe { self.command_port.in8() }
+    }
+
+    /// Returns the interrupt mask register (a set bit masks that line)
+    #[inline]
+    fn read_mask(&self) -> u8 {
         unsafe { self.data_port.in8() }
     }
 
+    #[inline]
+    fn write_mask(&self, mask: u8) {
+        self.send_data(mask)
+    }
+
 }
 
 trait IRQHandler {
@@ -173,16 +202,16 @@ impl BothPICs {
 
     /// Initialize the system's PICs.
     fn initialize(&mut self) {
-        let mut wait_port = unsafe { Port::new(0x80) };
+        let wait_port: Port<u8> = unsafe { Port::new(0x80) };
         let mut wait = || unsafe { wait_port.out8(0); };
         // helper macro to avoid writing repetitive code
         macro_rules! send {
             (pic0 => $data:expr) => {
-                self.0.send_data($data)
+                self.0.send_data($data);
                 wait();
             };
             (pic1 => $data:expr) => {
-                self.1.send_data($data)
+                self.1.send_data($data);
                 wait();
             };
         }
@@ -211,6 +240,32 @@ impl BothPICs {
     }
 }
 
+impl BothPICs {
+    /// Returns the PIC that `irq` is on, and its line number on that PIC
+    fn pic_for(&self, irq: IRQ) -> (&PIC, u8) {
+        let pic = if self.1.handles(irq) { &self.1 } else { &self.0 };
+        (pic, irq as u8 - pic.offset)
+    }
+
+    fn is_masked(&self, irq: IRQ) -> bool {
+        let (pic, line) = self.pic_for(irq);
+        pic.read_mask() & (1 << line) != 0
+    }
+
+    /// Returns true if `irq` has been requested, but not yet delivered
+    fn is_pending(&self, irq: IRQ) -> bool {
+        let (pic, line) = self.pic_for(irq);
+        pic.read_IRR() & (1 << line) != 0
+    }
+
+    fn set_masked(&self, irq: IRQ, masked: bool) {
+        let (pic, line) = self.pic_for(irq);
+        let mask = pic.read_mask();
+        pic.write_mask(if masked { mask | (1 << line) }
+                       else { mask & !(1 << line) });
+    }
+}
+
 impl IRQHandler for BothPICs {
 
     fn handles(&self, irq: IRQ) -> bool {
@@ -231,10 +286,132 @@ impl IRQHandler for BothPICs {
 static PICS: Mutex<BothPICs>
     = Mutex::new(BothPICs::new());
 
+/// Set once the PICs have been remapped by `initialize`
+static INITIALIZED: AtomicBool = ATOMIC_BOOL_INIT;
+
 /// Initialize the system's Programmable Interrupt Controller
 pub fn initialize() {
     PICS.lock()
-        .initialize()
+        .initialize();
+    INITIALIZED.store(true, Ordering::SeqCst);
+}
+
+/// Returns true once `initialize` has remapped the PICs.
+///
+/// Until then, IRQs 0 - 7 arrive on the CPU exception vectors.
+#[inline]
+pub fn is_initialized() -> bool {
+    INITIALIZED.load(Ordering::SeqCst)
+}
+
+/// Returns true if `irq` is currently masked
+pub fn is_masked(irq: IRQ) -> bool {
+    without_interrupts(|| PICS.lock().is_masked(irq))
+}
+
+/// Mask `irq`, so that the PICs won't raise it until it's unmasked.
+pub fn mask_irq(irq: IRQ) {
+    without_interrupts(|| PICS.lock().set_masked(irq, true))
+}
+
+/// Returns true if `irq` has been requested, but not yet delivered to the
+/// CPU (because it's masked, interrupts are disabled, or an interrupt of
+/// higher priority is in service).
+pub fn is_pending(irq: IRQ) -> bool {
+    without_interrupts(|| PICS.lock().is_pending(irq))
+}
+
+/// Unmask `irq`.
+///
+/// If `irq` was requested while it was masked, the request is still
+/// latched, and will be delivered as soon as interrupts are enabled; use
+/// `unmask_irq_drained` if that request might be stale.
+pub fn unmask_irq(irq: IRQ) {
+    without_interrupts(|| PICS.lock().set_masked(irq, false))
+}
+
+/// Get rid of a request latched on `irq`, by calling `drain` until it goes
+/// away.
+///
+/// `drain` should do whatever makes the device drop its interrupt line
+/// (reading the keyboard's data port, say); that's what clears the request
+/// in the IRR, since we can't clear it ourselves. `irq` is masked while this
+/// runs, and put back the way it was afterwards. If the request is still
+/// there after `MAX_DRAINS` calls, the device is producing new ones faster
+/// than we're draining them, and we give up.
+///
+/// # Returns
+///   - `true` if there was a request to get rid of
+pub fn drain_pending<F>(irq: IRQ, mut drain: F) -> bool
+where F: FnMut() {
+    without_interrupts(|| {
+        let _mask = IrqMask::new(irq);
+        let was_pending = PICS.lock().is_pending(irq);
+        let mut drains = 0;
+        // don't hold the lock while `drain` runs, in case it masks or
+        // unmasks something itself
+        while drains < MAX_DRAINS && PICS.lock().is_pending(irq) {
+            drain();
+            drains += 1;
+        }
+        was_pending
+    })
+}
+
+/// Unmask `irq`, first getting rid of any request that was latched while it
+/// was masked (see `drain_pending`).
+///
+/// This is how a device that's been ignored for a while should be brought
+/// back: otherwise, its handler runs straight away, for a request that's
+/// nothing to do with anything it's about to do.
+///
+/// # Returns
+///   - `true` if there was a stale request to get rid of
+pub fn unmask_irq_drained<F>(irq: IRQ, drain: F) -> bool
+where F: FnMut() {
+    without_interrupts(|| {
+        let was_pending = drain_pending(irq, drain);
+        unmask_irq(irq);
+        was_pending
+    })
+}
+
+/// Keeps an IRQ masked for as long as it's alive.
+///
+/// When it's dropped, the line goes back to whatever state it was in
+/// beforehand, so this never unmasks a line that someone else masked.
+pub struct IrqMask { irq: IRQ
+                   , was_masked: bool
+                   }
+
+impl IrqMask {
+    /// Mask `irq` until the returned guard is dropped.
+    pub fn new(irq: IRQ) -> IrqMask {
+        without_interrupts(|| {
+            let pics = PICS.lock();
+            let was_masked = pics.is_masked(irq);
+            pics.set_masked(irq, true);
+            IrqMask { irq: irq, was_masked: was_masked }
+        })
+    }
+}
+
+impl Drop for IrqMask {
+    fn drop(&mut self) {
+        if !self.was_masked {
+            unmask_irq(self.irq)
+        }
+    }
+}
+
+/// Run `f` with just `irq` masked, leaving every other interrupt alone.
+///
+/// This is a finer-grained alternative to `without_interrupts`, for when it's
+/// only one device's handler that mustn't run.
+pub fn mask_during<F, R>(irq: IRQ, f: F) -> R
+where F: FnOnce() -> R {
+    let _mask = IrqMask::new(irq);
+    f()
 }
 
 /// If an interrupt is being handled by the PICs, end that interrupt.
@@ -242,10 +419,15 @@ pub fn initialize() {
 /// This is called by the interrupt handler at the end of all interrupts.
 /// If the interrupt is not a PIC interrupt, it silently does nothing.
 pub unsafe fn end_pic_interrupt(interrupt_id: u8) {
+    // Not every vector in the PICs' range has an `IRQ` variant (there's no
+    // IRQ 9, for instance), so we can't just turn the ID into an `IRQ`.
+    if interrupt_id < OFFSET || interrupt_id >= OFFSET + 16 {
+        return
+    }
     let pics = PICS.lock();
-    let irq: IRQ = transmute(interrupt_id);
-
-    if pics.handles(irq) {
-        pics.end_interrupt(irq)
+    // interrupts from the follower have to be ended on both PICs
+    if interrupt_id >= pics.1.offset {
+        pics.1.send_command(Command::EndIRQ);
     }
+    pics.0.send_command(Command::EndIRQ);
 }
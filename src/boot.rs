@@ -0,0 +1,329 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Boot phase breadcrumbs.
+//!
+//! A triple fault resets the machine instantly, without leaving any clue as
+//! to what went wrong. To get at least some idea, we record which step of
+//! initialization we're on in CMOS NVRAM, which survives the reset. On the
+//! next boot, we can then look at how far the previous one got.
+//!
+//! Within a boot, each step is also appended to the boot log, along with the
+//! TSC when it happened. That's a fixed-size ring in memory (so it works
+//! before there's a heap, and can't grow without bound), which can be
+//! printed long after the messages have scrolled off the screen: by the
+//! monitor's `bootlog` command, or at the end of a panic report.
+//!
+//! This is also where the information the bootloader hands us is checked
+//! and unpacked, in `BootInfo`, and where `print_banner` sums up what we
+//! found once we've finished booting.
+use core::{cmp, fmt};
+use spin::Mutex;
+use multiboot::{self, MemMapTag, FramebufferTag, Modules};
+use multiboot::elf64::SectionsTag;
+use arch::cpu::{self, apic, cpuid, tsc};
+use arch::cpu::interrupts::pics;
+use arch::drivers::cmos;
+use arch::sections;
+use memory;
+use util::is_aligned;
+
+/// CMOS register used to store the boot phase.
+///
+/// This is in the upper part of the CMOS, which isn't used by the RTC or
+/// (as far as I know) by any common BIOS or by QEMU.
+const PHASE_REGISTER: u8 = 0x7E;
+
+/// How many entries the boot log holds; once it's full, each new entry
+/// replaces the oldest one
+pub const BOOT_LOG_LEN: usize = 64;
+
+/// A step in kernel initialization.
+#[repr(u8)]
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Copy, Clone)]
+pub enum Phase { /// No phase was recorded (or we don't recognize it)
+                 Unknown = 0
+               , /// We made it into `kernel_main`
+                 Started
+               , /// Enabling the FPU and SSE
+                 Fpu
+               , /// Calibrating the TSC
+                 Tsc
+               , /// Reading the Multiboot info and memory map
+                 Multiboot
+               , /// Setting up the frame allocator
+                 Allocator
+               , /// Setting up the IDT, PICs, and APIC, and enabling
+                 /// interrupts
+                 Interrupts
+               , /// Loading the initrd
+                 Initrd
+               , /// Initialization finished
+                 Booted
+               }
+
+impl Phase {
+    fn from_u8(value: u8) -> Phase {
+        match value {
+            1 => Phase::Started
+          , 2 => Phase::Fpu
+          , 3 => Phase::Tsc
+          , 4 => Phase::Multiboot
+          , 5 => Phase::Allocator
+          , 6 => Phase::Interrupts
+          , 7 => Phase::Initrd
+          , 8 => Phase::Booted
+          , _ => Phase::Unknown
+        }
+    }
+
+    /// Returns what the phase is, for the boot log
+    fn name(&self) -> &'static str {
+        match *self {
+            Phase::Unknown => "unknown phase"
+          , Phase::Started => "started"
+          , Phase::Fpu => "FPU and SSE"
+          , Phase::Tsc => "TSC calibration"
+          , Phase::Multiboot => "Multiboot info"
+          , Phase::Allocator => "frame allocator"
+          , Phase::Interrupts => "interrupts"
+          , Phase::Initrd => "initrd"
+          , Phase::Booted => "booted"
+        }
+    }
+}
+
+/// Record that the kernel has reached `phase`, both in CMOS and in the boot
+/// log.
+pub fn set_boot_phase(phase: Phase) {
+    cmos::cmos_write(PHASE_REGISTER, phase as u8);
+    boot_log(phase.name());
+}
+
+/// One step of initialization, in the boot log.
+#[derive(Debug, Copy, Clone)]
+pub struct LogEntry { /// The TSC when the step was logged
+                      pub tsc: u64
+                    , /// What the step was
+                      pub step: &'static str
+                    }
+
+const EMPTY_ENTRY: LogEntry = LogEntry { tsc: 0, step: "" };
+
+/// The last `BOOT_LOG_LEN` entries logged.
+struct BootLog { entries: [LogEntry; BOOT_LOG_LEN]
+               , /// Number of entries ever logged; the next one goes in
+                 /// `entries[count % BOOT_LOG_LEN]`
+                 count: usize
+               , /// The TSC when the first entry was logged, which the
+                 /// others' times are given relative to
+                 start: u64
+               }
+
+static BOOT_LOG: Mutex<BootLog>
+    = Mutex::new(BootLog { entries: [EMPTY_ENTRY; BOOT_LOG_LEN]
+                         , count: 0
+                         , start: 0
+                         });
+
+/// Append `step` to the boot log, timestamped with the TSC.
+///
+/// This is cheap enough to call between any two steps of initialization
+/// that might be worth telling apart later.
+pub fn boot_log(step: &'static str) {
+    let now = tsc::rdtsc();
+    // an interrupt handler that logged something while we held the lock
+    // would wait for it forever
+    cpu::without_interrupts(|| {
+        let mut log = BOOT_LOG.lock();
+        if log.count == 0 {
+            log.start = now;
+        }
+        let index = log.count % BOOT_LOG_LEN;
+        log.entries[index] = LogEntry { tsc: now, step: step };
+        log.count += 1;
+    })
+}
+
+/// Write the last `last` entries of the boot log to `out`, one per line,
+/// with how long after the first entry each one was logged.
+///
+/// The times are in microseconds if the TSC has been calibrated, and in TSC
+/// ticks if it hasn't. Since this is called when panicking, it doesn't wait
+/// for the log's lock: if someone has it, we just say so.
+pub fn write_boot_log<W: fmt::Write>(out: &mut W, last: usize)
+                                     -> fmt::Result {
+    let log = match BOOT_LOG.try_lock() {
+        Some(log) => log
+      , None => return out.write_str("\n  (the boot log is locked)")
+    };
+    let kept = cmp::min(cmp::min(log.count, BOOT_LOG_LEN), last);
+    let first = log.count - kept;
+    if first > 0 {
+        try!(write!(out, "\n  ({} earlier entries not shown)", first));
+    }
+    for i in first..log.count {
+        let entry = log.entries[i % BOOT_LOG_LEN];
+        let elapsed = entry.tsc.wrapping_sub(log.start);
+        try!(match tsc::tsc_hz() {
+            Some(_) => write!( out, "\n  +{:>10} us  {}"
+                             , tsc::tsc_to_ns(elapsed) / 1000, entry.step )
+          , None => write!( out, "\n  +{:>10} ticks  {}"
+                          , elapsed, entry.step )
+        });
+    }
+    Ok(())
+}
+
+/// Returns the last boot phase recorded.
+///
+/// If this is called before the first `set_boot_phase` of this boot, it's
+/// how far the previous boot got.
+pub fn last_boot_phase() -> Phase {
+    Phase::from_u8(cmos::cmos_read(PHASE_REGISTER))
+}
+
+/// Returns a description of the interrupt controllers that are set up.
+///
+/// IRQs are always routed through the 8259 PICs; the local APIC, if it's
+/// enabled, is used for IPIs and spurious interrupts.
+fn interrupt_controller() -> &'static str {
+    match (pics::is_initialized(), apic::is_enabled()) {
+        (true, true)   => "8259 PIC + local APIC"
+      , (true, false)  => "8259 PIC"
+      , (false, true)  => "local APIC"
+      , (false, false) => "none yet"
+    }
+}
+
+/// Print a summary of the machine we've booted on: the CPU's vendor and
+/// brand string, how much usable RAM the memory map reports, and which
+/// interrupt controller is in use, and then where the kernel image is and
+/// how big its sections are.
+///
+/// This is meant to be called once, at the end of initialization, so that
+/// a glance at it tells whether the environment was detected correctly.
+pub fn print_banner() {
+    let vendor = cpuid::vendor();
+    let brand = cpuid::brand_string();
+    println!( "SOS booted on {} ({}), {} MiB usable RAM, interrupts: {}."
+            , vendor.as_str()
+            , brand.as_ref().map_or("no brand string", |b| b.as_str())
+            , memory::map::usable_bytes().unwrap_or(0) / (1024 * 1024)
+            , interrupt_controller() );
+    println!("Kernel image: {}.", sections::kernel_image());
+}
+
+/// Why the bootloader's handoff couldn't be used.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum BootError { /// We weren't started by a Multiboot 2 bootloader; this
+                     /// is the magic number we got instead
+                     BadMagic(u32)
+                   , /// The Multiboot info pointer was null, or not 8-byte
+                     /// aligned
+                     BadInfoAddr(usize)
+                   , /// There was no memory map tag
+                     NoMemoryMap
+                   , /// There was no ELF sections tag
+                     NoElfSections
+                   }
+
+impl fmt::Display for BootError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BootError::BadMagic(magic) =>
+                write!( f, "bad Multiboot magic number {:#x} (expected {:#x}); \
+                            was this kernel started by a Multiboot 2 \
+                            bootloader?"
+                      , magic, multiboot::BOOTLOADER_MAGIC )
+          , BootError::BadInfoAddr(addr) =>
+                write!(f, "bad Multiboot info address {:#x}", addr)
+          , BootError::NoMemoryMap =>
+                f.write_str("the bootloader didn't give us a memory map")
+          , BootError::NoElfSections =>
+                f.write_str("the bootloader didn't give us the kernel's \
+                             ELF sections")
+        }
+    }
+}
+
+/// Everything we need from the bootloader, checked and unpacked.
+#[derive(Copy, Clone)]
+pub struct BootInfo { info: &'static multiboot::Info
+                    , /// Physical address of the Multiboot info
+                      addr: usize
+                    , mem_map: &'static MemMapTag
+                    , elf_sections: &'static SectionsTag
+                    , framebuffer: Option<&'static FramebufferTag>
+                    }
+
+impl BootInfo {
+    /// Check the bootloader's `magic` number and unpack the Multiboot info
+    /// at `addr`.
+    ///
+    /// `boot.asm` has already refused to go on with the wrong magic number,
+    /// but checking it again here means we never look at `addr` unless a
+    /// Multiboot 2 bootloader really gave it to us.
+    ///
+    /// # Returns
+    ///   - `Err(BootError)` if the magic number is wrong, `addr` can't be
+    ///     the Multiboot info, or a tag we can't boot without is missing
+    ///
+    /// # Unsafe due to
+    ///   - Trusting that a bootloader that got the magic number right also
+    ///     left valid Multiboot info at `addr`, identity-mapped
+    pub unsafe fn from_multiboot(magic: u32, addr: usize)
+                                 -> Result<BootInfo, BootError> {
+        if magic != multiboot::BOOTLOADER_MAGIC {
+            return Err(BootError::BadMagic(magic))
+        }
+        if addr == 0 || !is_aligned(addr, 8) {
+            return Err(BootError::BadInfoAddr(addr))
+        }
+        let info = multiboot::Info::from(addr);
+        let mem_map = try!(info.mem_map().ok_or(BootError::NoMemoryMap));
+        let elf_sections = try!( info.elf64_sections()
+                                     .ok_or(BootError::NoElfSections) );
+        Ok(BootInfo { info: info
+                    , addr: addr
+                    , mem_map: mem_map
+                    , elf_sections: elf_sections
+                    , framebuffer: info.framebuffer()
+                    })
+    }
+
+    /// Returns the physical address where the Multiboot info starts
+    #[inline] pub fn start(&self) -> usize { self.addr }
+
+    /// Returns the physical address just past the end of the Multiboot info
+    #[inline]
+    pub fn end(&self) -> usize { self.addr + self.info.length as usize }
+
+    /// Returns the memory map
+    #[inline] pub fn mem_map(&self) -> &'static MemMapTag { self.mem_map }
+
+    /// Returns the kernel command line (which is empty if the bootloader
+    /// didn't pass one)
+    #[inline]
+    pub fn command_line(&self) -> &'static str {
+        self.info.command_line().unwrap_or("")
+    }
+
+    /// Returns the kernel's ELF sections
+    #[inline]
+    pub fn elf_sections(&self) -> &'static SectionsTag { self.elf_sections }
+
+    /// Returns the framebuffer the bootloader set up, if any
+    #[inline]
+    pub fn framebuffer(&self) -> Option<&'static FramebufferTag> {
+        self.framebuffer
+    }
+
+    /// Returns an iterator over the modules the bootloader loaded
+    #[inline] pub fn modules(&self) -> Modules { self.info.modules() }
+}
@@ -0,0 +1,133 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! The kernel command line.
+//!
+//! The bootloader passes along whatever follows the kernel's path in its
+//! config (`multiboot2 /boot/kernel.bin loglevel=debug noapic`, say). That's
+//! a list of whitespace-separated arguments, each either a `key=value` pair
+//! or a bare flag. Double quotes group whitespace into one argument, so a
+//! value can have spaces in it (`root="my disk"`); the quotes around a value
+//! aren't part of it.
+//!
+//! Nothing here allocates: the line is parsed again on every lookup, which
+//! is fine for something that's only consulted a few times during boot.
+//!
+//! The arguments we know about so far are:
+//!
+//!   - `noapic`: leave the local APIC alone, and handle everything with the
+//!     8259 PICs
+//!   - `quantum=<ticks>`: how many timer ticks a task runs for before it's
+//!     preempted. A tick is 10 ms with the APIC timer, and about 55 ms on
+//!     the PIT (with `noapic`)
+use util::once::Once;
+
+/// The command line we were booted with, once `init` has been called
+static COMMAND_LINE: Once<&'static str> = Once::new();
+
+/// One argument on a command line.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Arg<'a> { /// A bare word, like `noapic`
+                   Flag(&'a str)
+                 , /// A `key=value` pair, with any quotes taken off the
+                   /// value
+                   Pair(&'a str, &'a str)
+                 }
+
+/// Returns true for the characters that separate arguments
+#[inline]
+fn is_space(b: u8) -> bool { b == b' ' || b == b'\t' || b == b'\n' }
+
+/// Take one pair of double quotes off the ends of `s`, if they're there.
+///
+/// A value missing its closing quote (at the very end of the line) just has
+/// the opening one taken off.
+fn unquote(s: &str) -> &str {
+    let s = if s.starts_with('"') { &s[1..] } else { s };
+    if s.ends_with('"') { &s[..s.len() - 1] } else { s }
+}
+
+/// An iterator over the arguments on a command line.
+pub struct Args<'a> { rest: &'a str }
+
+impl<'a> Iterator for Args<'a> {
+    type Item = Arg<'a>;
+
+    fn next(&mut self) -> Option<Arg<'a>> {
+        // all of the delimiters are ASCII, so scanning for them a byte at a
+        // time can't land us in the middle of a character
+        let bytes = self.rest.as_bytes();
+        let start = match bytes.iter().position(|&b| !is_space(b)) {
+            Some(start) => start
+          , None => { self.rest = ""; return None }
+        };
+        let mut quoted = false;
+        let mut end = bytes.len();
+        for (i, &b) in bytes.iter().enumerate().skip(start) {
+            if b == b'"' {
+                quoted = !quoted;
+            } else if is_space(b) && !quoted {
+                end = i;
+                break
+            }
+        }
+        let word = &self.rest[start..end];
+        self.rest = &self.rest[end..];
+        Some(match word.find('=') {
+            Some(eq) => Arg::Pair(&word[..eq], unquote(&word[eq + 1..]))
+          , None => Arg::Flag(unquote(word))
+        })
+    }
+}
+
+/// A command line.
+#[derive(Debug, Copy, Clone)]
+pub struct CommandLine<'a>(pub &'a str);
+
+impl<'a> CommandLine<'a> {
+    /// Returns an iterator over the arguments
+    #[inline] pub fn args(&self) -> Args<'a> { Args { rest: self.0 } }
+
+    /// Returns the value of `key`, if it's on the command line.
+    ///
+    /// If `key` is given more than once, the last one counts.
+    pub fn get(&self, key: &str) -> Option<&'a str> {
+        self.args()
+            .filter_map(|arg| match arg {
+                Arg::Pair(k, value) if k == key => Some(value)
+              , _ => None
+            })
+            .last()
+    }
+
+    /// Returns true if the flag `name` is on the command line
+    pub fn has_flag(&self, name: &str) -> bool {
+        self.args().any(|arg| arg == Arg::Flag(name))
+    }
+}
+
+/// Remember the kernel command line the bootloader passed us.
+///
+/// Only the first call does anything.
+pub fn init(line: &'static str) {
+    COMMAND_LINE.call_once(|| line);
+}
+
+/// Returns the kernel command line (or an empty one, before `init`)
+#[inline]
+pub fn command_line() -> CommandLine<'static> {
+    CommandLine(COMMAND_LINE.get().map_or("", |line| *line))
+}
+
+/// Returns the value of `key` on the kernel command line, if it's there
+#[inline]
+pub fn get(key: &str) -> Option<&'static str> { command_line().get(key) }
+
+/// Returns true if the flag `name` is on the kernel command line
+#[inline]
+pub fn has_flag(name: &str) -> bool { command_line().has_flag(name) }
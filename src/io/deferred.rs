@@ -0,0 +1,97 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Output that couldn't be printed straight away.
+//!
+//! If an interrupt handler prints while the code it interrupted is holding
+//! the console lock, waiting for the lock would deadlock. Instead, `print!`
+//! puts the text here, and whoever next holds the console lock prints it.
+//!
+//! The buffer is lock-free on the writing side, since writers may well be
+//! interrupting each other. Space is claimed by bumping `RESERVED`; once a
+//! writer has copied its text in, it adds its length to `COMMITTED`. The
+//! buffer can only be read when the two agree, so that there's nobody
+//! halfway through writing. Only the console lock's holder ever reads it, so
+//! there's only ever one reader.
+use core::{cmp, fmt, ptr, slice, str};
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+/// How many bytes of output can be waiting at once
+pub const DEFERRED_SIZE: usize = 1024;
+
+static mut BUF: [u8; DEFERRED_SIZE] = [0; DEFERRED_SIZE];
+
+/// Number of bytes of `BUF` that writers have claimed
+static RESERVED: AtomicUsize = ATOMIC_USIZE_INIT;
+/// Number of claimed bytes that have actually been written
+static COMMITTED: AtomicUsize = ATOMIC_USIZE_INIT;
+/// Number of bytes at the start of `BUF` that have already been printed.
+/// Only the reader touches this.
+static FLUSHED: AtomicUsize = ATOMIC_USIZE_INIT;
+/// Number of bytes that didn't fit, since the last flush
+static DROPPED: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Put `s` in the buffer, to be printed by the next `flush`.
+///
+/// If it doesn't all fit, as much as will fit (up to a character boundary)
+/// is kept, and the rest is counted as dropped.
+pub fn defer(s: &str) {
+    let mut start = RESERVED.load(Ordering::SeqCst);
+    let mut n;
+    loop {
+        n = cmp::min(s.len(), DEFERRED_SIZE - start);
+        while n > 0 && !s.is_char_boundary(n) { n -= 1; }
+        let seen = RESERVED.compare_and_swap( start, start + n
+                                            , Ordering::SeqCst );
+        if seen == start {
+            break
+        }
+        start = seen;
+    }
+    unsafe {
+        ptr::copy_nonoverlapping( s.as_ptr()
+                                , BUF.as_mut_ptr().offset(start as isize), n );
+    }
+    COMMITTED.fetch_add(n, Ordering::SeqCst);
+    if n < s.len() {
+        DROPPED.fetch_add(s.len() - n, Ordering::SeqCst);
+    }
+}
+
+/// Write out everything waiting in the buffer to `out`.
+///
+/// This must only be called while holding the console lock. If someone is
+/// in the middle of adding to the buffer (an interrupt on another CPU, say),
+/// it's left alone until next time.
+pub fn flush<W: fmt::Write>(out: &mut W) {
+    let reserved = RESERVED.load(Ordering::SeqCst);
+    if reserved != COMMITTED.load(Ordering::SeqCst) {
+        return
+    }
+    let flushed = FLUSHED.load(Ordering::SeqCst);
+    if reserved > flushed {
+        let text = unsafe {
+            // `defer` only ever copies in whole `str`s
+            str::from_utf8_unchecked(slice::from_raw_parts(
+                BUF.as_ptr().offset(flushed as isize), reserved - flushed))
+        };
+        let _ = out.write_str(text);
+    }
+    let dropped = DROPPED.swap(0, Ordering::SeqCst);
+    if dropped > 0 {
+        let _ = write!(out, "[{} bytes of output lost]\n", dropped);
+    }
+    // if nobody's claimed any more since we looked, start again from the
+    // beginning; otherwise, remember how far we got
+    if RESERVED.compare_and_swap(reserved, 0, Ordering::SeqCst) == reserved {
+        COMMITTED.fetch_sub(reserved, Ordering::SeqCst);
+        FLUSHED.store(0, Ordering::SeqCst);
+    } else {
+        FLUSHED.store(reserved, Ordering::SeqCst);
+    }
+}
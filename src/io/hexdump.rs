@@ -0,0 +1,84 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Hex dumps, for staring at memory.
+//!
+//! Output looks like this, with the address of each line, sixteen bytes of
+//! hex, and the same bytes as ASCII (with `.` for anything unprintable):
+//!
+//! ```text
+//! 0x00000000000b8000  4f 2f 4b 2f 00 00 00 00  00 00 00 00 00 00 00 00  |O/K/............|
+//! ```
+use core::fmt::{self, Write};
+use core::slice;
+use super::{term, fmt_addr};
+
+/// Number of bytes printed on each line of a dump
+pub const BYTES_PER_LINE: usize = 16;
+
+/// Write a hexdump of `bytes` to `out`, labelling the first byte as being at
+/// address `base`.
+pub fn write_hexdump<W>(out: &mut W, base: usize, bytes: &[u8]) -> fmt::Result
+where W: Write {
+    for (n, line) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+        try!(write!(out, "{}  ", fmt_addr((base + n * BYTES_PER_LINE) as u64)));
+
+        // hex column, padded out if this is a short last line
+        for i in 0..BYTES_PER_LINE {
+            match line.get(i) {
+                Some(byte) => try!(write!(out, "{:02x} ", byte))
+              , None       => try!(out.write_str("   "))
+            }
+            // an extra space halfway across makes it easier to count
+            if i == BYTES_PER_LINE / 2 - 1 { try!(out.write_str(" ")) }
+        }
+
+        // ASCII column
+        try!(out.write_str(" |"));
+        for byte in line {
+            try!(out.write_char(match *byte {
+                0x20...0x7e => *byte as char
+              , _           => '.'
+            }));
+        }
+        try!(out.write_str("|\n"));
+    }
+    Ok(())
+}
+
+/// Print a hexdump of `bytes` to the console.
+///
+/// Addresses are printed relative to the start of the slice.
+pub fn hexdump_slice(bytes: &[u8]) {
+    let _ = write_hexdump(&mut *term::CONSOLE.lock(), 0, bytes);
+}
+
+/// Print a hexdump of `len` bytes of memory starting at `addr` to the
+/// console.
+///
+/// If `addr + len` would overflow the address space, only the memory up to
+/// the end of the address space is dumped.
+///
+/// # Unsafe due to
+///   - Reading arbitrary memory. If any part of the range isn't mapped,
+///     this will page fault partway through the dump, so make sure the whole
+///     range is mapped before dumping it.
+pub unsafe fn hexdump(addr: usize, len: usize) {
+    if addr == 0 {
+        println!("hexdump: refusing to dump from a null pointer");
+        return
+    }
+    let len = match addr.checked_add(len) {
+        Some(_) => len
+        // everything from `addr` up to and including the last byte; `addr`
+        // isn't zero, so this can't overflow
+      , None    => usize::max_value() - (addr - 1)
+    };
+    let bytes = slice::from_raw_parts(addr as *const u8, len);
+    let _ = write_hexdump(&mut *term::CONSOLE.lock(), addr, bytes);
+}
@@ -0,0 +1,101 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Wrappers for memory-mapped I/O.
+//!
+//! Device registers that are mapped into memory have to be accessed with
+//! volatile loads and stores; otherwise, the optimizer is free to assume
+//! that nobody else can see that memory, and may elide, merge, or reorder
+//! reads and writes to it. Drivers should go through these types rather than
+//! dereferencing raw pointers to device memory.
+use core::intrinsics::{volatile_load, volatile_store};
+use core::fmt;
+
+/// A value that is always read and written with volatile accesses.
+///
+/// `Volatile<T>` has the same layout as `T`, so it can be used for the
+/// fields of a `#[repr(C)]` struct describing a block of device registers.
+#[repr(C)]
+pub struct Volatile<T: Copy>(T);
+
+impl<T: Copy> Volatile<T> {
+
+    pub const fn new(value: T) -> Self { Volatile(value) }
+
+    /// Read the value, without letting the compiler elide the load.
+    #[inline]
+    pub fn read(&self) -> T {
+        unsafe { volatile_load(&self.0) }
+    }
+
+    /// Write `value`, without letting the compiler elide the store.
+    #[inline]
+    pub fn write(&mut self, value: T) {
+        unsafe { volatile_store(&mut self.0, value) }
+    }
+
+    /// Read the value, pass it to `f`, and write back the result.
+    #[inline]
+    pub fn update<F>(&mut self, f: F)
+    where F: FnOnce(T) -> T {
+        let value = self.read();
+        self.write(f(value));
+    }
+}
+
+impl<T> fmt::Debug for Volatile<T>
+where T: Copy + fmt::Debug {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Volatile({:?})", self.read())
+    }
+}
+
+/// A single memory-mapped device register of type `T`.
+///
+/// This is the MMIO equivalent of a `Port`: it just remembers where the
+/// register lives, and all accesses through it are volatile.
+pub struct Mmio<T: Copy> { reg: *mut Volatile<T> }
+
+// Device registers are shared with the hardware anyway; whoever owns an
+// `Mmio` is responsible for synchronizing access to it (e.g. with a `Mutex`).
+unsafe impl<T: Copy> Send for Mmio<T> { }
+unsafe impl<T: Copy> Sync for Mmio<T> { }
+
+impl<T: Copy> Mmio<T> {
+
+    /// Create a new `Mmio` for the register at `addr`.
+    ///
+    /// # Unsafe due to
+    ///   - The caller must ensure that `addr` is mapped, is suitably aligned
+    ///     for `T`, and actually refers to a device register of that size.
+    pub const unsafe fn new(addr: usize) -> Self {
+        Mmio { reg: addr as *mut Volatile<T> }
+    }
+
+    /// Returns the address of this register
+    #[inline] pub fn addr(&self) -> usize { self.reg as usize }
+
+    /// Read the register's current value.
+    #[inline]
+    pub fn read(&self) -> T {
+        unsafe { (*self.reg).read() }
+    }
+
+    /// Write `value` to the register.
+    #[inline]
+    pub fn write(&self, value: T) {
+        unsafe { (*self.reg).write(value) }
+    }
+
+    /// Read the register, pass its value to `f`, and write back the result.
+    #[inline]
+    pub fn update<F>(&self, f: F)
+    where F: FnOnce(T) -> T {
+        unsafe { (*self.reg).update(f) }
+    }
+}
@@ -18,13 +18,21 @@ macro_rules! println {
 
 macro_rules! print {
     ($($arg:tt)*) => ({
-            use core::fmt::Write;
-            $crate::io::term::CONSOLE.lock()
-                                     .write_fmt(format_args!($($arg)*))
-                                     .unwrap();
+            $crate::io::term::print_fmt(format_args!($($arg)*));
     });
 }
 
+pub mod deferred;
+pub mod hexdump;
+pub mod mmio;
+pub mod num;
+pub mod stack_writer;
+
+pub use self::hexdump::{hexdump, hexdump_slice};
+pub use self::mmio::{Volatile, Mmio};
+pub use self::num::{fmt_hex, fmt_oct, fmt_bin, fmt_addr};
+pub use self::stack_writer::StackWriter;
+
 /// This is basically a braindead reimplementation of the standard
 /// library's `Read` trait. Most of the methods available on the
 /// standard lib's `Read` are not yet implemented.
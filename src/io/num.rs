@@ -0,0 +1,124 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Formatting numbers in hex, octal and binary.
+//!
+//! `core::fmt` can do all of this, but getting the width right means
+//! remembering that `{:#018x}` counts the `0x`, and keeping every address
+//! column in the kernel's diagnostics in agreement is easier when they all
+//! go through `fmt_addr`. These format into a buffer on the stack, so they
+//! work anywhere, including while the heap is broken:
+//!
+//! ```ignore
+//! println!("{}  {}", fmt_addr(rip), fmt_bin(flags, 8));
+//! // 0x0000000000101a2c  0b00010110
+//! ```
+use core::{cmp, fmt, slice, str};
+
+/// The most digits a number can have (a `u64` in binary)
+pub const MAX_DIGITS: usize = 64;
+
+/// Number of hex digits in an address
+pub const ADDR_DIGITS: usize = 16;
+
+/// Digits, in order of value
+const DIGITS: &'static [u8; 16] = b"0123456789abcdef";
+
+/// A number formatted by one of the `fmt_` functions.
+///
+/// Use `as_str` or `digits` to get at the text, or just format it with `{}`.
+#[derive(Copy, Clone)]
+pub struct Num { buf: [u8; MAX_DIGITS + 2]
+               , /// Where the prefix starts
+                 start: usize
+               , /// Where the digits start, after the prefix
+                 digits: usize
+               }
+
+impl Num {
+    /// Format `value` in base `radix`, with at least `width` digits (padded
+    /// with zeroes) after `prefix`.
+    ///
+    /// # Panics
+    ///   - If `radix` isn't between 2 and 16
+    ///   - If `prefix` is longer than two bytes
+    pub fn new(value: u64, radix: u64, width: usize, prefix: &str) -> Num {
+        assert!(radix >= 2 && radix <= 16, "can't format in base {}", radix);
+        assert!(prefix.len() <= 2, "number prefix {:?} is too long", prefix);
+        let width = cmp::min(width, MAX_DIGITS);
+        let mut buf = [0u8; MAX_DIGITS + 2];
+        let mut i = buf.len();
+        let mut value = value;
+        // always at least one digit, so that zero comes out as "0"
+        loop {
+            i -= 1;
+            buf[i] = DIGITS[(value % radix) as usize];
+            value /= radix;
+            if value == 0 && buf.len() - i >= width { break }
+        }
+        let digits = i;
+        for &b in prefix.as_bytes().iter().rev() {
+            i -= 1;
+            buf[i] = b;
+        }
+        Num { buf: buf, start: i, digits: digits }
+    }
+
+    /// Returns the formatted number, including its prefix
+    #[inline]
+    pub fn as_str(&self) -> &str { self.slice(self.start) }
+
+    /// Returns just the digits, without the prefix
+    #[inline]
+    pub fn digits(&self) -> &str { self.slice(self.digits) }
+
+    fn slice(&self, from: usize) -> &str {
+        unsafe {
+            // everything in `buf` from `start` on is ASCII digits, or the
+            // prefix, which came from a `str`
+            str::from_utf8_unchecked(
+                slice::from_raw_parts( self.buf.as_ptr().offset(from as isize)
+                                     , self.buf.len() - from ))
+        }
+    }
+}
+
+impl fmt::Display for Num {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+impl fmt::Debug for Num {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Format `value` in hex, with a `0x` prefix and at least `width` digits
+#[inline]
+pub fn fmt_hex(value: u64, width: usize) -> Num {
+    Num::new(value, 16, width, "0x")
+}
+
+/// Format `value` in octal, with a `0o` prefix and at least `width` digits
+#[inline]
+pub fn fmt_oct(value: u64, width: usize) -> Num {
+    Num::new(value, 8, width, "0o")
+}
+
+/// Format `value` in binary, with a `0b` prefix and at least `width` digits
+#[inline]
+pub fn fmt_bin(value: u64, width: usize) -> Num {
+    Num::new(value, 2, width, "0b")
+}
+
+/// Format `addr` the way every address in the kernel's output should look:
+/// all sixteen hex digits, with a `0x` prefix.
+#[inline]
+pub fn fmt_addr(addr: u64) -> Num { fmt_hex(addr, ADDR_DIGITS) }
@@ -0,0 +1,77 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Formatting into a buffer on the stack.
+//!
+//! This is for formatting somewhere we can't allocate and can't write the
+//! output out yet: when `print!` finds the console locked, say, it formats
+//! into a `StackWriter` and defers the result until the console is free:
+//!
+//! ```ignore
+//! let mut w = StackWriter::new([0u8; 128]);
+//! let _ = w.write_fmt(args);
+//! deferred::defer(w.as_str());
+//! ```
+use core::{fmt, ptr, slice, str};
+use util::array::Array;
+
+/// A `fmt::Write` that formats into a fixed-size array.
+///
+/// If the formatted output doesn't fit, the rest of it is dropped (at a
+/// character boundary), and `is_truncated` returns true.
+pub struct StackWriter<A: Array<Item=u8>> { buf: A
+                                          , len: usize
+                                          , truncated: bool
+                                          }
+
+impl<A: Array<Item=u8>> StackWriter<A> {
+    /// Returns a new empty writer that formats into `buf`
+    pub fn new(buf: A) -> Self {
+        StackWriter { buf: buf, len: 0, truncated: false }
+    }
+
+    /// Returns the text written so far
+    pub fn as_str(&self) -> &str {
+        unsafe {
+            // we only ever copy in whole `str`s, cut at char boundaries
+            str::from_utf8_unchecked(
+                slice::from_raw_parts(self.buf.as_ptr(), self.len))
+        }
+    }
+
+    /// Returns the number of bytes written so far
+    #[inline] pub fn len(&self) -> usize { self.len }
+
+    /// Returns true if some output had to be dropped
+    #[inline] pub fn is_truncated(&self) -> bool { self.truncated }
+
+    /// Throw away everything written so far
+    #[inline]
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.truncated = false;
+    }
+}
+
+impl<A: Array<Item=u8>> fmt::Write for StackWriter<A> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let room = A::capacity() - self.len;
+        let mut n = if s.len() > room { room } else { s.len() };
+        if n < s.len() {
+            self.truncated = true;
+            // don't cut a multi-byte character in half
+            while n > 0 && !s.is_char_boundary(n) { n -= 1; }
+        }
+        unsafe {
+            let dst = self.buf.as_mut_ptr().offset(self.len as isize);
+            ptr::copy_nonoverlapping(s.as_ptr(), dst, n);
+        }
+        self.len += n;
+        Ok(())
+    }
+}
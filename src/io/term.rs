@@ -6,8 +6,18 @@
 //  Released under the terms of the MIT license. See `LICENSE` in the root
 //  directory of this repository for more information.
 //
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
 use vga::{Terminal, Palette, Color};
 use spin::Mutex;
+use multiboot::FramebufferTag;
+use arch::drivers::keyboard::{self, KEYBOARD};
+use arch::drivers::serial::COM1;
+use task::WaitQueue;
+use super::{deferred, StackWriter};
+
+/// ASCII backspace, as produced by the keyboard layouts
+const BACKSPACE: u8 = 0x08;
 
 /// The system's global VGA terminal
 pub static CONSOLE: Mutex<Terminal>
@@ -15,3 +25,132 @@ pub static CONSOLE: Mutex<Terminal>
          Palette::new(Color::LightGreen, Color::Black )
        , 0xB8000
     )});
+
+/// Set if the VGA text buffer isn't on screen, because the bootloader left
+/// us in a graphics mode
+static VGA_DISABLED: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Everywhere `print!` output goes: the VGA terminal, if it's on screen, and
+/// the serial port, if there is one.
+struct Outputs<'a> { vga: &'a mut Terminal }
+
+impl<'a> fmt::Write for Outputs<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if !VGA_DISABLED.load(Ordering::Relaxed) {
+            let _ = self.vga.write_str(s);
+        }
+        // `Serial` ignores writes if there's no port
+        if let Some(mut serial) = COM1.try_lock() {
+            let _ = serial.write_str(s);
+        }
+        Ok(())
+    }
+}
+
+/// Work out where `print!` output can go.
+///
+/// The VGA text buffer is used unless the bootloader's framebuffer tag says
+/// it set up a graphics mode instead (with no tag, we assume we're in the
+/// text mode the BIOS left us in), and then the console is sized to match
+/// whatever text mode we're in. The serial port is used if `COM1` finds a
+/// UART. Whichever of them are there get all the output.
+pub fn init_outputs(framebuffer: Option<&FramebufferTag>) {
+    let vga = framebuffer.map_or(true, |fb| fb.is_text());
+    VGA_DISABLED.store(!vga, Ordering::SeqCst);
+    if vga {
+        CONSOLE.lock().detect_dimensions();
+    }
+    let serial = COM1.lock().init();
+    if vga {
+        let (width, height) = {
+            let console = CONSOLE.lock();
+            (console.width(), console.height())
+        };
+        println!( "Console output: VGA text {}x{}, serial {}."
+                , width, height, if serial { "yes" } else { "no" } );
+    } else {
+        println!( "Console output: VGA text no, serial {}."
+                , if serial { "yes" } else { "no" } );
+    }
+}
+
+/// Print `args` to the console. This is what `print!` does.
+///
+/// If the console is locked, we might be an interrupt handler that
+/// interrupted whoever has the lock, so rather than wait for it, the text is
+/// deferred (up to 128 bytes of it), and printed the next time someone gets
+/// the lock. Whoever does get the lock prints anything deferred while they
+/// had it before letting go.
+///
+/// The console's lock also stands for the serial port's here, so the output
+/// goes to both of them in the same order.
+pub fn print_fmt(args: fmt::Arguments) {
+    match CONSOLE.try_lock() {
+        Some(mut console) => {
+            let mut out = Outputs { vga: &mut *console };
+            deferred::flush(&mut out);
+            let _ = out.write_fmt(args);
+            deferred::flush(&mut out);
+        }
+      , None => {
+            let mut w = StackWriter::new([0u8; 128]);
+            let _ = w.write_fmt(args);
+            deferred::defer(w.as_str());
+        }
+    }
+}
+
+/// A `fmt::Write` that prints to the console the same way `print!` does,
+/// for handing to things that write to a `fmt::Write`.
+pub struct Printer;
+
+impl fmt::Write for Printer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        print_fmt(format_args!("{}", s));
+        Ok(())
+    }
+}
+
+/// Tasks waiting for keyboard input.
+///
+/// The keyboard interrupt handler wakes these up whenever a key is pressed.
+pub static INPUT_WAITERS: WaitQueue = WaitQueue::new();
+
+/// Read a line of input from the keyboard into `buf`, echoing it to the
+/// console.
+///
+/// Backspace erases the last character (on screen, too), and Enter ends the
+/// line. Any other non-printable keys are ignored, as is anything typed once
+/// the buffer is full. The trailing newline is not stored in `buf`.
+///
+/// # Returns
+///   - The number of bytes read into `buf`
+pub fn read_line(buf: &mut [u8]) -> usize {
+    let mut len = 0;
+    loop {
+        // don't hold the keyboard lock while we echo, so that the keyboard
+        // interrupt handler isn't kept waiting.
+        let event = KEYBOARD.lock().poll();
+        if event.is_none() {
+            // sleep until the keyboard has something for us
+            INPUT_WAITERS.wait_until(keyboard::has_input);
+            continue
+        }
+        match event.and_then(|e| e.ascii) {
+            Some(b'\n') => {
+                CONSOLE.lock().write_byte(b'\n').update_cursor();
+                return len
+            }
+          , Some(BACKSPACE) if len > 0 => {
+                len -= 1;
+                CONSOLE.lock().backspace();
+            }
+          , Some(c @ 0x20...0x7e) if len < buf.len() => {
+                buf[len] = c;
+                len += 1;
+                CONSOLE.lock().write_byte(c).update_cursor();
+            }
+          , _ => { }
+        }
+    }
+}
@@ -18,102 +18,210 @@
 #![feature( no_std
           , lang_items)]
 #![feature( const_fn
+          , core_intrinsics
           , core_slice_ext
           , slice_patterns
           )]
 #![no_std]
+// we provide our own `memcpy` and friends (in `mem`), so LLVM mustn't turn
+// loops into calls to them
+#![no_builtins]
 
-extern crate rlibc;
 extern crate spin;
 extern crate sos_multiboot2 as multiboot;
 extern crate sos_alloc as alloc;
 #[macro_use] extern crate sos_vga as vga;
-#[macro_use] extern crate bitflags;
 
+#[macro_use] pub mod util;
+pub mod mem;
 pub mod arch;
 #[macro_use] pub mod io;
-pub mod util;
 pub mod panic;
 pub mod memory;
+pub mod vfs;
+pub mod monitor;
+pub mod task;
+pub mod boot;
+pub mod cmdline;
 
 use arch::cpu;
+use boot::{Phase, set_boot_phase};
 
 use alloc::Allocator;
 use alloc::simple::SimpleAreaAllocator;
+use memory::PAddr;
 
 /// Kernel main loop
 ///
 /// The kernel main loop expects to be passed the address of a valid
-/// Multiboot 2 info struct. It's the bootloader's responsibility to ensure
-/// that this is passed in the correct register as expected by the calling
-/// convention (`edi` on x86). If this isn't there, you can expect to have a
-/// bad problem and not go to space today.
+/// Multiboot 2 info struct and the bootloader's magic number, in the
+/// registers the calling convention expects (`rdi` and `rsi`), which is
+/// `boot.asm`'s job. If the magic number isn't right, we stop before looking
+/// at the info, rather than having a bad problem and not going to space
+/// today.
 #[no_mangle]
-pub extern fn kernel_main(multiboot_addr: usize) {
+pub extern fn kernel_main(multiboot_addr: usize, multiboot_magic: u32) {
     io::term::CONSOLE.lock().clear();
 
     println!("Hello from the kernel!");
 
-    // Unpack multiboot tag
-    let boot_info = unsafe { multiboot::Info::from(multiboot_addr) };
-    let mmap_tag // Extract the memory map tag from the multiboot info
-        = boot_info.mem_map()
-                   .expect("Memory map tag required!");
+    // if the last boot didn't finish, it probably triple faulted
+    match boot::last_boot_phase() {
+        Phase::Booted | Phase::Unknown => { }
+      , phase => println!("The last boot died during phase {:?}!", phase)
+    }
+    set_boot_phase(Phase::Started);
 
-    println!("Detected memory areas:");
-    for a in mmap_tag.areas() {
-        println!("     start: {:#08x}, end: {:#08x}"
-                , a.base, a.length );
+    // point `gs` at the boot CPU's per-CPU data, so there's a place to keep
+    // track of the current task
+    cpu::percpu::init_bsp();
+    boot::boot_log("per-CPU data");
+
+    // this has to happen before anything uses floating point or SSE
+    set_boot_phase(Phase::Fpu);
+    if !cpu::fpu::init_fpu() {
+        println!("No FPU/SSE support detected! Floating point won't work.");
     }
 
-    let elf_sections_tag // Extract ELF sections tag from the multiboot info
-        = boot_info.elf64_sections()
-                   .expect("ELF sections tag required!");
+    set_boot_phase(Phase::Tsc);
+    println!( "Calibrated the TSC at {} MHz."
+            , cpu::tsc::calibrate_tsc() / 1_000_000 );
 
-    println!("Detecting kernel ELF sections:");
-    // for section in elf_sections_tag.sections() {
-    //
-    // }
+    // Unpack multiboot tag
+    set_boot_phase(Phase::Multiboot);
+    let boot_info = match unsafe {
+        boot::BootInfo::from_multiboot(multiboot_magic, multiboot_addr)
+    } {
+        Ok(info) => info
+      , Err(why) => {
+            println!("Can't boot: {}", why);
+            cpu::halt()
+        }
+    };
+    io::term::init_outputs(boot_info.framebuffer());
+    boot::boot_log("console outputs");
+    cmdline::init(boot_info.command_line());
+    if !boot_info.command_line().is_empty() {
+        println!("Kernel command line: {}", boot_info.command_line());
+    }
+    let mmap_tag = boot_info.mem_map();
+
+    println!("Detected memory areas:");
+    memory::map::set_memory_map(mmap_tag);
+    memory::print_memory_map();
+    boot::boot_log("memory map");
 
-    let kernel_begin
-        = elf_sections_tag.sections()
-            .map(|s| {
-                println!("     address: {:#08x}, size: {:#08x}, flags: {:#08x}"
-                        , s.address
-                        , s.length
-                        , s.flags );
-                s.address })
-            .min()
-            .expect("Could not find kernel start section!\
-                    \nSomething is deeply wrong.");
-
-    let mut n_elf_sections = 0;
-    let kernel_end
-        = elf_sections_tag.sections()
-            .map(|s| { n_elf_sections += 1;
-                     s.address })
-            .max()
-            .expect("Could not find kernel end section!\
-                    \nSomething is deeply wrong.");
-
-    println!( "Detected {} kernel ELF sections.", n_elf_sections);
-    println!( "Kernel begins at {:#x} and ends at {:#x}."
-             , kernel_begin, kernel_end );
-
-    let multiboot_end = multiboot_addr + boot_info.length as usize;
+    let kernel = arch::sections::kernel_image();
+
+    let multiboot_end = boot_info.end();
 
     println!( "Multiboot info begins at {:#x} and ends at {:#x}."
              , multiboot_addr, multiboot_end);
 
-    let mut alloc
-        = SimpleAreaAllocator::new( kernel_begin as usize
-                                  , kernel_end as usize
-                                  , multiboot_addr, multiboot_end
-                                  , mmap_tag.areas());
+    set_boot_phase(Phase::Allocator);
+    // the allocator wants the kernel's last byte, not the address after it
+    *memory::frame::FRAME_ALLOCATOR.lock()
+        = Some(SimpleAreaAllocator::new( kernel.start.as_u64() as usize
+                                       , kernel.end.as_u64() as usize - 1
+                                       , multiboot_addr, multiboot_end
+                                       , mmap_tag.areas()));
+
+    // keep the allocator away from anything the firmware says is reserved,
+    // from the VGA text buffer, and from where the AP trampoline goes
+    for area in mmap_tag.all_areas().filter(|a| !a.is_available()) {
+        memory::frame::reserve( PAddr::from_u64(area.base)
+                              , PAddr::from_u64(area.base + area.length) );
+    }
+    memory::frame::reserve( PAddr::from_u64(0xB8000)
+                          , PAddr::from_u64( 0xB8000
+                                           + vga::BUFFER_BYTES as u64 ) );
+    memory::frame::reserve( PAddr::from_u64(cpu::smp::TRAMPOLINE_ADDR)
+                          , PAddr::from_u64( cpu::smp::TRAMPOLINE_ADDR
+                                           + alloc::PAGE_SIZE as u64 ) );
+    // the initrd is still sitting wherever the bootloader put it
+    for module in boot_info.modules() {
+        memory::frame::reserve( PAddr::from_u64(module.mod_start as u64)
+                              , PAddr::from_u64(module.mod_end as u64) );
+    }
 
     // alloc.allocate(0,0);
 
     println!( "Created initial allocator." );
+    boot::boot_log("reserved frames");
+
+    // this has to come before anything else allocates frames, so that the
+    // frames the heap ends up in are still free
+    match memory::heap::init_heap(memory::heap::DEFAULT_HEAP_PAGES) {
+        Some(start) => println!( "Set up a {} KiB heap at {}."
+                               , memory::heap::DEFAULT_HEAP_PAGES
+                                    * alloc::PAGE_SIZE / 1024
+                               , start )
+      , None => println!("Couldn't find anywhere to put the heap!")
+    }
+    boot::boot_log("heap");
+
+    // `make test` builds with `--cfg selftest`, runs us in QEMU, and reads
+    // the result from the exit status
+    if cfg!(selftest) {
+        use arch::drivers::qemu::{self, ExitCode};
+        use memory::heap_stress;
+        match util::ring_buffer::self_check() {
+            Ok(()) => println!("selftest: ring buffer checks passed")
+          , Err(why) => {
+                println!("selftest: ring buffer check failed: {}", why);
+                qemu::exit(ExitCode::Failure)
+            }
+        }
+        match alloc::arena::self_check() {
+            Ok(()) => println!("selftest: arena checks passed")
+          , Err(why) => {
+                println!("selftest: arena check failed: {}", why);
+                qemu::exit(ExitCode::Failure)
+            }
+        }
+        let seed = cpu::rand::seed();
+        println!("selftest: heap stress test, seed {:#x}", seed);
+        match heap_stress::stress(heap_stress::DEFAULT_OPS, seed) {
+            Ok(stats) => {
+                println!( "selftest: heap stress test passed ({} allocations, \
+                           {} frees)", stats.allocs, stats.frees );
+                qemu::exit(ExitCode::Success)
+            }
+          , Err(why) => {
+                println!( "selftest: heap stress test failed at operation {}: \
+                           {:?}", why.op, why.error );
+                qemu::exit(ExitCode::Failure)
+            }
+        }
+    }
+
+    // from here on, kernel_main is task 0
+    task::scheduler::init();
+    task::work::start();
+    boot::boot_log("scheduler");
+
+    // the scheduler's idle loop and `read_line` both sleep until an
+    // interrupt wakes them, so this has to happen before anything waits
+    set_boot_phase(Phase::Interrupts);
+    cpu::interrupts::initialize();
+    boot::boot_log("interrupts");
+    // only now do we know which timer is ticking, and so how long a tick is
+    println!( "Preempting tasks every {} timer ticks ({} ms at {} Hz)."
+            , task::scheduler::default_quantum()
+            , task::scheduler::default_quantum_ms()
+            , cpu::interrupts::timer_hz() );
+
+    // If the bootloader gave us an initrd, load it into the ramfs and
+    // mount that as the root filesystem.
+    set_boot_phase(Phase::Initrd);
+    if let Some(initrd) = boot_info.modules().next() {
+        println!( "Loading initrd ({} bytes) from {:#x}."
+                 , initrd.len(), initrd.mod_start );
+        match vfs::initrd::load_initrd(unsafe { initrd.data() }) {
+            Ok(())   => vfs::mount_root(&vfs::ramfs::RAMFS)
+          , Err(why) => println!("Could not load initrd: {:?}", why)
+        }
+    }
 
     // for i in 0.. {
     //     if let None = alloc.allocate() {
@@ -121,9 +229,8 @@ pub extern fn kernel_main(multiboot_addr: usize) {
     //         break;
     //     }
     // }
-    // println!("Intializing interrupts...");
-    // cpu::interrupts::initialize()
-
-    loop { }
+    set_boot_phase(Phase::Booted);
+    boot::print_banner();
+    monitor::run()
 
 }
@@ -0,0 +1,157 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Memory intrinsics.
+//!
+//! The compiler assumes that `memcpy`, `memmove`, `memset`, and `memcmp` are
+//! always available, and will happily emit calls to them (e.g. for struct
+//! copies or slice operations). We don't have a libc to get them from, so we
+//! provide them here.
+//!
+//! These copy a machine word at a time where they can, and fall back to
+//! copying bytes for any part of the region that isn't word-aligned. Note
+//! that the crate is `#![no_builtins]`, since otherwise LLVM could recognize
+//! the loops in here as `memcpy` and turn them into calls to themselves.
+
+#[cfg(target_pointer_width = "64")] const WORD_SIZE: usize = 8;
+#[cfg(target_pointer_width = "32")] const WORD_SIZE: usize = 4;
+const WORD_MASK: usize = WORD_SIZE - 1;
+
+type Word = usize;
+
+/// Returns true if `a` and `b` are equally far from a word boundary, so that
+/// once one of them is word-aligned, the other will be too.
+#[inline(always)]
+fn aligned_together(a: *const u8, b: *const u8) -> bool {
+    (a as usize ^ b as usize) & WORD_MASK == 0
+}
+
+/// Copy `n` bytes from `src` to `dest`, lowest address first.
+///
+/// This is safe for overlapping regions as long as `dest` is below `src`.
+#[inline(always)]
+unsafe fn copy_forward(dest: *mut u8, src: *const u8, n: usize) {
+    let mut i = 0;
+    if aligned_together(dest, src) {
+        // copy bytes until we reach a word boundary...
+        while i < n && (dest as usize + i) & WORD_MASK != 0 {
+            *dest.offset(i as isize) = *src.offset(i as isize);
+            i += 1;
+        }
+        // ...then copy as many whole words as we can
+        while i + WORD_SIZE <= n {
+            *(dest.offset(i as isize) as *mut Word)
+                = *(src.offset(i as isize) as *const Word);
+            i += WORD_SIZE;
+        }
+    }
+    // copy whatever's left over (or everything, if we couldn't align)
+    while i < n {
+        *dest.offset(i as isize) = *src.offset(i as isize);
+        i += 1;
+    }
+}
+
+/// Copy `n` bytes from `src` to `dest`, highest address first.
+///
+/// This is safe for overlapping regions as long as `dest` is above `src`.
+#[inline(always)]
+unsafe fn copy_backward(dest: *mut u8, src: *const u8, n: usize) {
+    let mut i = n;
+    if aligned_together(dest, src) {
+        while i > 0 && (dest as usize + i) & WORD_MASK != 0 {
+            i -= 1;
+            *dest.offset(i as isize) = *src.offset(i as isize);
+        }
+        while i >= WORD_SIZE {
+            i -= WORD_SIZE;
+            *(dest.offset(i as isize) as *mut Word)
+                = *(src.offset(i as isize) as *const Word);
+        }
+    }
+    while i > 0 {
+        i -= 1;
+        *dest.offset(i as isize) = *src.offset(i as isize);
+    }
+}
+
+/// Copy `n` bytes from `src` to `dest`. The regions must not overlap.
+#[no_mangle]
+pub unsafe extern "C" fn memcpy(dest: *mut u8, src: *const u8, n: usize)
+                                -> *mut u8 {
+    copy_forward(dest, src, n);
+    dest
+}
+
+/// Copy `n` bytes from `src` to `dest`. The regions may overlap.
+#[no_mangle]
+pub unsafe extern "C" fn memmove(dest: *mut u8, src: *const u8, n: usize)
+                                 -> *mut u8 {
+    if (dest as usize) <= (src as usize) {
+        copy_forward(dest, src, n);
+    } else {
+        // `dest` is above `src`, so if they overlap, copying forwards would
+        // clobber the end of `src` before we got to it
+        copy_backward(dest, src, n);
+    }
+    dest
+}
+
+/// Fill `n` bytes starting at `s` with the byte `c`.
+#[no_mangle]
+pub unsafe extern "C" fn memset(s: *mut u8, c: i32, n: usize) -> *mut u8 {
+    let byte = c as u8;
+    let mut i = 0;
+    while i < n && (s as usize + i) & WORD_MASK != 0 {
+        *s.offset(i as isize) = byte;
+        i += 1;
+    }
+    // `Word::max_value() / 0xff` is `0x0101...01`, so this puts `byte` in
+    // every byte of the word
+    let word = (Word::max_value() / 0xff) * byte as Word;
+    while i + WORD_SIZE <= n {
+        *(s.offset(i as isize) as *mut Word) = word;
+        i += WORD_SIZE;
+    }
+    while i < n {
+        *s.offset(i as isize) = byte;
+        i += 1;
+    }
+    s
+}
+
+/// Compare the first `n` bytes at `s1` and `s2`.
+///
+/// # Returns
+///   - zero if they're the same
+///   - the difference between the first pair of bytes that aren't
+#[no_mangle]
+pub unsafe extern "C" fn memcmp(s1: *const u8, s2: *const u8, n: usize)
+                                -> i32 {
+    let mut i = 0;
+    if aligned_together(s1, s2) {
+        while i < n && (s1 as usize + i) & WORD_MASK != 0 {
+            let (a, b) = (*s1.offset(i as isize), *s2.offset(i as isize));
+            if a != b { return a as i32 - b as i32 }
+            i += 1;
+        }
+        // skip over whole words that are equal; once we find a word that
+        // isn't, the byte loop below will find where they differ
+        while i + WORD_SIZE <= n
+            && *(s1.offset(i as isize) as *const Word)
+                == *(s2.offset(i as isize) as *const Word) {
+            i += WORD_SIZE;
+        }
+    }
+    while i < n {
+        let (a, b) = (*s1.offset(i as isize), *s2.offset(i as isize));
+        if a != b { return a as i32 - b as i32 }
+        i += 1;
+    }
+    0
+}
@@ -1,24 +1,148 @@
 use core::fmt;
 
+/// Number of bits of a virtual address that are the offset into its page
+const PAGE_SHIFT: usize = 12;
+/// Number of bits of a virtual address that index each level of page table
+const INDEX_BITS: usize = 9;
+/// Mask for one page table index
+const INDEX_MASK: usize = (1 << INDEX_BITS) - 1;
+
+/// Number of bits of a virtual address that four-level paging translates.
+///
+/// The bits above these have to be copies of the top one (bit 47), which
+/// makes the address _canonical_; anything else is a general protection
+/// fault. That splits the address space into a lower half and a higher
+/// half, with a huge hole between them.
+pub const VADDR_BITS: usize = 48;
+/// The address just past the end of the lower half
+pub const LOWER_HALF_END: usize = 1 << (VADDR_BITS - 1);
+/// The first address in the higher half
+pub const HIGHER_HALF_START: usize = !(LOWER_HALF_END - 1);
+
+/// Number of bits a physical address can have (the most any x86_64 CPU
+/// supports, and the most a page table entry can hold)
+pub const PADDR_BITS: usize = 52;
+
 /// A virtual address is a machine-sized unsigned integer
 #[derive(Eq, PartialEq, Ord, PartialOrd, Copy, Clone)]
 pub struct VAddr(usize);
 
-impl fmt::Debug for VAddr {
+impl VAddr {
+    #[inline] pub const fn from_usize(addr: usize) -> Self { VAddr(addr) }
+    #[inline] pub fn as_usize(&self) -> usize { self.0 }
+
+    /// Returns true if this address is canonical: in one half of the address
+    /// space or the other, and not in the hole between them
+    #[inline]
+    pub fn is_canonical(&self) -> bool {
+        self.0 < LOWER_HALF_END || self.0 >= HIGHER_HALF_START
+    }
+
+    /// Returns true if this address is in the higher half
+    #[inline]
+    pub fn is_higher_half(&self) -> bool { self.0 >= HIGHER_HALF_START }
+
+    /// Returns the index into the level `level` page table (1 for the P1,
+    /// up to 4 for the P4) of the entry that maps this address
+    #[inline]
+    pub fn table_index(&self, level: usize) -> usize {
+        (self.0 >> (PAGE_SHIFT + INDEX_BITS * (level - 1))) & INDEX_MASK
+    }
+
+    /// Returns the index of this address's entry in the P4 table
+    #[inline] pub fn p4_index(&self) -> usize { self.table_index(4) }
+    /// Returns the index of this address's entry in its P3 table
+    #[inline] pub fn p3_index(&self) -> usize { self.table_index(3) }
+    /// Returns the index of this address's entry in its P2 table
+    #[inline] pub fn p2_index(&self) -> usize { self.table_index(2) }
+    /// Returns the index of this address's entry in its P1 table
+    #[inline] pub fn p1_index(&self) -> usize { self.table_index(1) }
+
+    /// Returns the offset of this address into its (4 KiB) page
+    #[inline]
+    pub fn page_offset(&self) -> usize { self.0 & ((1 << PAGE_SHIFT) - 1) }
+}
+
+impl fmt::Display for VAddr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{:#x}", self.0)
     }
 }
 
+impl fmt::Debug for VAddr {
+    /// Formats as `VAddr(0x...)`; the alternate form (`{:#?}`) also shows
+    /// the page table indices and page offset the address breaks down into.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if f.alternate() {
+            write!( f, "VAddr({:#x} [P4 {}, P3 {}, P2 {}, P1 {}, offset {:#x}])"
+                  , self.0, self.p4_index(), self.p3_index(), self.p2_index()
+                  , self.p1_index(), self.page_offset() )
+        } else {
+            write!(f, "VAddr({:#x})", self.0)
+        }
+    }
+}
 
-/// A physical address is a 64-bit nsigned integer
+
+/// A physical address is a 64-bit unsigned integer
 #[derive(Eq, PartialEq, Ord, PartialOrd, Copy, Clone)]
 pub struct PAddr(u64);
 
-impl fmt::Debug for PAddr {
+impl PAddr {
+    #[inline] pub const fn from_u64(addr: u64) -> Self { PAddr(addr) }
+    #[inline] pub fn as_u64(&self) -> u64 { self.0 }
+}
+
+impl fmt::Display for PAddr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{:#x}", self.0)
     }
 }
 
+impl fmt::Debug for PAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PAddr({:#x})", self.0)
+    }
+}
+
+/// Where the direct map of physical memory starts.
+///
+/// `boot.asm` maps the first gigabyte of physical memory here, in the first
+/// P4 entry of the higher half, as well as at address zero. This is only a
+/// view of physical memory: the kernel image is linked at, and runs from,
+/// its identity-mapped low addresses, and the identity map stays. Code that
+/// has a physical address to get at (a page table, a frame being zeroed or
+/// copied) should go through `phys_to_virt` rather than assume the address
+/// is mapped where it is.
+pub const PHYS_OFFSET: usize = 0xffff_8000_0000_0000;
+
+/// How much physical memory the direct map covers (1 GiB)
+pub const PHYS_MAP_SIZE: usize = 1 << 30;
+
+/// Returns the address of physical address `addr` in the direct map.
+///
+/// # Panics
+///   - If `addr` is past the end of the direct map
+#[inline]
+pub fn phys_to_virt(addr: PAddr) -> VAddr {
+    assert!( (addr.as_u64() as usize) < PHYS_MAP_SIZE
+           , "{:?} is outside the direct map of physical memory", addr );
+    VAddr::from_usize(addr.as_u64() as usize + PHYS_OFFSET)
+}
+
+/// Returns the physical address that `addr` in the direct map points at.
+///
+/// # Returns
+///   - `None` if `addr` isn't in the direct map. This doesn't walk the page
+///     tables, so it knows nothing about any other mapping.
+#[inline]
+pub fn virt_to_phys(addr: VAddr) -> Option<PAddr> {
+    let addr = addr.as_usize();
+    if addr >= PHYS_OFFSET && addr - PHYS_OFFSET < PHYS_MAP_SIZE {
+        Some(PAddr::from_u64((addr - PHYS_OFFSET) as u64))
+    } else {
+        None
+    }
+}
+
 // pub struct
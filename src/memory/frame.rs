@@ -0,0 +1,220 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Physical frame allocation.
+//!
+//! This wraps the kernel's frame allocator, and keeps a reference count for
+//! each frame, so that frames can be shared between several mappings (e.g.
+//! for copy-on-write) and we can tell when the last mapping goes away.
+//!
+//! The simple frame allocator can't take frames back, so frames whose last
+//! mapping has gone away are kept on a free list here instead, and handed
+//! out again before the allocator is asked for more.
+//!
+//! The page fault handler allocates and releases frames (for copy-on-write
+//! pages), so it can take any of the locks here. To keep that from
+//! deadlocking, each lock is only ever held with interrupts disabled, none
+//! of them is ever taken while another is held, and nothing done while
+//! holding one can fault (we only touch frames through the direct map).
+use core::ptr;
+use spin::Mutex;
+use arch::cpu;
+use arch::cpu::paging::Frame;
+use alloc::{Allocator, PAGE_SIZE};
+use alloc::simple::SimpleAreaAllocator;
+use super::{PAddr, phys_to_virt};
+
+/// Number of frames we keep reference counts for.
+///
+/// This covers the identity-mapped first gigabyte of physical memory, which
+/// is the only memory we can currently get at.
+pub const MAX_FRAMES: usize = (1 << 30) / PAGE_SIZE;
+
+/// The kernel's frame allocator, once it's been set up
+pub static FRAME_ALLOCATOR: Mutex<Option<SimpleAreaAllocator>>
+    = Mutex::new(None);
+
+/// Reference counts for each frame, indexed by frame number.
+///
+/// A count of zero means nobody is tracking the frame (it's either free, or
+/// was never handed out by the frame allocator, such as the kernel's own
+/// frames).
+static REF_COUNTS: Mutex<[u8; MAX_FRAMES]> = Mutex::new([0; MAX_FRAMES]);
+
+/// Marks the end of the free list. Frame 0 can be allocated, so this can't
+/// be zero.
+const FREE_LIST_END: u64 = !0;
+
+/// The first frame on the free list.
+///
+/// Each free frame holds the address of the next one in its first eight
+/// bytes, written through the direct map.
+static FREE_LIST: Mutex<u64> = Mutex::new(FREE_LIST_END);
+
+/// Put `frame` on the free list.
+fn free(frame: PAddr) {
+    cpu::without_interrupts(|| {
+        let mut head = FREE_LIST.lock();
+        unsafe { *(phys_to_virt(frame).as_usize() as *mut u64) = *head };
+        *head = frame.as_u64();
+    })
+}
+
+/// Take a frame off the free list, if there's one on it.
+fn take_free() -> Option<PAddr> {
+    cpu::without_interrupts(|| {
+        let mut head = FREE_LIST.lock();
+        if *head == FREE_LIST_END {
+            return None
+        }
+        let frame = PAddr::from_u64(*head);
+        *head = unsafe { *(phys_to_virt(frame).as_usize() as *const u64) };
+        Some(frame)
+    })
+}
+
+#[inline]
+fn frame_number(frame: PAddr) -> usize {
+    let number = Frame::containing(frame).number() as usize;
+    assert!( number < MAX_FRAMES
+           , "frame {:?} is outside of tracked physical memory", frame );
+    number
+}
+
+/// Stop the frame allocator from ever handing out the physical memory from
+/// `start` up to (but not including) `end`.
+///
+/// Device memory (like the local APIC's registers) and any ranges the
+/// firmware says are reserved should be passed to this, in case they overlap
+/// an area the bootloader claims is available.
+///
+/// # Returns
+///   - `false` if the frame allocator hasn't been set up, or has run out of
+///     room to remember reserved ranges
+pub fn reserve(start: PAddr, end: PAddr) -> bool {
+    cpu::without_interrupts(|| {
+        FRAME_ALLOCATOR.lock()
+            .as_mut()
+            .map_or(false, |alloc| alloc.reserve( start.as_u64() as usize
+                                                , end.as_u64() as usize ))
+    })
+}
+
+/// Returns true if the frame containing `addr` must not be allocated.
+///
+/// Drivers for memory-mapped devices can use this to check that their
+/// registers were reserved.
+pub fn is_reserved(addr: PAddr) -> bool {
+    cpu::without_interrupts(|| {
+        FRAME_ALLOCATOR.lock()
+            .as_ref()
+            .map_or(false, |alloc| alloc.is_reserved(addr.as_u64() as usize))
+    })
+}
+
+/// Allocate a frame with a reference count of one.
+///
+/// Frames that have been released are reused first.
+///
+/// # Returns
+///   - `Some(PAddr)` with the address of the new frame
+///   - `None` if we're out of frames or the frame allocator hasn't been set up
+pub fn allocate_frame() -> Option<PAddr> {
+    let frame = take_free().or_else(|| cpu::without_interrupts(|| {
+        FRAME_ALLOCATOR.lock()
+            .as_mut()
+            .and_then(|alloc| unsafe { alloc.allocate(PAGE_SIZE, PAGE_SIZE) })
+            .map(|ptr| PAddr::from_u64(ptr as u64))
+    }));
+    frame.map(|frame| {
+        let number = frame_number(frame);
+        cpu::without_interrupts(|| REF_COUNTS.lock()[number] = 1);
+        frame
+    })
+}
+
+/// Allocate a frame filled with zeroes, with a reference count of one.
+///
+/// Anything that will be used as a page table must come from here, since
+/// the CPU would follow whatever garbage a fresh frame happens to contain.
+/// Every frame we can allocate is in the direct map, so it's zeroed through
+/// that, without needing a temporary mapping.
+///
+/// The simple frame allocator never gets frames back, so there's no way to
+/// know that a frame is already zero, and this always clears it.
+///
+/// # Returns
+///   - `Some(PAddr)` with the address of the new frame
+///   - `None` if we're out of frames or the frame allocator hasn't been set up
+pub fn allocate_zeroed_frame() -> Option<PAddr> {
+    allocate_frame().map(|frame| {
+        unsafe {
+            ptr::write_bytes( phys_to_virt(frame).as_usize() as *mut u8
+                            , 0, PAGE_SIZE );
+        }
+        frame
+    })
+}
+
+/// Returns the number of mappings currently sharing `frame`.
+///
+/// Frames that aren't tracked are assumed to have exactly one owner.
+pub fn ref_count(frame: PAddr) -> usize {
+    let number = frame_number(frame);
+    match cpu::without_interrupts(|| REF_COUNTS.lock()[number]) {
+        0 => 1
+      , n => n as usize
+    }
+}
+
+/// Record that another mapping now shares `frame`.
+///
+/// # Panics
+///   - If this would overflow the frame's reference count
+pub fn share_frame(frame: PAddr) {
+    let number = frame_number(frame);
+    let shared = cpu::without_interrupts(|| {
+        let mut counts = REF_COUNTS.lock();
+        let count = &mut counts[number];
+        if *count == u8::max_value() {
+            return false
+        }
+        *count = if *count == 0 { 2 } else { *count + 1 };
+        true
+    });
+    // panic only once we've let go of the lock
+    assert!(shared, "frame {:?} is shared too many times", frame);
+}
+
+/// Record that a mapping of `frame` has gone away.
+///
+/// When the last mapping goes, the frame is freed, so it mustn't be touched
+/// after that. Frames that were never tracked (like the kernel's own) are
+/// never freed.
+///
+/// # Returns
+///   - The number of mappings still sharing the frame
+pub fn release_frame(frame: PAddr) -> usize {
+    let number = frame_number(frame);
+    let remaining = match cpu::without_interrupts(|| {
+        let mut counts = REF_COUNTS.lock();
+        let count = &mut counts[number];
+        match *count {
+            0 => None
+          , n => { *count = n - 1; Some(*count) }
+        }
+    }) {
+        Some(remaining) => remaining
+        // never tracked, so it isn't ours to free
+      , None => return 0
+    };
+    if remaining == 0 {
+        free(frame);
+    }
+    remaining as usize
+}
@@ -0,0 +1,102 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Finding somewhere to put the kernel heap.
+//!
+//! The heap is a physically contiguous run of frames, taken from the top of
+//! one of the available areas in the bootloader's memory map and reached
+//! through the direct map. The frame allocator hands out frames from the
+//! bottom of memory up, so the top of an area is the part least likely to
+//! have been handed out already; even so, the heap should be set up before
+//! anything else allocates frames.
+use core::{cmp, ptr, slice};
+use alloc::PAGE_SIZE;
+use alloc::buddy::FreeList;
+use alloc::buddy::system;
+use util::align_down;
+use super::{frame, map, PAddr, PHYS_MAP_SIZE, phys_to_virt};
+
+/// The size of the heap (in pages) if nobody asks for anything else
+pub const DEFAULT_HEAP_PAGES: usize = 256;
+
+/// The smallest block the heap will hand out (in bytes)
+const MIN_BLOCK_SIZE: usize = 16;
+
+/// Returns the highest `size` bytes of free memory in the area from `base`
+/// up to `end`, avoiding anything the frame allocator has reserved (which
+/// includes the kernel image and the Multiboot info).
+fn highest_free_run(base: u64, end: u64, size: u64) -> Option<u64> {
+    let mut end = align_down(end as usize, PAGE_SIZE) as u64;
+    while end >= base + size {
+        let start = end - size;
+        // the highest reserved page in the run, if there is one
+        let reserved = (0..size / PAGE_SIZE as u64).rev()
+            .map(|page| start + page * PAGE_SIZE as u64)
+            .find(|&addr| frame::is_reserved(PAddr::from_u64(addr)));
+        match reserved {
+            None => return Some(start)
+          , Some(addr) => end = addr
+        }
+    }
+    None
+}
+
+/// Set up the kernel heap, with room for `n_pages` pages of allocations.
+///
+/// This picks the highest stretch of usable memory that's big enough, out
+/// of the memory map and below the end of the direct map, and reserves it
+/// so that the frame allocator never hands it out. One extra page before the
+/// heap holds the buddy allocator's free lists. The heap's size is rounded
+/// up to a power of two, since that's what the buddy allocator needs.
+///
+/// The memory map must have been set with `map::set_memory_map`, and the
+/// frame allocator set up, first.
+///
+/// # Returns
+///   - `Some(PAddr)` with the physical address the heap starts at
+///   - `None` if there's no memory map, no area has room for the heap, or
+///     the frame allocator couldn't reserve it
+pub fn init_heap(n_pages: usize) -> Option<PAddr> {
+    let heap_size = (n_pages * PAGE_SIZE).next_power_of_two();
+    let n_free_lists
+        = (heap_size.trailing_zeros() - MIN_BLOCK_SIZE.trailing_zeros())
+              as usize + 1;
+    let size = (heap_size + PAGE_SIZE) as u64;
+
+    let map = match map::memory_map() {
+        Some(map) => map
+      , None => return None
+    };
+    let highest = map.areas()
+        .filter(|area| area.base < PHYS_MAP_SIZE as u64)
+        .filter_map(|area| {
+            let end = cmp::min(area.base + area.length, PHYS_MAP_SIZE as u64);
+            highest_free_run(area.base, end, size)
+        })
+        .max();
+    let start = match highest {
+        Some(start) => PAddr::from_u64(start)
+      , None => return None
+    };
+    if !frame::reserve(start, PAddr::from_u64(start.as_u64() + size)) {
+        // if we can't keep the frame allocator off it, it's no good to us
+        return None
+    }
+
+    unsafe {
+        let lists = phys_to_virt(start).as_usize() as *mut FreeList<'static>;
+        for i in 0..n_free_lists {
+            ptr::write(lists.offset(i as isize), FreeList::new());
+        }
+        let heap = (phys_to_virt(start).as_usize() + PAGE_SIZE) as *mut u8;
+        system::init_heap( heap
+                         , slice::from_raw_parts_mut(lists, n_free_lists)
+                         , heap_size );
+    }
+    Some(PAddr::from_u64(start.as_u64() + PAGE_SIZE as u64))
+}
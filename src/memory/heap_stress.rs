@@ -0,0 +1,197 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! A randomized stress test for the system heap.
+//!
+//! `stress` does a long run of random allocations and frees, of random
+//! sizes and alignments, and checks everything it can along the way: that
+//! each block is aligned as asked, that no block overlaps one that's still
+//! live, that nothing scribbles on a block while it's live (each one is
+//! filled with a pattern, which is checked when it's freed), and, every so
+//! often, that the heap's free lists pass `check_consistency`. Once
+//! everything's been freed again, the heap should have exactly as much free
+//! memory as when we started.
+//!
+//! The numbers come from an `XorShift64`, so a failing run can be repeated
+//! by giving it the same seed.
+use core::ptr;
+use alloc::Allocator;
+use alloc::buddy::AllocError;
+use alloc::buddy::system::{self, System};
+use util::{ArrayVec, XorShift64, is_aligned};
+
+/// Number of operations `make test` runs
+pub const DEFAULT_OPS: usize = 10_000;
+
+/// Maximum number of allocations that are live at once
+pub const MAX_LIVE: usize = 64;
+
+/// Largest allocation made (in bytes)
+const MAX_SIZE: u64 = 4096;
+
+/// Largest alignment asked for, as a power of two (so 2^7 = 128)
+const MAX_ALIGN_SHIFT: u64 = 7;
+
+/// Number of operations between consistency checks
+const CHECK_INTERVAL: usize = 64;
+
+/// What went wrong in a failed stress test.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum StressError { /// The heap hasn't been set up
+                       NoHeap
+                     , /// We asked for `align` and got `ptr`
+                       Misaligned { ptr: usize, align: usize }
+                     , /// The block at `ptr` overlaps the live block at
+                       /// `other`
+                       Overlap { ptr: usize, size: usize, other: usize }
+                     , /// Something overwrote the block at `ptr` while it
+                       /// was live
+                       Corrupted { ptr: usize }
+                     , /// The heap's free lists are broken
+                       Inconsistent(AllocError)
+                     , /// After freeing everything, the heap had `after`
+                       /// bytes free, not the `before` it started with
+                       Leaked { before: usize, after: usize }
+                     }
+
+/// A failed stress test: what went wrong, and on which operation.
+#[derive(Debug, Copy, Clone)]
+pub struct StressFailure { pub op: usize
+                         , pub error: StressError
+                         }
+
+/// What a successful stress test did.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct StressStats { pub allocs: usize
+                       , pub frees: usize
+                       , /// Allocations the heap couldn't satisfy
+                         pub failed_allocs: usize
+                       }
+
+/// A live allocation
+#[derive(Copy, Clone)]
+struct Live { ptr: *mut u8
+            , size: usize
+            , align: usize
+            , /// The byte the block is filled with
+              pattern: u8
+            }
+
+/// The live allocations, in no particular order.
+type LiveSet = ArrayVec<[Live; MAX_LIVE]>;
+
+/// Returns the address of a live block overlapping the `size` bytes at
+/// `ptr`, if there is one
+fn overlapping(live: &LiveSet, ptr: usize, size: usize) -> Option<usize> {
+    live.iter()
+        .find(|b| ptr < b.ptr as usize + b.size
+                  && (b.ptr as usize) < ptr + size)
+        .map(|b| b.ptr as usize)
+}
+
+/// Check that the block is still filled with its pattern, and free it.
+fn free(block: Live) -> Result<(), StressError> {
+    let intact = (0..block.size).all(|i| unsafe {
+        *block.ptr.offset(i as isize) == block.pattern
+    });
+    unsafe { System.deallocate(block.ptr, block.size, block.align) };
+    if intact { Ok(()) }
+    else { Err(StressError::Corrupted { ptr: block.ptr as usize }) }
+}
+
+fn check() -> Result<(), StressError> {
+    match system::check_consistency() {
+        Some(Ok(())) => Ok(())
+      , Some(Err(why)) => Err(StressError::Inconsistent(why))
+      , None => Err(StressError::NoHeap)
+    }
+}
+
+/// Do one random operation.
+fn step( rng: &mut XorShift64, live: &mut LiveSet, stats: &mut StressStats)
+       -> Result<(), StressError> {
+    // free about as often as we allocate, so the heap fills up and drains
+    // over and over
+    if live.is_full() || (!live.is_empty() && rng.one_in(2)) {
+        let i = rng.next_below(live.len() as u64) as usize;
+        stats.frees += 1;
+        return free(live.swap_remove(i))
+    }
+    // mostly small allocations, with the odd big one
+    let size = if rng.one_in(8) { rng.next_below(MAX_SIZE) + 1 }
+               else { rng.next_below(128) + 1 } as usize;
+    let align = 1 << rng.next_below(MAX_ALIGN_SHIFT + 1) as usize;
+    let ptr = match unsafe { System.allocate(size, align) } {
+        Some(ptr) => ptr
+      , None => { stats.failed_allocs += 1; return Ok(()) }
+    };
+    stats.allocs += 1;
+    let addr = ptr as usize;
+    if !is_aligned(addr, align) {
+        return Err(StressError::Misaligned { ptr: addr, align: align })
+    }
+    if let Some(other) = overlapping(live, addr, size) {
+        return Err(StressError::Overlap { ptr: addr, size: size
+                                        , other: other })
+    }
+    let pattern = rng.next_u64() as u8;
+    unsafe { ptr::write_bytes(ptr, pattern, size) };
+    // there's room, since we'd have freed something instead if it was full
+    let _ = live.push(Live { ptr: ptr, size: size, align: align
+                           , pattern: pattern });
+    Ok(())
+}
+
+/// Run `ops` random allocations and frees against the system heap, with
+/// numbers from `seed`.
+///
+/// Everything that's still allocated at the end is freed again before this
+/// returns, even if the test failed (unless the heap is corrupt, in which
+/// case we leave it alone).
+///
+/// # Returns
+///   - `Ok(StressStats)` saying what was done, if every check passed
+///   - `Err(StressFailure)` with the first check that failed
+pub fn stress(ops: usize, seed: u64) -> Result<StressStats, StressFailure> {
+    let fail = |op, error| StressFailure { op: op, error: error };
+    let before = match system::heap_stats() {
+        Some(stats) => stats.free_bytes
+      , None => return Err(fail(0, StressError::NoHeap))
+    };
+    try!(check().map_err(|e| fail(0, e)));
+
+    let mut rng = XorShift64::new(seed);
+    let mut live = LiveSet::new();
+    let mut stats = StressStats::default();
+    let mut result = Ok(());
+    for op in 0..ops {
+        result = step(&mut rng, &mut live, &mut stats)
+                     .and_then(|_| if op % CHECK_INTERVAL == 0 { check() }
+                                   else { Ok(()) })
+                     .map_err(|e| fail(op, e));
+        if result.is_err() { break }
+    }
+
+    // clean up, unless the heap's too broken to touch
+    match result {
+        Err(StressFailure { error: StressError::Inconsistent(_), .. }) => { }
+      , _ => while let Some(block) = live.pop() {
+            let freed = free(block).map_err(|e| fail(ops, e));
+            if result.is_ok() { result = freed }
+        }
+    }
+    try!(result);
+
+    try!(check().map_err(|e| fail(ops, e)));
+    let after = system::heap_stats().map_or(0, |s| s.free_bytes);
+    if after != before {
+        return Err(fail(ops, StressError::Leaked { before: before
+                                                 , after: after }))
+    }
+    Ok(stats)
+}
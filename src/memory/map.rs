@@ -0,0 +1,52 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! The physical memory map provided by the bootloader.
+use spin::Mutex;
+use multiboot::{MemMapTag, MemAreaType};
+
+/// The memory map tag from the Multiboot info, once we've found it
+static MEMORY_MAP: Mutex<Option<&'static MemMapTag>> = Mutex::new(None);
+
+/// Remember the bootloader's memory map, so that it can be printed later.
+pub fn set_memory_map(tag: &'static MemMapTag) {
+    *MEMORY_MAP.lock() = Some(tag);
+}
+
+/// Returns the bootloader's memory map, if we've been given one
+pub fn memory_map() -> Option<&'static MemMapTag> {
+    *MEMORY_MAP.lock()
+}
+
+/// Returns the total size of the memory map's available areas (in bytes),
+/// if we've been given a memory map
+pub fn usable_bytes() -> Option<u64> {
+    memory_map().map(|map| map.all_areas()
+                              .filter(|a| a.ty() == MemAreaType::Available)
+                              .fold(0, |sum, a| sum + a.length))
+}
+
+/// Print a table of all the areas in the memory map, and how much of it is
+/// usable.
+pub fn print_memory_map() {
+    let map = match memory_map() {
+        Some(map) => map
+      , None => { println!("No memory map has been detected."); return }
+    };
+
+    println!( "  {:<18} {:<18} {:>12}  {}"
+            , "start", "end", "size (KiB)", "type" );
+    for area in map.all_areas() {
+        println!( "  {:#018x} {:#018x} {:>12}  {:?}"
+                , area.base, area.base + area.length
+                , area.length / 1024, area.ty() );
+    }
+    let usable = usable_bytes().unwrap_or(0);
+    println!( "Total usable memory: {} KiB ({} MiB)"
+            , usable / 1024, usable / (1024 * 1024) );
+}
@@ -7,4 +7,11 @@
 //  directory of this repository for more information.
 //
 pub mod addr;
+pub mod frame;
+pub mod heap;
+pub mod heap_stress;
+pub mod map;
+pub mod vmalloc;
 pub use self::addr::*;
+pub use self::map::print_memory_map;
+pub use self::vmalloc::{vmalloc, vfree};
@@ -0,0 +1,156 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Virtually contiguous allocations.
+//!
+//! `vmalloc` hands out memory that's contiguous in virtual memory, but is
+//! made of whatever frames the frame allocator has lying around. This is
+//! good for big buffers that don't need to be physically contiguous, such as
+//! anything that isn't going to be handed to a DMA device.
+//!
+//! The allocations come from a dedicated region of the kernel's half of the
+//! address space. Which pages of the region are in use is tracked in a
+//! bitmap, and every allocation is followed by an unmapped guard page, so
+//! running off the end of one faults instead of scribbling on the next.
+//!
+//! Note that the region's page tables are created on demand in the current
+//! address space, so address spaces created before the first `vmalloc` won't
+//! see it. `vfree` leaves them in place once they're empty, since they're in
+//! the kernel's half, where `unmap_and_free` never frees page tables.
+use spin::Mutex;
+use alloc::PAGE_SIZE;
+use util::{align_up, is_aligned};
+use arch::cpu::paging::{AddressSpace, WRITABLE, NO_EXECUTE};
+use super::{frame, VAddr};
+
+/// Start of the `vmalloc` region (this is P4 entry 510)
+pub const VMALLOC_START: usize = 0xFFFF_FF00_0000_0000;
+/// Size of the `vmalloc` region (in bytes)
+pub const VMALLOC_SIZE: usize = 1 << 30;
+
+const N_PAGES: usize = VMALLOC_SIZE / PAGE_SIZE;
+const BITS: usize = 64;
+
+/// Bookkeeping for the pages in the `vmalloc` region.
+struct Region { /// Set bits are pages that are allocated (or guard pages)
+                used: [u64; N_PAGES / BITS]
+              , /// Set bits are the guard pages that end each allocation,
+                /// so `vfree` knows where to stop
+                guard: [u64; N_PAGES / BITS]
+              }
+
+impl Region {
+    #[inline]
+    fn get(bits: &[u64], page: usize) -> bool {
+        bits[page / BITS] & (1 << (page % BITS)) != 0
+    }
+
+    #[inline]
+    fn set(bits: &mut [u64], page: usize, value: bool) {
+        if value { bits[page / BITS] |= 1 << (page % BITS) }
+        else { bits[page / BITS] &= !(1 << (page % BITS)) }
+    }
+
+    /// Find and claim `n` free pages in a row (first fit).
+    ///
+    /// # Returns
+    ///   - The index of the first page
+    fn claim(&mut self, n: usize) -> Option<usize> {
+        let mut start = 0;
+        while start + n <= N_PAGES {
+            match (start..start + n).find(|&p| Self::get(&self.used, p)) {
+                Some(used) => start = used + 1
+              , None => {
+                    for page in start..start + n {
+                        Self::set(&mut self.used, page, true);
+                    }
+                    Self::set(&mut self.guard, start + n - 1, true);
+                    return Some(start)
+                }
+            }
+        }
+        None
+    }
+}
+
+static REGION: Mutex<Region>
+    = Mutex::new(Region { used: [0; N_PAGES / BITS]
+                        , guard: [0; N_PAGES / BITS]
+                        });
+
+#[inline]
+fn page_addr(page: usize) -> VAddr {
+    VAddr::from_usize(VMALLOC_START + page * PAGE_SIZE)
+}
+
+/// Allocate `size` bytes of virtually contiguous memory.
+///
+/// The memory is zeroed, and at least page-aligned.
+///
+/// # Returns
+///   - `Some(*mut u8)` pointing at the new memory
+///   - `None` if there's no room left in the `vmalloc` region, or we ran out
+///     of frames
+pub fn vmalloc(size: usize) -> Option<*mut u8> {
+    if size == 0 {
+        return None
+    }
+    // one extra page for the guard
+    let n_pages = align_up(size, PAGE_SIZE) / PAGE_SIZE + 1;
+    let start = match REGION.lock().claim(n_pages) {
+        Some(start) => start
+      , None => return None
+    };
+    let space = AddressSpace::current();
+    for page in start..start + n_pages - 1 {
+        // zeroed frames, so the memory comes out zeroed as promised
+        let mapped = frame::allocate_zeroed_frame().map_or(false, |frame|
+            unsafe {
+                space.map_to(page_addr(page), frame, WRITABLE | NO_EXECUTE)
+            });
+        if !mapped {
+            // give back what we got before running out
+            unsafe { vfree(page_addr(start).as_usize() as *mut u8) };
+            return None
+        }
+    }
+    Some(page_addr(start).as_usize() as *mut u8)
+}
+
+/// Free memory allocated by `vmalloc`.
+///
+/// # Unsafe due to
+///   - `ptr` must have come from `vmalloc`, and mustn't be used afterwards
+///
+/// # Panics
+///   - If `ptr` isn't the start of a `vmalloc` allocation
+pub unsafe fn vfree(ptr: *mut u8) {
+    let addr = ptr as usize;
+    assert!( addr >= VMALLOC_START && addr < VMALLOC_START + VMALLOC_SIZE
+           && is_aligned(addr, PAGE_SIZE)
+           , "vfree({:#x}): not a vmalloc address", addr );
+    let space = AddressSpace::current();
+    let mut region = REGION.lock();
+    let mut page = (addr - VMALLOC_START) / PAGE_SIZE;
+    assert!( page == 0 || !Region::get(&region.used, page - 1)
+           || Region::get(&region.guard, page - 1)
+           , "vfree({:#x}): not the start of an allocation", addr );
+    loop {
+        assert!( Region::get(&region.used, page)
+               , "vfree({:#x}): not allocated", addr );
+        Region::set(&mut region.used, page, false);
+        if Region::get(&region.guard, page) {
+            Region::set(&mut region.guard, page, false);
+            return
+        }
+        // if `vmalloc` ran out of frames, the pages after that weren't
+        // mapped, so it's fine for this to fail
+        let _ = space.unmap_and_free(page_addr(page));
+        page += 1;
+    }
+}
@@ -0,0 +1,318 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! A minimal kernel monitor.
+//!
+//! This is a tiny REPL for poking at the kernel while it's running. Each line
+//! is split on whitespace, and the first word is looked up in the `COMMANDS`
+//! table; the rest of the words are passed to the command as arguments.
+use core::str;
+use core::sync::atomic::Ordering;
+use io::{self, term};
+use boot;
+use memory::{self, heap_stress};
+use arch::cpu::{self, apic, control_regs, interrupts, rand, rflags, smp};
+use arch::cpu::interrupts::IDT_ENTRIES;
+use alloc::buddy::system::heap_stats;
+use util::ArrayVec;
+use task;
+
+/// Maximum length of a line of input
+const LINE_MAX: usize = 80;
+
+/// Maximum number of words in a line of input
+const ARGS_MAX: usize = 8;
+
+const PROMPT: &'static str = "sos> ";
+
+/// A monitor command.
+pub struct Command { /// The name the command is invoked by
+                     pub name: &'static str
+                   , /// A short description of the command's arguments
+                     pub usage: &'static str
+                   , /// One line describing what the command does
+                     pub help: &'static str
+                   , /// The function implementing the command, which is
+                     /// passed the arguments following the command name
+                     pub run: fn(&[&str])
+                   }
+
+/// All the commands the monitor knows about
+pub static COMMANDS: &'static [Command]
+    = &[ Command { name: "help", usage: ""
+                 , help: "list the available commands"
+                 , run: help }
+       , Command { name: "mem", usage: "<addr> <len>"
+                 , help: "hexdump <len> bytes of memory starting at <addr>"
+                 , run: mem }
+       , Command { name: "regs", usage: ""
+                 , help: "print the values of some CPU registers"
+                 , run: regs }
+       , Command { name: "uptime", usage: ""
+                 , help: "print the time since interrupts were enabled"
+                 , run: uptime }
+       , Command { name: "heap", usage: ""
+                 , help: "print heap usage statistics"
+                 , run: heap }
+       , Command { name: "heaptest", usage: "[ops] [seed]"
+                 , help: "stress test the heap with random allocations"
+                 , run: heaptest }
+       , Command { name: "idt", usage: ""
+                 , help: "list the present IDT gates"
+                 , run: idt }
+       , Command { name: "irqs", usage: ""
+                 , help: "list the most frequently handled interrupt vectors"
+                 , run: irqs }
+       , Command { name: "latency", usage: ""
+                 , help: "list the interrupt handlers with the worst latency"
+                 , run: latency }
+       , Command { name: "cpus", usage: ""
+                 , help: "start the other CPUs (the first time) and count them"
+                 , run: cpus }
+       , Command { name: "bootlog", usage: ""
+                 , help: "print the boot log"
+                 , run: bootlog }
+       , Command { name: "memmap", usage: ""
+                 , help: "print the physical memory map"
+                 , run: memmap }
+       , Command { name: "reboot", usage: ""
+                 , help: "reset the machine"
+                 , run: reboot }
+       , Command { name: "shutdown", usage: ""
+                 , help: "turn the machine off"
+                 , run: shutdown }
+       ];
+
+/// Parse a number, in hex if it starts with `0x` and decimal otherwise.
+fn parse_number(s: &str) -> Option<usize> {
+    if s.starts_with("0x") {
+        usize::from_str_radix(&s[2..], 16).ok()
+    } else {
+        usize::from_str_radix(s, 10).ok()
+    }
+}
+
+fn help(_args: &[&str]) {
+    for command in COMMANDS {
+        println!( "  {:<8} {:<14} {}"
+                , command.name, command.usage, command.help );
+    }
+}
+
+fn mem(args: &[&str]) {
+    match args {
+        [addr, len] => match (parse_number(addr), parse_number(len)) {
+            (Some(addr), Some(len)) => unsafe { io::hexdump(addr, len) }
+          , _ => println!("mem: addresses and lengths must be numbers")
+        }
+      , _ => println!("usage: mem <addr> <len>")
+    }
+}
+
+fn regs(_args: &[&str]) {
+    let (rsp, rbp) = (cpu::current_rsp(), cpu::current_rbp());
+    let rflags = rflags::read_rflags();
+    unsafe {
+        println!( "  rsp: {:#018x}  rbp: {:#018x}  rflags: {:#018x}"
+                , rsp, rbp, rflags.bits() );
+        println!("  flags: {:?} (iopl {})", rflags, rflags.iopl());
+        if let (Some(used), Some(left)) = ( task::stack_usage()
+                                          , task::stack_remaining() ) {
+            println!("  stack: {} bytes used, {} bytes left", used, left);
+        }
+        println!( "  cr0: {:#018x}  cr2: {:#018x}"
+                , control_regs::cr0_read(), control_regs::cr2_read() );
+        println!( "  cr3: {:#018x}  cr4: {:#018x}"
+                , control_regs::cr3_read(), control_regs::cr4_read() );
+    }
+}
+
+fn uptime(_args: &[&str]) {
+    let ticks = interrupts::ticks();
+    println!( "  {} timer ticks (about {} seconds)"
+            , ticks, ticks / interrupts::timer_hz() );
+}
+
+fn cpus(_args: &[&str]) {
+    // the APs that started are still running, and mustn't be sent another
+    // INIT, so only ever start them once
+    if smp::cpus_online() == 1 {
+        if !apic::is_enabled() {
+            println!("  the local APIC isn't enabled, so we can't start APs");
+        } else {
+            println!("  started {} APs", smp::start_aps());
+        }
+    }
+    println!("  {} CPUs online", smp::cpus_online());
+}
+
+fn bootlog(_args: &[&str]) {
+    let _ = boot::write_boot_log(&mut term::Printer, boot::BOOT_LOG_LEN);
+    println!("");
+}
+
+fn heap(_args: &[&str]) {
+    match heap_stats() {
+        Some(stats) => println!( "  {} of {} bytes free ({} byte blocks)"
+                               , stats.free_bytes, stats.heap_size
+                               , stats.min_block_size )
+      , None => println!("  the heap hasn't been initialized")
+    }
+}
+
+fn heaptest(args: &[&str]) {
+    // without a seed, pick a random one (which gets printed, so that a
+    // failure can be run again)
+    let (ops, seed) = match args {
+        [] => (Some(heap_stress::DEFAULT_OPS), Some(rand::seed()))
+      , [ops] => (parse_number(ops), Some(rand::seed()))
+      , [ops, seed] => ( parse_number(ops)
+                       , parse_number(seed).map(|s| s as u64) )
+      , _ => (None, None)
+    };
+    match (ops, seed) {
+        (Some(ops), Some(seed)) => {
+            println!("  {} operations, seed {:#x}", ops, seed);
+            match heap_stress::stress(ops, seed) {
+                Ok(stats) => println!( "  passed: {} allocations ({} \
+                                        failed), {} frees"
+                                     , stats.allocs, stats.failed_allocs
+                                     , stats.frees )
+              , Err(why) => println!( "  failed at operation {}: {:?}"
+                                    , why.op, why.error )
+            }
+        }
+      , _ => println!("usage: heaptest [ops] [seed]")
+    }
+}
+
+fn idt(_args: &[&str]) {
+    let idt = match interrupts::idt() {
+        Some(idt) => idt
+      , None => { println!("The IDT hasn't been initialized yet."); return }
+    };
+    let mut n_gates = 0;
+    for (vector, gate) in idt.gates() {
+        println!( "  {:#04x}: {:<9} handler {:#018x} selector {:#06x} dpl {}"
+                , vector, gate.ty, gate.handler, gate.selector.bits()
+                , gate.dpl );
+        n_gates += 1;
+    }
+    println!("  {} gates present", n_gates);
+}
+
+fn irqs(_args: &[&str]) {
+    // how many of the busiest vectors to list
+    const TOP_N: usize = 10;
+    let counts = interrupts::interrupt_counts();
+    // take a snapshot, so the list doesn't change while we're sorting it
+    let mut snapshot = [0usize; IDT_ENTRIES];
+    for (count, counter) in snapshot.iter_mut().zip(counts.iter()) {
+        *count = counter.load(Ordering::Relaxed);
+    }
+    let total = snapshot.iter().fold(0, |sum, &n| sum + n);
+    // pick out the busiest vector, TOP_N times over
+    for _ in 0..TOP_N {
+        let (vector, count) = snapshot.iter().cloned().enumerate()
+                                      .fold((0, 0), |(v, max), (i, n)|
+                                            if n > max { (i, n) }
+                                            else { (v, max) });
+        if count == 0 { break }
+        println!("  {:#04x}: {:>12}", vector, count);
+        snapshot[vector] = 0;
+    }
+    println!("  {} interrupts handled in total", total);
+}
+
+fn latency(_args: &[&str]) {
+    // how many of the slowest vectors to list
+    const TOP_N: usize = 10;
+    if !cfg!(irq_latency) {
+        println!("Latency isn't measured; rebuild with `--cfg irq_latency`.");
+        return
+    }
+    let mut listed = [false; IDT_ENTRIES];
+    // pick out the vector with the worst maximum latency, TOP_N times over
+    for _ in 0..TOP_N {
+        let worst = (0..IDT_ENTRIES)
+            .filter(|&v| !listed[v])
+            .map(|v| (v, interrupts::interrupt_latency(v as u8)))
+            .fold(None, |worst: Option<(usize, interrupts::LatencyStats)>
+                       , (v, stats)|
+                  match worst {
+                      Some((_, w)) if w.max_cycles >= stats.max_cycles => worst
+                    , _ => Some((v, stats))
+                  });
+        match worst {
+            Some((vector, stats)) if stats.samples > 0 => {
+                listed[vector] = true;
+                match (stats.max_ns(), stats.avg_ns()) {
+                    (Some(max), Some(avg)) =>
+                        println!( "  {:#04x}: max {:>10} ns  avg {:>10} ns  \
+                                   ({} samples)"
+                                , vector, max, avg, stats.samples )
+                  , _ =>
+                        println!( "  {:#04x}: max {:>10} cycles  avg {:>10} \
+                                   cycles  ({} samples)"
+                                , vector, stats.max_cycles, stats.avg_cycles
+                                , stats.samples )
+                }
+            }
+          , _ => break
+        }
+    }
+}
+
+fn memmap(_args: &[&str]) {
+    memory::print_memory_map();
+}
+
+fn reboot(_args: &[&str]) {
+    println!("Rebooting...");
+    cpu::reboot()
+}
+
+fn shutdown(_args: &[&str]) {
+    println!("Shutting down...");
+    cpu::shutdown()
+}
+
+/// Run a single line of input.
+pub fn execute(line: &str) {
+    let mut words: ArrayVec<[&str; ARGS_MAX]> = ArrayVec::new();
+    for word in line.split(' ').filter(|w| !w.is_empty()) {
+        if words.push(word).is_err() {
+            println!("Too many arguments (the limit is {}).", ARGS_MAX - 1);
+            return
+        }
+    }
+
+    match &words[..] {
+        [] => { }
+      , [name, args..] =>
+            match COMMANDS.iter().find(|c| c.name == *name) {
+                Some(command) => (command.run)(args)
+              , None => println!( "Unknown command `{}`; try `help`."
+                                , name )
+            }
+    }
+}
+
+/// Run the monitor forever.
+pub fn run() -> ! {
+    println!("SOS kernel monitor. Type `help` for a list of commands.");
+    let mut buf = [0u8; LINE_MAX];
+    loop {
+        print!("{}", PROMPT);
+        let len = term::read_line(&mut buf);
+        match str::from_utf8(&buf[..len]) {
+            Ok(line) => execute(line)
+          , Err(_)   => println!("Input wasn't valid UTF-8.")
+        }
+    }
+}
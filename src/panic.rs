@@ -9,23 +9,157 @@
 //! Panic handling and stack unwinding
 
 use core::fmt::{Arguments, Write};
-use super::io::term;
-use vga::Color;
+use core::intrinsics::volatile_store;
+use super::io::{term, fmt_addr};
+use super::boot;
+use super::arch::cpu;
+use super::task;
+use vga::{Terminal, Palette, Color};
 
+/// The most stack frames we'll print in a backtrace
+const MAX_FRAMES: usize = 16;
+
+/// How many of the most recent boot log entries to print after the
+/// backtrace
+const BOOT_LOG_ENTRIES: usize = 6;
+
+/// Address of the VGA text buffer
+const VGA_BUFFER: usize = 0xB8000;
+/// White on red, for `die_raw`
+const RAW_ATTRIBUTE: u16 = 0x4F00;
+
+extern {
+    /// Bottom of the boot stack. Exported by `boot.asm`
+    static stack_end: u8;
+    /// Top of the boot stack. Exported by `boot.asm`
+    static stack_top: u8;
+}
+
+/// Returns the bounds of the stack we're running on: the current task's, or
+/// the boot stack's, if the scheduler hasn't started yet.
+///
+/// # Returns
+///   - `(rsp, top)`, since nothing below `rsp` is a live frame
+///   - `None` if we're on some other stack, whose bounds we don't know
+fn current_stack() -> Option<(u64, u64)> {
+    let rsp = cpu::current_rsp();
+    let boot = unsafe { ( &stack_end as *const u8 as usize
+                        , &stack_top as *const u8 as usize ) };
+    match task::stack_bounds() {
+        Some((bottom, top)) if rsp >= bottom && rsp < top =>
+            Some((rsp as u64, top as u64))
+      , _ if rsp >= boot.0 && rsp < boot.1 => Some((rsp as u64, boot.1 as u64))
+      , _ => None
+    }
+}
+
+/// Print the return addresses of up to `MAX_FRAMES` stack frames, by
+/// following the chain of saved frame pointers.
+///
+/// If the kernel was built without frame pointers, this will print garbage
+/// (but it checks that each frame pointer looks sane before following it, so
+/// it shouldn't make things any worse). The frames have to be in the stack
+/// we're on, so we never follow a frame pointer out of it; if we don't know
+/// where that stack is, we don't follow any at all.
+fn backtrace<W: Write>(out: &mut W) {
+    let mut rbp = cpu::current_rbp() as u64;
+    let _ = write!(out, "\nBacktrace:");
+    let (low, high) = match current_stack() {
+        Some(bounds) => bounds
+      , None => {
+            let _ = write!(out, " not on a stack we know the bounds of");
+            return
+        }
+    };
+    for _ in 0..MAX_FRAMES {
+        // the frame is two words: the saved frame pointer and return address
+        if rbp == 0 || rbp < low || rbp % 8 != 0 || rbp + 16 > high {
+            break
+        }
+        // each frame starts with the caller's frame pointer, followed by
+        // the return address into the caller
+        let (next, ret) = unsafe {
+            (*(rbp as *const u64), *((rbp + 8) as *const u64))
+        };
+        if ret == 0 { break }
+        let _ = write!(out, "\n  {}", fmt_addr(ret));
+        // frames only ever go up the stack; anything else means we're lost
+        if next <= rbp { break }
+        rbp = next;
+    }
+}
+
+fn report<W: Write>( out: &mut W, args: Arguments
+                   , file: &'static str, line: usize) {
+    let _ = write!( out
+                  , "KERNEL PANIC at {}:{}\
+                    \nSomething has gone horribly wrong: {}\
+                    \nThis is fine.\n"
+                  , file, line, args );
+    backtrace(out);
+    let _ = write!(out, "\nLast boot steps:");
+    let _ = boot::write_boot_log(out, BOOT_LOG_ENTRIES);
+}
+
+/// The panic handler, which libcore calls with the panic's message and
+/// location.
+///
+/// The location is only a file and line: this toolchain's `panic_fmt` isn't
+/// given a column (nor is the compiler's `panic` lang item, which it calls
+/// for overflow and bounds checks), so there's none for us to print.
 #[lang = "panic_fmt"]
 #[no_mangle] #[inline(never)] #[cold]
 pub extern "C" fn rust_begin_unwind( args: Arguments, file: &'static str
                                , line: usize )
                                -> ! {
-    write!(term::CONSOLE.lock()
-                        .set_colors(Color::White, Color::Red)
-                        .clear()
-          , "KERNEL PANIC in {} at line {}\
-            \nSomething has gone horribly wrong: {}. \
-            This is fine."
-          , file, line, args
-          );
-    loop { }
+    // nothing should interrupt us while we're printing our last words
+    unsafe { asm!("cli" :::: "volatile") }
+
+    match term::CONSOLE.try_lock() {
+        Some(mut console) => {
+            console.set_colors(Color::White, Color::Red).clear();
+            report(&mut *console, args, file, line);
+        }
+      , None => {
+            // whoever was holding the console lock won't ever be getting
+            // back to it, so just write straight to the VGA buffer
+            let mut vga = unsafe {
+                Terminal::new(Palette::new(Color::White, Color::Red), 0xB8000)
+            };
+            vga.clear();
+            report(&mut vga, args, file, line);
+        }
+    }
+    loop { unsafe { asm!("hlt" :::: "volatile") } }
+}
+
+/// Print `msg` followed by `value` in hex on the top line of the VGA
+/// buffer, and halt.
+///
+/// This is for when things are too broken to trust `panic!`, like a fault
+/// in a fault handler: it takes no locks, doesn't format anything, and uses
+/// hardly any stack, so it has the best chance of getting a message out
+/// before the machine goes down.
+#[inline(never)] #[cold]
+pub fn die_raw(msg: &str, value: u64) -> ! {
+    unsafe { asm!("cli" :::: "volatile") }
+    let vga = VGA_BUFFER as *mut u16;
+    let mut col = 0;
+    {
+        let mut put = |byte: u8| {
+            unsafe {
+                volatile_store(vga.offset(col), RAW_ATTRIBUTE | byte as u16);
+            }
+            col += 1;
+        };
+        for byte in msg.bytes() { put(byte) }
+        put(b'0'); put(b'x');
+        for shift in (0..16).rev() {
+            let digit = ((value >> (shift * 4)) & 0xF) as u8;
+            put(if digit < 10 { b'0' + digit } else { b'a' + digit - 10 });
+        }
+    }
+    loop { unsafe { asm!("hlt" :::: "volatile") } }
 }
 
 #[lang = "stack_exhausted"]
@@ -0,0 +1,275 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Kernel tasks.
+//!
+//! A task is a thread of kernel execution, with its own stack and saved
+//! execution context.
+use core::mem;
+use core::sync::atomic::AtomicBool;
+use alloc::RawLink;
+use arch::cpu::context::{self, Context};
+use arch::cpu::fpu::{self, FpuState};
+use arch::cpu::{self, percpu};
+
+pub mod preempt;
+pub mod queue;
+pub mod scheduler;
+pub mod stack_pool;
+pub mod sync;
+pub mod timer;
+pub mod wait_queue;
+pub mod work;
+
+pub use self::sync::{Semaphore, KMutex};
+pub use self::wait_queue::WaitQueue;
+
+/// Value written to the lowest word of every task stack.
+///
+/// If this is ever overwritten, the task has run off the end of its stack.
+pub const STACK_CANARY: u64 = 0xDEAD_BEEF_CAFE_BABE;
+
+/// Unique identifier for a task
+pub type TaskId = usize;
+
+/// A task's stack.
+///
+/// Stacks grow downwards, so the first value pushed goes at the highest
+/// address (the `top`) and an overflow runs off the lowest address (the
+/// `bottom`). The canary lives in the lowest word of the stack, where a
+/// runaway task will hit it just before leaving its stack.
+pub struct Stack { /// The lowest address of the stack's memory
+                   bottom: *mut u8
+                 , /// The size of the stack (in bytes)
+                   size: usize
+                 }
+
+impl Stack {
+    /// Create a new stack in the region of `size` bytes beginning at
+    /// `bottom`, and place a canary at the bottom of it.
+    ///
+    /// # Unsafe due to
+    ///   - The caller must guarantee that the region is valid, writable, and
+    ///     not used for anything else for as long as the stack lives.
+    ///   - `bottom` must be aligned to 8 bytes.
+    pub unsafe fn new(bottom: *mut u8, size: usize) -> Self {
+        assert!( size > mem::size_of::<u64>()
+               , "stack of {} bytes is too small for a canary", size );
+        *(bottom as *mut u64) = STACK_CANARY;
+        Stack { bottom: bottom, size: size }
+    }
+
+    /// Returns the initial value of the stack pointer for this stack.
+    ///
+    /// This is the top of the stack, rounded down to a 16-byte boundary, as
+    /// the System V ABI expects.
+    #[inline]
+    pub fn top(&self) -> *mut u8 {
+        ((self.bottom as usize + self.size) & !0xf) as *mut u8
+    }
+
+    /// Returns the lowest address of the stack
+    #[inline] pub fn bottom(&self) -> *mut u8 { self.bottom }
+
+    /// Returns the size of the stack (in bytes)
+    #[inline] pub fn size(&self) -> usize { self.size }
+
+    /// Returns the lowest address of the stack, and the address just past
+    /// its highest one
+    #[inline]
+    pub fn bounds(&self) -> (usize, usize) {
+        (self.bottom as usize, self.bottom as usize + self.size)
+    }
+
+    /// Returns true if `addr` is in this stack
+    #[inline]
+    pub fn contains(&self, addr: usize) -> bool {
+        let (bottom, top) = self.bounds();
+        addr >= bottom && addr < top
+    }
+
+    /// Returns true if the canary at the bottom of the stack is intact
+    #[inline]
+    pub fn canary_intact(&self) -> bool {
+        unsafe { *(self.bottom as *const u64) == STACK_CANARY }
+    }
+}
+
+/// What a task is currently doing
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum State { /// Waiting in the ready queue for its turn to run
+                 Ready
+               , /// Running on a CPU
+                 Running
+               , /// Waiting in a `WaitQueue` for something to happen
+                 Blocked
+               , /// Finished, with `scheduler::exit`; it will never run
+                 /// again
+                 Exited
+               }
+
+/// A kernel task
+pub struct Task { /// This task's unique ID
+                  pub id: TaskId
+                , /// The saved execution context, while not running
+                  pub context: Context
+                , /// The task's stack
+                  pub stack: Stack
+                , /// The task's saved FPU state, while it doesn't own the FPU
+                  pub fpu: FpuState
+                , /// Whether the task is ready, running, or blocked
+                  pub state: State
+                , /// Timer ticks left in the task's time slice, while it's
+                  /// running. This is filled up whenever the task is
+                  /// switched to, so a task that blocks or yields early
+                  /// gives up whatever it had left.
+                  pub quantum: usize
+                , /// The next task in whichever `TaskQueue` this task is in
+                  next: RawLink<Task>
+                , /// Set while a CPU is running the task, up until that CPU
+                  /// has finished saving its context. No other CPU may
+                  /// switch to it until then.
+                  on_cpu: AtomicBool
+                }
+
+impl Task {
+    /// Create a new task that will begin executing at `entry` on `stack`.
+    ///
+    /// The task starts with interrupts enabled.
+    pub fn new(id: TaskId, stack: Stack, entry: extern "C" fn() -> !) -> Self {
+        let mut context = Context::empty();
+        context.rsp = unsafe { context::init_stack(stack.top(), entry) };
+        context.rip = entry as *mut u8;
+        Task { id: id, context: context, stack: stack, fpu: FpuState::new()
+             , state: State::Ready
+             , quantum: 0
+             , next: RawLink::none()
+             , on_cpu: AtomicBool::new(false)
+             }
+    }
+
+    /// Create a task for the code that's already running on `stack`.
+    ///
+    /// This is how the code that was running before the scheduler started
+    /// (namely, `kernel_main`) becomes a task. Its context is filled in the
+    /// first time it's switched away from.
+    fn running(id: TaskId, stack: Stack) -> Self {
+        Task { id: id, context: Context::empty(), stack: stack
+             , fpu: FpuState::new()
+             , state: State::Running
+             , quantum: scheduler::default_quantum()
+             , next: RawLink::none()
+             , on_cpu: AtomicBool::new(true)
+             }
+    }
+}
+
+impl Drop for Task {
+    fn drop(&mut self) {
+        // make sure the FPU code doesn't try to save into us once we're gone
+        fpu::forget(&mut self.fpu);
+    }
+}
+
+/// Check that `task` hasn't overflowed its stack.
+///
+/// The scheduler should call this for each task it switches away from.
+/// If `task` is the one that's running, this also checks that the stack
+/// pointer is still in its stack, which catches an overflow that jumped
+/// right over the canary.
+///
+/// # Panics
+///   - If the canary at the bottom of the task's stack has been overwritten
+///   - If `task` is running, and the stack pointer isn't in its stack
+pub fn check_canary(task: &Task) {
+    if !task.stack.canary_intact() {
+        panic!( "Stack overflow in task {}: canary at {:#x} was smashed!"
+              , task.id, task.stack.bottom() as usize );
+    }
+    let rsp = cpu::current_rsp();
+    if task as *const Task == current_task_ptr() && !task.stack.contains(rsp) {
+        let (bottom, top) = task.stack.bounds();
+        panic!( "Stack overflow in task {}: the stack pointer is at {:#x}, \
+                 outside its stack ({:#x} to {:#x})!"
+              , task.id, rsp, bottom, top );
+    }
+}
+
+/// Returns the bounds of the current task's stack: its lowest address, and
+/// the address just past its highest one.
+///
+/// # Returns
+///   - `None` if there's no current task
+pub fn stack_bounds() -> Option<(usize, usize)> {
+    unsafe { current_task_ptr().as_ref() }.map(|task| task.stack.bounds())
+}
+
+/// Returns how many bytes of the current task's stack are in use.
+///
+/// # Returns
+///   - `None` if there's no current task, or we aren't on its stack (in a
+///     handler with a stack of its own, say)
+pub fn stack_usage() -> Option<usize> {
+    let rsp = cpu::current_rsp();
+    match stack_bounds() {
+        Some((bottom, top)) if rsp >= bottom && rsp < top => Some(top - rsp)
+      , _ => None
+    }
+}
+
+/// Returns how many bytes of the current task's stack are left, not
+/// counting the canary.
+///
+/// # Returns
+///   - `None` if there's no current task, or we aren't on its stack
+pub fn stack_remaining() -> Option<usize> {
+    let rsp = cpu::current_rsp();
+    match stack_bounds() {
+        Some((bottom, top)) if rsp >= bottom && rsp < top =>
+            Some((rsp - bottom).saturating_sub(mem::size_of::<u64>()))
+      , _ => None
+    }
+}
+
+/// Returns a pointer to the task running on this CPU.
+///
+/// # Returns
+///   - A null pointer if no task has been made current yet (for instance,
+///     during boot, before the scheduler has started)
+#[inline]
+pub fn current_task_ptr() -> *mut Task {
+    percpu::current_task() as *mut Task
+}
+
+/// Returns the task running on this CPU.
+///
+/// The pointer is read from the per-CPU data with a single instruction, so
+/// being preempted in the middle of this can't give us half of one task's
+/// pointer and half of another's.
+///
+/// # Unsafe due to
+///   - The returned reference aliases the scheduler's own pointer to the
+///     task; the caller must not hold onto it across a context switch
+///
+/// # Panics
+///   - If there's no current task
+pub unsafe fn current_task<'a>() -> &'a mut Task {
+    current_task_ptr().as_mut()
+        .expect("current_task() called with no task running!")
+}
+
+/// Make `task` the task running on this CPU.
+///
+/// This only updates the bookkeeping; it's up to the scheduler to actually
+/// switch to `task`.
+///
+/// # Unsafe due to
+///   - `task` must point to a `Task` that lives for as long as it's current
+pub unsafe fn set_current_task(task: *mut Task) {
+    percpu::set_current_task(task as usize)
+}
@@ -0,0 +1,85 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Deciding when the current task may be preempted.
+//!
+//! The system timer (the APIC timer, or the PIT if there's no APIC) doesn't
+//! switch tasks itself: each `tick` counts against the current task's
+//! quantum, and once that's used up, it just `request`s a switch. The switch
+//! happens on the way out of the outermost interrupt handler; if the task
+//! isn't preemptible then, the request waits for a later interrupt.
+//! A task isn't preemptible while it's in an interrupt
+//! handler (so a nested tick can't switch away from the handler it
+//! interrupted), or while it holds a `PreemptGuard`.
+//!
+//! All of this is per-CPU, so a task mustn't block or yield while it holds
+//! a `PreemptGuard`: it might wake up on a different CPU.
+use core::marker::PhantomData;
+use core::sync::atomic::Ordering;
+use arch::cpu::percpu;
+use super::current_task_ptr;
+
+/// Keeps the current task from being preempted for as long as it's alive.
+///
+/// Guards nest: preemption comes back once the last one is dropped. This
+/// isn't `Send`, since it has to be dropped on the CPU it was made on.
+pub struct PreemptGuard { _not_send: PhantomData<*const ()> }
+
+impl Drop for PreemptGuard {
+    fn drop(&mut self) {
+        percpu::current().preempt_count.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Don't preempt the current task until the returned guard is dropped.
+///
+/// Interrupts still happen as usual; it's only switching tasks that's put
+/// off.
+pub fn disable() -> PreemptGuard {
+    percpu::current().preempt_count.fetch_add(1, Ordering::SeqCst);
+    PreemptGuard { _not_send: PhantomData }
+}
+
+/// Returns true if nothing is keeping the current task from being
+/// preempted: there are no `PreemptGuard`s, and we aren't in an interrupt
+/// handler.
+pub fn is_preemptible() -> bool {
+    let cpu = percpu::current();
+    cpu.preempt_count.load(Ordering::SeqCst) == 0
+        && cpu.irq_depth.load(Ordering::SeqCst) == 0
+}
+
+/// Ask for the current task to be preempted, as soon as it's preemptible.
+#[inline]
+pub fn request() {
+    percpu::current().need_resched.store(true, Ordering::SeqCst);
+}
+
+/// Count a timer tick against the current task's quantum, and `request` a
+/// switch once it's used up.
+///
+/// This is called by the system timer's interrupt handler, so the current
+/// task can't be switched away from while we're looking at it.
+pub fn tick() {
+    let current = current_task_ptr();
+    // before the scheduler starts, there's nothing to switch to anyway
+    if let Some(task) = unsafe { current.as_mut() } {
+        if task.quantum > 0 {
+            task.quantum -= 1;
+        }
+        if task.quantum == 0 {
+            request()
+        }
+    }
+}
+
+/// Returns true if a switch has been `request`ed, and clears the request.
+#[inline]
+pub fn take_request() -> bool {
+    percpu::current().need_resched.swap(false, Ordering::SeqCst)
+}
@@ -0,0 +1,64 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Intrusive FIFO queues of tasks.
+//!
+//! Each task has a single `next` link, so a task can only be in one queue at
+//! a time; this is fine, since a task is either ready (and in the ready
+//! queue) or blocked (and in exactly one wait queue).
+use alloc::RawLink;
+use super::Task;
+
+/// A first-in, first-out queue of tasks.
+pub struct TaskQueue { head: RawLink<Task>
+                     , tail: RawLink<Task>
+                     }
+
+// The queue doesn't own its tasks, and is only ever accessed behind a lock.
+unsafe impl Send for TaskQueue { }
+
+impl TaskQueue {
+    /// Returns a new empty queue
+    pub const fn new() -> Self {
+        TaskQueue { head: RawLink::none(), tail: RawLink::none() }
+    }
+
+    /// Returns true if there are no tasks in the queue
+    #[inline] pub fn is_empty(&self) -> bool { self.head.is_none() }
+
+    /// Add `task` to the back of the queue.
+    ///
+    /// # Unsafe due to
+    ///   - `task` must be valid for as long as it's in the queue, and must not
+    ///     already be in a queue
+    pub unsafe fn push_back(&mut self, task: *mut Task) {
+        (*task).next = RawLink::none();
+        match self.tail.resolve_mut() {
+            Some(tail) => tail.next = RawLink::from_raw(task)
+          , None => self.head = RawLink::from_raw(task)
+        }
+        self.tail = RawLink::from_raw(task);
+    }
+
+    /// Remove the task at the front of the queue.
+    ///
+    /// # Returns
+    ///   - `Some(*mut Task)` with the task that's been waiting longest
+    ///   - `None` if the queue is empty
+    pub fn pop_front(&mut self) -> Option<*mut Task> {
+        unsafe {
+            self.head.resolve_mut().map(|head| {
+                self.head = head.next.take();
+                if self.head.is_none() {
+                    self.tail = RawLink::none();
+                }
+                head as *mut Task
+            })
+        }
+    }
+}
@@ -0,0 +1,286 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! A simple round-robin scheduler.
+//!
+//! The ready tasks are kept in per-CPU locked queues: each CPU has its own
+//! FIFO queue, behind a spinlock in its `PerCpu`, so CPUs don't contend over
+//! a single global queue. A CPU with nothing left to run steals from the
+//! others, taking the other CPU's queue lock to do it (the queues aren't
+//! lock-free). Tasks stop running when they yield or block, or when they've
+//! used up their quantum of timer ticks and the timer interrupt preempts
+//! them. All the scheduler's bookkeeping is done
+//! with interrupts disabled, so that an interrupt handler waking a task
+//! can't see it half-updated.
+use core::{mem, ptr};
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use spin::Mutex;
+use alloc::PAGE_SIZE;
+use arch::cpu::{self, context, fpu, interrupts, percpu};
+use arch::acpi::madt::MAX_CPUS;
+use ::memory::{frame, phys_to_virt};
+use ::cmdline;
+use super::{Task, Stack, State, check_canary, current_task_ptr
+           , set_current_task, stack_pool};
+use super::queue::TaskQueue;
+
+extern {
+    /// Bottom of the boot stack. Exported by `boot.asm`
+    static stack_end: u8;
+    /// Top of the boot stack. Exported by `boot.asm`
+    static stack_top: u8;
+}
+
+/// How many timer ticks a task runs for before it's preempted, unless
+/// `set_default_quantum` (or the `quantum` command line argument) says
+/// otherwise. At the APIC timer's 100 Hz, this is 50 ms; on the PIT's 18 Hz
+/// (with `noapic`, or no APIC), it's about 275 ms.
+pub const DEFAULT_QUANTUM: usize = 5;
+
+/// The quantum set by `set_default_quantum`, or zero for `DEFAULT_QUANTUM`
+static QUANTUM: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Returns how many timer ticks a task gets each time it's switched to
+#[inline]
+pub fn default_quantum() -> usize {
+    match QUANTUM.load(Ordering::Relaxed) {
+        0 => DEFAULT_QUANTUM
+      , ticks => ticks
+    }
+}
+
+/// Returns how long the default quantum lasts (in ms), at the rate the system
+/// timer currently ticks at.
+///
+/// This isn't settled until `interrupts::initialize` has decided whether the
+/// APIC timer or the PIT drives the tick.
+pub fn default_quantum_ms() -> usize {
+    default_quantum() * 1000 / interrupts::timer_hz()
+}
+
+/// Give each task `ticks` timer ticks whenever it's switched to.
+///
+/// Tasks that are already running keep what they've got left of their
+/// current quantum.
+///
+/// # Panics
+///   - If `ticks` is zero
+pub fn set_default_quantum(ticks: usize) {
+    assert!(ticks > 0, "a task's quantum can't be zero ticks");
+    QUANTUM.store(ticks, Ordering::Relaxed);
+}
+
+/// Returns this CPU's queue of ready tasks
+#[inline]
+fn local_queue() -> &'static Mutex<TaskQueue> {
+    &percpu::current().run_queue
+}
+
+/// Turn the code that's currently running into task 0, and start scheduling.
+///
+/// This needs the frame allocator, since the boot task's `Task` has to live
+/// somewhere. The `quantum` command line argument, if it's there, sets the
+/// default quantum.
+///
+/// # Panics
+///   - If there's no frame to put the boot task in
+pub fn init() {
+    match cmdline::get("quantum").map(|ticks| ticks.parse::<usize>()) {
+        Some(Ok(ticks)) if ticks > 0 => set_default_quantum(ticks)
+      , Some(_) => println!( "Ignoring a bad quantum; it must be a number of \
+                              ticks greater than zero." )
+      , None => { }
+    }
+    assert!( mem::size_of::<Task>() <= PAGE_SIZE
+           , "a `Task` doesn't fit in a frame!" );
+    let frame = frame::allocate_frame()
+                    .expect("no memory left for the boot task!");
+    unsafe {
+        let bottom = &stack_end as *const u8 as *mut u8;
+        let size = &stack_top as *const u8 as usize - bottom as usize;
+        let task = phys_to_virt(frame).as_usize() as *mut Task;
+        ptr::write(task, Task::running(0, Stack::new(bottom, size)));
+        set_current_task(task);
+        fpu::task_switched(&mut (*task).fpu);
+    }
+}
+
+/// Add `task` to the ready queue.
+///
+/// # Unsafe due to
+///   - `task` must stay valid for as long as the scheduler knows about it,
+///     and must not already be queued
+pub unsafe fn spawn(task: *mut Task) {
+    cpu::without_interrupts(|| {
+        (*task).state = State::Ready;
+        local_queue().lock().push_back(task);
+    })
+}
+
+/// Move `task` to the ready queue, if it's blocked.
+///
+/// This is what waking a task does, and it's safe to call from an interrupt
+/// handler. Tasks that are already ready or running are left alone. The task
+/// goes in this CPU's queue, wherever it ran before.
+///
+/// # Unsafe due to
+///   - `task` must point to a valid task
+pub unsafe fn make_ready(task: *mut Task) {
+    cpu::without_interrupts(|| {
+        if (*task).state == State::Blocked {
+            (*task).state = State::Ready;
+            local_queue().lock().push_back(task);
+        }
+    })
+}
+
+/// Give the CPU to the next ready task, if there is one.
+///
+/// The current task goes to the back of the ready queue, so it'll run again
+/// once everything else has had a turn.
+pub fn yield_now() {
+    let current = current_task_ptr();
+    if current.is_null() {
+        return
+    }
+    cpu::without_interrupts(|| unsafe {
+        (*current).state = State::Ready;
+        local_queue().lock().push_back(current);
+        reschedule();
+    })
+}
+
+/// Preempt the current task, if anything else is ready to run.
+///
+/// This is called on the way out of the outermost interrupt handler, once
+/// the interrupt's been acknowledged, if the system timer has asked for it
+/// with `preempt::request` and the task is preemptible. The current task
+/// goes to the back of the ready queue, just as if it had called
+/// `yield_now`, and picks up where it left off in the interrupt handler
+/// once it runs again. If it's the only thing ready, it just gets another
+/// quantum.
+pub fn preempt() {
+    let nothing_ready =
+        cpu::without_interrupts(|| local_queue().lock().is_empty());
+    if nothing_ready {
+        if let Some(current) = unsafe { current_task_ptr().as_mut() } {
+            current.quantum = default_quantum();
+        }
+    } else {
+        yield_now()
+    }
+}
+
+/// Stop running the current task until something calls `make_ready` on it.
+///
+/// The caller must have interrupts disabled, and must already have put the
+/// current task somewhere it'll be found by whoever is going to wake it up
+/// (such as a `WaitQueue`). Interrupts are still disabled when this returns.
+///
+/// # Unsafe due to
+///   - If nothing is going to wake the current task, it'll never run again
+pub unsafe fn block_current() {
+    let current = current_task_ptr();
+    kassert!(!current.is_null(), "block_current() called with no task!");
+    (*current).state = State::Blocked;
+    reschedule();
+}
+
+/// Take a ready task from the CPU numbered `cpu`, for this CPU to run.
+///
+/// This takes the task that's been waiting longest in `cpu`'s queue. The
+/// queue's lock keeps this from racing with `cpu` itself.
+///
+/// # Returns
+///   - `None` if `cpu` has nothing ready, or there's no such CPU
+pub fn steal_from(cpu: usize) -> Option<*mut Task> {
+    percpu::cpu(cpu).and_then(|cpu| cpu.run_queue.lock().pop_front())
+}
+
+/// Returns the next task this CPU should run, from its own queue if there's
+/// anything in it, and stolen from another CPU's otherwise.
+fn next_task() -> Option<*mut Task> {
+    let me = percpu::current().id;
+    local_queue().lock().pop_front()
+        .or_else(|| (0..MAX_CPUS).filter(|&cpu| cpu != me)
+                                 .filter_map(steal_from)
+                                 .next())
+}
+
+/// Stop running the current task, for good.
+///
+/// The task's stack goes back to the stack pool, but not until we've
+/// switched off it: `finish_switch` takes care of that, on the next task's
+/// stack. The `Task` itself belongs to whoever spawned it, and can be freed
+/// once it's `Exited`.
+///
+/// # Panics
+///   - If there's no current task
+pub fn exit() -> ! {
+    let current = current_task_ptr();
+    kassert!(!current.is_null(), "exit() called with no task!");
+    cpu::without_interrupts(|| unsafe {
+        (*current).state = State::Exited;
+        reschedule();
+    });
+    unreachable!("task {} ran again after exiting!", unsafe { (*current).id })
+}
+
+/// Finish a context switch, on the stack of the task that was switched to.
+///
+/// Until this runs, the task we switched away from still has its registers
+/// on this CPU, so other CPUs mustn't run it. If it had exited, nothing is
+/// using its stack any longer, so this is where the stack is given back.
+/// This is called by `reschedule` once `switch_context` returns, and by
+/// `task_start` for new tasks.
+#[no_mangle]
+pub extern "C" fn finish_switch() {
+    let prev = percpu::current().prev_task.swap(0, Ordering::SeqCst);
+    if let Some(prev) = unsafe { (prev as *mut Task).as_ref() } {
+        if prev.state == State::Exited {
+            unsafe { stack_pool::release(&prev.stack) };
+        }
+        prev.on_cpu.store(false, Ordering::Release);
+    }
+}
+
+/// Switch to the next ready task.
+///
+/// Called with interrupts disabled, once the current task has been put
+/// wherever it belongs. If nothing is ready anywhere, we wait for an
+/// interrupt to make something ready, which might well be the current task
+/// again.
+unsafe fn reschedule() {
+    let current = current_task_ptr();
+    let mut next = next_task();
+    while next.is_none() {
+        // idle: let interrupts in, but don't return to the current task
+        // until something is ready
+        asm!( "sti
+               hlt
+               cli" :::: "volatile" );
+        next = next_task();
+    }
+    let next = next.unwrap();
+    (*next).state = State::Running;
+    // a fresh quantum, whatever the task had left last time
+    (*next).quantum = default_quantum();
+    if next == current {
+        return
+    }
+    // if another CPU only just switched away from `next`, wait for it to
+    // finish saving `next`'s context
+    while (*next).on_cpu.load(Ordering::Acquire) { cpu::spin_hint() }
+    (*next).on_cpu.store(true, Ordering::SeqCst);
+    check_canary(&*current);
+    fpu::task_switched(&mut (*next).fpu);
+    set_current_task(next);
+    percpu::current().prev_task.store(current as usize, Ordering::SeqCst);
+    context::switch_context(&mut (*current).context.rsp, (*next).context.rsp);
+    finish_switch();
+}
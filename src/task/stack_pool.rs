@@ -0,0 +1,125 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! A pool of kernel stacks for tasks.
+//!
+//! Every task stack is the same size, and comes from `vmalloc`. When a task
+//! exits, its stack goes on a free list to be handed straight to the next
+//! task that's spawned, rather than being unmapped and then mapped all over
+//! again; only once the list is full do stacks go back to `vmalloc`.
+//!
+//! A stack overflows downwards, off its lowest page, and the page below
+//! every `vmalloc` allocation is never mapped: it's either free, or the
+//! guard page at the end of the allocation before. So that page is the
+//! stack's guard page, and running off the stack faults rather than
+//! corrupting someone else's memory. We check that it's still unmapped
+//! whenever a stack changes hands, in case something has mapped it since.
+//!
+//! The free list is threaded through the stacks themselves: a free stack's
+//! lowest word (where a task's canary goes) holds the address of the next
+//! free stack.
+use spin::Mutex;
+use alloc::PAGE_SIZE;
+use arch::cpu;
+use arch::cpu::paging::AddressSpace;
+use ::memory::{vmalloc, vfree, VAddr};
+use super::Stack;
+
+/// Size of each task's stack (in bytes)
+pub const STACK_SIZE: usize = 4 * PAGE_SIZE;
+
+/// How many free stacks to keep around for reuse
+pub const MAX_FREE: usize = 16;
+
+/// The stacks waiting to be reused.
+struct FreeList { /// Address of the first free stack, or zero
+                  head: usize
+                , /// Number of stacks on the list
+                  len: usize
+                }
+
+static FREE: Mutex<FreeList> = Mutex::new(FreeList { head: 0, len: 0 });
+
+/// Returns true if the page just below the stack at `bottom` is unmapped.
+fn guard_intact(bottom: usize) -> bool {
+    let guard = VAddr::from_usize(bottom - PAGE_SIZE);
+    unsafe {
+        AddressSpace::current().entry_mut(guard)
+                               .map_or(true, |entry| entry.is_unused())
+    }
+}
+
+/// Returns a stack for a new task.
+///
+/// The stack comes from the free list if there's one there, and from
+/// `vmalloc` if not. Either way, it's `STACK_SIZE` bytes with a fresh
+/// canary at the bottom; a stack from the free list still has whatever its
+/// last task left in it.
+///
+/// # Returns
+///   - `None` if the free list is empty, and `vmalloc` is out of memory
+///
+/// # Panics
+///   - If the stack's guard page has been mapped
+pub fn allocate() -> Option<Stack> {
+    let reused = cpu::without_interrupts(|| {
+        let mut free = FREE.lock();
+        match free.head {
+            0 => None
+          , head => {
+                free.head = unsafe { *(head as *const usize) };
+                free.len -= 1;
+                Some(head as *mut u8)
+            }
+        }
+    });
+    reused.or_else(|| vmalloc(STACK_SIZE)).map(|bottom| {
+        assert!( guard_intact(bottom as usize)
+               , "the guard page below the stack at {:#x} is mapped!"
+               , bottom as usize );
+        unsafe { Stack::new(bottom, STACK_SIZE) }
+    })
+}
+
+/// Give back a stack that came from `allocate`.
+///
+/// A stack whose canary or guard page have been disturbed doesn't go back
+/// on the free list, since whatever it was that disturbed them might not be
+/// done with it; it's just given back to `vmalloc`.
+///
+/// # Unsafe due to
+///   - Nothing may run on `stack`, or use its memory, ever again
+///
+/// # Panics
+///   - If `stack` isn't `STACK_SIZE` bytes, so it can't have come from
+///     `allocate`
+pub unsafe fn release(stack: &Stack) {
+    assert!( stack.size() == STACK_SIZE
+           , "released a {}-byte stack to the stack pool, which only has \
+              {}-byte stacks", stack.size(), STACK_SIZE );
+    let bottom = stack.bottom();
+    let reusable = stack.canary_intact() && guard_intact(bottom as usize);
+    let recycled = reusable && cpu::without_interrupts(|| {
+        let mut free = FREE.lock();
+        if free.len == MAX_FREE {
+            return false
+        }
+        *(bottom as *mut usize) = free.head;
+        free.head = bottom as usize;
+        free.len += 1;
+        true
+    });
+    if !recycled {
+        vfree(bottom);
+    }
+}
+
+/// Returns the number of stacks waiting on the free list
+pub fn free_stacks() -> usize {
+    cpu::without_interrupts(|| FREE.lock().len)
+}
@@ -0,0 +1,113 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Sleeping synchronization primitives.
+//!
+//! Unlike the spinlocks in `spin`, these put a contended task to sleep on a
+//! `WaitQueue` instead of burning CPU until the lock is free. They're meant
+//! for things held for a long time (like a disk during I/O); since they can
+//! sleep, they must never be taken in an interrupt handler, although a
+//! `Semaphore` can be _released_ from one.
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use spin::Mutex;
+use arch::cpu;
+use super::WaitQueue;
+
+/// A counting semaphore.
+pub struct Semaphore { count: Mutex<usize>
+                     , waiters: WaitQueue
+                     }
+
+impl Semaphore {
+    /// Returns a new semaphore with `count` units available
+    pub const fn new(count: usize) -> Self {
+        Semaphore { count: Mutex::new(count), waiters: WaitQueue::new() }
+    }
+
+    /// Take a unit, without waiting.
+    ///
+    /// # Returns
+    ///   - `true` if a unit was taken
+    ///   - `false` if there were none available
+    pub fn try_acquire(&self) -> bool {
+        cpu::without_interrupts(|| {
+            let mut count = self.count.lock();
+            if *count > 0 { *count -= 1; true }
+            else { false }
+        })
+    }
+
+    /// Take a unit, sleeping until one is available.
+    ///
+    /// The count is re-checked every time we're woken, since another task may
+    /// have taken the unit first.
+    pub fn acquire(&self) {
+        self.waiters.wait_until(|| {
+            let mut count = self.count.lock();
+            if *count > 0 { *count -= 1; true }
+            else { false }
+        })
+    }
+
+    /// Give back a unit, waking up a task waiting for it.
+    ///
+    /// This never sleeps, so it's safe to call from an interrupt handler.
+    pub fn release(&self) {
+        cpu::without_interrupts(|| *self.count.lock() += 1);
+        self.waiters.wake_one();
+    }
+
+    /// Returns the number of units currently available
+    pub fn count(&self) -> usize {
+        cpu::without_interrupts(|| *self.count.lock())
+    }
+}
+
+/// A mutual exclusion lock that sleeps while it's contended.
+pub struct KMutex<T> { sem: Semaphore
+                     , data: UnsafeCell<T>
+                     }
+
+unsafe impl<T: Send> Sync for KMutex<T> { }
+unsafe impl<T: Send> Send for KMutex<T> { }
+
+/// A held `KMutex`; the lock is released when this is dropped.
+pub struct KMutexGuard<'a, T: 'a> { mutex: &'a KMutex<T> }
+
+impl<T> KMutex<T> {
+    /// Returns a new, unlocked `KMutex` protecting `data`
+    pub const fn new(data: T) -> Self {
+        KMutex { sem: Semaphore::new(1), data: UnsafeCell::new(data) }
+    }
+
+    /// Take the lock, sleeping until it's free.
+    pub fn lock(&self) -> KMutexGuard<T> {
+        self.sem.acquire();
+        KMutexGuard { mutex: self }
+    }
+
+    /// Take the lock if it's free, without waiting.
+    pub fn try_lock(&self) -> Option<KMutexGuard<T>> {
+        if self.sem.try_acquire() { Some(KMutexGuard { mutex: self }) }
+        else { None }
+    }
+}
+
+impl<'a, T> Deref for KMutexGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T { unsafe { &*self.mutex.data.get() } }
+}
+
+impl<'a, T> DerefMut for KMutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T { unsafe { &mut *self.mutex.data.get() } }
+}
+
+impl<'a, T> Drop for KMutexGuard<'a, T> {
+    fn drop(&mut self) { self.mutex.sem.release() }
+}
@@ -0,0 +1,218 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Timers, for running something after a number of system timer ticks.
+//!
+//! Timers are kept in a list sorted by the tick they expire at. All the
+//! system timer interrupt does is compare the tick count with the earliest
+//! deadline; once that's passed, it schedules `fire_expired` on the work
+//! queue, which runs the callbacks of every timer that's due. Callbacks
+//! therefore run in the worker task, not in interrupt context, and may do
+//! anything a task can.
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::sync::atomic::{ATOMIC_BOOL_INIT, ATOMIC_USIZE_INIT};
+use spin::Mutex;
+use arch::cpu;
+use arch::cpu::interrupts::ticks;
+use super::work;
+
+/// Number of timers that can be registered at once
+pub const MAX_TIMERS: usize = 32;
+
+/// Identifies a registered timer, so that it can be cancelled
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct TimerId(usize);
+
+/// What a timer calls when it expires
+pub type Callback = fn();
+
+#[derive(Copy, Clone)]
+struct Timer { id: TimerId
+             , /// The tick this timer expires at
+               deadline: usize
+             , /// Number of ticks between expiries, or 0 for a one-shot
+               period: usize
+             , callback: Callback
+             }
+
+/// The registered timers, in order of deadline.
+struct Timers { /// `timers[..len]` are all `Some`, sorted by deadline
+                timers: [Option<Timer>; MAX_TIMERS]
+              , len: usize
+              , next_id: usize
+              }
+
+impl Timers {
+    /// Add `timer`, keeping the list in order. Timers with the same
+    /// deadline fire in the order they were added.
+    fn insert(&mut self, timer: Timer) -> bool {
+        if self.len == MAX_TIMERS {
+            return false
+        }
+        let mut i = self.len;
+        while i > 0 && self.deadline(i - 1) > timer.deadline {
+            self.timers[i] = self.timers[i - 1];
+            i -= 1;
+        }
+        self.timers[i] = Some(timer);
+        self.len += 1;
+        true
+    }
+
+    /// Remove the timer at index `i`.
+    fn remove_at(&mut self, i: usize) -> Timer {
+        let timer = self.timers[i].take().unwrap();
+        for j in i..self.len - 1 {
+            self.timers[j] = self.timers[j + 1];
+        }
+        self.len -= 1;
+        self.timers[self.len] = None;
+        timer
+    }
+
+    /// Returns the index of the timer identified by `id`
+    fn position(&self, id: TimerId) -> Option<usize> {
+        self.timers[..self.len].iter()
+            .position(|t| t.map_or(false, |t| t.id == id))
+    }
+
+    #[inline]
+    fn deadline(&self, i: usize) -> usize {
+        self.timers[i].map_or(0, |t| t.deadline)
+    }
+
+    /// Returns the earliest deadline, or 0 if there are no timers
+    #[inline]
+    fn next_deadline(&self) -> usize {
+        if self.len == 0 { 0 } else { self.deadline(0) }
+    }
+}
+
+static TIMERS: Mutex<Timers>
+    = Mutex::new(Timers { timers: [None; MAX_TIMERS]
+                        , len: 0
+                        , next_id: 0
+                        });
+
+/// Copy of `TIMERS`'s earliest deadline, for the timer interrupt to check
+/// without taking the lock. 0 means there are no timers. Deadlines are
+/// always after the tick they were set on, so no real deadline is 0.
+static NEXT_DEADLINE: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Set while `fire_expired` is on the work queue, so that it's only ever on
+/// there once
+static FIRE_PENDING: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Do something to the timer list, with interrupts disabled, and keep
+/// `NEXT_DEADLINE` up to date.
+fn with_timers<F, T>(f: F) -> T
+where F: FnOnce(&mut Timers) -> T {
+    cpu::without_interrupts(|| {
+        let mut timers = TIMERS.lock();
+        let result = f(&mut timers);
+        NEXT_DEADLINE.store(timers.next_deadline(), Ordering::SeqCst);
+        result
+    })
+}
+
+fn add(ticks_from_now: usize, period: usize, callback: Callback)
+       -> Option<TimerId> {
+    let deadline = ticks() + if ticks_from_now == 0 { 1 }
+                             else { ticks_from_now };
+    with_timers(|timers| {
+        let id = TimerId(timers.next_id);
+        let timer = Timer { id: id, deadline: deadline, period: period
+                          , callback: callback };
+        if timers.insert(timer) {
+            timers.next_id += 1;
+            Some(id)
+        } else {
+            None
+        }
+    })
+}
+
+/// Call `callback` once, `ticks` timer ticks from now.
+///
+/// A timer set for 0 ticks fires on the next tick.
+///
+/// # Returns
+///   - `None` if there are already `MAX_TIMERS` timers
+pub fn after(ticks: usize, callback: Callback) -> Option<TimerId> {
+    add(ticks, 0, callback)
+}
+
+/// Call `callback` every `ticks` timer ticks, starting `ticks` from now.
+///
+/// If the worker falls so far behind that a periodic timer misses whole
+/// periods, the missed ones are skipped rather than all run at once.
+///
+/// # Returns
+///   - `None` if there are already `MAX_TIMERS` timers
+///
+/// # Panics
+///   - If `ticks` is 0
+pub fn every(ticks: usize, callback: Callback) -> Option<TimerId> {
+    assert!(ticks > 0, "a periodic timer needs a period of at least 1 tick");
+    add(ticks, ticks, callback)
+}
+
+/// Cancel a timer.
+///
+/// # Returns
+///   - `false` if there was no such timer (it was a one-shot that already
+///     fired, or it was already cancelled)
+pub fn cancel(id: TimerId) -> bool {
+    with_timers(|timers| {
+        let index = timers.position(id);
+        match index {
+            Some(i) => { timers.remove_at(i); true }
+          , None => false
+        }
+    })
+}
+
+/// Check for expired timers. Called by the system timer interrupt handler
+/// on every tick, with the new tick count.
+pub fn tick(now: usize) {
+    let next = NEXT_DEADLINE.load(Ordering::SeqCst);
+    if next != 0 && now >= next && !FIRE_PENDING.swap(true, Ordering::SeqCst) {
+        if !work::schedule(fire_expired) {
+            // we'll try again next tick
+            FIRE_PENDING.store(false, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Run the callbacks of every timer that's expired. Runs on the work queue.
+fn fire_expired() {
+    FIRE_PENDING.store(false, Ordering::SeqCst);
+    let now = ticks();
+    loop {
+        let expired = with_timers(|timers| {
+            if timers.len == 0 || timers.deadline(0) > now {
+                return None
+            }
+            let mut timer = timers.remove_at(0);
+            if timer.period != 0 {
+                let mut next = timer.deadline + timer.period;
+                if next <= now { next = now + timer.period }
+                timer.deadline = next;
+                // there's always room, since we just took this one out
+                timers.insert(timer);
+            }
+            Some(timer.callback)
+        });
+        match expired {
+            // the lock is released before the callback runs, so it can set
+            // or cancel timers
+            Some(callback) => callback()
+          , None => break
+        }
+    }
+}
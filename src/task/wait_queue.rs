@@ -0,0 +1,94 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Wait queues, for tasks to sleep on until something happens.
+//!
+//! The usual way to wait for a condition without missing the wakeup is
+//! `wait_until`: the condition is checked with interrupts disabled, and the
+//! task stays that way until it's safely in the queue and switched away
+//! from, so an interrupt handler can't sneak its `wake_all` in between the
+//! check and the sleep.
+use spin::Mutex;
+use arch::cpu;
+use super::{current_task_ptr, scheduler};
+use super::queue::TaskQueue;
+
+/// A queue of tasks waiting for an event.
+pub struct WaitQueue { tasks: Mutex<TaskQueue> }
+
+impl WaitQueue {
+    /// Returns a new wait queue with nobody waiting on it
+    pub const fn new() -> Self {
+        WaitQueue { tasks: Mutex::new(TaskQueue::new()) }
+    }
+
+    /// Put the current task to sleep until this queue is woken.
+    ///
+    /// If there's no current task (the scheduler hasn't started), this just
+    /// returns, so callers should always re-check whatever they were waiting
+    /// for. Prefer `wait_until`, which does that for you.
+    pub fn wait(&self) {
+        cpu::without_interrupts(|| unsafe { self.sleep() })
+    }
+
+    /// Sleep on this queue until `condition` returns true.
+    ///
+    /// `condition` is always called with interrupts disabled. Before the
+    /// scheduler has started, this busy-waits instead.
+    pub fn wait_until<F>(&self, mut condition: F)
+    where F: FnMut() -> bool {
+        loop {
+            let done = cpu::without_interrupts(|| {
+                if condition() { true }
+                else { unsafe { self.sleep() }; false }
+            });
+            if done { return }
+        }
+    }
+
+    /// Add the current task to the queue and switch away from it.
+    ///
+    /// Must be called with interrupts disabled.
+    unsafe fn sleep(&self) {
+        let current = current_task_ptr();
+        if current.is_null() {
+            return
+        }
+        self.tasks.lock().push_back(current);
+        scheduler::block_current();
+    }
+
+    /// Wake up the task that's been waiting longest.
+    ///
+    /// # Returns
+    ///   - `true` if a task was woken
+    pub fn wake_one(&self) -> bool {
+        cpu::without_interrupts(|| {
+            match self.tasks.lock().pop_front() {
+                Some(task) => { unsafe { scheduler::make_ready(task) }; true }
+              , None => false
+            }
+        })
+    }
+
+    /// Wake up every task waiting on this queue.
+    ///
+    /// # Returns
+    ///   - The number of tasks woken
+    pub fn wake_all(&self) -> usize {
+        cpu::without_interrupts(|| {
+            let mut tasks = self.tasks.lock();
+            let mut woken = 0;
+            while let Some(task) = tasks.pop_front() {
+                unsafe { scheduler::make_ready(task) };
+                woken += 1;
+            }
+            woken
+        })
+    }
+}
@@ -0,0 +1,122 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! A work queue, for getting things done outside of interrupt context.
+//!
+//! An interrupt handler can't sleep, take a `KMutex`, or run for long, so
+//! anything like that gets handed off to the worker task with `schedule`.
+//! The worker runs each item in the order it was scheduled, as an ordinary
+//! task with interrupts enabled.
+use core::ptr;
+use spin::Mutex;
+use arch::cpu;
+use ::memory::{frame, phys_to_virt};
+use super::{Task, TaskId, WaitQueue, scheduler, stack_pool};
+
+/// Number of work items that can be waiting at once
+pub const WORK_QUEUE_SIZE: usize = 32;
+
+/// The worker task's ID
+pub const WORKER_TASK_ID: TaskId = 1;
+
+/// A piece of work for the worker to do
+pub type Work = fn();
+
+/// A FIFO of work items.
+struct WorkQueue { items: [Option<Work>; WORK_QUEUE_SIZE]
+                 , /// Index of the oldest item
+                   head: usize
+                 , len: usize
+                 }
+
+impl WorkQueue {
+    fn push(&mut self, work: Work) -> bool {
+        if self.len == WORK_QUEUE_SIZE {
+            return false
+        }
+        self.items[(self.head + self.len) % WORK_QUEUE_SIZE] = Some(work);
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<Work> {
+        if self.len == 0 {
+            return None
+        }
+        let work = self.items[self.head].take();
+        self.head = (self.head + 1) % WORK_QUEUE_SIZE;
+        self.len -= 1;
+        work
+    }
+}
+
+/// Pending work. Only locked with interrupts disabled, since interrupt
+/// handlers push onto it.
+static QUEUE: Mutex<WorkQueue>
+    = Mutex::new(WorkQueue { items: [None; WORK_QUEUE_SIZE]
+                           , head: 0
+                           , len: 0
+                           });
+
+/// The worker waits here for something to do
+static WORKER_WAITERS: WaitQueue = WaitQueue::new();
+
+/// Have the worker task run `work`.
+///
+/// This is safe to call from an interrupt handler.
+///
+/// # Returns
+///   - `false` if the queue was full, in which case `work` won't run
+pub fn schedule(work: Work) -> bool {
+    let queued = cpu::without_interrupts(|| QUEUE.lock().push(work));
+    if queued {
+        WORKER_WAITERS.wake_one();
+    }
+    queued
+}
+
+/// Run every work item that's waiting, on the current task.
+///
+/// Each item is taken off the queue before it runs, so work can schedule
+/// more work.
+///
+/// # Returns
+///   - The number of items that were run
+pub fn run_pending() -> usize {
+    let mut n = 0;
+    while let Some(work) = cpu::without_interrupts(|| QUEUE.lock().pop()) {
+        work();
+        n += 1;
+    }
+    n
+}
+
+extern "C" fn worker() -> ! {
+    loop {
+        WORKER_WAITERS.wait_until(|| QUEUE.lock().len > 0);
+        run_pending();
+    }
+}
+
+/// Start the worker task.
+///
+/// This needs the scheduler to have been started with `scheduler::init`.
+///
+/// # Panics
+///   - If there's no memory for the worker's stack or `Task`
+pub fn start() {
+    let stack = stack_pool::allocate()
+                    .expect("no memory left for the worker's stack!");
+    let frame = frame::allocate_frame()
+                    .expect("no memory left for the worker task!");
+    unsafe {
+        let task = phys_to_virt(frame).as_usize() as *mut Task;
+        ptr::write(task, Task::new(WORKER_TASK_ID, stack, worker));
+        scheduler::spawn(task);
+    }
+}
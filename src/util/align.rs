@@ -0,0 +1,54 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Rounding addresses to a multiple of an alignment.
+//!
+//! Every alignment here must be a power of two, which is checked in debug
+//! builds. These would be `const fn`s, but a `const fn` can't contain an
+//! assertion (or an `if`) on the compiler we build with, and the check is
+//! the whole point: it's what catches an alignment that was computed wrong.
+
+/// Returns `addr` rounded down to a multiple of `align`
+#[inline]
+pub fn align_down(addr: usize, align: usize) -> usize {
+    debug_assert!( align.is_power_of_two()
+                 , "alignment {:#x} isn't a power of two", align );
+    addr & !(align - 1)
+}
+
+/// Returns `addr` rounded up to a multiple of `align`.
+///
+/// # Panics
+///   - In debug builds, if the result doesn't fit in a `usize` (in release
+///     builds, it wraps around to zero); use `checked_align_up` if that can
+///     happen
+#[inline]
+pub fn align_up(addr: usize, align: usize) -> usize {
+    debug_assert!( align.is_power_of_two()
+                 , "alignment {:#x} isn't a power of two", align );
+    (addr + (align - 1)) & !(align - 1)
+}
+
+/// Returns `addr` rounded up to a multiple of `align`.
+///
+/// # Returns
+///   - `None` if the result doesn't fit in a `usize`
+#[inline]
+pub fn checked_align_up(addr: usize, align: usize) -> Option<usize> {
+    debug_assert!( align.is_power_of_two()
+                 , "alignment {:#x} isn't a power of two", align );
+    addr.checked_add(align - 1).map(|a| a & !(align - 1))
+}
+
+/// Returns true if `addr` is a multiple of `align`
+#[inline]
+pub fn is_aligned(addr: usize, align: usize) -> bool {
+    debug_assert!( align.is_power_of_two()
+                 , "alignment {:#x} isn't a power of two", align );
+    addr & (align - 1) == 0
+}
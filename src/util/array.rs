@@ -0,0 +1,48 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Fixed-size arrays as backing storage.
+//!
+//! Rust can't be generic over the length of an array, so fixed-capacity
+//! containers are generic over an `Array` instead, which is implemented for
+//! arrays of a handful of useful sizes.
+
+/// A fixed-size array that can be used as backing storage.
+///
+/// This is unsafe to implement, since containers trust that `capacity()`
+/// really is the number of `Item`s the array holds.
+pub unsafe trait Array {
+    type Item;
+
+    /// Returns the number of items the array holds
+    fn capacity() -> usize;
+
+    /// Returns a pointer to the first item in the array
+    fn as_ptr(&self) -> *const Self::Item;
+
+    /// Returns a mutable pointer to the first item in the array
+    fn as_mut_ptr(&mut self) -> *mut Self::Item;
+}
+
+macro_rules! impl_array {
+    ($($n:expr),+) => { $(
+        unsafe impl<T> Array for [T; $n] {
+            type Item = T;
+            #[inline] fn capacity() -> usize { $n }
+            #[inline] fn as_ptr(&self) -> *const T {
+                self as *const _ as *const T
+            }
+            #[inline] fn as_mut_ptr(&mut self) -> *mut T {
+                self as *mut _ as *mut T
+            }
+        }
+    )+ }
+}
+
+impl_array!( 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16
+           , 24, 32, 48, 64, 96, 128, 256, 512, 1024, 2048, 4096 );
@@ -0,0 +1,188 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! A vector with a fixed capacity, stored inline.
+//!
+//! This is for the places that want to collect a varying number of things
+//! before there's a heap (or without wanting to depend on one): the items
+//! live in an `Array`, and pushing onto a full `ArrayVec` just fails.
+//!
+//! Tables with a slot for every one of a fixed set of keys, like the
+//! per-vector interrupt tables, are better off as plain arrays indexed by
+//! key, and the memory map doesn't need collecting at all, since the
+//! bootloader's tag can be walked in place.
+//!
+//! Only the first `len` items of the array are ever initialized, so the
+//! array itself must never be dropped, which would drop every item in it,
+//! including the ones that were never there. It's kept in a `NoDrop`, whose
+//! destructor forgets the array instead, and `ArrayVec`'s destructor drops
+//! just the items that are really there.
+use core::{fmt, mem, ops, ptr, slice};
+use super::array::Array;
+
+/// Something that's never dropped.
+///
+/// Dropping a `NoDrop` overwrites the `Alive` variant with `Dropped`,
+/// without running the destructor of what was in it.
+enum NoDrop<T> { Alive(T)
+               , Dropped
+               }
+
+impl<T> NoDrop<T> {
+    #[inline]
+    fn get(&self) -> &T {
+        match *self {
+            NoDrop::Alive(ref value) => value
+          , NoDrop::Dropped => unreachable!()
+        }
+    }
+
+    #[inline]
+    fn get_mut(&mut self) -> &mut T {
+        match *self {
+            NoDrop::Alive(ref mut value) => value
+          , NoDrop::Dropped => unreachable!()
+        }
+    }
+}
+
+impl<T> Drop for NoDrop<T> {
+    fn drop(&mut self) {
+        unsafe { ptr::write(self, NoDrop::Dropped) }
+    }
+}
+
+/// A vector of at most `A::capacity()` items, stored in the array `A`.
+///
+/// It derefs to a slice of the items it holds, for indexing and iteration.
+pub struct ArrayVec<A: Array> { /// Storage for the items; only the first
+                                /// `len` are initialized
+                                buf: NoDrop<A>
+                              , len: usize
+                              }
+
+impl<A: Array> ArrayVec<A> {
+    /// Returns a new, empty `ArrayVec`
+    #[inline]
+    pub fn new() -> Self {
+        ArrayVec { buf: NoDrop::Alive(unsafe { mem::uninitialized() })
+                 , len: 0
+                 }
+    }
+
+    /// Returns the most items this can hold
+    #[inline] pub fn capacity(&self) -> usize { A::capacity() }
+
+    /// Returns the number of items in the vector
+    #[inline] pub fn len(&self) -> usize { self.len }
+
+    #[inline] pub fn is_empty(&self) -> bool { self.len == 0 }
+    #[inline] pub fn is_full(&self) -> bool { self.len == A::capacity() }
+
+    /// Add `item` to the end of the vector.
+    ///
+    /// # Returns
+    ///   - `Ok(())` if the item was added
+    ///   - `Err(item)` if the vector was already full
+    pub fn push(&mut self, item: A::Item) -> Result<(), A::Item> {
+        if self.is_full() {
+            return Err(item)
+        }
+        unsafe {
+            let end = self.buf.get_mut().as_mut_ptr().offset(self.len as isize);
+            ptr::write(end, item);
+        }
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Take the last item off the end of the vector.
+    ///
+    /// # Returns
+    ///   - `None` if the vector was empty
+    pub fn pop(&mut self) -> Option<A::Item> {
+        if self.len == 0 {
+            return None
+        }
+        self.len -= 1;
+        Some(unsafe {
+            ptr::read(self.buf.get().as_ptr().offset(self.len as isize))
+        })
+    }
+
+    /// Take out the item at `index`, moving the last item into its place.
+    ///
+    /// This doesn't keep the items in order, but it's O(1).
+    ///
+    /// # Panics
+    ///   - If `index` is out of bounds
+    pub fn swap_remove(&mut self, index: usize) -> A::Item {
+        let len = self.len;
+        assert!( index < len
+               , "swap_remove index {} is out of bounds (the length is {})"
+               , index, len );
+        self.swap(index, len - 1);
+        self.pop().unwrap()
+    }
+
+    /// Drop every item, leaving the vector empty
+    pub fn clear(&mut self) {
+        while let Some(_) = self.pop() { }
+    }
+
+    /// Returns the items as a slice
+    #[inline]
+    pub fn as_slice(&self) -> &[A::Item] {
+        unsafe { slice::from_raw_parts(self.buf.get().as_ptr(), self.len) }
+    }
+
+    /// Returns the items as a mutable slice
+    #[inline]
+    pub fn as_mut_slice(&mut self) -> &mut [A::Item] {
+        let len = self.len;
+        unsafe {
+            slice::from_raw_parts_mut(self.buf.get_mut().as_mut_ptr(), len)
+        }
+    }
+}
+
+impl<A: Array> Drop for ArrayVec<A> {
+    fn drop(&mut self) {
+        // `buf` won't drop anything, so the items that are there have to be
+        // dropped here
+        self.clear()
+    }
+}
+
+impl<A: Array> ops::Deref for ArrayVec<A> {
+    type Target = [A::Item];
+    #[inline] fn deref(&self) -> &[A::Item] { self.as_slice() }
+}
+
+impl<A: Array> ops::DerefMut for ArrayVec<A> {
+    #[inline] fn deref_mut(&mut self) -> &mut [A::Item] { self.as_mut_slice() }
+}
+
+impl<'a, A: Array> IntoIterator for &'a ArrayVec<A> {
+    type Item = &'a A::Item;
+    type IntoIter = slice::Iter<'a, A::Item>;
+    #[inline] fn into_iter(self) -> Self::IntoIter { self.iter() }
+}
+
+impl<'a, A: Array> IntoIterator for &'a mut ArrayVec<A> {
+    type Item = &'a mut A::Item;
+    type IntoIter = slice::IterMut<'a, A::Item>;
+    #[inline] fn into_iter(self) -> Self::IntoIter { self.iter_mut() }
+}
+
+impl<A: Array> fmt::Debug for ArrayVec<A>
+where A::Item: fmt::Debug {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.as_slice().fmt(f)
+    }
+}
@@ -0,0 +1,67 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Kernel assertion macros.
+//!
+//! `kassert!`, `kassert_eq!`, and `kassert_ne!` work like their `std`
+//! counterparts, but their messages are laid out for the panic screen: the
+//! condition that failed on one line, and the values that were compared on
+//! the lines after it. The source location is printed by the panic handler.
+//!
+//! Building with `--cfg 'release_asserts="off"'` turns them into no-ops
+//! (though their arguments still have to type check).
+
+macro_rules! kassert {
+    ($cond:expr) => (
+        if !cfg!(release_asserts = "off") && !$cond {
+            panic!("kernel assertion failed: `{}`", stringify!($cond))
+        }
+    );
+    ($cond:expr, $($arg:tt)+) => (
+        if !cfg!(release_asserts = "off") && !$cond {
+            panic!( "kernel assertion failed: `{}`\n{}"
+                  , stringify!($cond), format_args!($($arg)+) )
+        }
+    );
+}
+
+macro_rules! kassert_eq {
+    ($left:expr, $right:expr) => (
+        kassert_eq!($left, $right, "")
+    );
+    ($left:expr, $right:expr, $($arg:tt)+) => (
+        if !cfg!(release_asserts = "off") {
+            match (&$left, &$right) {
+                (left, right) => if !(*left == *right) {
+                    panic!( "kernel assertion failed: `{} == {}`\n  \
+                              left: {:?}\n right: {:?}\n{}"
+                          , stringify!($left), stringify!($right)
+                          , left, right, format_args!($($arg)+) )
+                }
+            }
+        }
+    );
+}
+
+macro_rules! kassert_ne {
+    ($left:expr, $right:expr) => (
+        kassert_ne!($left, $right, "")
+    );
+    ($left:expr, $right:expr, $($arg:tt)+) => (
+        if !cfg!(release_asserts = "off") {
+            match (&$left, &$right) {
+                (left, right) => if *left == *right {
+                    panic!( "kernel assertion failed: `{} != {}`\n  \
+                              left: {:?}\n right: {:?}\n{}"
+                          , stringify!($left), stringify!($right)
+                          , left, right, format_args!($($arg)+) )
+                }
+            }
+        }
+    );
+}
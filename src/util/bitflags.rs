@@ -0,0 +1,158 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! A `bitflags!` macro for sets of bit flags.
+//!
+//! This is a stripped-down version of the `bitflags` crate's macro, with the
+//! same syntax:
+//!
+//! ```ignore
+//! bitflags! {
+//!     /// Some flags
+//!     flags Flags: u8 { const A = 1 << 0
+//!                     , const B = 1 << 1
+//!                     }
+//! }
+//! ```
+//!
+//! This generates a `pub struct Flags` wrapping a `u8` (in a private field
+//! named `bits`), and a `pub const` of type `Flags` for each flag. Any
+//! attributes on the `flags` item (including `#[derive]`s) are applied to the
+//! generated struct; it already derives `Copy`, `Clone`, `Eq`, `PartialEq`,
+//! `Ord`, `PartialOrd`, and `Hash`, and has a `Debug` impl that lists the
+//! names of the flags that are set.
+
+macro_rules! bitflags {
+    ( $(#[$attr:meta])*
+      flags $Flags:ident: $T:ty {
+          $( $(#[$flag_attr:meta])* const $Flag:ident = $value:expr ),+
+          $(,)*
+      } ) => {
+        $(#[$attr])*
+        #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+        pub struct $Flags { bits: $T }
+
+        $( $(#[$flag_attr])* pub const $Flag: $Flags = $Flags { bits: $value }; )+
+
+        #[allow(dead_code)]
+        impl $Flags {
+            /// Returns an empty set of flags
+            #[inline] pub fn empty() -> $Flags { $Flags { bits: 0 } }
+
+            /// Returns the set of all defined flags
+            #[inline] pub fn all() -> $Flags {
+                $Flags { bits: $($value)|+ }
+            }
+
+            /// Returns the raw value of the flags
+            #[inline] pub fn bits(&self) -> $T { self.bits }
+
+            /// Convert from a raw value.
+            ///
+            /// # Returns
+            ///   - `None` if `bits` has any bits set that don't correspond
+            ///     to a flag
+            #[inline] pub fn from_bits(bits: $T) -> Option<$Flags> {
+                if bits & !$Flags::all().bits != 0 { None }
+                else { Some($Flags { bits: bits }) }
+            }
+
+            /// Convert from a raw value, dropping any bits that don't
+            /// correspond to a flag
+            #[inline] pub fn from_bits_truncate(bits: $T) -> $Flags {
+                $Flags { bits: bits } & $Flags::all()
+            }
+
+            /// Returns true if no flags are set
+            #[inline] pub fn is_empty(&self) -> bool { self.bits == 0 }
+
+            /// Returns true if all the flags are set
+            #[inline] pub fn is_all(&self) -> bool {
+                self.bits == $Flags::all().bits
+            }
+
+            /// Returns true if all the flags in `other` are set
+            #[inline] pub fn contains(&self, other: $Flags) -> bool {
+                self.bits & other.bits == other.bits
+            }
+
+            /// Returns true if any of the flags in `other` are set
+            #[inline] pub fn intersects(&self, other: $Flags) -> bool {
+                self.bits & other.bits != 0
+            }
+
+            /// Set all the flags in `other`
+            #[inline] pub fn insert(&mut self, other: $Flags) {
+                self.bits |= other.bits;
+            }
+
+            /// Clear all the flags in `other`
+            #[inline] pub fn remove(&mut self, other: $Flags) {
+                self.bits &= !other.bits;
+            }
+
+            /// Flip all the flags in `other`
+            #[inline] pub fn toggle(&mut self, other: $Flags) {
+                self.bits ^= other.bits;
+            }
+        }
+
+        impl ::core::ops::BitOr for $Flags {
+            type Output = $Flags;
+            #[inline] fn bitor(self, other: $Flags) -> $Flags {
+                $Flags { bits: self.bits | other.bits }
+            }
+        }
+
+        impl ::core::ops::BitAnd for $Flags {
+            type Output = $Flags;
+            #[inline] fn bitand(self, other: $Flags) -> $Flags {
+                $Flags { bits: self.bits & other.bits }
+            }
+        }
+
+        impl ::core::ops::BitXor for $Flags {
+            type Output = $Flags;
+            #[inline] fn bitxor(self, other: $Flags) -> $Flags {
+                $Flags { bits: self.bits ^ other.bits }
+            }
+        }
+
+        impl ::core::ops::Sub for $Flags {
+            type Output = $Flags;
+            #[inline] fn sub(self, other: $Flags) -> $Flags {
+                $Flags { bits: self.bits & !other.bits }
+            }
+        }
+
+        impl ::core::ops::Not for $Flags {
+            type Output = $Flags;
+            #[inline] fn not(self) -> $Flags {
+                $Flags { bits: !self.bits } & $Flags::all()
+            }
+        }
+
+        impl ::core::fmt::Debug for $Flags {
+            fn fmt(&self, f: &mut ::core::fmt::Formatter)
+                  -> ::core::fmt::Result {
+                let mut first = true;
+                $(
+                    // flags with no bits set are always "contained", so
+                    // they'd show up every time if we didn't skip them
+                    if $Flag.bits != 0 && self.contains($Flag) {
+                        if !first { try!(f.write_str(" | ")); }
+                        first = false;
+                        try!(f.write_str(stringify!($Flag)));
+                    }
+                )+
+                if first { try!(f.write_str("(empty)")); }
+                Ok(())
+            }
+        }
+    };
+}
@@ -0,0 +1,131 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! An intrusive list in least-recently-used order, for caches.
+//!
+//! Each node embeds an `LruLink`, and says where it is by implementing
+//! `LruNode`. The list itself is just the two ends, so putting something on
+//! it never allocates, and both using a node (`touch`, which moves it to
+//! the front) and evicting one (`pop_lru`, which takes it off the back) are
+//! O(1).
+//!
+//! Since the list holds raw pointers to its nodes, the nodes mustn't move
+//! while they're on it; in practice they live in a static array, as the
+//! buffer cache's do.
+use alloc::RawLink;
+
+/// The links a node needs to be on an `LruList`.
+#[derive(Debug, Copy, Clone)]
+pub struct LruLink<T> { /// The next more recently used node
+                        prev: RawLink<T>
+                      , /// The next less recently used node
+                        next: RawLink<T>
+                      }
+
+impl<T> LruLink<T> {
+    /// Returns the links of a node that isn't on a list
+    pub const fn new() -> LruLink<T> {
+        LruLink { prev: RawLink::none(), next: RawLink::none() }
+    }
+}
+
+/// Something with an `LruLink` in it.
+pub trait LruNode: Sized {
+    /// Returns this node's links
+    fn lru_link(&mut self) -> &mut LruLink<Self>;
+}
+
+/// A list of nodes, from most recently used (the front) to least (the back).
+pub struct LruList<T: LruNode> { head: RawLink<T>
+                               , tail: RawLink<T>
+                               , len: usize
+                               }
+
+impl<T: LruNode> LruList<T> {
+    /// Returns a new, empty list
+    pub const fn new() -> LruList<T> {
+        LruList { head: RawLink::none(), tail: RawLink::none(), len: 0 }
+    }
+
+    /// Returns the number of nodes on the list
+    #[inline] pub fn len(&self) -> usize { self.len }
+
+    #[inline] pub fn is_empty(&self) -> bool { self.len == 0 }
+
+    /// Returns the least recently used node, without taking it off the list
+    #[inline]
+    pub fn lru(&self) -> Option<*mut T> {
+        if self.tail.is_some() { Some(unsafe { self.tail.as_raw() }) }
+        else { None }
+    }
+
+    /// Put `node` at the front of the list, as the most recently used.
+    ///
+    /// # Unsafe due to
+    ///   - `node` must be valid, and mustn't move or go away until it's taken
+    ///     off the list again
+    ///   - `node` mustn't already be on a list
+    pub unsafe fn push_front(&mut self, node: *mut T) {
+        {
+            let link = (*node).lru_link();
+            link.prev = RawLink::none();
+            link.next = self.head;
+        }
+        match self.head.resolve_mut() {
+            Some(head) => head.lru_link().prev = RawLink::from_raw(node)
+          , None => self.tail = RawLink::from_raw(node)
+        }
+        self.head = RawLink::from_raw(node);
+        self.len += 1;
+    }
+
+    /// Take `node` off the list.
+    ///
+    /// # Unsafe due to
+    ///   - `node` must be on this list
+    pub unsafe fn unlink(&mut self, node: *mut T) {
+        let (prev, next) = {
+            let link = (*node).lru_link();
+            (link.prev.take(), link.next.take())
+        };
+        match prev.resolve_mut() {
+            Some(prev) => prev.lru_link().next = next
+          , None => self.head = next
+        }
+        match next.resolve_mut() {
+            Some(next) => next.lru_link().prev = prev
+          , None => self.tail = prev
+        }
+        self.len -= 1;
+    }
+
+    /// Mark `node` as the most recently used, moving it to the front.
+    ///
+    /// # Unsafe due to
+    ///   - `node` must be on this list
+    pub unsafe fn touch(&mut self, node: *mut T) {
+        // it's already at the front; unlinking it would be harmless, but
+        // there's no need
+        if self.head.as_raw() == node {
+            return
+        }
+        self.unlink(node);
+        self.push_front(node);
+    }
+
+    /// Take the least recently used node off the back of the list.
+    ///
+    /// # Returns
+    ///   - `None` if the list is empty
+    pub fn pop_lru(&mut self) -> Option<*mut T> {
+        self.lru().map(|node| {
+            unsafe { self.unlink(node) };
+            node
+        })
+    }
+}
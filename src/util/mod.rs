@@ -0,0 +1,35 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Miscellaneous utilities.
+use core::fmt;
+
+#[macro_use] pub mod assert;
+#[macro_use] pub mod bitflags;
+pub mod align;
+pub mod array;
+pub mod array_vec;
+pub mod lru;
+pub mod once;
+pub mod ring_buffer;
+pub mod scope_guard;
+pub mod xorshift;
+
+pub use self::align::{align_down, align_up, checked_align_up, is_aligned};
+pub use self::array_vec::ArrayVec;
+pub use self::lru::{LruLink, LruList, LruNode};
+pub use self::ring_buffer::RingBuffer;
+pub use self::scope_guard::{ScopeGuard, defer};
+pub use self::xorshift::XorShift64;
+
+pub enum Void {}
+impl fmt::Debug for Void {
+    fn fmt(&self, _: &mut fmt::Formatter) -> fmt::Result {
+        unreachable!()
+    }
+}
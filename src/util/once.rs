@@ -0,0 +1,69 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! A value that's initialized exactly once.
+//!
+//! This is for globals that have to be built at runtime, but never change
+//! after that: once a `Once` has been initialized, it hands out plain shared
+//! references to its value, with no lock to take.
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+/// Nobody has started initializing the value yet
+const INCOMPLETE: usize = 0;
+/// Someone is running the initializer right now
+const RUNNING: usize = 1;
+/// The value is ready
+const COMPLETE: usize = 2;
+
+/// A value that's initialized exactly once, by the first `call_once`.
+pub struct Once<T> { state: AtomicUsize
+                   , data: UnsafeCell<Option<T>>
+                   }
+
+unsafe impl<T: Send + Sync> Sync for Once<T> { }
+
+impl<T> Once<T> {
+    /// Returns a new, uninitialized `Once`.
+    pub const fn new() -> Once<T> {
+        Once { state: ATOMIC_USIZE_INIT, data: UnsafeCell::new(None) }
+    }
+
+    /// Initialize the value with `f`, if it hasn't been already.
+    ///
+    /// If another CPU is running its initializer, this spins until it's
+    /// finished. `f` must not call `call_once` on the same `Once`, or it will
+    /// spin forever.
+    ///
+    /// # Returns
+    ///   - A reference to the value, whether or not it was `f` that made it
+    pub fn call_once<F>(&self, f: F) -> &T
+    where F: FnOnce() -> T {
+        if self.state.compare_and_swap(INCOMPLETE, RUNNING, Ordering::SeqCst)
+            == INCOMPLETE {
+            unsafe { *self.data.get() = Some(f()) };
+            self.state.store(COMPLETE, Ordering::SeqCst);
+        }
+        while self.state.load(Ordering::SeqCst) != COMPLETE {
+            ::arch::cpu::spin_hint()
+        }
+        self.get().unwrap()
+    }
+
+    /// Returns the value, if it's been initialized
+    pub fn get(&self) -> Option<&T> {
+        if self.state.load(Ordering::SeqCst) == COMPLETE {
+            unsafe { (*self.data.get()).as_ref() }
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if the value has been initialized
+    #[inline] pub fn is_completed(&self) -> bool { self.get().is_some() }
+}
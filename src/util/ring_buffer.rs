@@ -0,0 +1,187 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! A fixed-size, lock-free ring buffer.
+//!
+//! This is meant for passing events from an interrupt handler to a task:
+//! the handler pushes and the task pops, and neither of them ever has to
+//! wait for the other (which the interrupt handler couldn't do anyway).
+use core::cell::UnsafeCell;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use super::array::Array;
+
+/// A bounded single-producer, single-consumer queue.
+///
+/// The queue holds `A::capacity()` items, stored in the array `A`. Only one
+/// thread (or interrupt handler) may push at a time, and only one may pop at
+/// a time; other than that, pushing and popping don't need any locking.
+///
+/// When the queue is full, new items are dropped rather than overwriting old
+/// ones, and the number of dropped items is counted.
+pub struct RingBuffer<A>
+where A: Array
+    , A::Item: Copy { /// Storage for the items in the queue
+                      buf: UnsafeCell<A>
+                    , /// Number of items ever popped; the next item to pop
+                      /// is at `head % capacity`
+                      head: AtomicUsize
+                    , /// Number of items ever pushed; the next item will be
+                      /// pushed at `tail % capacity`
+                      tail: AtomicUsize
+                    , /// Number of items dropped because the queue was full
+                      dropped: AtomicUsize
+                    }
+
+unsafe impl<A> Sync for RingBuffer<A>
+where A: Array
+    , A::Item: Copy + Send { }
+
+impl<A> RingBuffer<A>
+where A: Array
+    , A::Item: Copy {
+
+    /// Create a new, empty ring buffer using `buf` for storage.
+    ///
+    /// The initial contents of `buf` don't matter; it's just there so that
+    /// this can be a `const fn`.
+    pub const fn new(buf: A) -> Self {
+        RingBuffer { buf: UnsafeCell::new(buf)
+                   , head: ATOMIC_USIZE_INIT
+                   , tail: ATOMIC_USIZE_INIT
+                   , dropped: ATOMIC_USIZE_INIT
+                   }
+    }
+
+    /// Returns the maximum number of items the queue can hold
+    #[inline] pub fn capacity(&self) -> usize { A::capacity() }
+
+    /// Returns the number of items currently in the queue
+    #[inline]
+    pub fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        self.tail.load(Ordering::Acquire).wrapping_sub(head)
+    }
+
+    #[inline] pub fn is_empty(&self) -> bool { self.len() == 0 }
+    #[inline] pub fn is_full(&self) -> bool { self.len() == self.capacity() }
+
+    /// Returns the number of items that have been dropped because the queue
+    /// was full when they were pushed
+    #[inline]
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Push `item` onto the back of the queue.
+    ///
+    /// # Returns
+    ///   - `Ok(())` if the item was pushed
+    ///   - `Err(item)` if the queue was full. The item is counted as dropped.
+    pub fn try_push(&self, item: A::Item) -> Result<(), A::Item> {
+        // only we change `tail`, so it can't move out from under us
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+        if tail.wrapping_sub(head) == A::capacity() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return Err(item)
+        }
+        unsafe {
+            let index = (tail % A::capacity()) as isize;
+            ptr::write((*self.buf.get()).as_mut_ptr().offset(index), item);
+        }
+        // publish the item only once it's been written
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    /// Pop the item at the front of the queue.
+    ///
+    /// # Returns
+    ///   - `Some(item)` if there was an item in the queue
+    ///   - `None` if the queue was empty
+    pub fn try_pop(&self) -> Option<A::Item> {
+        // only we change `head`, so it can't move out from under us
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+        if head == tail {
+            return None
+        }
+        let item = unsafe {
+            let index = (head % A::capacity()) as isize;
+            ptr::read((*self.buf.get()).as_ptr().offset(index))
+        };
+        // let the producer reuse the slot only once we've read it
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(item)
+    }
+}
+
+/// Check that a small ring buffer fills, empties, and wraps around the way it
+/// should. `make test` runs this as part of the self-test.
+///
+/// # Returns
+///   - `Err(what)` saying which check failed, if one did
+pub fn self_check() -> Result<(), &'static str> {
+    let ring: RingBuffer<[usize; 4]> = RingBuffer::new([0; 4]);
+    if !ring.is_empty() || ring.is_full() || ring.try_pop().is_some() {
+        return Err("new buffer wasn't empty")
+    }
+
+    for i in 0..4 {
+        if ring.try_push(i).is_err() {
+            return Err("push failed before the buffer was full")
+        }
+    }
+    if !ring.is_full() || ring.len() != 4 {
+        return Err("buffer wasn't full after filling it")
+    }
+    if ring.try_push(4) != Err(4) || ring.dropped() != 1 {
+        return Err("push to a full buffer wasn't dropped")
+    }
+
+    // go round several times, so the slots get reused
+    let mut next = 0;
+    for i in 4..20 {
+        if ring.try_pop() != Some(next) {
+            return Err("items came out in the wrong order")
+        }
+        next += 1;
+        if ring.try_push(i).is_err() {
+            return Err("push failed after making room")
+        }
+    }
+    while let Some(item) = ring.try_pop() {
+        if item != next { return Err("items came out in the wrong order") }
+        next += 1;
+    }
+    if next != 20 || !ring.is_empty() {
+        return Err("buffer wasn't empty after draining it")
+    }
+
+    // and make sure the counts themselves can wrap around
+    ring.head.store(usize::max_value() - 1, Ordering::Relaxed);
+    ring.tail.store(usize::max_value() - 1, Ordering::Relaxed);
+    for i in 0..4 {
+        if ring.try_push(i).is_err() {
+            return Err("push failed when the counts wrapped")
+        }
+    }
+    if !ring.is_full() || ring.try_push(4).is_ok() {
+        return Err("buffer wasn't full when the counts wrapped")
+    }
+    for i in 0..4 {
+        if ring.try_pop() != Some(i) {
+            return Err("items came out wrong when the counts wrapped")
+        }
+    }
+    if !ring.is_empty() {
+        return Err("buffer wasn't empty when the counts wrapped")
+    }
+    Ok(())
+}
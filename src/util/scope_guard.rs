@@ -0,0 +1,50 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Running some code when a scope is left.
+//!
+//! Lots of kernel code has to do something and then undo it afterwards
+//! (re-enable interrupts, unmask an IRQ, put the terminal's colours back).
+//! Rather than writing a RAII struct for every one of those, `defer` the
+//! undoing, and it'll happen however the scope is left.
+
+/// Runs a closure when it's dropped, unless it's been dismissed.
+///
+/// Made by `defer`.
+#[must_use = "the closure runs as soon as the guard is dropped"]
+pub struct ScopeGuard<F: FnMut()> { f: F
+                                  , armed: bool
+                                  }
+
+/// Run `f` when the returned guard is dropped.
+///
+/// ```ignore
+/// cli();
+/// let _guard = defer(|| sti());
+/// // interrupts are enabled again however we get out of here
+/// ```
+#[inline]
+pub fn defer<F: FnMut()>(f: F) -> ScopeGuard<F> {
+    ScopeGuard { f: f, armed: true }
+}
+
+impl<F: FnMut()> ScopeGuard<F> {
+    /// Drop the guard without running its closure.
+    #[inline]
+    pub fn dismiss(mut self) {
+        self.armed = false;
+    }
+}
+
+impl<F: FnMut()> Drop for ScopeGuard<F> {
+    fn drop(&mut self) {
+        if self.armed {
+            (self.f)()
+        }
+    }
+}
@@ -0,0 +1,62 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! A tiny pseudo-random number generator.
+//!
+//! This is Marsaglia's xorshift64: three shifts and three xors per number.
+//! It's nowhere near good enough for anything that has to be unpredictable,
+//! but it's plenty for shuffling test inputs around, and the same seed always
+//! gives the same numbers, so a failed test can be run again exactly.
+//!
+//! Refer to George Marsaglia, "Xorshift RNGs", _Journal of Statistical
+//! Software_ 8(14), 2003.
+
+/// What a zero seed is replaced with, since xorshift never leaves zero
+const DEFAULT_SEED: u64 = 0x2545_F491_4F6C_DD1D;
+
+/// An xorshift64 generator.
+#[derive(Debug, Copy, Clone)]
+pub struct XorShift64 { state: u64 }
+
+impl XorShift64 {
+    /// Returns a new generator starting from `seed`.
+    ///
+    /// A seed of 0 would only ever produce zeroes, so it's swapped for a
+    /// fixed non-zero seed instead.
+    pub fn new(seed: u64) -> XorShift64 {
+        XorShift64 { state: if seed == 0 { DEFAULT_SEED } else { seed } }
+    }
+
+    /// Returns the next number in the sequence
+    #[inline]
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state = x;
+        x
+    }
+
+    /// Returns a number from 0 up to (but not including) `bound`.
+    ///
+    /// This takes the remainder, so it's very slightly biased towards small
+    /// numbers unless `bound` is a power of two; for tests, that's fine.
+    ///
+    /// # Panics
+    ///   - If `bound` is 0
+    #[inline]
+    pub fn next_below(&mut self, bound: u64) -> u64 {
+        assert!(bound != 0, "can't pick a number below 0");
+        self.next_u64() % bound
+    }
+
+    /// Returns true with probability 1 in `n`
+    #[inline]
+    pub fn one_in(&mut self, n: u64) -> bool { self.next_below(n) == 0 }
+}
@@ -0,0 +1,149 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! A cache of recently used disk sectors.
+//!
+//! Reading a sector over ATA PIO is slow, and filesystems read the same few
+//! sectors (the FAT, directories) over and over, so they go through here
+//! instead of straight to the disk. The cache is a fixed set of buffers, on
+//! an `LruList`; when a sector that isn't cached is needed, the least
+//! recently used buffer is reused for it.
+//!
+//! Writes are write-back: they only change the cached copy, which is written
+//! to the disk when its buffer is reused, or by `sync`.
+use core::ptr;
+use spin::Mutex;
+use arch::drivers::ata::{self, AtaError, Drive, SECTOR_SIZE};
+use util::{LruLink, LruList, LruNode};
+
+/// Number of sectors the cache holds
+pub const N_BUFFERS: usize = 32;
+
+/// A cached sector.
+#[derive(Copy, Clone)]
+struct Buffer { /// The drive and LBA of the sector in this buffer, if any
+                key: Option<(Drive, u32)>
+              , /// True if `data` has been written to since it was read
+                dirty: bool
+              , data: [u8; SECTOR_SIZE]
+              , lru: LruLink<Buffer>
+              }
+
+impl Buffer {
+    const fn empty() -> Buffer {
+        Buffer { key: None
+               , dirty: false
+               , data: [0; SECTOR_SIZE]
+               , lru: LruLink::new()
+               }
+    }
+
+    /// Write the buffer back to the disk, if it's dirty.
+    fn write_back(&mut self) -> Result<(), AtaError> {
+        if let (true, Some((drive, lba))) = (self.dirty, self.key) {
+            try!(ata::PRIMARY.lock().write_sectors(drive, lba, 1, &self.data));
+            self.dirty = false;
+        }
+        Ok(())
+    }
+}
+
+impl LruNode for Buffer {
+    #[inline] fn lru_link(&mut self) -> &mut LruLink<Buffer> { &mut self.lru }
+}
+
+struct BufferCache { buffers: [Buffer; N_BUFFERS]
+                   , /// The buffers, most recently used first
+                     lru: LruList<Buffer>
+                   }
+
+impl BufferCache {
+    /// Put every buffer on the list, if they aren't already.
+    ///
+    /// The links point into the cache, so this can't be done until it's
+    /// sitting where it's going to stay.
+    unsafe fn link(&mut self) {
+        if !self.lru.is_empty() {
+            return
+        }
+        for i in 0..N_BUFFERS {
+            let buffer: *mut Buffer = &mut self.buffers[i];
+            self.lru.push_front(buffer);
+        }
+    }
+
+    /// Returns the buffer holding `lba` on `drive`, reading it from the disk
+    /// if it isn't cached. Either way, it becomes the most recently used.
+    unsafe fn get(&mut self, drive: Drive, lba: u32)
+                 -> Result<&mut Buffer, AtaError> {
+        self.link();
+        let key = Some((drive, lba));
+        let buffer = match self.buffers.iter().position(|b| b.key == key) {
+            Some(i) => &mut self.buffers[i] as *mut Buffer
+          , None => {
+                let buffer = self.lru.lru()
+                                     .expect("the buffer cache is empty");
+                try!((*buffer).write_back());
+                (*buffer).key = None;
+                try!(ata::PRIMARY.lock().read_sectors( drive, lba, 1
+                                                     , &mut (*buffer).data ));
+                (*buffer).key = key;
+                buffer
+            }
+        };
+        self.lru.touch(buffer);
+        Ok(&mut *buffer)
+    }
+}
+
+unsafe impl Send for BufferCache { }
+
+static CACHE: Mutex<BufferCache>
+    = Mutex::new(BufferCache { buffers: [Buffer::empty(); N_BUFFERS]
+                             , lru: LruList::new()
+                             });
+
+/// Read the sector at `lba` on `drive` into `buf`, from the cache if it's
+/// there.
+pub fn read(drive: Drive, lba: u32, buf: &mut [u8; SECTOR_SIZE])
+           -> Result<(), AtaError> {
+    let mut cache = CACHE.lock();
+    let buffer = try!(unsafe { cache.get(drive, lba) });
+    unsafe {
+        ptr::copy_nonoverlapping(buffer.data.as_ptr(), buf.as_mut_ptr()
+                                , SECTOR_SIZE);
+    }
+    Ok(())
+}
+
+/// Write `buf` to the sector at `lba` on `drive`.
+///
+/// Only the cached copy is changed; it's written to the disk when its
+/// buffer is reused, or by `sync`.
+pub fn write(drive: Drive, lba: u32, buf: &[u8; SECTOR_SIZE])
+            -> Result<(), AtaError> {
+    let mut cache = CACHE.lock();
+    // this reads the sector first if it isn't cached, which is wasted, but
+    // keeps the eviction in one place
+    let buffer = try!(unsafe { cache.get(drive, lba) });
+    unsafe {
+        ptr::copy_nonoverlapping(buf.as_ptr(), buffer.data.as_mut_ptr()
+                                , SECTOR_SIZE);
+    }
+    buffer.dirty = true;
+    Ok(())
+}
+
+/// Write every dirty buffer back to the disk.
+pub fn sync() -> Result<(), AtaError> {
+    let mut cache = CACHE.lock();
+    for buffer in cache.buffers.iter_mut() {
+        try!(buffer.write_back());
+    }
+    Ok(())
+}
@@ -0,0 +1,457 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! A read-only FAT16/FAT32 filesystem.
+//!
+//! A FAT volume starts with a boot sector holding the BIOS Parameter Block
+//! (BPB), which describes the rest of the volume: some reserved sectors,
+//! then one or more copies of the File Allocation Table, then (on FAT16
+//! only) a fixed-size root directory, and then the data area, divided into
+//! clusters. The FAT has one entry per cluster, giving the number of the
+//! next cluster in the same file, so each file is a linked list of clusters
+//! starting at the cluster named in its directory entry. Directories are
+//! just files full of 32-byte entries.
+//!
+//! We only understand 8.3 names, and skip over long file name entries.
+//! File ids are the number of the file's first cluster; the FAT16 root
+//! directory, which isn't in a cluster, is id 0.
+//!
+//! Every sector is read through the partition, and so through the buffer
+//! cache, which is what keeps following cluster chains from hitting the
+//! disk for each link.
+//!
+//! Refer to the OS Dev Wiki for more information:
+//! http://wiki.osdev.org/FAT
+use core::cmp;
+use arch::drivers::ata::SECTOR_SIZE;
+use super::{FileSystem, File, FileKind, DirEntry, Error};
+use super::mbr::Partition;
+
+/// Size of a directory entry (in bytes)
+const DIR_ENTRY_SIZE: usize = 32;
+
+/// The `id` of the FAT16 root directory
+const FAT16_ROOT_ID: u64 = 0;
+
+/// Directory entry attribute bits
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+/// Long file name entries have all of these attribute bits set
+const ATTR_LONG_NAME: u8 = 0x0F;
+
+/// The first byte of the name of a deleted entry
+const DELETED: u8 = 0xE5;
+
+/// Which flavour of FAT a volume uses.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum FatKind { Fat16
+                 , Fat32
+                 }
+
+/// Returns `c` in upper case, if it's an ASCII letter
+#[inline]
+fn to_upper(c: u8) -> u8 {
+    if c >= b'a' && c <= b'z' { c - (b'a' - b'A') } else { c }
+}
+
+#[inline]
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    bytes[offset] as u16 | (bytes[offset + 1] as u16) << 8
+}
+
+#[inline]
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    read_u16(bytes, offset) as u32 | (read_u16(bytes, offset + 2) as u32) << 16
+}
+
+/// A directory entry, as stored on disk.
+#[derive(Copy, Clone)]
+struct RawEntry { name: [u8; 11]
+                , attrs: u8
+                , cluster: u32
+                , size: u32
+                }
+
+impl RawEntry {
+    fn parse(bytes: &[u8]) -> RawEntry {
+        let mut name = [0; 11];
+        for (dst, src) in name.iter_mut().zip(&bytes[..11]) {
+            *dst = *src;
+        }
+        // 0x05 stands in for a real 0xE5, which would mean "deleted"
+        if name[0] == 0x05 {
+            name[0] = DELETED;
+        }
+        RawEntry { name: name
+                 , attrs: bytes[11]
+                 , cluster: (read_u16(bytes, 20) as u32) << 16
+                          | read_u16(bytes, 26) as u32
+                 , size: read_u32(bytes, 28)
+                 }
+    }
+
+    #[inline] fn is_dir(&self) -> bool { self.attrs & ATTR_DIRECTORY != 0 }
+
+    /// Returns true if this entry names a real file or directory, rather
+    /// than being deleted, part of a long name, a volume label, or `.` or
+    /// `..`
+    fn is_visible(&self) -> bool {
+        self.name[0] != DELETED
+            && self.attrs & ATTR_LONG_NAME != ATTR_LONG_NAME
+            && self.attrs & ATTR_VOLUME_ID == 0
+            && self.name[0] != b'.'
+    }
+
+    /// Write the entry's name into `buf`, as `NAME.EXT`.
+    ///
+    /// # Returns
+    ///   - The length of the name
+    fn name(&self, buf: &mut [u8; 12]) -> usize {
+        let base = self.name[..8].iter().rposition(|&c| c != b' ')
+                                 .map_or(0, |i| i + 1);
+        let ext = self.name[8..].iter().rposition(|&c| c != b' ')
+                                .map_or(0, |i| i + 1);
+        for (dst, src) in buf.iter_mut().zip(&self.name[..base]) {
+            *dst = *src;
+        }
+        if ext == 0 {
+            return base
+        }
+        buf[base] = b'.';
+        let ext = &self.name[8..8 + ext];
+        for (dst, src) in buf[base + 1..].iter_mut().zip(ext) {
+            *dst = *src;
+        }
+        base + 1 + ext.len()
+    }
+
+    /// Returns true if this entry's name is `name`, ignoring case (as FAT
+    /// does)
+    fn is_named(&self, name: &str) -> bool {
+        let mut buf = [0; 12];
+        let len = self.name(&mut buf);
+        len == name.len()
+            && buf[..len].iter().zip(name.bytes())
+                         .all(|(&a, b)| to_upper(a) == to_upper(b))
+    }
+
+    fn as_file(&self) -> File {
+        if self.is_dir() {
+            File { id: self.cluster as u64, kind: FileKind::Directory, size: 0 }
+        } else {
+            File { id: self.cluster as u64
+                 , kind: FileKind::Regular
+                 , size: self.size as u64
+                 }
+        }
+    }
+}
+
+/// A mounted FAT volume.
+pub struct FatFs { partition: Partition
+                 , kind: FatKind
+                 , sectors_per_cluster: u32
+                 , /// First sector of the first FAT
+                   fat_start: u32
+                 , /// First sector of the FAT16 root directory
+                   root_start: u32
+                 , /// Number of sectors in the FAT16 root directory
+                   root_sectors: u32
+                 , /// First cluster of the FAT32 root directory
+                   root_cluster: u32
+                 , /// First sector of cluster 2, the first data cluster
+                   data_start: u32
+                 , /// Number of data clusters
+                   n_clusters: u32
+                 }
+
+/// How far we've got in walking the sectors of a directory.
+enum DirCursor { /// The FAT16 root directory, which is a fixed run of sectors
+                 /// rather than a cluster chain: the next sector to read
+                 Fixed(u32)
+               , /// A directory in a cluster chain
+                 Chain { /// The cluster we're in
+                         cluster: u32
+                       , /// The next sector of `cluster` to read
+                         sector: u32
+                       , /// How many clusters we've followed the chain past
+                         walked: u32
+                       }
+               }
+
+/// Read one sector of `partition`, turning disk errors into `Error::Io`.
+fn read_sector(partition: &Partition, lba: u32, buf: &mut [u8; SECTOR_SIZE])
+               -> Result<(), Error> {
+    partition.read_sectors(lba, 1, buf).map_err(|_| Error::Io)
+}
+
+impl FatFs {
+    /// Mount the FAT volume on `partition`.
+    ///
+    /// # Returns
+    ///   - `Err(Error::Corrupt)` if the partition doesn't hold a FAT16 or
+    ///     FAT32 volume we can read (FAT12 isn't supported, and nor are
+    ///     sectors that aren't 512 bytes)
+    ///   - `Err(Error::Io)` if the boot sector couldn't be read
+    pub fn mount(partition: Partition) -> Result<FatFs, Error> {
+        let mut boot = [0u8; SECTOR_SIZE];
+        try!(read_sector(&partition, 0, &mut boot));
+        if boot[510] != 0x55 || boot[511] != 0xAA
+            || read_u16(&boot, 11) as usize != SECTOR_SIZE {
+            return Err(Error::Corrupt)
+        }
+        let sectors_per_cluster = boot[13] as u32;
+        let reserved = read_u16(&boot, 14) as u32;
+        let n_fats = boot[16] as u32;
+        let root_entries = read_u16(&boot, 17) as u32;
+        let total = match read_u16(&boot, 19) {
+            0 => read_u32(&boot, 32)
+          , n => n as u32
+        };
+        let fat_size = match read_u16(&boot, 22) {
+            0 => read_u32(&boot, 36)
+          , n => n as u32
+        };
+        if sectors_per_cluster == 0 || n_fats == 0 || fat_size == 0 {
+            return Err(Error::Corrupt)
+        }
+
+        let root_sectors = (root_entries * DIR_ENTRY_SIZE as u32
+                            + SECTOR_SIZE as u32 - 1) / SECTOR_SIZE as u32;
+        // `fat_size` comes straight from the boot sector, so a corrupt one
+        // could overflow these
+        let root_start = try!( n_fats.checked_mul(fat_size)
+                                     .and_then(|n| n.checked_add(reserved))
+                                     .ok_or(Error::Corrupt) );
+        let data_start = try!( root_start.checked_add(root_sectors)
+                                         .ok_or(Error::Corrupt) );
+        if data_start >= total || total > partition.sectors {
+            return Err(Error::Corrupt)
+        }
+        let n_clusters = (total - data_start) / sectors_per_cluster;
+        // the number of clusters is the only thing that decides which FAT
+        // this is, whatever the boot sector's label says
+        let kind = if n_clusters < 4085 { return Err(Error::Corrupt) }
+                   else if n_clusters < 65525 { FatKind::Fat16 }
+                   else { FatKind::Fat32 };
+
+        Ok(FatFs { partition: partition
+                 , kind: kind
+                 , sectors_per_cluster: sectors_per_cluster
+                 , fat_start: reserved
+                 , root_start: root_start
+                 , root_sectors: root_sectors
+                 , root_cluster: if kind == FatKind::Fat32 {
+                       read_u32(&boot, 44)
+                   } else { 0 }
+                 , data_start: data_start
+                 , n_clusters: n_clusters
+                 })
+    }
+
+    /// Returns which kind of FAT this volume uses
+    #[inline] pub fn kind(&self) -> FatKind { self.kind }
+
+    /// Returns the size of a cluster (in bytes)
+    #[inline]
+    pub fn cluster_size(&self) -> usize {
+        self.sectors_per_cluster as usize * SECTOR_SIZE
+    }
+
+    /// Returns the sector (within the partition) that `cluster` starts at.
+    fn cluster_lba(&self, cluster: u32) -> Result<u32, Error> {
+        if cluster < 2 || cluster - 2 >= self.n_clusters {
+            return Err(Error::Corrupt)
+        }
+        Ok(self.data_start + (cluster - 2) * self.sectors_per_cluster)
+    }
+
+    /// Look up the cluster after `cluster` in the FAT.
+    ///
+    /// # Returns
+    ///   - `Ok(Some(u32))` with the next cluster in the chain
+    ///   - `Ok(None)` if `cluster` is the last one
+    fn next_cluster(&self, cluster: u32) -> Result<Option<u32>, Error> {
+        let width = match self.kind { FatKind::Fat16 => 2
+                                    , FatKind::Fat32 => 4 };
+        let offset = cluster as usize * width;
+        let sector = self.fat_start + (offset / SECTOR_SIZE) as u32;
+        let offset = offset % SECTOR_SIZE;
+
+        // walking a chain reads the same FAT sector over and over, but it'll
+        // be in the buffer cache after the first time
+        let mut data = [0u8; SECTOR_SIZE];
+        try!(read_sector(&self.partition, sector, &mut data));
+        let data = &data;
+        let (next, end, bad) = match self.kind {
+            FatKind::Fat16 => (read_u16(data, offset) as u32, 0xFFF8, 0xFFF7)
+          , FatKind::Fat32 => ( read_u32(data, offset) & 0x0FFF_FFFF
+                              , 0x0FFF_FFF8, 0x0FFF_FFF7 )
+        };
+        if next >= end {
+            Ok(None)
+        } else if next == bad || next < 2 {
+            Err(Error::Corrupt)
+        } else {
+            Ok(Some(next))
+        }
+    }
+
+    /// Returns a cursor at the start of the directory `dir`
+    fn dir_cursor(&self, dir: &File) -> DirCursor {
+        if dir.id == FAT16_ROOT_ID && self.kind == FatKind::Fat16 {
+            DirCursor::Fixed(0)
+        } else {
+            DirCursor::Chain { cluster: dir.id as u32, sector: 0, walked: 0 }
+        }
+    }
+
+    /// Returns the next sector of the directory `cursor` is walking, as a
+    /// sector number within the partition, and moves `cursor` past it.
+    ///
+    /// A chain with more clusters in it than the volume has must loop back
+    /// on itself, so rather than going round it forever, we give up once
+    /// we've walked that far.
+    ///
+    /// # Returns
+    ///   - `Ok(None)` if there are no more sectors in the directory
+    ///   - `Err(Error::Corrupt)` if the directory's cluster chain loops
+    fn dir_sector(&self, cursor: &mut DirCursor) -> Result<Option<u32>, Error> {
+        match *cursor {
+            DirCursor::Fixed(ref mut n) => {
+                if *n >= self.root_sectors {
+                    return Ok(None)
+                }
+                *n += 1;
+                Ok(Some(self.root_start + *n - 1))
+            }
+          , DirCursor::Chain { ref mut cluster, ref mut sector
+                             , ref mut walked } => {
+                if *sector == self.sectors_per_cluster {
+                    *walked += 1;
+                    if *walked >= self.n_clusters {
+                        return Err(Error::Corrupt)
+                    }
+                    *cluster = match try!(self.next_cluster(*cluster)) {
+                        Some(next) => next
+                      , None => return Ok(None)
+                    };
+                    *sector = 0;
+                }
+                let lba = try!(self.cluster_lba(*cluster)) + *sector;
+                *sector += 1;
+                Ok(Some(lba))
+            }
+        }
+    }
+
+    /// Returns the first visible entry in `dir` for which `pred` is true.
+    fn find_entry<F>(&self, dir: &File, mut pred: F)
+                    -> Result<Option<RawEntry>, Error>
+    where F: FnMut(&RawEntry) -> bool {
+        if !dir.is_dir() {
+            return Err(Error::NotADirectory)
+        }
+        let mut buf = [0u8; SECTOR_SIZE];
+        let mut cursor = self.dir_cursor(dir);
+        while let Some(sector) = try!(self.dir_sector(&mut cursor)) {
+            try!(read_sector(&self.partition, sector, &mut buf));
+            for bytes in buf.chunks(DIR_ENTRY_SIZE) {
+                // a zero here means there are no more entries after this
+                if bytes[0] == 0 {
+                    return Ok(None)
+                }
+                let entry = RawEntry::parse(bytes);
+                if entry.is_visible() && pred(&entry) {
+                    return Ok(Some(entry))
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the root directory
+    fn root(&self) -> File {
+        let id = match self.kind { FatKind::Fat16 => FAT16_ROOT_ID
+                                 , FatKind::Fat32 => self.root_cluster as u64
+                                 };
+        File { id: id, kind: FileKind::Directory, size: 0 }
+    }
+}
+
+impl FileSystem for FatFs {
+
+    fn open(&self, path: &str) -> Result<File, Error> {
+        let mut file = self.root();
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            file = match try!(self.find_entry(&file, |e| e.is_named(name))) {
+                Some(entry) => entry.as_file()
+              , None => return Err(Error::NotFound)
+            };
+        }
+        Ok(file)
+    }
+
+    fn read(&self, file: &File, offset: u64, buf: &mut [u8])
+           -> Result<usize, Error> {
+        if file.is_dir() {
+            return Err(Error::IsADirectory)
+        }
+        if offset >= file.size {
+            return Ok(0)
+        }
+        let len = cmp::min(buf.len() as u64, file.size - offset) as usize;
+
+        // skip the clusters before `offset`
+        let cluster_size = self.cluster_size() as u64;
+        let mut cluster = file.id as u32;
+        for _ in 0..offset / cluster_size {
+            cluster = try!(try!(self.next_cluster(cluster))
+                               .ok_or(Error::Corrupt));
+        }
+
+        let mut sector_buf = [0u8; SECTOR_SIZE];
+        let mut pos = offset;
+        let mut done = 0;
+        while done < len {
+            let in_cluster = pos % cluster_size;
+            let lba = try!(self.cluster_lba(cluster))
+                    + (in_cluster / SECTOR_SIZE as u64) as u32;
+            try!(read_sector(&self.partition, lba, &mut sector_buf));
+            let start = (pos % SECTOR_SIZE as u64) as usize;
+            let n = cmp::min(SECTOR_SIZE - start, len - done);
+            for (dst, src) in buf[done..done + n].iter_mut()
+                                                  .zip(&sector_buf[start..]) {
+                *dst = *src;
+            }
+            done += n;
+            pos += n as u64;
+            if done < len && pos % cluster_size == 0 {
+                cluster = try!(try!(self.next_cluster(cluster))
+                                   .ok_or(Error::Corrupt));
+            }
+        }
+        Ok(len)
+    }
+
+    fn write(&self, _file: &File, _offset: u64, _buf: &[u8])
+            -> Result<usize, Error> {
+        Err(Error::ReadOnly)
+    }
+
+    fn readdir(&self, dir: &File, index: usize)
+              -> Result<Option<DirEntry>, Error> {
+        let mut seen = 0;
+        Ok(try!(self.find_entry(dir, |_| { seen += 1; seen > index }))
+            .map(|entry| {
+                let mut name = [0; 12];
+                let len = entry.name(&mut name);
+                DirEntry::new(&name[..len], entry.as_file())
+            }))
+    }
+}
@@ -0,0 +1,184 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Initial ramdisk loading.
+//!
+//! The initrd is a USTAR-format tar archive, either passed to us by the
+//! bootloader as a Multiboot module or embedded in the kernel image with
+//! `include_bytes!`. Every regular file in the archive is added to the ramfs
+//! in place; file contents are never copied, so the archive must stay put for
+//! the lifetime of the kernel.
+//!
+//! A tar archive is just a sequence of 512-byte header blocks, each followed
+//! by the file's contents padded out to a multiple of 512 bytes. The archive
+//! ends with (at least) one block of all zeroes.
+//!
+//! Refer to the OS Dev Wiki for more information:
+//! http://wiki.osdev.org/USTAR
+use core::str;
+use super::Error;
+use super::ramfs::RAMFS;
+
+/// Size of a tar header (and of the blocks that file contents are padded to)
+const BLOCK_SIZE: usize = 512;
+
+/// Offsets and lengths of the header fields we care about
+const NAME:     (usize, usize) = (0, 100);
+const SIZE:     (usize, usize) = (124, 12);
+const CHECKSUM: (usize, usize) = (148, 8);
+const TYPEFLAG: usize          = 156;
+const MAGIC:    (usize, usize) = (257, 5);
+const PREFIX:   (usize, usize) = (345, 155);
+
+/// Errors that can occur while loading an initrd.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum InitrdError { /// The archive ended in the middle of a header or file
+                       Truncated
+                     , /// A header didn't contain the `ustar` magic number
+                       BadMagic
+                     , /// A header's checksum didn't match its contents
+                       BadChecksum
+                     , /// A numeric header field wasn't valid octal
+                       BadNumber
+                     , /// A file name wasn't valid UTF-8
+                       BadName
+                     , /// A file name used the USTAR `prefix` field, which we
+                       /// can't stitch back together without allocating
+                       LongName
+                     , /// The ramfs wouldn't accept the file
+                       Fs(Error)
+                     }
+
+/// The type of an entry in a tar archive, from the header's typeflag byte.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+enum EntryType { Regular
+               , Directory
+               , /// Links, device nodes, FIFOs, etc., which we skip
+                 Other
+               }
+
+impl EntryType {
+    fn from_flag(flag: u8) -> Self {
+        match flag {
+            b'0' | b'\0' => EntryType::Regular
+          , b'5'         => EntryType::Directory
+          , _            => EntryType::Other
+        }
+    }
+}
+
+/// Returns the bytes of the header field at `(offset, length)`.
+#[inline]
+fn field(header: &'static [u8], (offset, len): (usize, usize))
+        -> &'static [u8] {
+    &header[offset..offset + len]
+}
+
+/// Strip the trailing NULs from a fixed-width string field.
+#[inline]
+fn trim_nul(bytes: &'static [u8]) -> &'static [u8] {
+    let len = bytes.iter()
+                   .position(|b| *b == 0)
+                   .unwrap_or(bytes.len());
+    &bytes[..len]
+}
+
+/// Parse a numeric header field.
+///
+/// Numbers are stored as ASCII octal, padded with leading zeroes or spaces
+/// and terminated by a NUL or a space.
+fn parse_octal(bytes: &[u8]) -> Result<usize, InitrdError> {
+    let mut result = 0;
+    for byte in bytes.iter()
+                     .skip_while(|b| **b == b' ')
+                     .take_while(|b| **b != 0 && **b != b' ') {
+        match *byte {
+            b'0'...b'7' => result = result * 8 + (*byte - b'0') as usize
+          , _           => return Err(InitrdError::BadNumber)
+        }
+    }
+    Ok(result)
+}
+
+/// Check a header's checksum.
+///
+/// The checksum is the sum of every byte in the header, with the checksum
+/// field itself counted as if it were all spaces.
+fn checksum_ok(header: &'static [u8]) -> Result<bool, InitrdError> {
+    let expected = try!(parse_octal(field(header, CHECKSUM)));
+    let (start, len) = CHECKSUM;
+    let actual = header.iter()
+                       .enumerate()
+                       .fold(0, |sum, (idx, byte)|
+                           if idx >= start && idx < start + len {
+                               sum + b' ' as usize
+                           } else {
+                               sum + *byte as usize
+                           });
+    Ok(actual == expected)
+}
+
+/// Returns the path of the file described by `header`.
+fn entry_name(header: &'static [u8]) -> Result<&'static str, InitrdError> {
+    if header[PREFIX.0] != 0 {
+        return Err(InitrdError::LongName)
+    }
+    let name = try!( str::from_utf8(trim_nul(field(header, NAME)))
+                         .map_err(|_| InitrdError::BadName) );
+    // archives made with `tar -C dir .` prefix everything with `./`
+    Ok(name.trim_left_matches("./"))
+}
+
+/// Load every regular file in the tar archive `bytes` into the ramfs.
+///
+/// Directories are skipped (the ramfs has a flat namespace), as are links
+/// and special files.
+pub fn load_initrd(bytes: &'static [u8]) -> Result<(), InitrdError> {
+    let mut offset = 0;
+    while offset + BLOCK_SIZE <= bytes.len() {
+        let header = &bytes[offset..offset + BLOCK_SIZE];
+
+        // an all-zero block marks the end of the archive
+        if header.iter().all(|b| *b == 0) {
+            return Ok(())
+        }
+        if field(header, MAGIC) != &b"ustar"[..] {
+            return Err(InitrdError::BadMagic)
+        }
+        if !try!(checksum_ok(header)) {
+            return Err(InitrdError::BadChecksum)
+        }
+
+        let size = try!(parse_octal(field(header, SIZE)));
+        let data_start = offset + BLOCK_SIZE;
+        let data_end = data_start + size;
+        if data_end > bytes.len() {
+            return Err(InitrdError::Truncated)
+        }
+
+        match EntryType::from_flag(header[TYPEFLAG]) {
+            EntryType::Regular => {
+                let name = try!(entry_name(header));
+                try!( RAMFS.add_file(name, &bytes[data_start..data_end])
+                           .map_err(InitrdError::Fs) );
+            }
+          , EntryType::Directory | EntryType::Other => { }
+        }
+
+        // file contents are padded out to a whole number of blocks
+        let n_blocks = (size + BLOCK_SIZE - 1) / BLOCK_SIZE;
+        offset = data_start + n_blocks * BLOCK_SIZE;
+    }
+
+    if offset == bytes.len() {
+        // some archivers don't bother with the trailing zero blocks
+        Ok(())
+    } else {
+        Err(InitrdError::Truncated)
+    }
+}
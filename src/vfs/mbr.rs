@@ -0,0 +1,173 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! MBR partition tables.
+//!
+//! The first sector of a disk is the Master Boot Record: some boot code,
+//! then a table of four primary partitions starting at byte `0x1BE`, and the
+//! signature `0x55 0xAA` in the last two bytes. Each entry in the table says
+//! where a partition starts and how long it is (in sectors), and what kind
+//! of partition it is. We only look at the LBA fields, and ignore the CHS
+//! ones, which nothing has used in decades.
+//!
+//! Extended partitions are listed like any other, but we don't look inside
+//! them.
+//!
+//! Refer to the OS Dev Wiki for more information:
+//! http://wiki.osdev.org/MBR_(x86)
+use arch::drivers::ata::{AtaError, Drive, SECTOR_SIZE};
+use super::bcache;
+
+/// Offset of the partition table in the boot sector
+const TABLE_OFFSET: usize = 0x1BE;
+/// Size of each partition table entry (in bytes)
+const ENTRY_SIZE: usize = 16;
+/// Number of primary partitions
+pub const N_PARTITIONS: usize = 4;
+
+/// Errors that can occur while reading a partition table.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum MbrError { /// The boot sector couldn't be read
+                    Ata(AtaError)
+                  , /// The boot sector doesn't end with `0x55 0xAA`, so
+                    /// there's no partition table
+                    BadSignature
+                  }
+
+impl From<AtaError> for MbrError {
+    fn from(err: AtaError) -> Self { MbrError::Ata(err) }
+}
+
+/// A primary partition on a disk on the primary ATA bus.
+#[derive(Debug, Copy, Clone)]
+pub struct Partition { /// The drive the partition is on
+                       pub drive: Drive
+                     , /// The partition type byte (e.g. `0x0C` for FAT32)
+                       pub kind: u8
+                     , /// True if the partition is marked as bootable
+                       pub bootable: bool
+                     , /// LBA of the partition's first sector
+                       pub start: u32
+                     , /// Length of the partition (in sectors)
+                       pub sectors: u32
+                     }
+
+#[inline]
+fn read_u32(bytes: &[u8]) -> u32 {
+    bytes[0] as u32 | (bytes[1] as u32) << 8
+        | (bytes[2] as u32) << 16 | (bytes[3] as u32) << 24
+}
+
+impl Partition {
+    /// Parse a partition table entry.
+    ///
+    /// # Returns
+    ///   - `None` if the entry is unused
+    fn from_entry(drive: Drive, entry: &[u8]) -> Option<Partition> {
+        let kind = entry[4];
+        let sectors = read_u32(&entry[12..16]);
+        if kind == 0 || sectors == 0 {
+            return None
+        }
+        Some(Partition { drive: drive
+                       , kind: kind
+                       , bootable: entry[0] & 0x80 != 0
+                       , start: read_u32(&entry[8..12])
+                       , sectors: sectors
+                       })
+    }
+
+    /// Returns the size of the partition (in bytes)
+    #[inline]
+    pub fn size(&self) -> u64 { self.sectors as u64 * SECTOR_SIZE as u64 }
+
+    /// Work out the absolute LBA of `count` sectors at `lba`, checking that
+    /// they're in the partition and that `buf_len` bytes is enough for them.
+    ///
+    /// A (corrupt) partition table can put a partition's end past the last
+    /// LBA there is, so this also checks that the absolute LBA doesn't
+    /// overflow.
+    fn absolute(&self, lba: u32, count: u8, buf_len: usize)
+                -> Result<u32, AtaError> {
+        if lba as u64 + count as u64 > self.sectors as u64 {
+            Err(AtaError::LbaOutOfRange)
+        } else if buf_len < count as usize * SECTOR_SIZE {
+            Err(AtaError::BufferTooSmall)
+        } else {
+            self.start.checked_add(lba).ok_or(AtaError::LbaOutOfRange)
+        }
+    }
+
+    /// Read `count` sectors into `buf`, starting `lba` sectors into the
+    /// partition.
+    ///
+    /// This goes through the buffer cache.
+    ///
+    /// # Returns
+    ///   - `Err(AtaError::LbaOutOfRange)` if that would go past the end of
+    ///     the partition, or any error from `bcache::read`
+    pub fn read_sectors(&self, lba: u32, count: u8, buf: &mut [u8])
+                       -> Result<(), AtaError> {
+        let start = try!(self.absolute(lba, count, buf.len()));
+        for (i, sector) in buf.chunks_mut(SECTOR_SIZE)
+                              .take(count as usize)
+                              .enumerate() {
+            let sector = unsafe {
+                &mut *(sector.as_mut_ptr() as *mut [u8; SECTOR_SIZE])
+            };
+            try!(bcache::read(self.drive, start + i as u32, sector));
+        }
+        Ok(())
+    }
+
+    /// Write `count` sectors from `buf`, starting `lba` sectors into the
+    /// partition.
+    ///
+    /// This goes through the buffer cache, so the sectors aren't on the
+    /// disk until they're evicted or `bcache::sync` is called.
+    ///
+    /// # Returns
+    ///   - `Err(AtaError::LbaOutOfRange)` if that would go past the end of
+    ///     the partition, or any error from `bcache::read`
+    pub fn write_sectors(&self, lba: u32, count: u8, buf: &[u8])
+                        -> Result<(), AtaError> {
+        let start = try!(self.absolute(lba, count, buf.len()));
+        for (i, sector) in buf.chunks(SECTOR_SIZE)
+                              .take(count as usize)
+                              .enumerate() {
+            let sector = unsafe {
+                &*(sector.as_ptr() as *const [u8; SECTOR_SIZE])
+            };
+            try!(bcache::write(self.drive, start + i as u32, sector));
+        }
+        Ok(())
+    }
+}
+
+/// Read the partition table of `drive`, on the primary ATA bus.
+///
+/// # Returns
+///   - `Ok([Option<Partition>; 4])` with each primary partition, in order,
+///     or `None` for an unused entry
+///   - `Err(MbrError)` if the boot sector couldn't be read, or has no
+///     partition table
+pub fn partitions(drive: Drive)
+                  -> Result<[Option<Partition>; N_PARTITIONS], MbrError> {
+    let mut sector = [0u8; SECTOR_SIZE];
+    try!(bcache::read(drive, 0, &mut sector));
+    if sector[510] != 0x55 || sector[511] != 0xAA {
+        return Err(MbrError::BadSignature)
+    }
+    let mut table = [None; N_PARTITIONS];
+    for (i, partition) in table.iter_mut().enumerate() {
+        let entry = TABLE_OFFSET + i * ENTRY_SIZE;
+        *partition
+            = Partition::from_entry(drive, &sector[entry..entry + ENTRY_SIZE]);
+    }
+    Ok(table)
+}
@@ -0,0 +1,189 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Virtual filesystem layer.
+//!
+//! This module defines the `FileSystem` trait that all of our filesystem
+//! implementations provide, and a (very) minimal VFS that resolves absolute
+//! paths against whichever filesystem is mounted as the root. Callers should
+//! only ever talk to the functions in this module, so that real filesystems
+//! can be slotted in underneath later without anyone noticing.
+//!
+//! None of this allocates, since we don't have a heap yet.
+use core::{cmp, fmt, str};
+use spin::Mutex;
+
+pub mod ramfs;
+pub mod initrd;
+pub mod bcache;
+pub mod mbr;
+pub mod fat;
+
+/// Maximum length (in bytes) of a name in a directory entry
+pub const NAME_MAX: usize = 256;
+
+/// Errors that can occur during filesystem operations.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Error { /// The requested path doesn't exist
+                 NotFound
+               , /// The path is not a valid absolute path
+                 InvalidPath
+               , /// Tried to `readdir` something that's not a directory
+                 NotADirectory
+               , /// Tried to `read` or `write` a directory
+                 IsADirectory
+               , /// The filesystem doesn't support writing
+                 ReadOnly
+               , /// The filesystem has no room left for new files
+                 NoSpace
+               , /// No filesystem has been mounted at the root
+                 NotMounted
+               , /// The underlying device returned an error
+                 Io
+               , /// The filesystem's on-disk structures don't make sense,
+                 /// or use features we don't support
+                 Corrupt
+               }
+
+/// The kinds of things that can live in a filesystem
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum FileKind { Regular
+                  , Directory
+                  }
+
+/// A handle to an open file or directory.
+///
+/// The meaning of `id` is up to the filesystem that created the handle; it
+/// might be an index into a table, a cluster number, or whatever.
+#[derive(Debug, Copy, Clone)]
+pub struct File { /// Filesystem-specific file identifier
+                  pub id: u64
+                , /// Whether this is a file or a directory
+                  pub kind: FileKind
+                , /// The length of the file (in bytes)
+                  pub size: u64
+                }
+
+impl File {
+    #[inline] pub fn is_dir(&self) -> bool {
+        self.kind == FileKind::Directory
+    }
+}
+
+/// An entry in a directory listing.
+pub struct DirEntry { name: [u8; NAME_MAX]
+                    , name_len: usize
+                    , /// The file this entry refers to
+                      pub file: File
+                    }
+
+impl DirEntry {
+    /// Construct a new `DirEntry` with the given name.
+    ///
+    /// Names longer than `NAME_MAX` bytes are truncated.
+    pub fn new(name: &[u8], file: File) -> Self {
+        let len = cmp::min(name.len(), NAME_MAX);
+        let mut entry = DirEntry { name: [0; NAME_MAX]
+                                 , name_len: len
+                                 , file: file
+                                 };
+        for (dst, src) in entry.name.iter_mut().zip(&name[..len]) {
+            *dst = *src;
+        }
+        entry
+    }
+
+    /// Returns the name of this entry.
+    pub fn name(&self) -> &str {
+        str::from_utf8(&self.name[..self.name_len])
+            .unwrap_or("<invalid name>")
+    }
+}
+
+impl fmt::Debug for DirEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DirEntry({:?}, {:?})", self.name(), self.file)
+    }
+}
+
+/// A filesystem.
+///
+/// Paths passed to a `FileSystem` are relative to the root of that
+/// filesystem, with no leading slash; the filesystem's root directory is the
+/// empty path `""`.
+pub trait FileSystem: Sync {
+    /// Open the file or directory at `path`.
+    fn open(&self, path: &str) -> Result<File, Error>;
+
+    /// Read from `file`, starting at `offset`, into `buf`.
+    ///
+    /// # Returns
+    ///   - `Ok(usize)` containing the number of bytes read, which is zero at
+    ///     the end of the file
+    ///   - `Err(Error)` if the file could not be read
+    fn read(&self, file: &File, offset: u64, buf: &mut [u8])
+           -> Result<usize, Error>;
+
+    /// Write `buf` to `file`, starting at `offset`.
+    ///
+    /// # Returns
+    ///   - `Ok(usize)` containing the number of bytes written
+    ///   - `Err(Error)` if the file could not be written
+    fn write(&self, file: &File, offset: u64, buf: &[u8])
+            -> Result<usize, Error>;
+
+    /// Returns the `index`th entry in the directory `dir`.
+    ///
+    /// # Returns
+    ///   - `Ok(Some(DirEntry))` if the directory has an `index`th entry
+    ///   - `Ok(None)` if there are no more entries
+    ///   - `Err(Error)` if `dir` couldn't be read
+    fn readdir(&self, dir: &File, index: usize)
+              -> Result<Option<DirEntry>, Error>;
+}
+
+/// The filesystem mounted at `/`
+static ROOT: Mutex<Option<&'static FileSystem>>
+    = Mutex::new(None);
+
+/// Mount `fs` as the root filesystem, replacing whatever was mounted before.
+pub fn mount_root(fs: &'static FileSystem) {
+    *ROOT.lock() = Some(fs);
+}
+
+#[inline]
+fn root() -> Result<&'static FileSystem, Error> {
+    ROOT.lock()
+        .ok_or(Error::NotMounted)
+}
+
+/// Open the file at the absolute path `path`.
+pub fn open(path: &str) -> Result<File, Error> {
+    if !path.starts_with('/') {
+        return Err(Error::InvalidPath)
+    }
+    // strip the leading slash (and any trailing ones) so that the
+    // filesystem sees a path relative to its own root
+    let relative = path.trim_matches('/');
+    try!(root()).open(relative)
+}
+
+/// Read from `file`, starting at `offset`, into `buf`.
+pub fn read(file: &File, offset: u64, buf: &mut [u8]) -> Result<usize, Error> {
+    try!(root()).read(file, offset, buf)
+}
+
+/// Write `buf` to `file`, starting at `offset`.
+pub fn write(file: &File, offset: u64, buf: &[u8]) -> Result<usize, Error> {
+    try!(root()).write(file, offset, buf)
+}
+
+/// Returns the `index`th entry in the directory `dir`.
+pub fn readdir(dir: &File, index: usize) -> Result<Option<DirEntry>, Error> {
+    try!(root()).readdir(dir, index)
+}
@@ -0,0 +1,143 @@
+//
+//  SOS: the Stupid Operating System
+//  by Hawk Weisman (hi@hawkweisman.me)
+//
+//  Copyright (c) 2015 Hawk Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! A read-only in-memory filesystem.
+//!
+//! Until we have a heap, files in the ramfs are just `'static` slices of
+//! memory that someone else owns (typically an archive embedded in the kernel
+//! image or loaded by the bootloader), and the file table is a fixed-size
+//! array.
+//!
+//! The namespace is flat: each file is stored under its whole path (e.g.
+//! `etc/motd`), and the root directory lists every file in the filesystem.
+use core::cmp;
+use spin::Mutex;
+
+use super::{FileSystem, File, FileKind, DirEntry, Error};
+
+/// Maximum number of files that can be stored in a ramfs
+pub const MAX_FILES: usize = 64;
+
+/// The `id` of the ramfs's root directory. File ids are their index in the
+/// file table plus one.
+const ROOT_ID: u64 = 0;
+
+#[derive(Copy, Clone)]
+struct Node { name: &'static str
+            , data: &'static [u8]
+            }
+
+impl Node {
+    #[inline]
+    fn as_file(&self, index: usize) -> File {
+        File { id: index as u64 + 1
+             , kind: FileKind::Regular
+             , size: self.data.len() as u64
+             }
+    }
+}
+
+/// The kernel's ramfs
+pub static RAMFS: RamFs = RamFs::new();
+
+/// A read-only in-memory filesystem
+pub struct RamFs { nodes: Mutex<[Option<Node>; MAX_FILES]> }
+
+impl RamFs {
+    /// Create a new empty `RamFs`.
+    pub const fn new() -> Self {
+        RamFs { nodes: Mutex::new([None; MAX_FILES]) }
+    }
+
+    /// Add a file to the ramfs.
+    ///
+    /// If a file already exists at `path`, it is replaced.
+    ///
+    /// # Returns
+    ///   - `Ok(())` if the file was added
+    ///   - `Err(Error::InvalidPath)` if `path` is empty
+    ///   - `Err(Error::NoSpace)` if the file table is full
+    pub fn add_file(&self, path: &'static str, data: &'static [u8])
+                   -> Result<(), Error> {
+        let name = path.trim_matches('/');
+        if name.is_empty() {
+            return Err(Error::InvalidPath)
+        }
+        let mut nodes = self.nodes.lock();
+        // replace any existing file with the same name, or take the first
+        // empty slot in the table.
+        let slot = match nodes.iter().position(|n|
+                        n.map_or(false, |n| n.name == name)) {
+            Some(idx) => Some(idx)
+          , None      => nodes.iter().position(|n| n.is_none())
+        };
+        slot.map(|idx| nodes[idx] = Some(Node { name: name, data: data }))
+            .ok_or(Error::NoSpace)
+    }
+
+    /// Look up the node behind a file handle.
+    fn node(&self, file: &File) -> Result<Node, Error> {
+        if file.id == ROOT_ID {
+            return Err(Error::IsADirectory)
+        }
+        self.nodes.lock()
+            .get(file.id as usize - 1)
+            .and_then(|n| *n)
+            .ok_or(Error::NotFound)
+    }
+}
+
+impl FileSystem for RamFs {
+
+    fn open(&self, path: &str) -> Result<File, Error> {
+        if path.is_empty() {
+            return Ok(File { id: ROOT_ID, kind: FileKind::Directory, size: 0 })
+        }
+        let nodes = self.nodes.lock();
+        nodes.iter()
+             .enumerate()
+             .filter_map(|(idx, n)| n.map(|n| (idx, n)))
+             .find(|&(_, n)| n.name == path)
+             .map(|(idx, n)| n.as_file(idx))
+             .ok_or(Error::NotFound)
+    }
+
+    fn read(&self, file: &File, offset: u64, buf: &mut [u8])
+           -> Result<usize, Error> {
+        let data = try!(self.node(file)).data;
+        if offset >= data.len() as u64 {
+            return Ok(0)
+        }
+        let remaining = &data[offset as usize..];
+        let len = cmp::min(remaining.len(), buf.len());
+        for (dst, src) in buf.iter_mut().zip(&remaining[..len]) {
+            *dst = *src;
+        }
+        Ok(len)
+    }
+
+    fn write(&self, _file: &File, _offset: u64, _buf: &[u8])
+            -> Result<usize, Error> {
+        Err(Error::ReadOnly)
+    }
+
+    fn readdir(&self, dir: &File, index: usize)
+              -> Result<Option<DirEntry>, Error> {
+        // the namespace is flat, so the root is the only directory.
+        if dir.id != ROOT_ID {
+            return Err(Error::NotADirectory)
+        }
+        let nodes = self.nodes.lock();
+        Ok(nodes.iter()
+                .enumerate()
+                .filter_map(|(idx, n)| n.map(|n| (idx, n)))
+                .nth(index)
+                .map(|(idx, n)|
+                    DirEntry::new(n.name.as_bytes(), n.as_file(idx))))
+    }
+}